@@ -0,0 +1,75 @@
+//! How fast a topic's review is going. Every batch served is stamped with
+//! the time it was served and the seconds spent scoring it, and every
+//! judgment in the topic's log with the time it was logged, so each round
+//! can be measured from both ends: what the machine spent putting the
+//! batch together, and how long the reviewers took to get through it.
+
+use crate::qrels::Judgment;
+use crate::topic::Session;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// One batch's costs. Times are seconds since the Unix epoch.
+#[derive(Debug, Clone, Serialize)]
+pub struct RoundStats {
+    pub round: u32,
+    pub served_at: u64,
+    pub served: usize,
+    /// Documents of the batch judged so far, and how many were relevant
+    pub judged: usize,
+    pub relevant: usize,
+    /// Seconds spent scoring the collection for the batch, if recorded
+    pub scoring_seconds: Option<f64>,
+    /// When the first and last of the batch's judgments were logged
+    pub first_judged_at: Option<u64>,
+    pub last_judged_at: Option<u64>,
+    /// Seconds from serving the batch to its first judgment
+    pub first_latency: Option<u64>,
+    /// Seconds from serving the batch to its last judgment, once every
+    /// document in it is judged
+    pub latency: Option<u64>,
+    /// Judgments per hour from serving the batch to its last judgment
+    pub throughput: Option<f64>,
+}
+
+/// The costs of each batch in a session, oldest first. A judgment counts
+/// toward every batch serving its document before it was logged.
+/// Judgments logged without a time, such as those imported from a plain
+/// qrels file, count as judged but are not timed.
+pub fn round_stats(session: &Session, judgments: &[Judgment], min_rel: i32) -> Vec<RoundStats> {
+    let judged: HashMap<&str, &Judgment> =
+        judgments.iter().map(|j| (j.docid.as_str(), j)).collect();
+    session
+        .batches
+        .iter()
+        .map(|batch| {
+            let in_batch: Vec<&Judgment> = batch
+                .docids
+                .iter()
+                .filter_map(|d| judged.get(d.as_str()).copied())
+                .filter(|j| j.logged.is_none_or(|t| t >= batch.served))
+                .collect();
+            let times: Vec<u64> = in_batch.iter().filter_map(|j| j.logged).collect();
+            let first = times.iter().min().copied();
+            let last = times.iter().max().copied();
+            let complete = in_batch.len() == batch.docids.len();
+            let throughput = last.map(|t| {
+                let hours = (t - batch.served).max(1) as f64 / 3600.0;
+                times.len() as f64 / hours
+            });
+            RoundStats {
+                round: batch.round,
+                served_at: batch.served,
+                served: batch.docids.len(),
+                judged: in_batch.len(),
+                relevant: in_batch.iter().filter(|j| j.rel >= min_rel).count(),
+                scoring_seconds: batch.seconds,
+                first_judged_at: first,
+                last_judged_at: last,
+                first_latency: first.map(|t| t - batch.served),
+                latency: last.filter(|_| complete).map(|t| t - batch.served),
+                throughput,
+            }
+        })
+        .collect()
+}
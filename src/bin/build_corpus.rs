@@ -1,14 +1,22 @@
 use clap::Parser;
 use flate2::read;
 use kdam::{tqdm, Bar, BarExt};
-use mycal::{tokenize, Dict, Docs, DocsDb, FeatureVec};
+use mycal::cancel::{ctrl_c, CancellationToken};
+use mycal::languages::Languages;
+use mycal::recency::{parse_date, Dates};
+use mycal::routing::{Route, RoutingRules};
+use mycal::{
+    ensure_space, fingerprint, tokens, write_intids, CollectionLayout, Dict, Docs, DocsDb,
+    FeatureVec, HashedTail,
+};
+use roaring::RoaringBitmap;
 use serde_json::{from_str, Map, Value};
 use std::collections::HashMap;
 use std::ffi::OsStr;
-use std::fs::{remove_file, File};
+use std::fs::{create_dir_all, metadata, remove_dir_all, remove_file, File};
 use std::io::Write;
 use std::io::{BufRead, BufReader, BufWriter, Result, Seek};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 #[derive(Parser)]
 struct Cli {
@@ -16,6 +24,46 @@ struct Cli {
     out_prefix: String,
     /// The path to a file of documents, formatted as JSON lines
     bundles: Vec<String>,
+    /// Routing rules marking documents to keep out of review
+    #[arg(short, long)]
+    routing: Option<String>,
+    /// Drop terms occurring in more than this fraction of documents
+    #[arg(long, value_parser = parse_fraction)]
+    max_df: Option<f32>,
+    /// Give exact ids to at most this many of the most frequent terms. With
+    /// 0 and --hash-buckets, every term is hashed, and models move freely
+    /// between collections built the same way
+    #[arg(long)]
+    exact_terms: Option<usize>,
+    /// Hash terms without an exact id into this many shared ids instead of
+    /// dropping them
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    hash_buckets: Option<u32>,
+    /// Record each document's language from this field of its record, for
+    /// per-language models
+    #[arg(long)]
+    lang_field: Option<String>,
+    /// Record each document's date from this field of its record, an ISO
+    /// date or seconds since the epoch, for a recency prior
+    #[arg(long)]
+    date_field: Option<String>,
+    /// Write temporary files here instead of next to the collection
+    /// (default: $MYCAL_TMPDIR, if set)
+    #[arg(long)]
+    temp_dir: Option<PathBuf>,
+    /// Build without first checking for enough free disk space
+    #[arg(long)]
+    no_space_check: bool,
+}
+
+/// Roughly how much larger gzipped JSON lines are uncompressed
+const GZIP_RATIO: u64 = 4;
+
+fn parse_fraction(s: &str) -> std::result::Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(f) if f > 0.0 && f <= 1.0 => Ok(f),
+        _ => Err(format!("{} is not a fraction in (0, 1]", s)),
+    }
 }
 
 /// Read normal or compressed files seamlessly
@@ -38,16 +86,30 @@ pub fn reader(filename: &str) -> Box<dyn BufRead> {
     }
 }
 
+/// Count a document's terms. A document exported from another index (see
+/// `export-tantivy`) has its term counts in a `terms` object instead of
+/// text in `passage`, and those terms are used as they are.
 fn tokenize_and_map(
-    docmap: serde_json::Map<String, serde_json::Value>,
+    docmap: &serde_json::Map<String, serde_json::Value>,
     dict: &mut Dict,
-) -> (String, HashMap<usize, i32>) {
+) -> (String, HashMap<u32, i32>) {
     let mut m = HashMap::new();
     let docid = docmap["pid"].as_str().unwrap();
-    let tokens = tokenize(docmap["passage"].as_str().unwrap());
 
-    for x in tokens {
-        let tokid = dict.add_tok(x.to_owned());
+    if let Some(Value::Object(terms)) = docmap.get("terms") {
+        for (tok, count) in terms {
+            let count = count.as_i64().expect("Term counts must be integers");
+            let tokid = dict.add_tok(tok.as_str());
+            if !m.contains_key(&tokid) {
+                dict.incr_df(tokid);
+            }
+            *m.entry(tokid).or_insert(0) += count as i32;
+        }
+        return (docid.to_owned(), m);
+    }
+
+    for x in tokens(docmap["passage"].as_str().unwrap()) {
+        let tokid = dict.add_tok(x);
         if !m.contains_key(&tokid) {
             dict.incr_df(tokid);
         }
@@ -57,18 +119,106 @@ fn tokenize_and_map(
     (docid.to_owned(), m)
 }
 
+/// Remove temporary features left by a build that failed, here or in the
+/// default place next to the collection.
+fn remove_orphans(coll: &CollectionLayout, prefix: &str) -> Result<()> {
+    for file in [
+        coll.temp_features(),
+        CollectionLayout::new(prefix).temp_features(),
+    ] {
+        if file.exists() {
+            println!("Removing {}, left by an earlier build", file.display());
+            remove_file(file)?;
+        }
+    }
+    Ok(())
+}
+
+/// Fail early unless there is room for the build. The temporary features
+/// and the final feature file each take about as much space as the
+/// uncompressed input.
+fn check_space(coll: &CollectionLayout, bundles: &[String]) -> Result<()> {
+    let mut input = 0;
+    for bundle in bundles {
+        let len = metadata(bundle)?.len();
+        input += if bundle.ends_with(".gz") {
+            len * GZIP_RATIO
+        } else {
+            len
+        };
+    }
+    let (temp, out) = (coll.temp_dir(), coll.dir());
+    if same_device(&temp, &out) {
+        ensure_space(&out, 2 * input, "The build")
+    } else {
+        ensure_space(&temp, input, "The temporary features")?;
+        ensure_space(&out, input, "The collection")
+    }
+}
+
+#[cfg(unix)]
+fn same_device(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (metadata(a), metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev(),
+        _ => true,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_device(_: &Path, _: &Path) -> bool {
+    true
+}
+
+/// Stop a cancelled build, removing the temporary features and the
+/// partly written feature file and docid database.
+fn give_up(coll: &CollectionLayout, cancel: &CancellationToken) -> Result<()> {
+    for file in [coll.temp_features(), coll.features()] {
+        if file.exists() {
+            remove_file(file)?;
+        }
+    }
+    if coll.docsdb().exists() {
+        remove_dir_all(coll.docsdb())?;
+    }
+    cancel.check()
+}
+
 fn main() -> Result<()> {
     let args = Cli::parse();
+    let mut coll = CollectionLayout::new(&args.out_prefix);
+    let temp_dir = args
+        .temp_dir
+        .clone()
+        .or_else(|| std::env::var_os("MYCAL_TMPDIR").map(PathBuf::from));
+    if let Some(dir) = temp_dir {
+        create_dir_all(&dir)?;
+        coll = coll.with_temp_dir(dir);
+    }
+    let cancel = ctrl_c();
+    remove_orphans(&coll, &args.out_prefix)?;
+    if !args.no_space_check {
+        check_space(&coll, &args.bundles)?;
+    }
 
     // First pass: collect dictionary, df counts
     println!("First pass, collect dictionary and docfeqs");
     let mut dict: Dict = Dict::new();
     let mut library = Docs::new();
 
+    let rules = args.routing.as_ref().map(RoutingRules::load).transpose()?;
+    let mut routed = RoaringBitmap::new();
+    let mut langs = Languages::default();
+    let mut dates = Dates::default();
+    let mut undated = 0;
+
     let mut num_docs = 0;
-    let mut binout = BufWriter::new(File::create(args.out_prefix.clone() + ".tmp")?);
+    let mut binout = BufWriter::new(File::create(coll.temp_features())?);
 
     for bundle in args.bundles {
+        if cancel.is_cancelled() {
+            break;
+        }
         let path = Path::new(&bundle);
         let desc = path.file_name().unwrap().to_str().unwrap();
         let mut progress = tqdm!();
@@ -79,42 +229,117 @@ fn main() -> Result<()> {
 
         reader
             .lines()
+            .take_while(|_| !cancel.is_cancelled())
             .map(|line| from_str::<Map<String, Value>>(&line.unwrap()).expect("Error parsing JSON"))
-            .map(|docmap| tokenize_and_map(docmap, &mut dict))
-            .map(|(docid, docmap)| {
+            .map(|docmap| {
+                let (docid, tfs) = tokenize_and_map(&docmap, &mut dict);
                 let mut fv = FeatureVec::new(docid.clone());
-                for (tok, count) in docmap {
+                for (tok, count) in tfs {
                     fv.push(tok, count as f32);
                 }
-                library.add_doc(&docid);
+                let intid = library.add_doc(&docid);
+                if let Some(rules) = &rules {
+                    if rules.route(&docid, &docmap) == Route::Exclude {
+                        routed.insert(intid as u32);
+                    }
+                }
+                if let Some(field) = &args.lang_field {
+                    if let Some(Value::String(lang)) = docmap.get(field) {
+                        langs.insert(lang, intid as u32);
+                    }
+                }
+                if let Some(field) = &args.date_field {
+                    let day = match docmap.get(field) {
+                        Some(Value::String(s)) => parse_date(s),
+                        Some(Value::Number(n)) => parse_date(&n.to_string()),
+                        _ => None,
+                    };
+                    match day {
+                        Some(day) => dates.insert(intid as u32, day),
+                        None => undated += 1,
+                    }
+                }
                 fv
             })
             .for_each(|fv| {
                 progress.update(1);
                 num_docs += 1;
-                bincode::serialize_into(&mut binout, &fv).expect("Error writing to bin file");
+                fv.write_into(&mut binout)
+                    .expect("Error writing to bin file");
             });
 
         binout.flush()?;
         progress.refresh();
     }
+    if cancel.is_cancelled() {
+        drop(binout);
+        return give_up(&coll, &cancel);
+    }
 
-    // Compute IDF, drop singleton terms
+    // Compute IDF, drop terms above the df ceiling, and give exact ids to
+    // the rest, most frequent first. Singletons and terms past
+    // --exact-terms are hashed if --hash-buckets is given, else dropped.
     println!("Compute IDFs and prune dictionary");
     let mut new_dict = Dict::new();
     let mut old_to_new = HashMap::new();
+    let max_df = args.max_df.map_or(f32::INFINITY, |f| f * num_docs as f32);
+    let max_exact = args.exact_terms.unwrap_or(usize::MAX);
 
-    dict.m.drain().for_each(|(tok, tokid)| {
-        if let Some(df) = dict.df.get(&tokid) {
-            if *df > 1.0 {
-                let new_tokid = new_dict.add_tok(tok);
-                old_to_new.insert(tokid, new_tokid);
-                new_dict
-                    .df
-                    .insert(new_tokid, (num_docs as f32 / df).log10());
-            }
+    let mut terms: Vec<(String, u32, f32)> = dict
+        .m
+        .drain()
+        .filter_map(|(tok, tokid)| dict.df.get(&tokid).map(|df| (tok, tokid, *df)))
+        .collect();
+    terms.sort_by(|a, b| b.2.total_cmp(&a.2).then_with(|| a.0.cmp(&b.0)));
+
+    let mut tail = Vec::new();
+    for (tok, tokid, df) in terms {
+        if df > max_df {
+            // Kept out of the hashed tail too, where it would collide
+            new_dict.stopped.insert(tok);
+        } else if df > 1.0 && new_dict.m.len() < max_exact {
+            let new_tokid = new_dict.add_tok(tok);
+            old_to_new.insert(tokid, new_tokid);
+            new_dict
+                .df
+                .insert(new_tokid, (num_docs as f32 / df).log10());
+        } else {
+            tail.push((tok, tokid, df));
         }
-    });
+    }
+    if args.max_df.is_some() {
+        println!(
+            "Dropped {} terms above the df ceiling",
+            new_dict.stopped.len()
+        );
+    }
+
+    if let Some(buckets) = args.hash_buckets {
+        // A bucket's df is the sum of its terms' dfs, which overcounts
+        // documents holding more than one of them
+        let hashed = HashedTail {
+            first_id: new_dict.last_tokid + 1,
+            buckets,
+        };
+        let mut bucket_df: HashMap<u32, f32> = HashMap::new();
+        for (tok, tokid, df) in tail.iter() {
+            let bucket = hashed.tokid(tok);
+            old_to_new.insert(*tokid, bucket);
+            *bucket_df.entry(bucket).or_insert(0.0) += df;
+        }
+        for (bucket, df) in bucket_df {
+            let df = df.min(num_docs as f32);
+            new_dict.df.insert(bucket, (num_docs as f32 / df).log10());
+        }
+        new_dict.last_tokid = hashed.first_id + buckets - 1;
+        new_dict.hashed = Some(hashed);
+        println!(
+            "{} exact terms, {} hashed into {} buckets",
+            new_dict.m.len(),
+            tail.len(),
+            buckets
+        );
+    }
 
     println!(
         "Library len {} cap {}",
@@ -126,25 +351,37 @@ fn main() -> Result<()> {
     println!("Second pass: precompute weights and fix up tokenids");
     let mut progress = Bar::new(num_docs);
     let mut intid = 0;
-    let mut binin = BufReader::new(File::open(args.out_prefix.clone() + ".tmp")?);
-    binout = BufWriter::new(File::create(args.out_prefix.clone() + ".ftr")?);
-    let libdb_fn = args.out_prefix.to_string() + ".lib";
-    let mut lib = DocsDb::create(&libdb_fn);
+    let mut binin = BufReader::new(File::open(coll.temp_features())?);
+    binout = BufWriter::new(File::create(coll.features())?);
+    let mut lib = DocsDb::create(coll.docsdb());
 
     while let Ok(fv) = FeatureVec::read_from(&mut binin) {
+        if cancel.is_cancelled() {
+            // Dropping the database releases its lock before it is removed
+            drop(lib);
+            drop(binout);
+            return give_up(&coll, &cancel);
+        }
         let mut new_fv = FeatureVec::new(fv.docid.clone());
+        // Hashed terms can share an id, so sum their counts first
+        let mut tfs: HashMap<u32, f32> = HashMap::new();
         for f in &fv.features {
             if let Some(new_tokid) = old_to_new.get(&f.id) {
-                let df = new_dict.df.get(new_tokid).unwrap();
-                new_fv.push(*new_tokid, (1.0 + f.value.log10()) * df);
+                *tfs.entry(*new_tokid).or_insert(0.0) += f.value;
             }
         }
+        for (new_tokid, tf) in tfs {
+            let df = new_dict.df.get(&new_tokid).unwrap();
+            new_fv.push(new_tokid, (1.0 + tf.log10()) * df);
+        }
         new_fv.compute_norm();
         if intid >= library.docs.len() {
             println!("oh shit: {}", intid);
         }
         library.docs[intid].offset = binout.stream_position().unwrap();
-        bincode::serialize_into(&mut binout, &new_fv).expect("Error writing to final bin file");
+        new_fv
+            .write_into(&mut binout)
+            .expect("Error writing to final bin file");
         binout.flush()?;
 
         lib.insert_batch(&library.docs[intid].docid, &library.docs[intid], 100_000);
@@ -152,8 +389,24 @@ fn main() -> Result<()> {
         intid += 1;
         progress.update(1);
     }
+    lib.process_remaining();
     binout.flush()?;
-    remove_file(args.out_prefix.to_string() + ".tmp")?;
+    remove_file(coll.temp_features())?;
+
+    if rules.is_some() {
+        println!("{} documents routed out of review", routed.len());
+        write_intids(&routed, 0, coll.excluded())?;
+    }
+    if args.lang_field.is_some() {
+        langs.num_docs = library.docs.len() as u32;
+        println!("{} languages", langs.intids.len());
+        langs.save(coll.languages())?;
+    }
+    if args.date_field.is_some() {
+        dates.days.resize(library.docs.len(), None);
+        println!("{} documents without a date", undated);
+        dates.save(coll.dates())?;
+    }
 
     // let libdb_fn = args.out_prefix.to_string() + ".lib";
     // let mut lib = DocsDb::create(&libdb_fn);
@@ -165,7 +418,22 @@ fn main() -> Result<()> {
     // }
     // lib.process_remaining();
 
-    new_dict.save(&(args.out_prefix + ".dct"))?;
+    new_dict.save(coll.dict())?;
+
+    let settings = format!(
+        "max_df={:?} exact_terms={:?} hash_buckets={:?}",
+        args.max_df, args.exact_terms, args.hash_buckets
+    );
+    let fp = fingerprint(
+        &settings,
+        &new_dict,
+        library.docs.iter().map(|di| di.docid.as_str()),
+    );
+    std::fs::write(coll.fingerprint(), format!("{}\n", fp))?;
+    // Left by compacting an earlier build at this prefix
+    if coll.generation().exists() {
+        remove_file(coll.generation())?;
+    }
 
     Ok(())
 }
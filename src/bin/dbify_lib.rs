@@ -1,10 +1,9 @@
 use clap::Parser;
 use kdam::{tqdm, BarExt};
 use kv::*;
-use mycal::{DocInfo, Docs};
+use mycal::{CollectionLayout, DocInfo, Docs};
 use std::fs::File;
 use std::io::{BufReader, Result};
-use std::path::Path;
 
 #[derive(Parser)]
 struct Cli {
@@ -14,15 +13,13 @@ struct Cli {
 fn main() -> Result<()> {
     let args = Cli::parse();
 
-    let root = Path::new(".");
-    let docs_file = root.join(format!("{}.lib", args.coll_prefix));
-    let db_file = root.join(format!("{}.lb2", args.coll_prefix));
+    let coll = CollectionLayout::new(&args.coll_prefix);
     println!("Reading lib structure...");
-    let docs_fp = BufReader::new(File::open(docs_file)?);
+    let docs_fp = BufReader::new(File::open(coll.docsdb())?);
     let mut docs: Docs = bincode::deserialize_from(docs_fp).unwrap();
 
     println!("Converting to database...");
-    let db = Config::new(db_file);
+    let db = Config::new(coll.kv_docsdb());
     let store = Store::new(db).unwrap();
     let bucket = store
         .bucket::<String, Bincode<DocInfo>>(Some("docinfo"))
@@ -31,7 +28,7 @@ fn main() -> Result<()> {
     let mut progbar = tqdm!(total = docs.docs.len());
 
     for (docid, intid) in docs.m.drain() {
-        let di = docs.docs.get(intid).unwrap();
+        let di = docs.docs.get(intid as usize).unwrap();
         let dib = Bincode(DocInfo {
             intid: di.intid,
             docid: di.docid.clone(),
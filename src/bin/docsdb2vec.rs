@@ -2,7 +2,7 @@ use std::{error::Error, fs::File, io::{BufWriter, Write}};
 
 use clap::{Arg, Command};
 use kdam::TqdmIterator;
-use mycal::{DocsDb, DocInfo};
+use mycal::{CollectionLayout, DocsDb, DocInfo};
 
 fn cli() -> Command {
     Command::new("docsdb2vec")
@@ -17,11 +17,9 @@ fn cli() -> Command {
 
 fn main() -> Result<(), Box<dyn Error>> {
     let args = cli().get_matches();
-    let coll_prefix = args.get_one::<String>("coll").unwrap();
-    let docsdb_file = coll_prefix.to_string() + ".lib";
-    let docvec_file = coll_prefix.to_string() + ".dvc";
+    let coll = CollectionLayout::new(args.get_one::<String>("coll").unwrap());
 
-    let docs = DocsDb::open(&docsdb_file);
+    let docs = DocsDb::open(coll.docsdb());
     let mut divec = vec![];
 
     docs.db
@@ -32,7 +30,7 @@ fn main() -> Result<(), Box<dyn Error>> {
         });
 
 
-    let mut vecfile = BufWriter::new(File::create(docvec_file)?);
+    let mut vecfile = BufWriter::new(File::create(coll.docvec())?);
     bincode::serialize_into(&mut vecfile, &divec).expect("Error writing DI vector");
     vecfile.flush()?;
 
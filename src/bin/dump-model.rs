@@ -1,27 +1,87 @@
 use clap::Parser;
-use std::io::Result;
-use mycal::Classifier;
+use std::io::{Error, ErrorKind, Result};
+use mycal::{Classifier, CollectionLayout, Dict};
 
 #[derive(Parser)]
 struct Cli {
     model: String,
+    /// Collection whose dictionary names the weights; they are then listed
+    /// by token, largest in magnitude first, with the scale folded in
+    #[arg(long, value_name = "COLL")]
+    with_tokens: Option<String>,
 }
 
 fn main() -> Result<()> {
     let args = Cli::parse();
 
-    let model = Classifier::load(&args.model).unwrap();
+    let model = Classifier::load(&args.model).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("Could not read model {}: {}", args.model, e),
+        )
+    })?;
 
-    println!("sparse model");
-    for (i, f) in model.w.iter().enumerate() {
-        if *f != 0.0 {
-            println!("{}: {}", i, *f);
+    if let Some(coll) = args.with_tokens {
+        let dict =
+            Dict::load(CollectionLayout::new(coll).dict()).expect("Could not load dictionary");
+        let names = dict.tokens_by_id();
+        let mut weights: Vec<(usize, f32)> = model
+            .w
+            .iter()
+            .enumerate()
+            .filter(|(_, w)| **w != 0.0)
+            .map(|(i, w)| (i, w * model.scale))
+            .collect();
+        weights.sort_by(|a, b| b.1.abs().total_cmp(&a.1.abs()));
+
+        println!("weights by magnitude");
+        for (i, w) in weights {
+            let id = i as u32;
+            match (names.get(&id), &dict.hashed) {
+                (Some(tok), _) => println!("{}\t{}", tok, w),
+                (None, Some(tail)) if id >= tail.first_id => {
+                    println!("<hash bucket {}>\t{}", id - tail.first_id, w)
+                }
+                _ => println!("<{}>\t{}", id, w),
+            }
+        }
+    } else {
+        println!("sparse model");
+        for (i, f) in model.w.iter().enumerate() {
+            if *f != 0.0 {
+                println!("{}: {}", i, *f);
+            }
         }
     }
 
+    if let Some(header) = &model.header {
+        println!("created: {}", header.created);
+        println!("collection: {}", header.collection);
+        println!("tokenizer: {}", header.tokenizer);
+        println!("vocab size: {}", header.vocab_size);
+        if let Some(fp) = &header.fingerprint {
+            println!("fingerprint: {}", fp);
+        }
+        if let Some(tail) = &header.hashed {
+            println!(
+                "hashed: {} buckets from id {}{}",
+                tail.buckets,
+                tail.first_id,
+                if header.is_hashed_only() { " (hashed only)" } else { "" }
+            );
+        }
+    }
     println!("lambda: {}", model.lambda);
     println!("scale: {}", model.scale);
+    println!("bias: {}", model.bias);
     println!("norm: {}", model.squared_norm);
+    println!("class weights: {:?}", model.class_weights);
+    println!("steps: {}", model.steps);
+    println!("l1 ratio: {}", model.l1_ratio);
+    println!("step sizes: {:?}", model.train_config);
+    if let Some(platt) = model.calibration {
+        println!("calibration: a {} b {}", platt.a, platt.b);
+    }
 
     Ok(())
 }
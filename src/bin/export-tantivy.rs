@@ -0,0 +1,74 @@
+use clap::Parser;
+use kdam::{tqdm, BarExt};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use tantivy::schema::{IndexRecordOption, Value as _};
+use tantivy::{DocSet, Index, Postings, TantivyDocument, TERMINATED};
+
+#[derive(Parser)]
+#[command(name = "export-tantivy")]
+#[command(about = "Write the term counts in a tantivy index as JSON lines for build_corpus.")]
+struct Cli {
+    /// The tantivy index directory
+    index_dir: String,
+    /// The JSON lines file to write
+    out_file: String,
+    /// The indexed text field to take terms from
+    #[arg(short, long, default_value = "body")]
+    field: String,
+    /// The stored field holding each document's docid
+    #[arg(short, long, default_value = "docid")]
+    id_field: String,
+}
+
+fn main() -> tantivy::Result<()> {
+    let args = Cli::parse();
+
+    let index = Index::open_in_dir(&args.index_dir)?;
+    let schema = index.schema();
+    let field = schema.get_field(&args.field)?;
+    let id_field = schema.get_field(&args.id_field)?;
+    let searcher = index.reader()?.searcher();
+    let mut out = BufWriter::new(File::create(&args.out_file)?);
+
+    // The index is inverted, so each segment's documents are rebuilt from
+    // its postings before any can be written
+    for segment in searcher.segment_readers() {
+        let mut docs: Vec<HashMap<String, u32>> = vec![HashMap::new(); segment.max_doc() as usize];
+        let inverted = segment.inverted_index(field)?;
+        let mut terms = inverted.terms().stream()?;
+        while let Some((term, term_info)) = terms.next() {
+            let tok = String::from_utf8_lossy(term).into_owned();
+            let mut postings =
+                inverted.read_postings_from_terminfo(term_info, IndexRecordOption::WithFreqs)?;
+            while postings.doc() != TERMINATED {
+                docs[postings.doc() as usize].insert(tok.clone(), postings.term_freq());
+                postings.advance();
+            }
+        }
+
+        let store = segment.get_store_reader(100)?;
+        let mut progress = tqdm!(total = docs.len());
+        for (doc, tfs) in docs.into_iter().enumerate() {
+            progress.update(1);
+            if segment.is_deleted(doc as u32) || tfs.is_empty() {
+                continue;
+            }
+            let stored: TantivyDocument = store.get(doc as u32)?;
+            let Some(docid) = stored.get_first(id_field).and_then(|v| v.as_str()) else {
+                eprintln!("warning: document {} has no {}", doc, args.id_field);
+                continue;
+            };
+            let terms: Map<String, Value> = tfs.into_iter().map(|(t, n)| (t, json!(n))).collect();
+            serde_json::to_writer(&mut out, &json!({ "pid": docid, "terms": terms }))
+                .expect("Error writing JSON");
+            out.write_all(b"\n")?;
+        }
+        progress.refresh();
+    }
+    out.flush()?;
+
+    Ok(())
+}
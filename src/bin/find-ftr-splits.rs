@@ -3,7 +3,7 @@ use kdam::{tqdm};
 use std::io::prelude::*;
 use std::{error::Error, fs::File, io::BufWriter};
 
-use mycal::{DocInfo, DocsDb};
+use mycal::{CollectionLayout, DocInfo, DocsDb};
 
 fn cli() -> Command {
     Command::new("find-ftr-splits")
@@ -26,11 +26,10 @@ fn cli() -> Command {
 
 fn main() -> Result<(), Box<dyn Error>> {
     let args = cli().get_matches();
-    let coll_prefix = args.get_one::<String>("coll").unwrap();
+    let coll = CollectionLayout::new(args.get_one::<String>("coll").unwrap());
     let num_splits = args.get_one::<usize>("num_splits").unwrap();
 
-    let docsdb_file = coll_prefix.to_string() + ".lib";
-    let docsdb = DocsDb::open(&docsdb_file);
+    let docsdb = DocsDb::open(coll.docsdb());
 
     let offsets: Vec<u64> = tqdm!(docsdb.db.iter())
         .map(|r| r.unwrap().1)
@@ -38,8 +37,7 @@ fn main() -> Result<(), Box<dyn Error>> {
         .collect();
     let step = offsets.len() / num_splits;
 
-    let out_file = coll_prefix.to_string() + ".cut";
-    let mut out = BufWriter::new(File::create(out_file)?);
+    let mut out = BufWriter::new(File::create(coll.splits())?);
     let mut cur: usize = 0;
     for _ in 0..*num_splits {
         writeln!(out, "{:}", offsets[cur])?;
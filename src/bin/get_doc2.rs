@@ -1,9 +1,8 @@
 use clap::Parser;
 use kv::*;
-use mycal::{DocInfo, FeatureVec};
+use mycal::{CollectionLayout, DocInfo, FeatureVec};
 use std::fs::File;
 use std::io::{BufReader, Result, Seek, SeekFrom};
-use std::path::Path;
 
 #[derive(Parser)]
 #[command(name = "get_doc2")]
@@ -16,13 +15,10 @@ struct Cli {
 fn main() -> Result<()> {
     let args = Cli::parse();
 
-    let root = Path::new(".");
-    let _dict_file = root.join(format!("{}.dct", args.coll_prefix));
-    let docs_file = root.join(format!("{}.lib", args.coll_prefix));
-    let feat_file = root.join(format!("{}.ftr", args.coll_prefix));
+    let coll = CollectionLayout::new(&args.coll_prefix);
 
     println!("Opening lb2 database...");
-    let db = Config::new(docs_file);
+    let db = Config::new(coll.docsdb());
     let store = Store::new(db).unwrap();
     let bucket = store
         .bucket::<String, Bincode<DocInfo>>(Some("docinfo"))
@@ -40,7 +36,7 @@ fn main() -> Result<()> {
     let docinfo = bin_docinfo.0;
 
     println!("Looking up features...");
-    let mut feat_fp = BufReader::new(File::open(feat_file)?);
+    let mut feat_fp = BufReader::new(File::open(coll.features())?);
     feat_fp.seek(SeekFrom::Start(docinfo.offset))?;
 
     let fv: FeatureVec = bincode::deserialize_from(feat_fp).unwrap();
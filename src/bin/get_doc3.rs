@@ -1,8 +1,7 @@
 use clap::Parser;
-use mycal::{DocsDb, FeatureVec};
+use mycal::{CollectionLayout, DocsDb, FeatureVec};
 use std::fs::File;
 use std::io::{BufReader, Result, Seek, SeekFrom};
-use std::path::Path;
 
 #[derive(Parser)]
 #[command(name = "get_doc2")]
@@ -15,13 +14,10 @@ struct Cli {
 fn main() -> Result<()> {
     let args = Cli::parse();
 
-    let root = Path::new(".");
-    let _dict_file = root.join(format!("{}.dct", args.coll_prefix));
-    let docs_file = root.join(format!("{}.lib", args.coll_prefix));
-    let feat_file = root.join(format!("{}.ftr", args.coll_prefix));
+    let coll = CollectionLayout::new(&args.coll_prefix);
 
     println!("Opening lb2 database...");
-    let docs = DocsDb::open(docs_file.to_str().unwrap());
+    let docs = DocsDb::open(coll.docsdb());
     // let db = Config::new(docs_file);
     // let store = Store::new(db).unwrap();
     // let bucket = store
@@ -35,7 +31,7 @@ fn main() -> Result<()> {
     };
 
     println!("Looking up features...");
-    let mut feat_fp = BufReader::new(File::open(feat_file)?);
+    let mut feat_fp = BufReader::new(File::open(coll.features())?);
     feat_fp.seek(SeekFrom::Start(docinfo.offset))?;
 
     let fv: FeatureVec = bincode::deserialize_from(feat_fp).unwrap();
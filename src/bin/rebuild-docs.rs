@@ -0,0 +1,91 @@
+use clap::Parser;
+use kdam::{tqdm, BarExt};
+use mycal::{CollectionLayout, DocInfo, DocsDb, FeatureVec};
+use std::collections::HashSet;
+use std::fs::{remove_dir_all, File};
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Result, Seek, SeekFrom, Write};
+
+#[derive(Parser)]
+#[command(name = "rebuild-docs")]
+#[command(
+    about = "Rebuild a collection's docs db and docid vector from its feature file.",
+    long_about = "Rebuild a collection's docs db and docid vector from its feature file. \
+                  Each feature vector records its docid, and the nth vector is intid n, \
+                  so both side files can be recovered if they are lost or corrupted. \
+                  The dictionary can't be: the feature file only has token ids."
+)]
+struct Cli {
+    coll_prefix: String,
+    /// Replace side files that still exist
+    #[arg(long)]
+    force: bool,
+}
+
+fn main() -> Result<()> {
+    let args = Cli::parse();
+    let coll = CollectionLayout::new(&args.coll_prefix);
+
+    for path in [coll.docsdb(), coll.docvec()] {
+        if path.exists() && !args.force {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{} exists; use --force to replace it", path.display()),
+            ));
+        }
+    }
+    if coll.docsdb().exists() {
+        remove_dir_all(coll.docsdb())?;
+    }
+
+    println!("Scanning feature vectors...");
+    let mut docs = DocsDb::create(coll.docsdb());
+    let mut binin = BufReader::new(File::open(coll.features())?);
+    let mut divec = Vec::new();
+    let mut seen = HashSet::new();
+    let mut progress = tqdm!();
+    let mut end = 0;
+
+    loop {
+        let offset = binin.stream_position()?;
+        let Ok(fv) = FeatureVec::read_from(&mut binin) else {
+            break;
+        };
+        end = binin.stream_position()?;
+        let di = DocInfo {
+            intid: divec.len() as u64,
+            docid: fv.docid,
+            offset,
+        };
+        if !seen.insert(di.docid.clone()) {
+            eprintln!(
+                "warning: {} appears more than once, keeping intid {}",
+                di.docid, di.intid
+            );
+        }
+        docs.insert_batch(&di.docid, &di, 100_000);
+        divec.push(di);
+        progress.update(1);
+    }
+    docs.process_remaining();
+    docs.db.flush().expect("Error flushing docs db");
+    eprintln!();
+
+    let len = binin.seek(SeekFrom::End(0))?;
+    if end < len {
+        eprintln!(
+            "warning: the last {} bytes of the feature file could not be read",
+            len - end
+        );
+    }
+
+    // The docid vector is in database order, which is docid order. As in
+    // the db, a repeated docid keeps its last intid.
+    divec.sort_by(|a, b| a.docid.cmp(&b.docid).then(b.intid.cmp(&a.intid)));
+    divec.dedup_by(|a, b| a.docid == b.docid);
+    let mut vecfile = BufWriter::new(File::create(coll.docvec())?);
+    bincode::serialize_into(&mut vecfile, &divec).expect("Error writing DI vector");
+    vecfile.flush()?;
+
+    println!("recovered {} documents", divec.len());
+    Ok(())
+}
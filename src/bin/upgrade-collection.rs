@@ -0,0 +1,199 @@
+use bincode::Options;
+use clap::Parser;
+use kdam::{tqdm, BarExt};
+use mycal::{CollectionLayout, Dict, DocsDb, FeatureVec, FEATURE_VEC_MAGIC};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs::{remove_dir_all, remove_file, rename, File};
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Result, Seek, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser)]
+#[command(name = "upgrade-collection")]
+#[command(about = "Rewrite a collection built with u64 token ids to use u32 ids.")]
+struct Cli {
+    coll_prefix: String,
+}
+
+// The pre-u32 layouts, kept only to read old collections.
+#[derive(Deserialize)]
+struct OldFeaturePair {
+    id: u64,
+    value: f32,
+}
+
+#[derive(Deserialize)]
+struct OldFeatureVec {
+    docid: String,
+    features: Vec<OldFeaturePair>,
+    squared_norm: f32,
+}
+
+#[derive(Deserialize)]
+struct OldDict {
+    m: HashMap<String, u64>,
+    df: HashMap<u64, f32>,
+    last_tokid: u64,
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidData, message.into())
+}
+
+/// Bincode's default settings, but reading no more than `len` bytes, so a
+/// file not in the old layout fails to decode rather than asking for an
+/// impossible allocation.
+fn old_layout(len: u64) -> impl Options + Copy {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(len)
+}
+
+fn narrow(id: u64) -> Result<u32> {
+    u32::try_from(id).map_err(|_| invalid(format!("Token id {} does not fit in u32", id)))
+}
+
+/// The files an upgrade rewrites
+fn rewritten(coll: &CollectionLayout) -> [PathBuf; 4] {
+    [coll.dict(), coll.features(), coll.docsdb(), coll.docvec()]
+}
+
+/// Where the upgraded copy of `path` is written
+fn new_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".new");
+    PathBuf::from(name)
+}
+
+/// Move the upgraded files into place, then remove the journal. Every step
+/// can be repeated, so running this again finishes a swap that was
+/// interrupted.
+fn swap_in(coll: &CollectionLayout) -> Result<()> {
+    for path in rewritten(coll) {
+        let new = new_path(&path);
+        if !new.exists() {
+            continue;
+        }
+        if path.is_dir() {
+            remove_dir_all(&path)?;
+        }
+        rename(&new, &path)?;
+    }
+    remove_file(coll.upgrading())
+}
+
+fn main() -> Result<()> {
+    let args = Cli::parse();
+    let coll = CollectionLayout::new(&args.coll_prefix);
+
+    // Every upgraded file is written beside the one it replaces, and only
+    // once all of them are does the journal appear, so stopping before
+    // then leaves the collection as it was, and stopping after it leaves a
+    // swap that running again finishes
+    if coll.upgrading().exists() {
+        println!("Finishing an interrupted upgrade...");
+        return swap_in(&coll);
+    }
+    for path in rewritten(&coll) {
+        let new = new_path(&path);
+        if new.is_dir() {
+            remove_dir_all(&new)?;
+        } else if new.exists() {
+            remove_file(&new)?;
+        }
+    }
+
+    println!("Converting dictionary...");
+    let dict_file = File::open(coll.dict())?;
+    let old_dict: OldDict = old_layout(dict_file.metadata()?.len())
+        .deserialize_from(BufReader::new(dict_file))
+        .map_err(|e| invalid(format!("Dictionary is not in the u64 layout: {}", e)))?;
+    let dict = Dict {
+        m: old_dict
+            .m
+            .into_iter()
+            .map(|(k, v)| Ok((k, narrow(v)?)))
+            .collect::<Result<_>>()?,
+        df: old_dict
+            .df
+            .into_iter()
+            .map(|(k, v)| Ok((narrow(k)?, v)))
+            .collect::<Result<_>>()?,
+        last_tokid: narrow(old_dict.last_tokid)?,
+        hashed: None,
+        stopped: HashSet::new(),
+    };
+
+    println!("Converting feature vectors...");
+    let docs = DocsDb::open(coll.docsdb());
+    let ftr_file = File::open(coll.features())?;
+    let opts = old_layout(ftr_file.metadata()?.len());
+    let mut binin = BufReader::new(ftr_file);
+    let head = binin.fill_buf()?;
+    if head.len() >= 8 && head[..8] == FEATURE_VEC_MAGIC.to_le_bytes() {
+        return Err(invalid(
+            "Feature file has records with sections, so is already upgraded",
+        ));
+    }
+    let mut binout = BufWriter::new(File::create(new_path(&coll.features()))?);
+    let mut progress = tqdm!();
+
+    let mut moved = Vec::new();
+    while !binin.fill_buf()?.is_empty() {
+        let old_fv: OldFeatureVec = opts.deserialize_from(&mut binin).map_err(|e| {
+            invalid(format!(
+                "Feature vector {} is not in the u64 layout: {}",
+                moved.len(),
+                e
+            ))
+        })?;
+        let mut fv = FeatureVec::new(old_fv.docid);
+        for fp in old_fv.features {
+            fv.push(narrow(fp.id)?, fp.value);
+        }
+        fv.squared_norm = old_fv.squared_norm;
+
+        let offset = binout.stream_position()?;
+        fv.write_into(&mut binout)
+            .map_err(|e| Error::other(e.to_string()))?;
+
+        let mut di = docs
+            .get(&fv.docid)
+            .ok_or_else(|| invalid(format!("Docid {} missing from docs db", fv.docid)))?;
+        di.offset = offset;
+        moved.push(di);
+        progress.update(1);
+    }
+    binout.flush()?;
+    if moved.len() != docs.db.len() {
+        return Err(invalid(format!(
+            "Feature file has {} vectors but the docs db has {} documents",
+            moved.len(),
+            docs.db.len()
+        )));
+    }
+    // Released before its directory is replaced
+    drop(docs);
+
+    let mut new_docs = DocsDb::create(new_path(&coll.docsdb()));
+    for di in &moved {
+        new_docs.insert_batch(&di.docid, di, 100_000);
+    }
+    new_docs.process_remaining();
+    new_docs.db.flush()?;
+    drop(new_docs);
+    dict.save(new_path(&coll.dict()))?;
+
+    if coll.docvec().exists() {
+        println!("Rewriting docid vector...");
+        // The docid vector is in database order, which is docid order
+        moved.sort_by(|a, b| a.docid.cmp(&b.docid));
+        let mut vecfile = BufWriter::new(File::create(new_path(&coll.docvec()))?);
+        bincode::serialize_into(&mut vecfile, &moved).map_err(|e| Error::other(e.to_string()))?;
+        vecfile.flush()?;
+    }
+
+    File::create(coll.upgrading())?.sync_all()?;
+    swap_in(&coll)
+}
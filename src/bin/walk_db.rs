@@ -1,7 +1,6 @@
 use clap::Parser;
-use mycal::{DocInfo, DocsDb};
+use mycal::{CollectionLayout, DocInfo, DocsDb};
 use std::io::Result;
-use std::path::Path;
 
 #[derive(Parser)]
 struct Cli {
@@ -11,12 +10,10 @@ struct Cli {
 fn main() -> Result<()> {
     let args = Cli::parse();
 
-    let root = Path::new(".");
-    let _dict_file = root.join(format!("{}.dct", args.coll_prefix));
-    let docs_file = root.join(format!("{}.lib", args.coll_prefix));
+    let coll = CollectionLayout::new(&args.coll_prefix);
 
     println!("Opening database...");
-    let docs = DocsDb::open(docs_file.to_str().unwrap());
+    let docs = DocsDb::open(coll.docsdb());
     // let db = Config::new(docs_file);
     // let store = Store::new(db).unwrap();
     // let bucket = store
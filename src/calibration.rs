@@ -0,0 +1,105 @@
+use serde::{Deserialize, Serialize};
+
+/// A sigmoid mapping raw classifier scores to P(relevant), fitted with
+/// Platt's method: `P(rel | s) = 1 / (1 + exp(a * s + b))`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Platt {
+    pub a: f32,
+    pub b: f32,
+}
+
+impl Platt {
+    /// Fit the sigmoid to scores and their labels (positive means relevant).
+    ///
+    /// Uses the Newton method with backtracking line search from Lin, Lin
+    /// and Weng (2007), including Platt's smoothed targets, which keep the
+    /// fit finite when the training scores separate the classes perfectly.
+    pub fn fit(scores: &[f32], labels: &[i8]) -> Platt {
+        assert_eq!(scores.len(), labels.len(), "One label per score");
+        let num_pos = labels.iter().filter(|l| **l > 0).count() as f64;
+        let num_neg = labels.len() as f64 - num_pos;
+
+        let hi_target = (num_pos + 1.0) / (num_pos + 2.0);
+        let lo_target = 1.0 / (num_neg + 2.0);
+        let data: Vec<(f64, f64)> = scores
+            .iter()
+            .zip(labels)
+            .map(|(s, l)| (*s as f64, if *l > 0 { hi_target } else { lo_target }))
+            .collect();
+
+        let objective = |a: f64, b: f64| -> f64 {
+            data.iter()
+                .map(|(s, t)| {
+                    let f = s * a + b;
+                    if f >= 0.0 {
+                        t * f + (-f).exp().ln_1p()
+                    } else {
+                        (t - 1.0) * f + f.exp().ln_1p()
+                    }
+                })
+                .sum()
+        };
+
+        const MAX_ITER: usize = 100;
+        const MIN_STEP: f64 = 1e-10;
+        const SIGMA: f64 = 1e-12;
+        const EPS: f64 = 1e-5;
+
+        let mut a = 0.0;
+        let mut b = ((num_neg + 1.0) / (num_pos + 1.0)).ln();
+        let mut fval = objective(a, b);
+
+        for _ in 0..MAX_ITER {
+            let (mut h11, mut h22, mut h21) = (SIGMA, SIGMA, 0.0);
+            let (mut g1, mut g2) = (0.0, 0.0);
+            for (s, t) in data.iter() {
+                let f = s * a + b;
+                let (p, q) = if f >= 0.0 {
+                    let e = (-f).exp();
+                    (e / (1.0 + e), 1.0 / (1.0 + e))
+                } else {
+                    let e = f.exp();
+                    (1.0 / (1.0 + e), e / (1.0 + e))
+                };
+                let d2 = p * q;
+                h11 += s * s * d2;
+                h22 += d2;
+                h21 += s * d2;
+                let d1 = t - p;
+                g1 += s * d1;
+                g2 += d1;
+            }
+            if g1.abs() < EPS && g2.abs() < EPS {
+                break;
+            }
+
+            let det = h11 * h22 - h21 * h21;
+            let da = -(h22 * g1 - h21 * g2) / det;
+            let db = -(-h21 * g1 + h11 * g2) / det;
+            let gd = g1 * da + g2 * db;
+
+            let mut step = 1.0;
+            while step >= MIN_STEP {
+                let (new_a, new_b) = (a + step * da, b + step * db);
+                let new_f = objective(new_a, new_b);
+                if new_f < fval + 0.0001 * step * gd {
+                    (a, b, fval) = (new_a, new_b, new_f);
+                    break;
+                }
+                step /= 2.0;
+            }
+            if step < MIN_STEP {
+                break;
+            }
+        }
+
+        Platt {
+            a: a as f32,
+            b: b as f32,
+        }
+    }
+
+    pub fn probability(&self, score: f32) -> f32 {
+        1.0 / (1.0 + (self.a * score + self.b).exp())
+    }
+}
@@ -0,0 +1,59 @@
+//! Cooperative cancellation of long scans and builds. A
+//! [`CancellationToken`] is checked between batches, so Ctrl-C stops a
+//! scoring pass or a collection build where it can clean up after itself,
+//! rather than killing the process mid-write and leaving temporary files
+//! and database locks behind.
+
+use std::io::{Error, ErrorKind, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Fail with [`ErrorKind::Interrupted`] if cancelled.
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(Error::new(ErrorKind::Interrupted, "Cancelled"));
+        }
+        Ok(())
+    }
+}
+
+/// The token cancelled by Ctrl-C, installing the handler the first time.
+/// A second Ctrl-C exits at once, for work that has stopped checking.
+pub fn ctrl_c() -> CancellationToken {
+    static TOKEN: OnceLock<CancellationToken> = OnceLock::new();
+    TOKEN
+        .get_or_init(|| {
+            let token = CancellationToken::new();
+            let handler_token = token.clone();
+            let installed = ctrlc::set_handler(move || {
+                if handler_token.is_cancelled() {
+                    std::process::exit(130);
+                }
+                eprintln!("\ncancelling; press Ctrl-C again to quit at once");
+                handler_token.cancel();
+            });
+            if let Err(e) = installed {
+                eprintln!("warning: Ctrl-C will not cancel cleanly: {}", e);
+            }
+            token
+        })
+        .clone()
+}
@@ -0,0 +1,332 @@
+//! The chunked feature file, `<prefix>.fch`: the same vectors as the
+//! `.ftr` file, grouped into fixed-size chunks that can be decoded
+//! independently. A scan can skip a chunk whose documents are all
+//! excluded without reading it, and with the `parallel` feature decodes
+//! several chunks at once. The `.ftr` file stays the random-access copy
+//! that [`crate::DocInfo`] offsets point into.
+//!
+//! All integers are little-endian.
+//!
+//! * header: the bytes `MYCH`, a u32 format version, a u32 chunk size.
+//! * chunks: a u32 first intid, a u32 document count, a u32 payload length,
+//!   then the payload.
+//! * chunk index: a u64 chunk count, then each chunk's u64 file offset.
+//! * footer: the u64 file offset of the chunk index.
+//!
+//! A payload holds the chunk's docids, each front-coded against the one
+//! before it (vbyte shared-prefix length, vbyte suffix length, suffix
+//! bytes), then each document's features: a vbyte count, the ids as
+//! zigzag vbyte differences from the id before, the values as f32, and
+//! the norm as f32.
+
+use crate::FeatureVec;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"MYCH";
+const VERSION: u32 = 1;
+
+/// Documents per chunk unless the writer is told otherwise
+pub const DEFAULT_CHUNK_SIZE: u32 = 4096;
+
+fn put_vbyte(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push((n as u8 & 0x7f) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+// Feature ids are stored as gaps in the order the features were written,
+// which is not always ascending; keeping that order keeps scores
+// bit-identical to the .ftr file
+fn zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+fn unzigzag(n: u64) -> i64 {
+    (n >> 1) as i64 ^ -((n & 1) as i64)
+}
+
+fn corrupt(what: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("Corrupt chunked feature file: {}", what),
+    )
+}
+
+/// A cursor over a chunk payload.
+struct Payload<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Payload<'_> {
+    fn vbyte(&mut self) -> Result<u64> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = *self
+                .bytes
+                .get(self.pos)
+                .ok_or_else(|| corrupt("truncated chunk"))?;
+            self.pos += 1;
+            n |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(corrupt("overlong integer"))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&[u8]> {
+        let end = self.pos.checked_add(len).filter(|e| *e <= self.bytes.len());
+        let end = end.ok_or_else(|| corrupt("truncated chunk"))?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+/// Writes a chunked feature file from vectors given in intid order.
+pub struct ChunkWriter {
+    out: BufWriter<File>,
+    chunk_size: u32,
+    pending: Vec<FeatureVec>,
+    offsets: Vec<u64>,
+    num_docs: u32,
+}
+
+impl ChunkWriter {
+    pub fn create(filename: impl AsRef<Path>, chunk_size: u32) -> Result<ChunkWriter> {
+        assert!(chunk_size > 0, "Chunk size must be positive");
+        let mut out = BufWriter::new(File::create(filename)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&chunk_size.to_le_bytes())?;
+        Ok(ChunkWriter {
+            out,
+            chunk_size,
+            pending: Vec::with_capacity(chunk_size as usize),
+            offsets: Vec::new(),
+            num_docs: 0,
+        })
+    }
+
+    pub fn push(&mut self, fv: FeatureVec) -> Result<()> {
+        self.pending.push(fv);
+        if self.pending.len() == self.chunk_size as usize {
+            self.write_chunk()?;
+        }
+        Ok(())
+    }
+
+    fn write_chunk(&mut self) -> Result<()> {
+        let mut payload = Vec::new();
+        let mut prev: &[u8] = &[];
+        for fv in self.pending.iter() {
+            let docid = fv.docid.as_bytes();
+            let shared = docid.iter().zip(prev).take_while(|(a, b)| a == b).count();
+            put_vbyte(&mut payload, shared as u64);
+            put_vbyte(&mut payload, (docid.len() - shared) as u64);
+            payload.extend_from_slice(&docid[shared..]);
+            prev = docid;
+        }
+        for fv in self.pending.iter() {
+            put_vbyte(&mut payload, fv.features.len() as u64);
+            let mut last = 0i64;
+            for f in fv.features.iter() {
+                put_vbyte(&mut payload, zigzag(f.id as i64 - last));
+                last = f.id as i64;
+            }
+            for f in fv.features.iter() {
+                payload.extend_from_slice(&f.value.to_le_bytes());
+            }
+            payload.extend_from_slice(&fv.squared_norm.to_le_bytes());
+        }
+
+        self.offsets.push(self.out.stream_position()?);
+        self.out.write_all(&self.num_docs.to_le_bytes())?;
+        self.out
+            .write_all(&(self.pending.len() as u32).to_le_bytes())?;
+        self.out.write_all(&(payload.len() as u32).to_le_bytes())?;
+        self.out.write_all(&payload)?;
+        self.num_docs += self.pending.len() as u32;
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Write the last partial chunk and the chunk index.
+    pub fn finish(mut self) -> Result<()> {
+        if !self.pending.is_empty() {
+            self.write_chunk()?;
+        }
+        let index_offset = self.out.stream_position()?;
+        self.out
+            .write_all(&(self.offsets.len() as u64).to_le_bytes())?;
+        for offset in self.offsets.iter() {
+            self.out.write_all(&offset.to_le_bytes())?;
+        }
+        self.out.write_all(&index_offset.to_le_bytes())?;
+        self.out.flush()
+    }
+}
+
+/// One chunk as read from disk, not yet decoded.
+pub struct RawChunk {
+    pub first_intid: u32,
+    pub num_docs: u32,
+    bytes: Vec<u8>,
+}
+
+impl RawChunk {
+    /// The intids of the chunk's documents.
+    pub fn intids(&self) -> Range<u32> {
+        self.first_intid..self.first_intid + self.num_docs
+    }
+
+    /// Decode the chunk's vectors, in intid order.
+    pub fn decode(&self) -> Result<Vec<FeatureVec>> {
+        let mut p = Payload {
+            bytes: &self.bytes,
+            pos: 0,
+        };
+        let mut fvs: Vec<FeatureVec> = Vec::with_capacity(self.num_docs as usize);
+        let mut prev: Vec<u8> = Vec::new();
+        for _ in 0..self.num_docs {
+            let shared = p.vbyte()? as usize;
+            let suffix = p.vbyte()? as usize;
+            if shared > prev.len() {
+                return Err(corrupt("bad docid prefix"));
+            }
+            prev.truncate(shared);
+            prev.extend_from_slice(p.take(suffix)?);
+            let docid = String::from_utf8(prev.clone()).map_err(|_| corrupt("docid not UTF-8"))?;
+            fvs.push(FeatureVec::new(docid));
+        }
+        for fv in fvs.iter_mut() {
+            let n = p.vbyte()? as usize;
+            if n > p.bytes.len() - p.pos {
+                return Err(corrupt("bad feature count"));
+            }
+            let mut ids = Vec::with_capacity(n);
+            let mut id = 0i64;
+            for _ in 0..n {
+                id += unzigzag(p.vbyte()?);
+                ids.push(u32::try_from(id).map_err(|_| corrupt("feature id out of range"))?);
+            }
+            for id in ids {
+                fv.push(id, p.f32()?);
+            }
+            fv.squared_norm = p.f32()?;
+        }
+        Ok(fvs)
+    }
+}
+
+/// An open chunked feature file.
+pub struct ChunkedFeatures {
+    fp: BufReader<File>,
+    pub chunk_size: u32,
+    offsets: Vec<u64>,
+}
+
+impl ChunkedFeatures {
+    pub fn open(filename: impl AsRef<Path>) -> Result<ChunkedFeatures> {
+        let mut fp = BufReader::new(File::open(filename)?);
+        let mut header = [0u8; 12];
+        fp.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(corrupt("bad magic"));
+        }
+        let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        if version != VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Unsupported chunked feature file version {}", version),
+            ));
+        }
+        let chunk_size = u32::from_le_bytes(header[8..12].try_into().unwrap());
+
+        let mut word = [0u8; 8];
+        let len = fp.seek(SeekFrom::End(-8))?;
+        fp.read_exact(&mut word)?;
+        let index_offset = u64::from_le_bytes(word);
+        fp.seek(SeekFrom::Start(index_offset))?;
+        fp.read_exact(&mut word)?;
+        let num_chunks = u64::from_le_bytes(word);
+        if num_chunks > len.saturating_sub(index_offset) / 8 {
+            return Err(corrupt("bad chunk index"));
+        }
+        let mut offsets = Vec::with_capacity(num_chunks as usize);
+        for _ in 0..num_chunks {
+            fp.read_exact(&mut word)?;
+            offsets.push(u64::from_le_bytes(word));
+        }
+        Ok(ChunkedFeatures {
+            fp,
+            chunk_size,
+            offsets,
+        })
+    }
+
+    pub fn num_chunks(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// The intids chunk `i` holds, known without reading it. Every chunk
+    /// but the last is full.
+    pub fn intids(&self, i: usize) -> Range<u32> {
+        let first = i as u32 * self.chunk_size;
+        first..first + self.chunk_size
+    }
+
+    pub fn read_chunk(&mut self, i: usize) -> Result<RawChunk> {
+        self.fp.seek(SeekFrom::Start(self.offsets[i]))?;
+        let mut header = [0u8; 12];
+        self.fp.read_exact(&mut header)?;
+        let first_intid = u32::from_le_bytes(header[..4].try_into().unwrap());
+        let num_docs = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        let len = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        let mut bytes = Vec::new();
+        (&mut self.fp).take(len as u64).read_to_end(&mut bytes)?;
+        if bytes.len() != len as usize {
+            return Err(corrupt("truncated chunk"));
+        }
+        Ok(RawChunk {
+            first_intid,
+            num_docs,
+            bytes,
+        })
+    }
+}
+
+/// Copy a `.ftr` feature file into a chunked one. Returns the number of
+/// documents written.
+pub fn write_chunked(
+    features: impl AsRef<Path>,
+    chunked: impl AsRef<Path>,
+    chunk_size: u32,
+    mut progress: impl FnMut(usize),
+) -> Result<u64> {
+    let mut feats = BufReader::new(File::open(features)?);
+    let mut writer = ChunkWriter::create(chunked, chunk_size)?;
+    let mut num_docs = 0;
+    while let Some(fv) = FeatureVec::read_next(&mut feats).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("Corrupt feature file: {}", e),
+        )
+    })? {
+        writer.push(fv)?;
+        num_docs += 1;
+        progress(1);
+    }
+    writer.finish()?;
+    Ok(num_docs)
+}
@@ -0,0 +1,469 @@
+//! Exchanging a collection with other IR systems, such as PISA and
+//! Anserini, as a CIFF (Common Index File Format) file, for checking
+//! mycal's effectiveness and efficiency against theirs on the same index.
+//!
+//! A CIFF file is a header, then a postings list per term in term order,
+//! then a record per document, each a length-prefixed protobuf message.
+//! Docids in a CIFF file are the documents' positions, and within a
+//! postings list each is stored as the gap from the one before.
+//!
+//! There is no inverted file, so [`export`] inverts the feature file in
+//! memory. Term frequencies are recovered from the stored weights with
+//! [`Dict::term_freq`], so a term in every document, whose idf is zero,
+//! counts once in each, and a document's length is the sum of its term
+//! frequencies, as [`crate::search::bm25_search`] takes it. Hashed buckets
+//! aren't terms, so they have no postings lists, though what they hold
+//! still counts toward document lengths. Deleted documents are left out,
+//! and the rest are numbered from zero in intid order.
+//!
+//! [`import`] builds a collection the way build_corpus would from the same
+//! term counts: ids most frequent first, and `(1 + log tf) * idf` weights.
+//! The file's document lengths are not kept.
+
+use crate::{fingerprint, tombstones, CollectionLayout, Dict, DocInfo, DocsDb, FeatureVec};
+use std::collections::{HashMap, HashSet};
+use std::fs::{remove_file, File};
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Read, Result, Seek, Write};
+
+/// The CIFF version written and read
+pub const CIFF_VERSION: u64 = 1;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CiffReport {
+    pub num_docs: u64,
+    /// Terms with a postings list
+    pub num_terms: usize,
+    pub num_postings: u64,
+    /// Hashed buckets, which have no postings list, on export
+    pub buckets: u32,
+    /// Deleted documents left out, on export
+    pub deleted: u64,
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidData, message.into())
+}
+
+// Protobuf wire types
+const VARINT: u64 = 0;
+const FIXED64: u64 = 1;
+const LEN: u64 = 2;
+const FIXED32: u64 = 5;
+
+/// A protobuf message being encoded. Fields holding their type's default
+/// are left out, as protobuf 3 leaves them out.
+#[derive(Default)]
+struct Message(Vec<u8>);
+
+fn put_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+impl Message {
+    fn uint(&mut self, field: u64, n: u64) {
+        if n != 0 {
+            put_varint(&mut self.0, field << 3 | VARINT);
+            put_varint(&mut self.0, n);
+        }
+    }
+
+    fn double(&mut self, field: u64, x: f64) {
+        if x != 0.0 {
+            put_varint(&mut self.0, field << 3 | FIXED64);
+            self.0.extend_from_slice(&x.to_le_bytes());
+        }
+    }
+
+    fn bytes(&mut self, field: u64, bytes: &[u8]) {
+        put_varint(&mut self.0, field << 3 | LEN);
+        put_varint(&mut self.0, bytes.len() as u64);
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn string(&mut self, field: u64, s: &str) {
+        if !s.is_empty() {
+            self.bytes(field, s.as_bytes());
+        }
+    }
+
+    /// Write the message with its length before it.
+    fn write_delimited(&self, out: &mut impl Write) -> Result<()> {
+        let mut len = Vec::new();
+        put_varint(&mut len, self.0.len() as u64);
+        out.write_all(&len)?;
+        out.write_all(&self.0)
+    }
+}
+
+/// A field of a decoded protobuf message
+enum Value<'a> {
+    Int(u64),
+    Bytes(&'a [u8]),
+}
+
+impl Value<'_> {
+    fn int(&self) -> Result<u64> {
+        match self {
+            Value::Int(n) => Ok(*n),
+            Value::Bytes(_) => Err(invalid("CIFF field should be a number")),
+        }
+    }
+
+    fn bytes(&self) -> Result<&[u8]> {
+        match self {
+            Value::Bytes(b) => Ok(b),
+            Value::Int(_) => Err(invalid("CIFF field should be length-delimited")),
+        }
+    }
+
+    fn string(&self) -> Result<String> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|e| invalid(e.to_string()))
+    }
+}
+
+fn get_varint(buf: &mut &[u8]) -> Result<u64> {
+    let mut n = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = buf
+            .split_first()
+            .ok_or_else(|| invalid("CIFF message ends inside a number"))?;
+        *buf = rest;
+        n |= u64::from(byte & 0x7f) << shift;
+        if byte < 0x80 {
+            return Ok(n);
+        }
+    }
+    Err(invalid("CIFF number is longer than 64 bits"))
+}
+
+/// The fields of an encoded message, in order, with their numbers. Fixed
+/// width numbers are returned as their bits.
+fn fields(mut buf: &[u8]) -> Result<Vec<(u64, Value<'_>)>> {
+    let mut out = Vec::new();
+    while !buf.is_empty() {
+        let key = get_varint(&mut buf)?;
+        let value = match key & 7 {
+            VARINT => Value::Int(get_varint(&mut buf)?),
+            FIXED64 | FIXED32 => {
+                let width = if key & 7 == FIXED64 { 8 } else { 4 };
+                if buf.len() < width {
+                    return Err(invalid("CIFF message ends inside a number"));
+                }
+                let (bits, rest) = buf.split_at(width);
+                buf = rest;
+                let mut word = [0; 8];
+                word[..width].copy_from_slice(bits);
+                Value::Int(u64::from_le_bytes(word))
+            }
+            LEN => {
+                let len = get_varint(&mut buf)? as usize;
+                if buf.len() < len {
+                    return Err(invalid("CIFF message ends inside a field"));
+                }
+                let (bytes, rest) = buf.split_at(len);
+                buf = rest;
+                Value::Bytes(bytes)
+            }
+            wire => return Err(invalid(format!("Unknown protobuf wire type {}", wire))),
+        };
+        out.push((key >> 3, value));
+    }
+    Ok(out)
+}
+
+/// Read the next length-prefixed message into `buf`.
+fn read_delimited(input: &mut impl Read, buf: &mut Vec<u8>, what: &str) -> Result<()> {
+    let mut len = 0u64;
+    let mut byte = [0u8];
+    for shift in (0..64).step_by(7) {
+        input
+            .read_exact(&mut byte)
+            .map_err(|_| invalid(format!("CIFF file ends before its {}", what)))?;
+        len |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] < 0x80 {
+            break;
+        }
+    }
+    buf.resize(len as usize, 0);
+    input
+        .read_exact(buf)
+        .map_err(|_| invalid(format!("CIFF file ends inside its {}", what)))
+}
+
+/// Write the collection as a CIFF file, with `description` in its header.
+/// `progress` is called with each document read.
+pub fn export(
+    coll: &CollectionLayout,
+    out: &mut impl Write,
+    description: &str,
+    mut progress: impl FnMut(usize),
+) -> Result<CiffReport> {
+    let dict = Dict::load(coll.dict()).map_err(|e| invalid(e.to_string()))?;
+    let deleted = tombstones::deleted(coll)?;
+    let mut report = CiffReport {
+        buckets: dict.hashed.map_or(0, |h| h.buckets),
+        ..CiffReport::default()
+    };
+
+    // Postings are gathered by term id, as (CIFF docid, tf)
+    let mut postings: HashMap<u32, Vec<(u32, u32)>> = HashMap::new();
+    let mut docs: Vec<(String, u64)> = Vec::new();
+    let mut feats = BufReader::new(File::open(coll.features())?);
+    let mut intid = 0;
+    while let Some(fv) = FeatureVec::read_next(&mut feats).map_err(|e| invalid(e.to_string()))? {
+        progress(1);
+        intid += 1;
+        if deleted.contains(intid - 1) {
+            report.deleted += 1;
+            continue;
+        }
+        let docid = docs.len() as u32;
+        let mut len = 0;
+        for f in &fv.features {
+            let tf = dict.term_freq(f.id, f.value) as u32;
+            len += u64::from(tf);
+            if !dict.hashed.is_some_and(|h| h.contains(f.id)) {
+                postings.entry(f.id).or_default().push((docid, tf));
+            }
+        }
+        docs.push((fv.docid, len));
+    }
+
+    let names = dict.tokens_by_id();
+    let mut terms: Vec<(&str, Vec<(u32, u32)>)> = postings
+        .into_iter()
+        .filter_map(|(id, list)| Some((*names.get(&id)?, list)))
+        .collect();
+    terms.sort_unstable_by(|a, b| a.0.cmp(b.0));
+    report.num_docs = docs.len() as u64;
+    report.num_terms = terms.len();
+    let total_len: u64 = docs.iter().map(|(_, len)| len).sum();
+
+    let mut header = Message::default();
+    header.uint(1, CIFF_VERSION);
+    header.uint(2, terms.len() as u64);
+    header.uint(3, docs.len() as u64);
+    header.uint(4, terms.len() as u64);
+    header.uint(5, docs.len() as u64);
+    header.uint(6, total_len);
+    header.double(7, total_len as f64 / docs.len().max(1) as f64);
+    header.string(8, description);
+    header.write_delimited(out)?;
+
+    for (term, list) in terms {
+        report.num_postings += list.len() as u64;
+        let mut msg = Message::default();
+        msg.string(1, term);
+        msg.uint(2, list.len() as u64);
+        msg.uint(3, list.iter().map(|&(_, tf)| u64::from(tf)).sum());
+        let mut last = 0;
+        for (docid, tf) in list {
+            let mut posting = Message::default();
+            posting.uint(1, u64::from(docid - last));
+            posting.uint(2, u64::from(tf));
+            msg.bytes(4, &posting.0);
+            last = docid;
+        }
+        msg.write_delimited(out)?;
+    }
+
+    for (docid, (name, len)) in docs.iter().enumerate() {
+        let mut msg = Message::default();
+        msg.uint(1, docid as u64);
+        msg.string(2, name);
+        msg.uint(3, *len);
+        msg.write_delimited(out)?;
+    }
+    out.flush()?;
+    Ok(report)
+}
+
+struct Header {
+    num_postings_lists: u64,
+    num_docs: u64,
+    description: String,
+}
+
+fn decode_header(buf: &[u8]) -> Result<Header> {
+    let mut version = 0;
+    let mut header = Header {
+        num_postings_lists: 0,
+        num_docs: 0,
+        description: String::new(),
+    };
+    for (field, value) in fields(buf)? {
+        match field {
+            1 => version = value.int()?,
+            2 => header.num_postings_lists = value.int()?,
+            3 => header.num_docs = value.int()?,
+            8 => header.description = value.string()?,
+            _ => {}
+        }
+    }
+    if version != CIFF_VERSION {
+        return Err(invalid(format!(
+            "CIFF version {} is not supported, only {}",
+            version, CIFF_VERSION
+        )));
+    }
+    Ok(header)
+}
+
+/// Build a new collection at `out` from a CIFF file. `progress` is called
+/// with each postings list and document read.
+pub fn import(
+    input: &mut impl BufRead,
+    out: &CollectionLayout,
+    mut progress: impl FnMut(usize),
+) -> Result<CiffReport> {
+    if out.dict().exists() || out.features().exists() {
+        return Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!("A collection exists at {}", out.prefix().display()),
+        ));
+    }
+    let mut buf = Vec::new();
+    read_delimited(input, &mut buf, "header")?;
+    let header = decode_header(&buf)?;
+    let num_docs = header.num_docs;
+
+    // Each document's (term index, tf), the term indexes into `terms`
+    let mut tfs: Vec<Vec<(u32, u32)>> = vec![Vec::new(); num_docs as usize];
+    let mut terms: Vec<(String, u64)> = Vec::new();
+    let mut seen = HashSet::new();
+    let mut report = CiffReport::default();
+    for _ in 0..header.num_postings_lists {
+        read_delimited(input, &mut buf, "postings lists")?;
+        let index = terms.len() as u32;
+        let mut term = String::new();
+        let mut docid = 0u64;
+        let mut df = 0;
+        for (field, value) in fields(&buf)? {
+            match field {
+                1 => term = value.string()?,
+                4 => {
+                    let (mut gap, mut tf) = (0, 0);
+                    for (field, value) in fields(value.bytes()?)? {
+                        match field {
+                            1 => gap = value.int()?,
+                            2 => tf = value.int()?,
+                            _ => {}
+                        }
+                    }
+                    docid += gap;
+                    if docid >= num_docs || tf == 0 || tf > u64::from(u32::MAX) {
+                        return Err(invalid(format!(
+                            "Posting ({}, {}) of {:?} is out of range",
+                            docid, tf, term
+                        )));
+                    }
+                    tfs[docid as usize].push((index, tf as u32));
+                    df += 1;
+                }
+                _ => {}
+            }
+        }
+        if !seen.insert(term.clone()) {
+            return Err(invalid(format!("Term {:?} has two postings lists", term)));
+        }
+        terms.push((term, df));
+        report.num_postings += df;
+        progress(1);
+    }
+    drop(seen);
+
+    let mut docids: Vec<Option<String>> = vec![None; num_docs as usize];
+    for _ in 0..num_docs {
+        read_delimited(input, &mut buf, "document records")?;
+        let mut docid = 0;
+        let mut name = String::new();
+        for (field, value) in fields(&buf)? {
+            match field {
+                1 => docid = value.int()?,
+                2 => name = value.string()?,
+                _ => {}
+            }
+        }
+        match docids.get_mut(docid as usize) {
+            Some(slot @ None) => *slot = Some(name),
+            _ => {
+                return Err(invalid(format!(
+                    "Document record {} is out of place",
+                    docid
+                )))
+            }
+        }
+        progress(1);
+    }
+
+    // Ids most frequent first, as build_corpus assigns them
+    let idf_of = |df: u64| (num_docs as f32 / df.max(1) as f32).log10();
+    let mut by_df: Vec<u32> = (0..terms.len() as u32).collect();
+    by_df.sort_by(|&a, &b| {
+        let (a, b) = (&terms[a as usize], &terms[b as usize]);
+        b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0))
+    });
+    let mut dict = Dict::new();
+    let mut tokid_of = vec![0u32; terms.len()];
+    for index in by_df {
+        let (term, df) = &terms[index as usize];
+        let tokid = dict.add_tok(term);
+        dict.df.insert(tokid, idf_of(*df));
+        tokid_of[index as usize] = tokid;
+    }
+    report.num_terms = terms.len();
+    drop(terms);
+
+    let mut binout = BufWriter::new(File::create(out.features())?);
+    let mut docs = DocsDb::create(out.docsdb());
+    let mut divec: Vec<DocInfo> = Vec::with_capacity(num_docs as usize);
+    let mut names = HashSet::new();
+    for (doc, docid) in tfs.into_iter().zip(docids) {
+        let docid = docid.unwrap_or_default();
+        if !names.insert(docid.clone()) {
+            return Err(invalid(format!("Docid {:?} is given twice", docid)));
+        }
+        let mut fv = FeatureVec::new(docid.clone());
+        let mut doc: Vec<(u32, u32)> = doc
+            .into_iter()
+            .map(|(index, tf)| (tokid_of[index as usize], tf))
+            .collect();
+        doc.sort_unstable();
+        for (tokid, tf) in doc {
+            fv.push(tokid, (1.0 + (tf as f32).log10()) * dict.df[&tokid]);
+        }
+        fv.compute_norm();
+        let di = DocInfo {
+            intid: divec.len() as u64,
+            docid,
+            offset: binout.stream_position()?,
+        };
+        fv.write_into(&mut binout).map_err(Error::other)?;
+        docs.insert_batch(&di.docid, &di, 100_000);
+        divec.push(di);
+    }
+    binout.flush()?;
+    docs.process_remaining();
+    docs.db.flush()?;
+    report.num_docs = divec.len() as u64;
+
+    dict.save(out.dict())?;
+    let settings = format!("ciff description={:?}", header.description);
+    let fp = fingerprint(&settings, &dict, divec.iter().map(|di| di.docid.as_str()));
+    std::fs::write(out.fingerprint(), format!("{}\n", fp))?;
+    // Left by compacting an earlier collection at this prefix
+    if out.generation().exists() {
+        remove_file(out.generation())?;
+    }
+
+    // The docid vector is in database order, which is docid order
+    divec.sort_by(|a, b| a.docid.cmp(&b.docid));
+    let mut vecfile = BufWriter::new(File::create(out.docvec())?);
+    bincode::serialize_into(&mut vecfile, &divec).map_err(Error::other)?;
+    vecfile.flush()?;
+    Ok(report)
+}
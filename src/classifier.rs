@@ -0,0 +1,1998 @@
+use crate::calibration::Platt;
+use crate::guardrails::Guardrails;
+use crate::quantize::{Quantization, QuantizedWeights};
+use crate::{Dict, FeatureVec, HashedTail};
+use bincode::Result;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{thread_rng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// A learner trained on judged documents that scores a document by its
+/// inner product with the learned weights. `train_qrels` and the scorers
+/// only use this interface, so other learners can be swapped in for
+/// [`Classifier`].
+pub trait Model {
+    fn train(&mut self, positives: &[FeatureVec], negatives: &[FeatureVec]) -> TrainReport;
+
+    /// Continue training a trained model for `num_iters` more iterations,
+    /// for quick updates between review rounds. Step sizes pick up where
+    /// earlier training left off, so the model is refined rather than
+    /// relearned. Learners that don't iterate just retrain.
+    fn update(
+        &mut self,
+        positives: &[FeatureVec],
+        negatives: &[FeatureVec],
+        _num_iters: u32,
+    ) -> TrainReport {
+        self.train(positives, negatives)
+    }
+
+    fn inner_product(&self, x: &FeatureVec) -> f32;
+
+    /// Score a slice of vectors, in order.
+    fn score_batch(&self, xs: &[FeatureVec]) -> Vec<f32> {
+        xs.iter().map(|x| self.inner_product(x)).collect()
+    }
+
+    /// An immutable copy of the model for scoring.
+    fn scoring_model(&self) -> ScoringModel;
+
+    /// Zero negligible weights, returning how many were zeroed.
+    fn prune(&mut self, how: Prune) -> usize;
+
+    /// Fit a [`Platt`] calibration to labeled examples (positive label
+    /// means relevant).
+    fn calibrate(&mut self, xs: &[FeatureVec], labels: &[i8]);
+
+    fn calibration(&self) -> Option<Platt>;
+
+    /// P(relevant) for a document. Without a calibration this is the
+    /// logistic function of the raw score.
+    fn predict_proba(&self, x: &FeatureVec) -> f32 {
+        probability(self.calibration(), self.inner_product(x))
+    }
+
+    /// Train as [`Model::train`] does, but stop once the loss on held-out
+    /// examples stops improving, keeping the best weights seen. The report
+    /// counts only the iterations kept. Learners that don't iterate just
+    /// train.
+    fn train_early_stopping(
+        &mut self,
+        positives: &[FeatureVec],
+        negatives: &[FeatureVec],
+        _validation: &Validation,
+    ) -> TrainReport {
+        self.train(positives, negatives)
+    }
+
+    /// Where the model came from, if its file says.
+    fn header(&self) -> Option<&ModelHeader> {
+        None
+    }
+
+    /// Record where the model came from; it is written by [`Model::save`].
+    /// Learners that can't store a header ignore it.
+    fn set_header(&mut self, _header: ModelHeader) {}
+
+    /// Weight individual examples in the following training runs. The
+    /// weights are parallel to the example slices training is given.
+    /// Learners that can't weight examples ignore them.
+    fn set_example_weights(&mut self, _weights: ExampleWeights) {}
+
+    /// Write a [`Checkpoint`] periodically during the following training
+    /// runs. Learners that don't iterate ignore it.
+    fn set_checkpoints(&mut self, _checkpoints: Checkpoints) {}
+
+    /// Hold the weights of banned terms at zero in the following training
+    /// runs, and refuse to save a model with weight on one.
+    fn set_guardrails(&mut self, _guardrails: Arc<Guardrails>) {}
+
+    /// Draw the following training runs' example order and tuning folds
+    /// from `seed`, so that they can be repeated. Learners that don't
+    /// sample ignore it.
+    fn set_seed(&mut self, _seed: u64) {}
+
+    fn save(&self, filename: &Path) -> std::io::Result<()>;
+
+    fn load(filename: &Path) -> Result<Self>
+    where
+        Self: Sized;
+}
+
+/// What a training run did, for the caller to print or ship as JSON.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TrainReport {
+    pub iterations: u32,
+    /// Mean pairwise training loss over each successive span of
+    /// iterations, up to [`TrainReport::LOSS_POINTS`] spans
+    pub loss_curve: Vec<f32>,
+    /// Loss on the held-out examples, when training stopped early
+    pub validation_loss: Option<f64>,
+    /// Precision and recall on the training examples, deciding
+    /// relevant when the score is positive
+    pub precision: f32,
+    pub recall: f32,
+    pub seconds: f64,
+}
+
+impl TrainReport {
+    pub const LOSS_POINTS: u32 = 100;
+
+    /// Fill in precision and recall for a trained model and the time
+    /// since `started`.
+    fn finish(
+        mut self,
+        model: &dyn Model,
+        positives: &[FeatureVec],
+        negatives: &[FeatureVec],
+        started: Instant,
+    ) -> TrainReport {
+        let true_pos = model
+            .score_batch(positives)
+            .iter()
+            .filter(|&&s| s > 0.0)
+            .count();
+        let false_pos = model
+            .score_batch(negatives)
+            .iter()
+            .filter(|&&s| s >= 0.0)
+            .count();
+        self.precision = true_pos as f32 / (true_pos + false_pos).max(1) as f32;
+        self.recall = true_pos as f32 / positives.len().max(1) as f32;
+        self.seconds = started.elapsed().as_secs_f64();
+        self
+    }
+}
+
+impl fmt::Display for TrainReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "trained {} iterations in {:.2}s, training precision {:.5}, recall {:.5}",
+            self.iterations, self.seconds, self.precision, self.recall
+        )?;
+        if let Some(loss) = self.validation_loss {
+            write!(f, ", validation loss {:.5}", loss)?;
+        }
+        Ok(())
+    }
+}
+
+/// Averages per-step losses into a [`TrainReport`] loss curve.
+struct LossCurve {
+    span: u32,
+    count: u32,
+    total: f32,
+    points: Vec<f32>,
+}
+
+impl LossCurve {
+    fn new(num_iters: u32) -> LossCurve {
+        LossCurve {
+            span: (num_iters / TrainReport::LOSS_POINTS).max(1),
+            count: 0,
+            total: 0.0,
+            points: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, loss: f32) {
+        self.total += loss;
+        self.count += 1;
+        if self.count == self.span {
+            self.points.push(self.total / self.count as f32);
+            (self.total, self.count) = (0.0, 0);
+        }
+    }
+
+    /// The curve, including any partial last span.
+    fn into_points(mut self) -> Vec<f32> {
+        if self.count > 0 {
+            self.points.push(self.total / self.count as f32);
+        }
+        self.points
+    }
+}
+
+/// The cumulative L1 penalty of Tsuruoka, Tsujii and Ananiadou (2009).
+/// Each step adds to `total`, and a weight is shrunk by whatever part of
+/// it has not been applied yet only when its feature is next updated, so
+/// a step costs nothing for untouched weights.
+struct L1Penalty {
+    total: f32,
+    /// The signed penalty already applied to each weight
+    applied: Vec<f32>,
+}
+
+impl L1Penalty {
+    fn new(dimensionality: usize) -> L1Penalty {
+        L1Penalty {
+            total: 0.0,
+            applied: vec![0.0; dimensionality],
+        }
+    }
+}
+
+/// Held-out examples for [`Model::train_early_stopping`].
+pub struct Validation {
+    pub positives: Vec<FeatureVec>,
+    pub negatives: Vec<FeatureVec>,
+    /// Iterations between validation checks
+    pub check_every: u32,
+    /// Checks without improvement before training stops
+    pub patience: u32,
+}
+
+impl Validation {
+    /// Mean pairwise logistic loss over every held-out
+    /// (relevant, nonrelevant) pair, the quantity training minimizes.
+    pub fn loss(&self, model: &dyn Model) -> f64 {
+        let pos = model.score_batch(&self.positives);
+        let neg = model.score_batch(&self.negatives);
+        let mut total = 0.0;
+        for p in pos.iter() {
+            for n in neg.iter() {
+                total += (-(p - n) as f64).exp().ln_1p();
+            }
+        }
+        total / (pos.len() * neg.len()).max(1) as f64
+    }
+}
+
+/// Where a model came from, written ahead of the model in its file so
+/// that it can be checked against the collection it is about to score.
+/// A model scoring a collection with a different vocabulary or tokenizer
+/// produces scores that look fine and mean nothing. Files from before the
+/// header load without one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelHeader {
+    /// Seconds since the Unix epoch
+    pub created: u64,
+    /// Prefix of the collection the model was trained on
+    pub collection: String,
+    pub tokenizer: String,
+    /// The collection's largest token id
+    pub vocab_size: u32,
+    /// The collection's [`crate::fingerprint`], if it had one. Stored
+    /// after the other fields, from format version 2.
+    #[serde(skip)]
+    pub fingerprint: Option<String>,
+    /// The collection's hashed tail, if it had one. Stored after the
+    /// fingerprint, from format version 3.
+    #[serde(skip)]
+    pub hashed: Option<HashedTail>,
+}
+
+impl ModelHeader {
+    /// Starts a model file that has a header. The magic is followed by a
+    /// u32 format version and the serialized header.
+    const MAGIC: &'static [u8; 4] = b"MYMH";
+    pub const VERSION: u32 = 3;
+
+    pub fn new(
+        collection: impl Into<String>,
+        tokenizer: impl Into<String>,
+        vocab_size: u32,
+    ) -> ModelHeader {
+        ModelHeader {
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            collection: collection.into(),
+            tokenizer: tokenizer.into(),
+            vocab_size,
+            fingerprint: None,
+            hashed: None,
+        }
+    }
+
+    /// Whether every token id in the collection is a hash bucket, as when
+    /// it is built with `--exact-terms 0 --hash-buckets N`. Ids then depend
+    /// only on the tokenizer and the number of buckets, not on the
+    /// documents.
+    pub fn is_hashed_only(&self) -> bool {
+        self.hashed
+            .is_some_and(|h| h.first_id == 1 && h.buckets == self.vocab_size)
+    }
+
+    /// Whether a model built for this collection means the same thing on
+    /// one described by `other` although they hold different documents:
+    /// both are hashed only, into the same buckets, with the same
+    /// tokenizer. Term weights are still the source collection's idfs.
+    pub fn shares_feature_space(&self, other: &ModelHeader) -> bool {
+        self.is_hashed_only() && self.hashed == other.hashed && self.tokenizer == other.tokenizer
+    }
+
+    /// Fail if a model with this header can't score a collection described
+    /// by `other`. The path doesn't have to match, since collections get
+    /// moved; the fingerprints do, if both have one and the collections
+    /// don't share a hashed feature space, and otherwise the vocabulary and
+    /// tokenizer.
+    pub fn check(&self, other: &ModelHeader) -> std::io::Result<()> {
+        let mismatch =
+            |what: String| Err(std::io::Error::new(std::io::ErrorKind::InvalidData, what));
+        if let (Some(ours), Some(theirs)) = (&self.fingerprint, &other.fingerprint) {
+            if ours != theirs && !self.shares_feature_space(other) {
+                return mismatch(format!(
+                    "Model was trained on collection {} (fingerprint {}), not one with fingerprint {}",
+                    self.collection, ours, theirs
+                ));
+            }
+        }
+        if self.tokenizer != other.tokenizer {
+            return mismatch(format!(
+                "Model was built with the {} tokenizer, not {}",
+                self.tokenizer, other.tokenizer
+            ));
+        }
+        if self.vocab_size != other.vocab_size {
+            return mismatch(format!(
+                "Model was built for a vocabulary of {} tokens (collection {}), not {}",
+                self.vocab_size, self.collection, other.vocab_size
+            ));
+        }
+        Ok(())
+    }
+
+    /// Split a model file into its header, if it has one, and the model.
+    fn split(bytes: &[u8]) -> Result<(Option<ModelHeader>, &[u8])> {
+        let Some(mut rest) = bytes.strip_prefix(Self::MAGIC) else {
+            return Ok((None, bytes));
+        };
+        let version: u32 = bincode::deserialize_from(&mut rest)?;
+        if version > Self::VERSION {
+            return Err(Box::new(bincode::ErrorKind::Custom(format!(
+                "Model file version {} is newer than this mycal reads",
+                version
+            ))));
+        }
+        let mut header: ModelHeader = bincode::deserialize_from(&mut rest)?;
+        if version >= 2 {
+            header.fingerprint = bincode::deserialize_from(&mut rest)?;
+        }
+        if version >= 3 {
+            header.hashed = bincode::deserialize_from(&mut rest)?;
+        }
+        Ok((Some(header), rest))
+    }
+
+    fn write_to(header: Option<&ModelHeader>, out: &mut impl Write) -> std::io::Result<()> {
+        if let Some(header) = header {
+            out.write_all(Self::MAGIC)?;
+            bincode::serialize_into(&mut *out, &Self::VERSION).expect("Error writing model");
+            bincode::serialize_into(&mut *out, header).expect("Error writing model");
+            bincode::serialize_into(&mut *out, &header.fingerprint).expect("Error writing model");
+            bincode::serialize_into(&mut *out, &header.hashed).expect("Error writing model");
+        }
+        Ok(())
+    }
+}
+
+/// How [`Model::prune`] decides which weights are negligible.
+#[derive(Debug, Clone, Copy)]
+pub enum Prune {
+    /// Zero weights whose magnitude is below the threshold
+    MinWeight(f32),
+    /// Keep only the k weights of largest magnitude
+    TopK(usize),
+}
+
+/// How much each class's examples count in training. Review sets are
+/// usually a handful of relevant documents against hundreds of
+/// nonrelevant ones. The pairwise SGD already draws one of each per step,
+/// so weighting its pairs by class would only scale every step alike; the
+/// weights apply where the class sizes do enter, the intercept fit and the
+/// naive Bayes prior, and a Pegasos model's weight vector is the same
+/// under any of them.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ClassWeights {
+    /// Every example counts the same
+    Uniform,
+    /// Each class counts as much in total as the other
+    Balanced,
+    /// Fixed weights for relevant and nonrelevant examples
+    Fixed { positive: f32, negative: f32 },
+}
+
+impl ClassWeights {
+    /// The (relevant, nonrelevant) example weights for the given counts.
+    pub fn resolve(&self, num_pos: usize, num_neg: usize) -> (f32, f32) {
+        match *self {
+            ClassWeights::Uniform => (1.0, 1.0),
+            ClassWeights::Balanced => {
+                let total = (num_pos + num_neg) as f32;
+                (
+                    total / (2 * num_pos.max(1)) as f32,
+                    total / (2 * num_neg.max(1)) as f32,
+                )
+            }
+            ClassWeights::Fixed { positive, negative } => (positive, negative),
+        }
+    }
+}
+
+impl FromStr for ClassWeights {
+    type Err = String;
+
+    /// `uniform`, `balanced`, or `POS:NEG` fixed weights
+    fn from_str(s: &str) -> std::result::Result<ClassWeights, String> {
+        match s {
+            "uniform" => Ok(ClassWeights::Uniform),
+            "balanced" => Ok(ClassWeights::Balanced),
+            _ => {
+                let bad = || {
+                    format!(
+                        "Class weights must be uniform, balanced or POS:NEG, not {}",
+                        s
+                    )
+                };
+                let (p, n) = s.split_once(':').ok_or_else(bad)?;
+                let positive: f32 = p.parse().map_err(|_| bad())?;
+                let negative: f32 = n.parse().map_err(|_| bad())?;
+                if positive > 0.0 && negative > 0.0 {
+                    Ok(ClassWeights::Fixed { positive, negative })
+                } else {
+                    Err(bad())
+                }
+            }
+        }
+    }
+}
+
+/// How the SGD step size falls over a training run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EtaSchedule {
+    /// `1 / (lambda t)`, as Pegasos prescribes. Large values of lambda
+    /// make the first steps tiny, and small ones make them huge.
+    Pegasos,
+    /// `eta0 / t^power_t`
+    InvScaling,
+    /// `eta0` throughout
+    Constant,
+    /// `eta0` falling along half a cosine to nearly zero at the end of
+    /// the run
+    Cosine,
+}
+
+impl FromStr for EtaSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<EtaSchedule, String> {
+        match s {
+            "pegasos" => Ok(EtaSchedule::Pegasos),
+            "invscaling" => Ok(EtaSchedule::InvScaling),
+            "constant" => Ok(EtaSchedule::Constant),
+            "cosine" => Ok(EtaSchedule::Cosine),
+            _ => Err(format!(
+                "Step size schedule must be pegasos, invscaling, constant or cosine, not {}",
+                s
+            )),
+        }
+    }
+}
+
+/// Step size settings for [`Classifier`] training. `eta0` and `power_t`
+/// are only used by the schedules that name them.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TrainConfig {
+    pub schedule: EtaSchedule,
+    /// The initial step size
+    pub eta0: f32,
+    /// How fast [`EtaSchedule::InvScaling`] decays
+    pub power_t: f32,
+}
+
+impl Default for TrainConfig {
+    fn default() -> TrainConfig {
+        TrainConfig {
+            schedule: EtaSchedule::Pegasos,
+            eta0: 0.1,
+            power_t: 0.5,
+        }
+    }
+}
+
+impl TrainConfig {
+    /// The step size of step `i` (from 0) of a run ending before step
+    /// `end`, for regularization strength `lambda`.
+    pub fn eta(&self, i: u32, end: u32, lambda: f32) -> f32 {
+        let t = (i + 1) as f32;
+        match self.schedule {
+            EtaSchedule::Pegasos => 1.0 / (lambda * t),
+            EtaSchedule::InvScaling => self.eta0 / t.powf(self.power_t),
+            EtaSchedule::Constant => self.eta0,
+            EtaSchedule::Cosine => {
+                let done = i as f32 / end.max(1) as f32;
+                self.eta0 * 0.5 * (1.0 + (std::f32::consts::PI * done).cos())
+            }
+        }
+    }
+}
+
+/// Per-example weights for training, parallel to the relevant and
+/// nonrelevant example slices. An example with twice the weight is drawn
+/// into SGD pairs twice as often and counts twice in the intercept fit.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExampleWeights {
+    pub positives: Vec<f32>,
+    pub negatives: Vec<f32>,
+}
+
+/// How relevance grades become [`ExampleWeights`], so that a highly
+/// relevant document counts for more than a marginal one instead of the
+/// qrels collapsing to binary.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GradeWeights {
+    /// A relevant document counts once per grade at or above the
+    /// relevance level: at level 1, grade 2 counts double. Nonrelevant
+    /// documents count once.
+    Linear,
+    /// Listed grades count their weight, and others once
+    Fixed(Vec<(i32, f32)>),
+}
+
+impl GradeWeights {
+    /// The weight of a judgment of grade `rel`, where grades from `level`
+    /// up are relevant.
+    pub fn weight(&self, rel: i32, level: i32) -> f32 {
+        match self {
+            GradeWeights::Linear if rel >= level => (rel - level + 1) as f32,
+            GradeWeights::Linear => 1.0,
+            GradeWeights::Fixed(grades) => grades
+                .iter()
+                .find(|(g, _)| *g == rel)
+                .map_or(1.0, |(_, w)| *w),
+        }
+    }
+}
+
+impl FromStr for GradeWeights {
+    type Err = String;
+
+    /// `linear`, or `GRADE:WEIGHT,...` such as `1:1,2:3`
+    fn from_str(s: &str) -> std::result::Result<GradeWeights, String> {
+        if s == "linear" {
+            return Ok(GradeWeights::Linear);
+        }
+        let bad = || {
+            format!(
+                "Grade weights must be linear or GRADE:WEIGHT,..., not {}",
+                s
+            )
+        };
+        s.split(',')
+            .map(|pair| {
+                let (g, w) = pair.split_once(':').ok_or_else(bad)?;
+                let grade: i32 = g.parse().map_err(|_| bad())?;
+                let weight: f32 = w.parse().map_err(|_| bad())?;
+                if weight > 0.0 {
+                    Ok((grade, weight))
+                } else {
+                    Err(bad())
+                }
+            })
+            .collect::<std::result::Result<Vec<_>, String>>()
+            .map(GradeWeights::Fixed)
+    }
+}
+
+/// Draws the examples for SGD pairs, uniformly or in proportion to
+/// [`ExampleWeights`].
+struct Picker {
+    /// Reseeded at each checkpoint, so that a resumed run draws the same
+    /// pairs as one that never stopped
+    rng: StdRng,
+    positives: Option<WeightedIndex<f32>>,
+    negatives: Option<WeightedIndex<f32>>,
+}
+
+impl Picker {
+    fn new(weights: Option<&ExampleWeights>, num_pos: usize, num_neg: usize, seed: u64) -> Picker {
+        let index = |w: &[f32], n: usize| {
+            assert_eq!(w.len(), n, "Example weights don't match the examples");
+            WeightedIndex::new(w).ok()
+        };
+        Picker {
+            rng: StdRng::seed_from_u64(seed),
+            positives: weights.and_then(|w| index(&w.positives, num_pos)),
+            negatives: weights.and_then(|w| index(&w.negatives, num_neg)),
+        }
+    }
+
+    fn pick<'a>(
+        rng: &mut StdRng,
+        index: &Option<WeightedIndex<f32>>,
+        xs: &'a [FeatureVec],
+    ) -> &'a FeatureVec {
+        match index {
+            Some(index) => &xs[index.sample(rng)],
+            None => xs.choose(rng).unwrap(),
+        }
+    }
+
+    fn pair<'a>(
+        &mut self,
+        positives: &'a [FeatureVec],
+        negatives: &'a [FeatureVec],
+    ) -> (&'a FeatureVec, &'a FeatureVec) {
+        (
+            Self::pick(&mut self.rng, &self.positives, positives),
+            Self::pick(&mut self.rng, &self.negatives, negatives),
+        )
+    }
+
+    /// Start a new random sequence, returning its seed.
+    fn reseed(&mut self) -> u64 {
+        let seed = self.rng.gen();
+        self.rng = StdRng::seed_from_u64(seed);
+        seed
+    }
+}
+
+/// Where and how often a [`Classifier`] saves its training state.
+#[derive(Debug, Clone)]
+pub struct Checkpoints {
+    /// SGD steps between checkpoints
+    pub every: u32,
+    pub file: PathBuf,
+}
+
+/// A training run interrupted after `model.steps` of its steps, with what
+/// it needs to carry on exactly as it would have: the SGD state, the seed
+/// of its random sequence from here, and the examples it was given, by
+/// docid, in order. See [`Classifier::resume`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub model: Classifier,
+    /// The step the run ends after
+    pub end: u32,
+    seed: u64,
+    l1_total: f32,
+    l1_applied: Vec<f32>,
+    pub example_weights: Option<ExampleWeights>,
+    pub positives: Vec<String>,
+    pub negatives: Vec<String>,
+}
+
+impl Checkpoint {
+    /// The checkpoint file of training runs saving to `model_file`.
+    pub fn file_for(model_file: &Path) -> PathBuf {
+        let mut name = model_file.as_os_str().to_os_string();
+        name.push(".ckpt");
+        PathBuf::from(name)
+    }
+
+    pub fn load(filename: impl AsRef<Path>) -> Result<Checkpoint> {
+        bincode::deserialize(&std::fs::read(filename)?)
+    }
+
+    /// Written beside the file and renamed over it, so a crash while
+    /// writing leaves the previous checkpoint intact.
+    pub fn save(&self, filename: &Path) -> std::io::Result<()> {
+        let mut temp = filename.as_os_str().to_os_string();
+        temp.push(".tmp");
+        let mut outfp = BufWriter::new(File::create(&temp)?);
+        bincode::serialize_into(&mut outfp, self).expect("Error writing checkpoint");
+        outfp.flush()?;
+        drop(outfp);
+        std::fs::rename(&temp, filename)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Classifier {
+    pub lambda: f32,
+    pub num_iters: u32,
+
+    pub w: Vec<f32>,
+    pub scale: f32,
+    pub squared_norm: f32,
+    /// Maps scores to probabilities; see [`Model::calibrate`]
+    pub calibration: Option<Platt>,
+    /// Added to every score, so the decision boundary need not pass
+    /// through the origin
+    pub bias: f32,
+    /// Whether `train` fits `bias`. Models from before the intercept
+    /// existed load with this off, and keep scoring as they did.
+    pub fit_intercept: bool,
+    /// Pairs averaged into each SGD step
+    pub batch_size: u32,
+    pub class_weights: ClassWeights,
+    /// SGD steps taken by the last training run and any updates since.
+    /// [`Model::update`] continues the step size schedule from here.
+    pub steps: u32,
+    /// Share of `lambda` spent on an L1 penalty rather than L2, from 0
+    /// (pure L2, the Pegasos default) to 1 (pure L1). L1 drives
+    /// uninformative weights to exactly zero, keeping models sparse.
+    pub l1_ratio: f32,
+    /// The step size schedule. Models from before it could be chosen load
+    /// with the Pegasos schedule they were trained with.
+    pub train_config: TrainConfig,
+    /// Written ahead of the model rather than with the other fields
+    #[serde(skip)]
+    pub header: Option<ModelHeader>,
+    /// How [`Classifier::save`] stores `w`. A model loaded from a
+    /// quantized file keeps its quantization when it is saved again.
+    #[serde(skip)]
+    pub quantization: Quantization,
+    /// Weights for the examples of the following training runs; see
+    /// [`Model::set_example_weights`]. Not saved.
+    #[serde(skip)]
+    pub example_weights: Option<ExampleWeights>,
+    /// Where to checkpoint the following training runs; see
+    /// [`Model::set_checkpoints`]. Not saved.
+    #[serde(skip)]
+    pub checkpoints: Option<Checkpoints>,
+    /// Terms held at zero; see [`Model::set_guardrails`]. Not saved.
+    #[serde(skip)]
+    pub guardrails: Option<Arc<Guardrails>>,
+    /// Seeds the following training runs; see [`Model::set_seed`]. Not
+    /// saved.
+    #[serde(skip)]
+    pub seed: Option<u64>,
+}
+
+/// The fields of the first model layout. Later fields were appended, and
+/// [`Classifier::load`] reads each group only if the file has more bytes.
+#[derive(Deserialize)]
+struct ClassifierV0 {
+    lambda: f32,
+    num_iters: u32,
+    w: Vec<f32>,
+    scale: f32,
+    squared_norm: f32,
+}
+
+impl From<ClassifierV0> for Classifier {
+    fn from(old: ClassifierV0) -> Classifier {
+        Classifier {
+            lambda: old.lambda,
+            num_iters: old.num_iters,
+            w: old.w,
+            scale: old.scale,
+            squared_norm: old.squared_norm,
+            calibration: None,
+            bias: 0.0,
+            fit_intercept: false,
+            batch_size: 1,
+            class_weights: ClassWeights::Uniform,
+            steps: 0,
+            l1_ratio: 0.0,
+            train_config: TrainConfig::default(),
+            header: None,
+            quantization: Quantization::F32,
+            example_weights: None,
+            checkpoints: None,
+            guardrails: None,
+            seed: None,
+        }
+    }
+}
+
+impl Classifier {
+    pub fn new(dimensionality: usize, num_iters: u32) -> Classifier {
+        Classifier {
+            w: vec![0.0; dimensionality + 1],
+            lambda: 0.0001,
+            num_iters,
+            scale: 1.0,
+            squared_norm: 0.0,
+            calibration: None,
+            bias: 0.0,
+            fit_intercept: true,
+            batch_size: 1,
+            class_weights: ClassWeights::Uniform,
+            steps: 0,
+            l1_ratio: 0.0,
+            train_config: TrainConfig::default(),
+            header: None,
+            quantization: Quantization::F32,
+            example_weights: None,
+            checkpoints: None,
+            guardrails: None,
+            seed: None,
+        }
+    }
+
+    pub fn load(filename: impl AsRef<Path>) -> Result<Classifier> {
+        Self::from_bytes(&std::fs::read(filename)?)
+    }
+
+    /// Fails with an `InvalidInput` I/O error on another learner's model
+    /// file, whose bytes would otherwise decode as a meaningless classifier.
+    fn from_bytes(bytes: &[u8]) -> Result<Classifier> {
+        let (header, mut rest) = ModelHeader::split(bytes)?;
+        for (magic, learner) in [
+            (NaiveBayes::MAGIC, "naive Bayes"),
+            (Rocchio::MAGIC, "Rocchio"),
+        ] {
+            if rest.starts_with(magic) {
+                return Err(Box::new(bincode::ErrorKind::Io(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Not a logistic regression model, but a {} one", learner),
+                ))));
+            }
+        }
+        let mut quantized: Option<QuantizedWeights> = None;
+        if let Some(mut after) = rest.strip_prefix(Self::QUANTIZED_MAGIC) {
+            quantized = Some(bincode::deserialize_from(&mut after)?);
+            rest = after;
+        }
+        let mut model = Classifier::from(bincode::deserialize_from::<_, ClassifierV0>(&mut rest)?);
+        if !rest.is_empty() {
+            model.calibration = bincode::deserialize_from(&mut rest)?;
+        }
+        if !rest.is_empty() {
+            model.bias = bincode::deserialize_from(&mut rest)?;
+            model.fit_intercept = bincode::deserialize_from(&mut rest)?;
+        }
+        if !rest.is_empty() {
+            model.batch_size = bincode::deserialize_from(&mut rest)?;
+        }
+        if !rest.is_empty() {
+            model.class_weights = bincode::deserialize_from(&mut rest)?;
+        }
+        if !rest.is_empty() {
+            model.steps = bincode::deserialize_from(&mut rest)?;
+        }
+        if !rest.is_empty() {
+            model.l1_ratio = bincode::deserialize_from(&mut rest)?;
+        }
+        if !rest.is_empty() {
+            model.train_config = bincode::deserialize_from(&mut rest)?;
+        }
+        if let Some(quantized) = quantized {
+            model.w = quantized.dequantize();
+            model.quantization = quantized.quantization();
+        }
+        model.header = header;
+        Ok(model)
+    }
+
+    /// Follows the header when the weights are quantized. The
+    /// [`QuantizedWeights`] come next, then the model with `w` empty.
+    const QUANTIZED_MAGIC: &'static [u8; 4] = b"MYQW";
+
+    pub fn save(&self, filename: impl AsRef<Path>) -> std::io::Result<()> {
+        if let Some(guardrails) = &self.guardrails {
+            guardrails.check(&self.w)?;
+        }
+        let mut outfp = BufWriter::new(File::create(filename)?);
+        ModelHeader::write_to(self.header.as_ref(), &mut outfp)?;
+        match QuantizedWeights::new(&self.w, self.quantization) {
+            None => bincode::serialize_into(&mut outfp, self).expect("Error writing model"),
+            Some(quantized) => {
+                outfp.write_all(Self::QUANTIZED_MAGIC)?;
+                bincode::serialize_into(&mut outfp, &quantized).expect("Error writing model");
+                let rest = Classifier {
+                    w: Vec::new(),
+                    header: None,
+                    example_weights: None,
+                    checkpoints: None,
+                    guardrails: None,
+                    ..*self
+                };
+                bincode::serialize_into(&mut outfp, &rest).expect("Error writing model");
+            }
+        }
+        outfp.flush()?;
+        Ok(())
+    }
+
+    const MIN_SCALE: f32 = 0.00000000001;
+
+    pub fn inner_product_on_difference(&self, a: &FeatureVec, b: &FeatureVec) -> f32 {
+        let mut prod = 0.0;
+        prod += self.inner_product(a);
+        prod += self.inner_product(b) * -1.0;
+        prod
+    }
+
+    /// Zero out negligible weights. The survivors are left as they are, so
+    /// the bias and calibration still fit them. Returns the number of
+    /// weights zeroed.
+    pub fn prune(&mut self, how: Prune) -> usize {
+        self.scale_to_one();
+        let zeroed = zero_negligible(&mut self.w, how);
+        self.squared_norm = self.w.iter().map(|w| w * w).sum();
+        zeroed
+    }
+
+    /// Move the weights from the token ids of dictionary `from` to those of
+    /// `to`, matching tokens by string, so a model survives a rebuild or
+    /// pruning of its collection's vocabulary. Weights of tokens `to`
+    /// doesn't have, and of hashed long-tail ids, are dropped. Returns the
+    /// number of nonzero weights dropped.
+    pub fn remap(&mut self, from: &Dict, to: &Dict) -> usize {
+        self.scale_to_one();
+        let mut w = vec![0.0; to.last_tokid as usize + 1];
+        let mut dropped = 0;
+        for (tok, &old) in from.m.iter() {
+            let weight = self.w.get(old as usize).copied().unwrap_or(0.0);
+            if weight == 0.0 {
+                continue;
+            }
+            match to.m.get(tok) {
+                Some(&new) => w[new as usize] = weight,
+                None => dropped += 1,
+            }
+        }
+        if let Some(tail) = &from.hashed {
+            let hashed = self
+                .w
+                .iter()
+                .skip(tail.first_id as usize)
+                .take(tail.buckets as usize);
+            dropped += hashed.filter(|w| **w != 0.0).count();
+        }
+        self.w = w;
+        self.squared_norm = self.w.iter().map(|w| w * w).sum();
+        dropped
+    }
+
+    /// Keep only the `k` largest-magnitude weights, as in
+    /// [`Classifier::prune`]. Scoring through an index touches one posting
+    /// list per nonzero weight, so this bounds the cost of a query.
+    pub fn prune_to_top_k(&mut self, k: usize) -> usize {
+        self.prune(Prune::TopK(k))
+    }
+
+    /// An immutable copy of the current weights for scoring.
+    pub fn scoring_model(&self) -> ScoringModel {
+        ScoringModel {
+            w: self.w.iter().map(|w| w * self.scale).collect(),
+            bias: self.bias,
+            calibration: self.calibration,
+        }
+    }
+
+    /// Candidate regularization strengths for [`Classifier::tune_lambda`]
+    pub const LAMBDA_GRID: [f32; 6] = [1e-6, 1e-5, 1e-4, 1e-3, 1e-2, 1e-1];
+
+    /// Pick `lambda` from `grid` by k-fold cross-validation, keeping the
+    /// value with the best mean AUC on the held-out folds. Each fold trains
+    /// a fresh model with this one's iterations and batch size. The
+    /// examples are shuffled in place. Returns the chosen lambda and its
+    /// AUC.
+    pub fn tune_lambda(
+        &mut self,
+        positives: &mut [FeatureVec],
+        negatives: &mut [FeatureVec],
+        grid: &[f32],
+        folds: usize,
+    ) -> (f32, f64) {
+        let folds = folds.min(positives.len()).min(negatives.len());
+        assert!(folds >= 2, "Too few examples for cross-validation");
+        let mut rng = StdRng::seed_from_u64(self.next_seed());
+        positives.shuffle(&mut rng);
+        negatives.shuffle(&mut rng);
+
+        let mut best = (self.lambda, f64::NEG_INFINITY);
+        for &lambda in grid {
+            let mut total = 0.0;
+            for k in 0..folds {
+                // Each fold is rotated to the end of the slice in turn, so
+                // the rest can be trained on without copying
+                let pos_test = fold_len(positives.len(), folds, k);
+                let neg_test = fold_len(negatives.len(), folds, k);
+                let (pos_train, pos_held) = positives.split_at(positives.len() - pos_test);
+                let (neg_train, neg_held) = negatives.split_at(negatives.len() - neg_test);
+
+                let mut model = Classifier::new(self.w.len() - 1, self.num_iters);
+                model.lambda = lambda;
+                model.batch_size = self.batch_size;
+                model.class_weights = self.class_weights;
+                model.l1_ratio = self.l1_ratio;
+                model.train_config = self.train_config;
+                let mut batch = Vec::with_capacity(model.batch_size.max(1) as usize);
+                let mut l1 = L1Penalty::new(model.w.len());
+                let mut picker = Picker::new(None, pos_train.len(), neg_train.len(), rng.gen());
+                for i in 0..model.num_iters {
+                    let eta = model.train_config.eta(i, model.num_iters, lambda);
+                    model.sgd_step(eta, &mut picker, pos_train, neg_train, &mut batch, &mut l1);
+                }
+                total += auc(&model.score_batch(pos_held), &model.score_batch(neg_held));
+
+                positives.rotate_right(pos_test);
+                negatives.rotate_right(neg_test);
+            }
+            let mean = total / folds as f64;
+            println!("lambda {} cross-validated AUC {:.5}", lambda, mean);
+            if mean > best.1 {
+                best = (lambda, mean);
+            }
+        }
+        self.lambda = best.0;
+        best
+    }
+
+    /// One SGD step of size `eta` of pairwise logistic regression with
+    /// Pegasos regularization and projection. Returns the batch's mean
+    /// loss, taken before the step.
+    fn sgd_step<'a>(
+        &mut self,
+        eta: f32,
+        picker: &mut Picker,
+        positives: &'a [FeatureVec],
+        negatives: &'a [FeatureVec],
+        batch: &mut Vec<(&'a FeatureVec, &'a FeatureVec, f32)>,
+        l1: &mut L1Penalty,
+    ) -> f32 {
+        let k = self.batch_size.max(1);
+        let mut batch_loss = 0.0;
+
+        // Losses for the whole batch are taken at the same weights, and
+        // their gradients averaged into one step
+        batch.clear();
+        for _ in 0..k {
+            let (a, b) = picker.pair(positives, negatives);
+
+            // let mut loss = self.inner_product_on_difference(a, b);
+            // loss *= y;
+            // loss = loss.exp();
+            // loss = y / (1.0 + loss);
+            let y = 1.0;
+            let ip = self.inner_product_on_difference(a, b);
+            let loss = y / (1.0 + f32::exp(y * ip));
+            // println!("ip {:.5} loss {:.5}", ip, loss);
+            batch.push((a, b, loss / k as f32));
+            batch_loss += (-y * ip).exp().ln_1p();
+        }
+
+        // Regularize
+        let scaling_factor = 1.0 - (eta * self.lambda * (1.0 - self.l1_ratio));
+        if scaling_factor > Self::MIN_SCALE {
+            self.scale_by(scaling_factor);
+        } else {
+            self.scale_by(Self::MIN_SCALE);
+        }
+
+        for (a, b, loss) in batch.iter() {
+            if *loss != 0.0 {
+                self.add_vector(a, eta * loss);
+                self.add_vector(b, -1.0 * eta * loss);
+                self.unlearn_banned(a);
+                self.unlearn_banned(b);
+            }
+        }
+
+        if self.l1_ratio > 0.0 {
+            l1.total += eta * self.lambda * self.l1_ratio;
+            for (a, b, _) in batch.iter() {
+                for feat in a.features.iter().chain(b.features.iter()) {
+                    self.apply_l1(feat.id as usize, l1);
+                }
+            }
+        }
+
+        // Pegasos projection
+        let projection_val = 1.0 / (self.lambda * self.squared_norm).sqrt();
+        if projection_val < 1.0 {
+            self.scale_by(projection_val);
+        }
+        batch_loss / k as f32
+    }
+
+    /// The seed for a training run's random draws: the one set, or a
+    /// fresh one.
+    fn next_seed(&self) -> u64 {
+        self.seed.unwrap_or_else(|| thread_rng().gen())
+    }
+
+    /// Take `num_iters` SGD steps, numbering them from `first` for the
+    /// step size schedule, then finish training.
+    fn run_steps(
+        &mut self,
+        first: u32,
+        num_iters: u32,
+        positives: &[FeatureVec],
+        negatives: &[FeatureVec],
+    ) -> TrainReport {
+        let picker = Picker::new(
+            self.example_weights.as_ref(),
+            positives.len(),
+            negatives.len(),
+            self.next_seed(),
+        );
+        let l1 = L1Penalty::new(self.w.len());
+        let end = first.saturating_add(num_iters);
+        self.steps_between(first, end, picker, l1, positives, negatives)
+    }
+
+    /// Take the SGD steps from `first` up to `end`, checkpointing along
+    /// the way if asked to, then finish training.
+    fn steps_between(
+        &mut self,
+        first: u32,
+        end: u32,
+        mut picker: Picker,
+        mut l1: L1Penalty,
+        positives: &[FeatureVec],
+        negatives: &[FeatureVec],
+    ) -> TrainReport {
+        assert!(!positives.is_empty(), "No positive examples");
+        assert!(!negatives.is_empty(), "No negative examples");
+        let started = Instant::now();
+        self.zero_banned();
+        let mut batch = Vec::with_capacity(self.batch_size.max(1) as usize);
+        let mut curve = LossCurve::new(end - first);
+        let every = self.checkpoints.as_ref().map_or(0, |c| c.every);
+
+        for i in first..end {
+            let eta = self.train_config.eta(i, end, self.lambda);
+            let loss = self.sgd_step(eta, &mut picker, positives, negatives, &mut batch, &mut l1);
+            curve.add(loss);
+            if every > 0 && (i + 1) % every == 0 && i + 1 < end {
+                self.steps = i + 1;
+                let seed = picker.reseed();
+                if let Err(e) = self.checkpoint(end, seed, &l1, positives, negatives) {
+                    eprintln!("warning: could not write checkpoint: {}", e);
+                }
+            }
+        }
+        self.steps = end;
+        self.settle_l1(&mut l1);
+        self.finish_training(positives, negatives);
+        TrainReport {
+            iterations: end - first,
+            loss_curve: curve.into_points(),
+            ..Default::default()
+        }
+        .finish(self, positives, negatives, started)
+    }
+
+    fn checkpoint(
+        &self,
+        end: u32,
+        seed: u64,
+        l1: &L1Penalty,
+        positives: &[FeatureVec],
+        negatives: &[FeatureVec],
+    ) -> std::io::Result<()> {
+        let Some(checkpoints) = &self.checkpoints else {
+            return Ok(());
+        };
+        let checkpoint = Checkpoint {
+            model: Classifier {
+                w: self.w.clone(),
+                header: None,
+                example_weights: None,
+                checkpoints: None,
+                guardrails: None,
+                ..*self
+            },
+            end,
+            seed,
+            l1_total: l1.total,
+            l1_applied: l1.applied.clone(),
+            example_weights: self.example_weights.clone(),
+            positives: positives.iter().map(|fv| fv.docid.clone()).collect(),
+            negatives: negatives.iter().map(|fv| fv.docid.clone()).collect(),
+        };
+        checkpoint.save(&checkpoints.file)
+    }
+
+    /// Carry on an interrupted training run from its checkpoint. The
+    /// examples must be the ones the checkpoint lists, in its order.
+    /// Checkpoints continue to be written if `checkpoints` is given, and
+    /// banned terms are held at zero if `guardrails` are.
+    pub fn resume(
+        checkpoint: Checkpoint,
+        checkpoints: Option<Checkpoints>,
+        guardrails: Option<Arc<Guardrails>>,
+        positives: &[FeatureVec],
+        negatives: &[FeatureVec],
+    ) -> (Classifier, TrainReport) {
+        let mut model = checkpoint.model;
+        model.example_weights = checkpoint.example_weights;
+        model.checkpoints = checkpoints;
+        model.guardrails = guardrails;
+        let picker = Picker::new(
+            model.example_weights.as_ref(),
+            positives.len(),
+            negatives.len(),
+            checkpoint.seed,
+        );
+        let l1 = L1Penalty {
+            total: checkpoint.l1_total,
+            applied: checkpoint.l1_applied,
+        };
+        let first = model.steps;
+        let report = model.steps_between(first, checkpoint.end, picker, l1, positives, negatives);
+        (model, report)
+    }
+
+    /// Fold the scale into the weights and fit the intercept.
+    fn finish_training(&mut self, positives: &[FeatureVec], negatives: &[FeatureVec]) {
+        self.scale_to_one();
+        if self.fit_intercept {
+            self.fit_bias(positives, negatives);
+        }
+    }
+
+    /// Fit the intercept by Newton's method on the pointwise logistic loss
+    /// of the training examples, holding the weights fixed. The pairwise
+    /// updates in `train` cancel any bias, so it has to be fit separately.
+    /// A small ridge penalty keeps it finite when the examples separate.
+    fn fit_bias(&mut self, positives: &[FeatureVec], negatives: &[FeatureVec]) {
+        const RIDGE: f64 = 0.01;
+        let (pos_weight, neg_weight) = self.class_weights.resolve(positives.len(), negatives.len());
+        let example_weight = |i: usize, positive: bool| {
+            self.example_weights.as_ref().map_or(1.0, |w| {
+                if positive {
+                    w.positives[i]
+                } else {
+                    w.negatives[i]
+                }
+            })
+        };
+        let examples: Vec<(f64, f64, f64)> = positives
+            .iter()
+            .enumerate()
+            .map(|(i, x)| (1.0, x, pos_weight * example_weight(i, true)))
+            .chain(
+                negatives
+                    .iter()
+                    .enumerate()
+                    .map(|(i, x)| (-1.0, x, neg_weight * example_weight(i, false))),
+            )
+            .map(|(y, x, weight)| (y, (self.inner_product(x) - self.bias) as f64, weight as f64))
+            .collect();
+
+        let mut b = self.bias as f64;
+        for _ in 0..50 {
+            let (mut grad, mut hess) = (RIDGE * b, RIDGE);
+            for (y, s, weight) in examples.iter() {
+                let p = 1.0 / (1.0 + (-(s + b)).exp());
+                grad += weight * (p - (y + 1.0) / 2.0);
+                hess += weight * p * (1.0 - p);
+            }
+            let step = grad / hess;
+            b -= step;
+            if step.abs() < 1e-6 {
+                break;
+            }
+        }
+        self.bias = b as f32;
+    }
+
+    fn scale_to_one(&mut self) {
+        for wt in self.w.iter_mut() {
+            *wt *= self.scale;
+        }
+        self.scale = 1.0;
+    }
+
+    fn scale_by(&mut self, scaling_factor: f32) {
+        if self.scale < Self::MIN_SCALE {
+            self.scale_to_one();
+        }
+        self.squared_norm *= scaling_factor * scaling_factor;
+
+        if scaling_factor > 0.0 {
+            self.scale *= scaling_factor;
+        }
+    }
+
+    /// Shrink weight `id` toward zero by the L1 penalty accrued since it
+    /// was last shrunk, stopping at zero.
+    fn apply_l1(&mut self, id: usize, l1: &mut L1Penalty) {
+        let old = self.w[id] * self.scale;
+        let new = if old > 0.0 {
+            (old - (l1.total + l1.applied[id])).max(0.0)
+        } else if old < 0.0 {
+            (old + (l1.total - l1.applied[id])).min(0.0)
+        } else {
+            return;
+        };
+        l1.applied[id] += new - old;
+        self.w[id] = new / self.scale;
+        self.squared_norm += new * new - old * old;
+    }
+
+    /// Apply the outstanding L1 penalty to every weight, including those
+    /// whose features were not seen again after it accrued.
+    fn settle_l1(&mut self, l1: &mut L1Penalty) {
+        if self.l1_ratio > 0.0 {
+            for id in 0..self.w.len() {
+                self.apply_l1(id, l1);
+            }
+        }
+    }
+
+    /// Zero the weights of any banned terms in `x`, as if its step had
+    /// skipped them.
+    fn unlearn_banned(&mut self, x: &FeatureVec) {
+        let Some(guardrails) = &self.guardrails else {
+            return;
+        };
+        for feat in x.features.iter() {
+            if guardrails.is_banned(feat.id) {
+                let old = self.w[feat.id as usize] * self.scale;
+                self.squared_norm -= old * old;
+                self.w[feat.id as usize] = 0.0;
+            }
+        }
+    }
+
+    /// Zero every banned weight, such as those of a model trained before
+    /// the guardrails were set.
+    fn zero_banned(&mut self) {
+        if let Some(guardrails) = &self.guardrails {
+            if guardrails.zero(&mut self.w) > 0 {
+                self.squared_norm =
+                    self.w.iter().map(|w| w * w).sum::<f32>() * self.scale * self.scale;
+            }
+        }
+    }
+
+    fn add_vector(&mut self, x: &FeatureVec, x_scale: f32) {
+        let mut inner_product = 0.0;
+
+        for feat in x.features.iter() {
+            let this_x_value = feat.value * x_scale;
+            let this_x_feature = feat.id as usize;
+            inner_product += self.w[this_x_feature] * this_x_value;
+            self.w[this_x_feature] += this_x_value / self.scale;
+        }
+
+        self.squared_norm +=
+            x.squared_norm * x_scale * x_scale + (2.0 * self.scale * inner_product);
+    }
+}
+
+impl Model for Classifier {
+    fn train(&mut self, positives: &[FeatureVec], negatives: &[FeatureVec]) -> TrainReport {
+        self.run_steps(0, self.num_iters, positives, negatives)
+    }
+
+    fn update(
+        &mut self,
+        positives: &[FeatureVec],
+        negatives: &[FeatureVec],
+        num_iters: u32,
+    ) -> TrainReport {
+        self.run_steps(self.steps, num_iters, positives, negatives)
+    }
+
+    fn train_early_stopping(
+        &mut self,
+        positives: &[FeatureVec],
+        negatives: &[FeatureVec],
+        validation: &Validation,
+    ) -> TrainReport {
+        assert!(!positives.is_empty(), "No positive examples");
+        assert!(!negatives.is_empty(), "No negative examples");
+        let started = Instant::now();
+        self.zero_banned();
+        let mut picker = Picker::new(
+            self.example_weights.as_ref(),
+            positives.len(),
+            negatives.len(),
+            self.next_seed(),
+        );
+        let mut batch = Vec::with_capacity(self.batch_size.max(1) as usize);
+        let mut curve = LossCurve::new(self.num_iters);
+        let mut l1 = L1Penalty::new(self.w.len());
+
+        let mut best_loss = validation.loss(self);
+        let mut best = (0, self.w.clone(), self.scale, self.squared_norm);
+        let mut checks_since_best = 0;
+
+        for i in 0..self.num_iters {
+            let eta = self.train_config.eta(i, self.num_iters, self.lambda);
+            let loss = self.sgd_step(eta, &mut picker, positives, negatives, &mut batch, &mut l1);
+            curve.add(loss);
+            if (i + 1) % validation.check_every.max(1) != 0 {
+                continue;
+            }
+            let loss = validation.loss(self);
+            if loss < best_loss {
+                best_loss = loss;
+                best = (i + 1, self.w.clone(), self.scale, self.squared_norm);
+                checks_since_best = 0;
+            } else {
+                checks_since_best += 1;
+                if checks_since_best >= validation.patience {
+                    break;
+                }
+            }
+        }
+
+        let (iters, w, scale, squared_norm) = best;
+        (self.w, self.scale, self.squared_norm) = (w, scale, squared_norm);
+        self.steps = iters;
+        self.settle_l1(&mut l1);
+        self.finish_training(positives, negatives);
+        // The curve stops where training did, past the iterations kept
+        TrainReport {
+            iterations: iters,
+            loss_curve: curve.into_points(),
+            validation_loss: Some(best_loss),
+            ..Default::default()
+        }
+        .finish(self, positives, negatives, started)
+    }
+
+    fn inner_product(&self, x: &FeatureVec) -> f32 {
+        let mut prod = 0.0;
+        for feat in x.features.iter() {
+            prod += self.w[feat.id as usize] * feat.value;
+        }
+        prod * self.scale + self.bias
+    }
+
+    fn score_batch(&self, xs: &[FeatureVec]) -> Vec<f32> {
+        map_batch(xs, |x| self.inner_product(x))
+    }
+
+    fn scoring_model(&self) -> ScoringModel {
+        Classifier::scoring_model(self)
+    }
+
+    fn prune(&mut self, how: Prune) -> usize {
+        Classifier::prune(self, how)
+    }
+
+    fn calibrate(&mut self, xs: &[FeatureVec], labels: &[i8]) {
+        let scores = self.score_batch(xs);
+        self.calibration = Some(Platt::fit(&scores, labels));
+    }
+
+    fn calibration(&self) -> Option<Platt> {
+        self.calibration
+    }
+
+    fn header(&self) -> Option<&ModelHeader> {
+        self.header.as_ref()
+    }
+
+    fn set_header(&mut self, header: ModelHeader) {
+        self.header = Some(header);
+    }
+
+    fn set_example_weights(&mut self, weights: ExampleWeights) {
+        self.example_weights = Some(weights);
+    }
+
+    fn set_checkpoints(&mut self, checkpoints: Checkpoints) {
+        self.checkpoints = Some(checkpoints);
+    }
+
+    fn set_guardrails(&mut self, guardrails: Arc<Guardrails>) {
+        self.guardrails = Some(guardrails);
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+        self.seed = Some(seed);
+    }
+
+    fn save(&self, filename: &Path) -> std::io::Result<()> {
+        Classifier::save(self, filename)
+    }
+
+    fn load(filename: &Path) -> Result<Classifier> {
+        Classifier::load(filename)
+    }
+}
+
+/// Multinomial naive Bayes over the feature values, treated as fractional
+/// term counts. Each weight is the log ratio of a term's smoothed
+/// probability in relevant and nonrelevant documents, so scoring is the
+/// same inner product as [`Classifier`] plus a log prior-odds bias.
+///
+/// Training starts from scratch on the examples given; nothing carries
+/// over from earlier calls.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NaiveBayes {
+    /// Laplace smoothing added to every term's count
+    pub alpha: f32,
+    pub w: Vec<f32>,
+    pub bias: f32,
+    pub calibration: Option<Platt>,
+    /// Stored after the rest of the model, and read only if present
+    #[serde(skip, default = "uniform")]
+    pub class_weights: ClassWeights,
+    /// Written ahead of the magic, as for a [`Classifier`]
+    #[serde(skip)]
+    pub header: Option<ModelHeader>,
+    /// Terms held at zero; see [`Model::set_guardrails`]. Not saved.
+    #[serde(skip)]
+    pub guardrails: Option<Arc<Guardrails>>,
+}
+
+fn uniform() -> ClassWeights {
+    ClassWeights::Uniform
+}
+
+impl NaiveBayes {
+    /// Marks a naive Bayes model file, which otherwise has the same bincode
+    /// layout conventions as a [`Classifier`] file.
+    const MAGIC: &'static [u8; 4] = b"MYNB";
+
+    pub fn new(dimensionality: usize) -> NaiveBayes {
+        NaiveBayes {
+            alpha: 1.0,
+            w: vec![0.0; dimensionality + 1],
+            bias: 0.0,
+            calibration: None,
+            class_weights: ClassWeights::Uniform,
+            header: None,
+            guardrails: None,
+        }
+    }
+
+    pub fn load(filename: impl AsRef<Path>) -> Result<NaiveBayes> {
+        Self::from_bytes(&std::fs::read(filename)?)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<NaiveBayes> {
+        let (header, rest) = ModelHeader::split(bytes)?;
+        let Some(mut rest) = rest.strip_prefix(Self::MAGIC) else {
+            return Err(Box::new(bincode::ErrorKind::Custom(
+                "Not a naive Bayes model".to_string(),
+            )));
+        };
+        let mut model: NaiveBayes = bincode::deserialize_from(&mut rest)?;
+        if !rest.is_empty() {
+            model.class_weights = bincode::deserialize_from(&mut rest)?;
+        }
+        model.header = header;
+        Ok(model)
+    }
+
+    pub fn save(&self, filename: impl AsRef<Path>) -> std::io::Result<()> {
+        if let Some(guardrails) = &self.guardrails {
+            guardrails.check(&self.w)?;
+        }
+        let mut outfp = BufWriter::new(File::create(filename)?);
+        ModelHeader::write_to(self.header.as_ref(), &mut outfp)?;
+        outfp.write_all(Self::MAGIC)?;
+        bincode::serialize_into(&mut outfp, self).expect("Error writing model");
+        bincode::serialize_into(&mut outfp, &self.class_weights).expect("Error writing model");
+        outfp.flush()
+    }
+
+    fn term_counts(&mut self, docs: &[FeatureVec]) -> Vec<f64> {
+        let mut counts = vec![0.0; self.w.len()];
+        for feat in docs.iter().flat_map(|fv| fv.features.iter()) {
+            let id = feat.id as usize;
+            if id >= counts.len() {
+                counts.resize(id + 1, 0.0);
+                self.w.resize(id + 1, 0.0);
+            }
+            counts[id] += feat.value as f64;
+        }
+        counts
+    }
+}
+
+impl Model for NaiveBayes {
+    fn train(&mut self, positives: &[FeatureVec], negatives: &[FeatureVec]) -> TrainReport {
+        assert!(!positives.is_empty(), "No positive examples");
+        assert!(!negatives.is_empty(), "No negative examples");
+        let started = Instant::now();
+
+        let mut pos = self.term_counts(positives);
+        let mut neg = self.term_counts(negatives);
+        pos.resize(self.w.len(), 0.0);
+        neg.resize(self.w.len(), 0.0);
+
+        let alpha = self.alpha as f64;
+        let vocab = self.w.len() as f64;
+        let pos_total: f64 = pos.iter().sum::<f64>() + alpha * vocab;
+        let neg_total: f64 = neg.iter().sum::<f64>() + alpha * vocab;
+        for (i, wt) in self.w.iter_mut().enumerate() {
+            let p = (pos[i] + alpha) / pos_total;
+            let q = (neg[i] + alpha) / neg_total;
+            *wt = (p.ln() - q.ln()) as f32;
+        }
+        if let Some(guardrails) = &self.guardrails {
+            guardrails.zero(&mut self.w);
+        }
+        let (pos_weight, neg_weight) = self.class_weights.resolve(positives.len(), negatives.len());
+        self.bias =
+            (pos_weight * positives.len() as f32 / (neg_weight * negatives.len() as f32)).ln();
+        TrainReport::default().finish(self, positives, negatives, started)
+    }
+
+    fn inner_product(&self, x: &FeatureVec) -> f32 {
+        let prod: f32 = x
+            .features
+            .iter()
+            .map(|feat| self.w.get(feat.id as usize).map_or(0.0, |w| w * feat.value))
+            .sum();
+        prod + self.bias
+    }
+
+    fn score_batch(&self, xs: &[FeatureVec]) -> Vec<f32> {
+        map_batch(xs, |x| self.inner_product(x))
+    }
+
+    fn scoring_model(&self) -> ScoringModel {
+        ScoringModel {
+            w: self.w.iter().copied().collect(),
+            bias: self.bias,
+            calibration: self.calibration,
+        }
+    }
+
+    fn prune(&mut self, how: Prune) -> usize {
+        zero_negligible(&mut self.w, how)
+    }
+
+    fn calibrate(&mut self, xs: &[FeatureVec], labels: &[i8]) {
+        let scores = self.score_batch(xs);
+        self.calibration = Some(Platt::fit(&scores, labels));
+    }
+
+    fn calibration(&self) -> Option<Platt> {
+        self.calibration
+    }
+
+    fn header(&self) -> Option<&ModelHeader> {
+        self.header.as_ref()
+    }
+
+    fn set_header(&mut self, header: ModelHeader) {
+        self.header = Some(header);
+    }
+
+    fn set_guardrails(&mut self, guardrails: Arc<Guardrails>) {
+        self.guardrails = Some(guardrails);
+    }
+
+    fn save(&self, filename: &Path) -> std::io::Result<()> {
+        NaiveBayes::save(self, filename)
+    }
+
+    fn load(filename: &Path) -> Result<NaiveBayes> {
+        NaiveBayes::load(filename)
+    }
+}
+
+/// Rocchio relevance feedback: the weights are the centroid of the
+/// length-normalized relevant documents less a fraction of the centroid of
+/// the nonrelevant ones. There is nothing to iterate, so training costs one
+/// pass over the examples, which makes it a cheap first-round model when
+/// only a handful of documents have been judged.
+///
+/// The bias puts the decision boundary midway between the mean scores of
+/// the two classes. Training starts from scratch on the examples given.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Rocchio {
+    /// Weight of the relevant centroid
+    pub beta: f32,
+    /// Weight of the nonrelevant centroid, subtracted
+    pub gamma: f32,
+    pub w: Vec<f32>,
+    pub bias: f32,
+    pub calibration: Option<Platt>,
+    /// Written ahead of the magic, as for a [`Classifier`]
+    #[serde(skip)]
+    pub header: Option<ModelHeader>,
+    /// Terms held at zero; see [`Model::set_guardrails`]. Not saved.
+    #[serde(skip)]
+    pub guardrails: Option<Arc<Guardrails>>,
+}
+
+impl Rocchio {
+    /// Marks a Rocchio model file.
+    const MAGIC: &'static [u8; 4] = b"MYRO";
+
+    pub fn new(dimensionality: usize) -> Rocchio {
+        Rocchio {
+            beta: 0.75,
+            gamma: 0.15,
+            w: vec![0.0; dimensionality + 1],
+            bias: 0.0,
+            calibration: None,
+            header: None,
+            guardrails: None,
+        }
+    }
+
+    pub fn load(filename: impl AsRef<Path>) -> Result<Rocchio> {
+        Self::from_bytes(&std::fs::read(filename)?)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Rocchio> {
+        let (header, rest) = ModelHeader::split(bytes)?;
+        let Some(rest) = rest.strip_prefix(Self::MAGIC) else {
+            return Err(Box::new(bincode::ErrorKind::Custom(
+                "Not a Rocchio model".to_string(),
+            )));
+        };
+        let mut model: Rocchio = bincode::deserialize(rest)?;
+        model.header = header;
+        Ok(model)
+    }
+
+    pub fn save(&self, filename: impl AsRef<Path>) -> std::io::Result<()> {
+        if let Some(guardrails) = &self.guardrails {
+            guardrails.check(&self.w)?;
+        }
+        let mut outfp = BufWriter::new(File::create(filename)?);
+        ModelHeader::write_to(self.header.as_ref(), &mut outfp)?;
+        outfp.write_all(Self::MAGIC)?;
+        bincode::serialize_into(&mut outfp, self).expect("Error writing model");
+        outfp.flush()
+    }
+
+    /// Add `coef` times the centroid of the unit-length `docs` to the
+    /// weights.
+    fn add_centroid(&mut self, docs: &[FeatureVec], coef: f32) {
+        let coef = coef / docs.len() as f32;
+        for fv in docs {
+            // The stored "squared_norm" is the L2 norm
+            let norm = if fv.squared_norm > 0.0 {
+                fv.squared_norm
+            } else {
+                1.0
+            };
+            for feat in fv.features.iter() {
+                let id = feat.id as usize;
+                if id >= self.w.len() {
+                    self.w.resize(id + 1, 0.0);
+                }
+                self.w[id] += coef * feat.value / norm;
+            }
+        }
+    }
+}
+
+impl Model for Rocchio {
+    fn train(&mut self, positives: &[FeatureVec], negatives: &[FeatureVec]) -> TrainReport {
+        assert!(!positives.is_empty(), "No positive examples");
+        assert!(!negatives.is_empty(), "No negative examples");
+        let started = Instant::now();
+
+        self.w.iter_mut().for_each(|w| *w = 0.0);
+        self.bias = 0.0;
+        self.add_centroid(positives, self.beta);
+        self.add_centroid(negatives, -self.gamma);
+        if let Some(guardrails) = &self.guardrails {
+            guardrails.zero(&mut self.w);
+        }
+
+        let mean = |xs: &[FeatureVec]| {
+            self.score_batch(xs).iter().map(|s| *s as f64).sum::<f64>() / xs.len() as f64
+        };
+        self.bias = -((mean(positives) + mean(negatives)) / 2.0) as f32;
+        TrainReport::default().finish(self, positives, negatives, started)
+    }
+
+    fn inner_product(&self, x: &FeatureVec) -> f32 {
+        let prod: f32 = x
+            .features
+            .iter()
+            .map(|feat| self.w.get(feat.id as usize).map_or(0.0, |w| w * feat.value))
+            .sum();
+        prod + self.bias
+    }
+
+    fn score_batch(&self, xs: &[FeatureVec]) -> Vec<f32> {
+        map_batch(xs, |x| self.inner_product(x))
+    }
+
+    fn scoring_model(&self) -> ScoringModel {
+        ScoringModel {
+            w: self.w.iter().copied().collect(),
+            bias: self.bias,
+            calibration: self.calibration,
+        }
+    }
+
+    fn prune(&mut self, how: Prune) -> usize {
+        zero_negligible(&mut self.w, how)
+    }
+
+    fn calibrate(&mut self, xs: &[FeatureVec], labels: &[i8]) {
+        let scores = self.score_batch(xs);
+        self.calibration = Some(Platt::fit(&scores, labels));
+    }
+
+    fn calibration(&self) -> Option<Platt> {
+        self.calibration
+    }
+
+    fn header(&self) -> Option<&ModelHeader> {
+        self.header.as_ref()
+    }
+
+    fn set_header(&mut self, header: ModelHeader) {
+        self.header = Some(header);
+    }
+
+    fn set_guardrails(&mut self, guardrails: Arc<Guardrails>) {
+        self.guardrails = Some(guardrails);
+    }
+
+    fn save(&self, filename: &Path) -> std::io::Result<()> {
+        Rocchio::save(self, filename)
+    }
+
+    fn load(filename: &Path) -> Result<Rocchio> {
+        Rocchio::load(filename)
+    }
+}
+
+/// Load a model file of any learner type.
+pub fn load_model(filename: impl AsRef<Path>) -> Result<Box<dyn Model>> {
+    let bytes = std::fs::read(filename)?;
+    let (_, rest) = ModelHeader::split(&bytes)?;
+    if rest.starts_with(NaiveBayes::MAGIC) {
+        Ok(Box::new(NaiveBayes::from_bytes(&bytes)?))
+    } else if rest.starts_with(Rocchio::MAGIC) {
+        Ok(Box::new(Rocchio::from_bytes(&bytes)?))
+    } else {
+        Ok(Box::new(Classifier::from_bytes(&bytes)?))
+    }
+}
+
+/// The inference half of a [`Classifier`]: weights with the scale folded
+/// in, behind an `Arc` so clones are cheap and can be shared across
+/// threads without locking.
+///
+/// Feature ids beyond the model's dimensionality score as zero, so a model
+/// can be applied to documents indexed after it was trained.
+#[derive(Debug, Clone)]
+pub struct ScoringModel {
+    w: Arc<[f32]>,
+    bias: f32,
+    calibration: Option<Platt>,
+}
+
+impl ScoringModel {
+    pub fn inner_product(&self, x: &FeatureVec) -> f32 {
+        x.features
+            .iter()
+            .map(|feat| self.w.get(feat.id as usize).map_or(0.0, |w| w * feat.value))
+            .sum::<f32>()
+            + self.bias
+    }
+
+    /// The score of a document given as parallel slices of feature ids and
+    /// values, as read in place from an Arrow batch. It is the same as
+    /// [`ScoringModel::inner_product`] of the vector they make up.
+    pub fn score_slices(&self, ids: &[u32], values: &[f32]) -> f32 {
+        ids.iter()
+            .zip(values)
+            .map(|(&id, value)| self.w.get(id as usize).map_or(0.0, |w| w * value))
+            .sum::<f32>()
+            + self.bias
+    }
+
+    /// Each feature's part in the score of `x`, largest first. With the
+    /// bias they add up to [`ScoringModel::inner_product`].
+    pub fn contributions(&self, x: &FeatureVec) -> Vec<(u32, f32)> {
+        let mut parts: Vec<(u32, f32)> = x
+            .features
+            .iter()
+            .filter_map(|feat| {
+                let w = self.w.get(feat.id as usize)?;
+                Some((feat.id, w * feat.value))
+            })
+            .filter(|(_, c)| *c != 0.0)
+            .collect();
+        parts.sort_by(|a, b| b.1.total_cmp(&a.1));
+        parts
+    }
+
+    /// Score a slice of vectors, in order. Reading documents in chunks and
+    /// scoring each chunk here keeps the weights hot in cache, and with the
+    /// `parallel` feature the chunk is split across the rayon thread pool.
+    pub fn score_batch(&self, xs: &[FeatureVec]) -> Vec<f32> {
+        map_batch(xs, |x| self.inner_product(x))
+    }
+
+    pub fn predict_proba(&self, x: &FeatureVec) -> f32 {
+        self.probability(self.inner_product(x))
+    }
+
+    /// Convert a score from [`ScoringModel::inner_product`] to P(relevant).
+    pub fn probability(&self, score: f32) -> f32 {
+        probability(self.calibration, score)
+    }
+
+    pub fn is_calibrated(&self) -> bool {
+        self.calibration.is_some()
+    }
+
+    pub fn weights(&self) -> &[f32] {
+        &self.w
+    }
+}
+
+/// The size of fold `k` when `n` examples are split into `folds` folds.
+fn fold_len(n: usize, folds: usize, k: usize) -> usize {
+    n * (k + 1) / folds - n * k / folds
+}
+
+/// The fraction of (relevant, nonrelevant) pairs ranked in the right
+/// order, counting ties as half.
+pub(crate) fn auc(pos_scores: &[f32], neg_scores: &[f32]) -> f64 {
+    let mut right = 0.0;
+    for p in pos_scores.iter() {
+        for n in neg_scores.iter() {
+            if p > n {
+                right += 1.0;
+            } else if p == n {
+                right += 0.5;
+            }
+        }
+    }
+    right / (pos_scores.len() * neg_scores.len()).max(1) as f64
+}
+
+/// Zero the weights `how` considers negligible, returning how many.
+fn zero_negligible(w: &mut [f32], how: Prune) -> usize {
+    // Weights tied with the k-th largest are kept in index order until
+    // there are k, so top-k never keeps more than k
+    let (threshold, mut ties) = match how {
+        Prune::MinWeight(t) => (t, usize::MAX),
+        Prune::TopK(k) => {
+            let mut mags: Vec<f32> = w.iter().map(|w| w.abs()).filter(|w| *w > 0.0).collect();
+            if k == 0 {
+                (f32::INFINITY, 0)
+            } else if k >= mags.len() {
+                (0.0, usize::MAX)
+            } else {
+                let (_, kth, _) = mags.select_nth_unstable_by(k - 1, |a, b| b.total_cmp(a));
+                let kth = *kth;
+                let above = mags.iter().filter(|m| **m > kth).count();
+                (kth, k - above)
+            }
+        }
+    };
+
+    let mut zeroed = 0;
+    for wt in w.iter_mut() {
+        if *wt == 0.0 {
+            continue;
+        }
+        let keep = if wt.abs() == threshold {
+            ties.checked_sub(1).map(|t| ties = t).is_some()
+        } else {
+            wt.abs() > threshold
+        };
+        if !keep {
+            *wt = 0.0;
+            zeroed += 1;
+        }
+    }
+    zeroed
+}
+
+fn probability(calibration: Option<Platt>, score: f32) -> f32 {
+    match calibration {
+        Some(platt) => platt.probability(score),
+        None => 1.0 / (1.0 + (-score).exp()),
+    }
+}
+
+#[cfg(feature = "parallel")]
+fn map_batch(xs: &[FeatureVec], f: impl Fn(&FeatureVec) -> f32 + Send + Sync) -> Vec<f32> {
+    use rayon::prelude::*;
+    xs.par_iter().map(f).collect()
+}
+
+#[cfg(not(feature = "parallel"))]
+fn map_batch(xs: &[FeatureVec], f: impl Fn(&FeatureVec) -> f32) -> Vec<f32> {
+    xs.iter().map(f).collect()
+}
+
+impl From<&Classifier> for ScoringModel {
+    fn from(model: &Classifier) -> ScoringModel {
+        model.scoring_model()
+    }
+}
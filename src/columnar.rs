@@ -0,0 +1,217 @@
+//! An Arrow IPC copy of the feature file, `<prefix>.arw`, built with the
+//! `arrow` feature. Reading a `.ftr` record means bincode decoding it field
+//! by field, which dominates a scoring pass; an Arrow record batch is read
+//! as whole buffers, and a document's features are then two slices of
+//! them. The copy can also be read directly by anything that reads Arrow.
+//!
+//! The file is in the Arrow IPC file format. Each record batch holds up to
+//! the batch size of documents in intid order, in four columns: `docid`
+//! (utf8), `ids` (list of u32), `values` (list of f32) and `norm` (the
+//! squared norm, f32). Optional record sections stay in the `.ftr` file,
+//! which remains the random-access copy that [`crate::DocInfo`] offsets
+//! point into.
+
+use crate::{FeaturePair, FeatureVec};
+use arrow_array::builder::{Float32Builder, ListBuilder, StringBuilder, UInt32Builder};
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Float32Type, UInt32Type};
+use arrow_array::{
+    Array, ArrayRef, Float32Array, ListArray, RecordBatch, StringArray, UInt32Array,
+};
+use arrow_ipc::reader::FileReader;
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{ArrowError, DataType, Field, Schema};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Result};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Documents per record batch unless the writer is told otherwise
+pub const DEFAULT_BATCH_SIZE: usize = 4096;
+
+fn schema() -> Schema {
+    let list = |item| DataType::List(Arc::new(Field::new("item", item, true)));
+    Schema::new(vec![
+        Field::new("docid", DataType::Utf8, false),
+        Field::new("ids", list(DataType::UInt32), false),
+        Field::new("values", list(DataType::Float32), false),
+        Field::new("norm", DataType::Float32, false),
+    ])
+}
+
+fn corrupt(e: ArrowError) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("Bad Arrow feature file: {}", e),
+    )
+}
+
+/// Builds one record batch at a time.
+struct BatchBuilder {
+    docids: StringBuilder,
+    ids: ListBuilder<UInt32Builder>,
+    values: ListBuilder<Float32Builder>,
+    norms: Float32Builder,
+    len: usize,
+}
+
+impl BatchBuilder {
+    fn new() -> BatchBuilder {
+        BatchBuilder {
+            docids: StringBuilder::new(),
+            ids: ListBuilder::new(UInt32Builder::new()),
+            values: ListBuilder::new(Float32Builder::new()),
+            norms: Float32Builder::new(),
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, fv: &FeatureVec) {
+        self.docids.append_value(&fv.docid);
+        for f in &fv.features {
+            self.ids.values().append_value(f.id);
+            self.values.values().append_value(f.value);
+        }
+        self.ids.append(true);
+        self.values.append(true);
+        self.norms.append_value(fv.squared_norm);
+        self.len += 1;
+    }
+
+    fn finish(&mut self, schema: &Arc<Schema>) -> Result<RecordBatch> {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(self.docids.finish()),
+            Arc::new(self.ids.finish()),
+            Arc::new(self.values.finish()),
+            Arc::new(self.norms.finish()),
+        ];
+        self.len = 0;
+        RecordBatch::try_new(schema.clone(), columns).map_err(corrupt)
+    }
+}
+
+/// Copy a `.ftr` feature file into an Arrow one. Returns the number of
+/// documents written.
+pub fn write_arrow(
+    features: impl AsRef<Path>,
+    arrow: impl AsRef<Path>,
+    batch_size: usize,
+    mut progress: impl FnMut(usize),
+) -> Result<u64> {
+    let schema = Arc::new(schema());
+    let mut feats = BufReader::new(File::open(features)?);
+    let out = BufWriter::new(File::create(arrow)?);
+    let mut writer = FileWriter::try_new(out, &schema).map_err(corrupt)?;
+    let mut batch = BatchBuilder::new();
+    let mut num_docs = 0;
+    while let Some(fv) =
+        FeatureVec::read_next(&mut feats).map_err(|e| Error::new(ErrorKind::InvalidData, e))?
+    {
+        batch.push(&fv);
+        if batch.len == batch_size.max(1) {
+            writer.write(&batch.finish(&schema)?).map_err(corrupt)?;
+        }
+        num_docs += 1;
+        progress(1);
+    }
+    if batch.len > 0 {
+        writer.write(&batch.finish(&schema)?).map_err(corrupt)?;
+    }
+    writer.finish().map_err(corrupt)?;
+    Ok(num_docs)
+}
+
+/// An open Arrow feature file, read a record batch at a time.
+pub struct ArrowFeatures {
+    reader: FileReader<BufReader<File>>,
+}
+
+impl ArrowFeatures {
+    pub fn open(filename: impl AsRef<Path>) -> Result<ArrowFeatures> {
+        let fp = BufReader::new(File::open(filename)?);
+        let reader = FileReader::try_new(fp, None).map_err(corrupt)?;
+        if reader.schema().as_ref() != &schema() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Arrow file does not hold feature vectors",
+            ));
+        }
+        Ok(ArrowFeatures { reader })
+    }
+
+    pub fn num_batches(&self) -> usize {
+        self.reader.num_batches()
+    }
+}
+
+/// A record batch of documents, read in place. A document's features are
+/// slices of the batch's buffers, and nothing is copied out of it unless
+/// asked for.
+pub struct ArrowBatch {
+    docids: StringArray,
+    ids: ListArray,
+    values: ListArray,
+    id_values: UInt32Array,
+    value_values: Float32Array,
+    norms: Float32Array,
+}
+
+impl ArrowBatch {
+    fn new(batch: &RecordBatch) -> ArrowBatch {
+        let ids = batch.column(1).as_list::<i32>().clone();
+        let values = batch.column(2).as_list::<i32>().clone();
+        ArrowBatch {
+            docids: batch.column(0).as_string::<i32>().clone(),
+            id_values: ids.values().as_primitive::<UInt32Type>().clone(),
+            value_values: values.values().as_primitive::<Float32Type>().clone(),
+            ids,
+            values,
+            norms: batch.column(3).as_primitive::<Float32Type>().clone(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.docids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn docid(&self, i: usize) -> &str {
+        self.docids.value(i)
+    }
+
+    /// The `i`th document's feature ids and values.
+    pub fn features(&self, i: usize) -> (&[u32], &[f32]) {
+        let (ids_at, values_at) = (self.ids.value_offsets(), self.values.value_offsets());
+        (
+            &self.id_values.values()[ids_at[i] as usize..ids_at[i + 1] as usize],
+            &self.value_values.values()[values_at[i] as usize..values_at[i + 1] as usize],
+        )
+    }
+
+    /// A copy of the `i`th document's vector.
+    pub fn feature_vec(&self, i: usize) -> FeatureVec {
+        let (ids, values) = self.features(i);
+        FeatureVec {
+            docid: self.docid(i).to_string(),
+            features: ids
+                .iter()
+                .zip(values)
+                .map(|(&id, &value)| FeaturePair { id, value })
+                .collect(),
+            squared_norm: self.norms.value(i),
+            sections: Vec::new(),
+        }
+    }
+}
+
+impl Iterator for ArrowFeatures {
+    type Item = Result<ArrowBatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let batch = self.reader.next()?;
+        Some(batch.map(|b| ArrowBatch::new(&b)).map_err(corrupt))
+    }
+}
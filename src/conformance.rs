@@ -0,0 +1,209 @@
+//! A conformance suite for [`Model`] implementations, built with the
+//! `test-support` feature. It builds a tiny collection in a scratch
+//! directory, trains a model on part of its planted relevant set, and
+//! checks that every scoring path agrees. Crates that embed mycal with
+//! their own learners can run the same checks against them.
+
+use crate::search::{rerank, search, SearchOptions};
+use crate::testdata::TestData;
+use crate::{tokens, CollectionLayout, Dict, DocInfo, DocsDb, FeatureVec, Model};
+use roaring::RoaringBitmap;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fs::{create_dir_all, remove_dir_all, File};
+use std::io::{BufRead, BufReader, BufWriter, Result, Seek, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A collection built from [`TestData`] in its own scratch directory,
+/// which is removed when the fixture is dropped.
+pub struct Fixture {
+    pub dir: PathBuf,
+    pub coll: CollectionLayout,
+    pub docs: DocsDb,
+    /// Intids of the planted relevant documents
+    pub relevant: RoaringBitmap,
+}
+
+impl Fixture {
+    /// Write `data` and index it the way `build_corpus` does, without the
+    /// dictionary pruning, which a collection this small can't afford.
+    pub fn build(data: &TestData) -> Result<Fixture> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_nanos());
+        let dir = std::env::temp_dir().join(format!("mycal-{}-{}", std::process::id(), nanos));
+        create_dir_all(&dir)?;
+        let coll = CollectionLayout::new(dir.join("coll"));
+        let relevant = data.write(dir.join("docs.jsonl"), dir.join("docs.qrels"))?;
+
+        let mut dict = Dict::new();
+        let mut counts = Vec::new();
+        for line in BufReader::new(File::open(dir.join("docs.jsonl"))?).lines() {
+            let doc: Map<String, Value> = serde_json::from_str(&line?)?;
+            let mut tfs: HashMap<u32, f32> = HashMap::new();
+            for tok in tokens(doc["passage"].as_str().unwrap_or_default()) {
+                let tokid = dict.add_tok(tok);
+                *tfs.entry(tokid).or_insert(0.0) += 1.0;
+            }
+            tfs.keys().for_each(|&tokid| dict.incr_df(tokid));
+            counts.push((doc["pid"].as_str().unwrap_or_default().to_string(), tfs));
+        }
+
+        let num_docs = counts.len() as f32;
+        for df in dict.df.values_mut() {
+            *df = (num_docs / *df).log10();
+        }
+        let docs = DocsDb::create(coll.docsdb());
+        let mut out = BufWriter::new(File::create(coll.features())?);
+        for (intid, (docid, tfs)) in counts.into_iter().enumerate() {
+            let mut fv = FeatureVec::new(docid.clone());
+            for (tokid, tf) in tfs {
+                fv.push(tokid, (1.0 + tf.log10()) * dict.df[&tokid]);
+            }
+            fv.compute_norm();
+            let offset = out.stream_position()?;
+            bincode::serialize_into(&mut out, &fv).expect("Error writing feature vector");
+            docs.insert(
+                &docid,
+                &DocInfo {
+                    intid: intid as u64,
+                    docid: docid.clone(),
+                    offset,
+                },
+            );
+        }
+        out.flush()?;
+        dict.save(coll.dict())?;
+
+        Ok(Fixture {
+            dir,
+            coll,
+            docs,
+            relevant,
+        })
+    }
+
+    /// Every document's feature vector, in intid order.
+    pub fn feature_vecs(&self) -> Result<Vec<FeatureVec>> {
+        let mut feats = BufReader::new(File::open(self.coll.features())?);
+        let mut fvs = Vec::new();
+        while let Some(fv) = FeatureVec::read_next(&mut feats)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?
+        {
+            fvs.push(fv);
+        }
+        Ok(fvs)
+    }
+
+    /// The dimensionality a new model for this collection needs.
+    pub fn dimensionality(&self) -> usize {
+        Dict::load(self.coll.dict()).map_or(0, |d| d.last_tokid as usize)
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = remove_dir_all(&self.dir);
+    }
+}
+
+/// Train `model` on half the fixture's relevant documents and four times
+/// as many nonrelevant ones, then check that
+///
+/// * the model, its [`crate::ScoringModel`], [`search`] and [`rerank`] all
+///   give each document the same score, and
+/// * at least half the held-out relevant documents rank within the top
+///   twice their number.
+///
+/// Panics on the first check that fails.
+pub fn check_model(model: &mut dyn Model, fixture: &Fixture) -> Result<()> {
+    let mut fvs: Vec<Option<FeatureVec>> = fixture.feature_vecs()?.into_iter().map(Some).collect();
+    let num_train = (fixture.relevant.len() as usize / 2).max(1);
+    let train_pos: Vec<u32> = fixture.relevant.iter().take(num_train).collect();
+    let train_neg: Vec<u32> = (0..fvs.len() as u32)
+        .filter(|i| !fixture.relevant.contains(*i))
+        .take(num_train * 4)
+        .collect();
+    let mut take = |ids: &[u32]| -> Vec<FeatureVec> {
+        ids.iter()
+            .map(|&i| fvs[i as usize].take().expect("Intid trained on twice"))
+            .collect()
+    };
+    let positives = take(&train_pos);
+    let negatives = take(&train_neg);
+    let rest: Vec<FeatureVec> = fvs.into_iter().flatten().collect();
+    model.train(&positives, &negatives);
+
+    // Every path scores every document the same
+    let scoring = model.scoring_model();
+    let direct = model.score_batch(&rest);
+    let batch = scoring.score_batch(&rest);
+    for ((fv, a), b) in rest.iter().zip(direct.iter()).zip(batch.iter()) {
+        assert!(
+            (a - b).abs() <= 1e-4 * a.abs().max(1.0),
+            "{}: model scores {} but its scoring model {}",
+            fv.docid,
+            a,
+            b
+        );
+    }
+
+    let mut opts = SearchOptions::new(rest.len());
+    opts.exclude_intids(&train_pos.iter().chain(train_neg.iter()).copied().collect());
+    let mut feats = BufReader::new(File::open(fixture.coll.features())?);
+    let hits = search(&scoring, &mut feats, &opts, |_| {})?;
+    assert_eq!(hits.len(), rest.len(), "search skipped documents");
+    let expected: HashMap<&str, f32> = rest
+        .iter()
+        .zip(direct.iter())
+        .map(|(fv, s)| (fv.docid.as_str(), *s))
+        .collect();
+    for hit in hits.iter() {
+        let want = expected[hit.docid.as_str()];
+        assert!(
+            (hit.score - want).abs() <= 1e-4 * want.abs().max(1.0),
+            "{}: search scores {} but the model {}",
+            hit.docid,
+            hit.score,
+            want
+        );
+    }
+    assert!(
+        hits.windows(2).all(|w| w[0].score >= w[1].score),
+        "search results are out of order"
+    );
+
+    let (reranked, missing) = rerank(
+        &scoring,
+        &fixture.docs,
+        &mut feats,
+        hits.iter().rev().map(|h| h.docid.as_str()),
+        None,
+    )?;
+    assert!(missing.is_empty(), "rerank could not find {:?}", missing);
+    for (a, b) in reranked.iter().zip(hits.iter()) {
+        assert_eq!(
+            a.score, b.score,
+            "rerank and search disagree at {}",
+            a.docid
+        );
+    }
+
+    // The planted documents are found
+    let held_out = fixture.relevant.len() as usize - num_train;
+    let depth = held_out.max(1) * 2;
+    let found = hits
+        .iter()
+        .take(depth)
+        .filter(|h| fixture.relevant.contains(h.intid))
+        .count();
+    assert!(
+        found * 2 >= held_out,
+        "only {} of {} held-out relevant documents in the top {}",
+        found,
+        held_out,
+        depth
+    );
+    Ok(())
+}
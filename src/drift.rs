@@ -0,0 +1,121 @@
+//! Alarms for a model that has lurched between rounds. A bad batch of
+//! judgments can teach the model something unintended, such as a new
+//! source's boilerplate, and the next batch then looks nothing like the
+//! last. Each batch a topic's model serves is compared with the one before
+//! it on two counts: how far the shape of the score distribution moved,
+//! and how many of the terms that put the previous batch on top (see
+//! [`crate::explain`]) still lead.
+//!
+//! Each batch's scores fall as the best documents are judged, and shift
+//! in scale whenever the model is retrained, so the scores are
+//! standardized before the two batches are compared by the two-sample
+//! Kolmogorov-Smirnov statistic. What is left is the shape: a batch of
+//! near-duplicates scored alike bunches up where the last batch was
+//! spread out.
+
+use crate::topic::Batch;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct DriftThresholds {
+    /// Largest Kolmogorov-Smirnov distance between rounds' standardized
+    /// scores
+    pub max_score_shift: f64,
+    /// Smallest share of the last batch's leading terms still leading
+    pub min_term_overlap: f64,
+}
+
+impl Default for DriftThresholds {
+    fn default() -> Self {
+        DriftThresholds {
+            max_score_shift: 0.5,
+            min_term_overlap: 0.3,
+        }
+    }
+}
+
+/// A threshold crossed between two rounds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DriftAlarm {
+    pub round: u32,
+    /// `score_shift` or `term_overlap`
+    pub kind: String,
+    pub value: f64,
+    pub threshold: f64,
+}
+
+impl fmt::Display for DriftAlarm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "round {} {} {:.3} is past {:.3}",
+            self.round, self.kind, self.value, self.threshold
+        )
+    }
+}
+
+/// The Kolmogorov-Smirnov statistic of two samples: the largest gap
+/// between their empirical distribution functions.
+pub fn ks_statistic(a: &[f32], b: &[f32]) -> f64 {
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    let mut a = a.to_vec();
+    let mut b = b.to_vec();
+    a.sort_by(f32::total_cmp);
+    b.sort_by(f32::total_cmp);
+    let (mut i, mut j, mut gap) = (0, 0, 0.0f64);
+    while i < a.len() && j < b.len() {
+        let x = a[i].min(b[j]);
+        while i < a.len() && a[i] <= x {
+            i += 1;
+        }
+        while j < b.len() && b[j] <= x {
+            j += 1;
+        }
+        gap = gap.max((i as f64 / a.len() as f64 - j as f64 / b.len() as f64).abs());
+    }
+    gap
+}
+
+/// Scores less their mean, over their standard deviation. Scores all
+/// alike are all zero.
+fn standardize(scores: &[f32]) -> Vec<f32> {
+    let n = scores.len() as f32;
+    let mean = scores.iter().sum::<f32>() / n;
+    let var = scores.iter().map(|s| (s - mean) * (s - mean)).sum::<f32>() / n;
+    let sd = var.sqrt().max(f32::MIN_POSITIVE);
+    scores.iter().map(|s| (s - mean) / sd).collect()
+}
+
+/// Compare a batch with the one served before it. Batches without scores
+/// or leading terms, such as those chosen by uncertainty, aren't compared
+/// on them.
+pub fn check(prev: &Batch, next: &Batch, thresholds: &DriftThresholds) -> Vec<DriftAlarm> {
+    let mut alarms = Vec::new();
+    let mut alarm = |kind: &str, value, threshold| {
+        alarms.push(DriftAlarm {
+            round: next.round,
+            kind: kind.to_string(),
+            value,
+            threshold,
+        })
+    };
+    if !prev.scores.is_empty() && !next.scores.is_empty() {
+        let shift = ks_statistic(&standardize(&prev.scores), &standardize(&next.scores));
+        if shift > thresholds.max_score_shift {
+            alarm("score_shift", shift, thresholds.max_score_shift);
+        }
+    }
+    if !prev.terms.is_empty() && !next.terms.is_empty() {
+        let leading: HashSet<u32> = next.terms.iter().copied().collect();
+        let kept = prev.terms.iter().filter(|t| leading.contains(t)).count();
+        let overlap = kept as f64 / prev.terms.len() as f64;
+        if overlap < thresholds.min_term_overlap {
+            alarm("term_overlap", overlap, thresholds.min_term_overlap);
+        }
+    }
+    alarms
+}
@@ -0,0 +1,140 @@
+use crate::search::SearchOptions;
+use crate::{DocInfo, FeatureVec, ScoringModel};
+use rand::seq::index;
+use rand::Rng;
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Result, Seek, SeekFrom};
+
+/// A scored sample of a collection, standing in for a full scoring pass
+/// while exploring hyperparameters. Each sampled document carries the
+/// number of eligible documents it represents, so stratified samples give
+/// unbiased estimates too.
+#[derive(Debug, Clone)]
+pub struct ScoreEstimate {
+    /// (score, weight, stratum index), highest score first
+    pub samples: Vec<(f32, f64, usize)>,
+    /// Stratum labels: the docid prefixes, or one empty label for a
+    /// uniform sample
+    pub strata: Vec<String>,
+    /// Eligible documents the sample was drawn from
+    pub population: u64,
+}
+
+impl ScoreEstimate {
+    /// The score at fraction `q` of the way up the estimated distribution.
+    pub fn quantile(&self, q: f64) -> Option<f32> {
+        let total: f64 = self.samples.iter().map(|s| s.1).sum();
+        let mut above = 0.0;
+        for (score, weight, _) in self.samples.iter() {
+            above += weight;
+            if above >= (1.0 - q) * total {
+                return Some(*score);
+            }
+        }
+        self.samples.last().map(|s| s.0)
+    }
+
+    /// The estimated score of the kth best document.
+    pub fn cutoff(&self, k: usize) -> Option<f32> {
+        let mut above = 0.0;
+        for (score, weight, _) in self.samples.iter() {
+            above += weight;
+            if above >= k as f64 {
+                return Some(*score);
+            }
+        }
+        self.samples.last().map(|s| s.0)
+    }
+
+    /// Estimated number of documents scoring at least `min`.
+    pub fn count_above(&self, min: f32) -> f64 {
+        self.samples
+            .iter()
+            .take_while(|s| s.0 >= min)
+            .map(|s| s.1)
+            .sum()
+    }
+
+    /// Estimated number of documents from each stratum scoring at least
+    /// `min`, in stratum order.
+    pub fn composition(&self, min: f32) -> Vec<(&str, f64)> {
+        let mut counts = vec![0.0; self.strata.len()];
+        for (_, weight, stratum) in self.samples.iter().take_while(|s| s.0 >= min) {
+            counts[*stratum] += weight;
+        }
+        self.strata.iter().map(String::as_str).zip(counts).collect()
+    }
+}
+
+/// Score a sample of about `n` documents not excluded by `opts`, either
+/// uniformly or, given docid prefixes, an equal share from each prefix.
+/// With prefixes, documents outside all of them are not represented.
+pub fn estimate_scores(
+    model: &ScoringModel,
+    docvec: &[DocInfo],
+    feats: &mut BufReader<File>,
+    opts: &SearchOptions,
+    n: usize,
+    prefixes: &[String],
+    rng: &mut impl Rng,
+) -> Result<ScoreEstimate> {
+    // The docvec is in docid order, so each prefix's documents are
+    // contiguous
+    let ranges: Vec<std::ops::Range<usize>> = if prefixes.is_empty() {
+        std::iter::once(0..docvec.len()).collect()
+    } else {
+        prefixes
+            .iter()
+            .map(|prefix| {
+                let lo = docvec.partition_point(|d| d.docid.as_str() < prefix.as_str());
+                let hi =
+                    lo + docvec[lo..].partition_point(|d| d.docid.starts_with(prefix.as_str()));
+                lo..hi
+            })
+            .collect()
+    };
+
+    let mut picked: Vec<(usize, f64, usize)> = Vec::new();
+    let mut population = 0;
+    for (stratum, range) in ranges.into_iter().enumerate() {
+        let eligible: Vec<usize> = range
+            .filter(|i| !opts.exclude.contains(docvec[*i].intid as u32))
+            .collect();
+        let share = n / prefixes.len().max(1) + usize::from(stratum < n % prefixes.len().max(1));
+        let share = share.min(eligible.len());
+        population += eligible.len() as u64;
+        if share == 0 {
+            continue;
+        }
+        let weight = eligible.len() as f64 / share as f64;
+        for k in index::sample(rng, eligible.len(), share) {
+            picked.push((eligible[k], weight, stratum));
+        }
+    }
+
+    // Read in file order
+    picked.sort_by_key(|(i, _, _)| docvec[*i].offset);
+    let mut batch = Vec::with_capacity(picked.len());
+    for (i, _, _) in picked.iter() {
+        feats.seek(SeekFrom::Start(docvec[*i].offset))?;
+        batch
+            .push(FeatureVec::read_from(feats).map_err(|e| Error::new(ErrorKind::InvalidData, e))?);
+    }
+    let mut samples: Vec<(f32, f64, usize)> = model
+        .score_batch(&batch)
+        .into_iter()
+        .zip(picked)
+        .map(|(score, (_, weight, stratum))| (score, weight, stratum))
+        .collect();
+    samples.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    Ok(ScoreEstimate {
+        samples,
+        strata: if prefixes.is_empty() {
+            vec![String::new()]
+        } else {
+            prefixes.to_vec()
+        },
+        population,
+    })
+}
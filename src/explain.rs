@@ -0,0 +1,93 @@
+//! What drove a batch of documents to the top of the ranking. Each
+//! document's score is split into its terms' contributions (weight times
+//! feature value), and the leading contributions are added up across the
+//! batch, so a review lead can see at a glance whether the batch is about
+//! the topic or the model has latched onto something else, such as a
+//! boilerplate footer or a custodian's signature.
+
+use crate::guardrails::Guardrails;
+use crate::{Dict, FeatureVec, ScoringModel};
+use std::collections::HashMap;
+use std::io::{Result, Write};
+
+/// Leading terms counted per document, enough to cover what most
+/// documents are scored on without their long tails drowning it out
+pub const TERMS_PER_DOC: usize = 10;
+
+/// One term's part in a batch.
+#[derive(Debug, Clone)]
+pub struct TermShare {
+    pub tokid: u32,
+    /// Sum of the term's contributions where it was among a document's
+    /// leading terms
+    pub total: f32,
+    /// Documents where it was among the leading terms
+    pub docs: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BatchSummary {
+    pub num_docs: usize,
+    /// Sum of every positive contribution in the batch, so each term's
+    /// total can be read as a share of what put the batch on top
+    pub positive_total: f32,
+    /// Largest total first
+    pub terms: Vec<TermShare>,
+}
+
+/// Summarize a batch, each document explained by the model that scored
+/// it, counting the `per_doc` largest positive contributions of each.
+/// Banned terms are left out, even if a model predating the ban has
+/// weight on them.
+pub fn summarize_batch(
+    batch: &[(&ScoringModel, &FeatureVec)],
+    per_doc: usize,
+    guardrails: Option<&Guardrails>,
+) -> BatchSummary {
+    let mut terms: HashMap<u32, TermShare> = HashMap::new();
+    let mut positive_total = 0.0;
+    for (model, fv) in batch {
+        let mut parts = model.contributions(fv);
+        if let Some(guardrails) = guardrails {
+            parts.retain(|(tokid, _)| !guardrails.is_banned(*tokid));
+        }
+        positive_total += parts.iter().map(|p| p.1.max(0.0)).sum::<f32>();
+        for (tokid, c) in parts.into_iter().take_while(|p| p.1 > 0.0).take(per_doc) {
+            let share = terms.entry(tokid).or_insert(TermShare {
+                tokid,
+                total: 0.0,
+                docs: 0,
+            });
+            share.total += c;
+            share.docs += 1;
+        }
+    }
+    let mut terms: Vec<TermShare> = terms.into_values().collect();
+    terms.sort_by(|a, b| b.total.total_cmp(&a.total));
+    BatchSummary {
+        num_docs: batch.len(),
+        positive_total,
+        terms,
+    }
+}
+
+impl BatchSummary {
+    /// Write the leading `num_terms` terms as tab-separated lines under a
+    /// header, naming them from `dict`.
+    pub fn write(&self, dict: &Dict, num_terms: usize, out: &mut impl Write) -> Result<()> {
+        let names = dict.tokens_by_id();
+        writeln!(out, "# batch of {} documents", self.num_docs)?;
+        writeln!(out, "term\tcontribution\tshare\tdocs")?;
+        for t in self.terms.iter().take(num_terms) {
+            writeln!(
+                out,
+                "{}\t{:.4}\t{:.4}\t{}",
+                names.get(&t.tokid).unwrap_or(&"?"),
+                t.total,
+                t.total / self.positive_total.max(f32::MIN_POSITIVE),
+                t.docs
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,309 @@
+//! Export a trained [`Classifier`] for use outside mycal, such as in a
+//! Python reranker. Both formats score a document the way
+//! [`crate::ScoringModel`] does: the inner product of its feature vector
+//! with the weights, plus the bias, with the model's scale folded into the
+//! weights.
+//!
+//! A model leaving the review team can first be privatized: rare terms,
+//! which can single out the documents they came from, are dropped, and the
+//! rest of the weights are clipped and perturbed with Laplace noise. This
+//! makes individual documents harder to read back out of the weights, but
+//! is not differentially private: nothing bounds how much one training
+//! document moves the weights, and the noise on each weight is drawn
+//! without accounting for the others.
+
+use crate::classifier::auc;
+use crate::{Classifier, Dict, FeatureVec, Model};
+use rand::Rng;
+use serde_json::json;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    /// A JSON object listing the nonzero weights, with their tokens if a
+    /// dictionary is given
+    Json,
+    /// An ONNX graph taking a dense batch of feature vectors, indexed by
+    /// token id, and giving each document's score and probability
+    Onnx,
+}
+
+impl FromStr for ExportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(ExportFormat::Json),
+            "onnx" => Ok(ExportFormat::Onnx),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown export format {}", s),
+            )),
+        }
+    }
+}
+
+/// How to privatize a model before export.
+#[derive(Debug, Clone, Copy)]
+pub struct Privacy {
+    /// Weights are clipped to `[-clip, clip]` before noise is added
+    pub clip: f32,
+    /// The noise level of each weight. Each weight, and the bias, gets its
+    /// own Laplace noise of scale `2 clip / epsilon`, the range of a
+    /// clipped weight over epsilon, so smaller values add more noise. It
+    /// is not a privacy budget for the model as a whole.
+    pub epsilon: f32,
+    /// Terms in fewer documents than this are dropped
+    pub min_df: f32,
+}
+
+/// What privatizing cost.
+#[derive(Debug, Clone, Default)]
+pub struct PrivacyReport {
+    /// Weights clipped to the bound
+    pub clipped: usize,
+    /// Nonzero weights dropped for being on rare terms
+    pub dropped: usize,
+    /// Cosine similarity of the private weights to the originals
+    pub cosine: f64,
+    /// AUC on judged documents before and after, if both relevant and
+    /// non-relevant ones were given
+    pub auc: Option<(f64, f64)>,
+}
+
+impl Classifier {
+    /// A privatized copy of the model, for export. Document frequencies
+    /// come from `dict`, holding the idf of a collection of `num_docs`
+    /// documents; without it no terms are dropped. Judged documents, as
+    /// (relevant, nonrelevant), measure the loss in ranking quality. The
+    /// copy has no calibration, since that was fit to the judgments too.
+    pub fn privatize(
+        &self,
+        privacy: &Privacy,
+        dict: Option<(&Dict, usize)>,
+        judged: Option<(&[FeatureVec], &[FeatureVec])>,
+        rng: &mut impl Rng,
+    ) -> (Classifier, PrivacyReport) {
+        let mut report = PrivacyReport::default();
+        let original: Vec<f32> = self.w.iter().map(|w| w * self.scale).collect();
+        let noise_scale = 2.0 * privacy.clip / privacy.epsilon;
+        let mut laplace = || {
+            // u on the open interval (-0.5, 0.5): at -0.5 the log is -inf
+            let mut u: f32 = rng.gen_range(-0.5..0.5);
+            while u == -0.5 {
+                u = rng.gen_range(-0.5..0.5);
+            }
+            -noise_scale * u.signum() * (1.0 - 2.0 * u.abs()).ln()
+        };
+
+        let mut w = Vec::with_capacity(original.len());
+        for wt in original.iter() {
+            report.clipped += usize::from(wt.abs() > privacy.clip);
+            w.push(wt.clamp(-privacy.clip, privacy.clip) + laplace());
+        }
+        if let Some((dict, num_docs)) = dict {
+            // The dictionary holds idf = log10(N/df)
+            for (id, wt) in w.iter_mut().enumerate() {
+                let df = dict
+                    .df
+                    .get(&(id as u32))
+                    .map_or(0.0, |idf| num_docs as f32 / 10f32.powf(*idf));
+                if df < privacy.min_df {
+                    report.dropped += usize::from(original[id] != 0.0);
+                    *wt = 0.0;
+                }
+            }
+        }
+
+        let dot: f64 = original
+            .iter()
+            .zip(w.iter())
+            .map(|(a, b)| (*a as f64) * (*b as f64))
+            .sum();
+        let norm = |v: &[f32]| v.iter().map(|x| (*x as f64).powi(2)).sum::<f64>().sqrt();
+        report.cosine = dot / (norm(&original) * norm(&w)).max(f64::MIN_POSITIVE);
+
+        let private = Classifier {
+            squared_norm: w.iter().map(|x| x * x).sum(),
+            w,
+            scale: 1.0,
+            bias: self.bias + laplace(),
+            calibration: None,
+            header: None,
+            example_weights: None,
+            checkpoints: None,
+            guardrails: None,
+            ..*self
+        };
+        if let Some((pos, neg)) = judged.filter(|(p, n)| !p.is_empty() && !n.is_empty()) {
+            let before = auc(&self.score_batch(pos), &self.score_batch(neg));
+            let after = auc(&private.score_batch(pos), &private.score_batch(neg));
+            report.auc = Some((before, after));
+        }
+        (private, report)
+    }
+
+    /// Write the model in `format`. `dict` names the weights in JSON
+    /// output; ONNX output doesn't use it.
+    pub fn export(
+        &self,
+        format: ExportFormat,
+        dict: Option<&Dict>,
+        out: &mut impl Write,
+    ) -> Result<()> {
+        let weights: Vec<f32> = self.w.iter().map(|w| w * self.scale).collect();
+        match format {
+            ExportFormat::Json => self.export_json(&weights, dict, out),
+            ExportFormat::Onnx => self.export_onnx(&weights, out),
+        }
+    }
+
+    fn export_json(
+        &self,
+        weights: &[f32],
+        dict: Option<&Dict>,
+        out: &mut impl Write,
+    ) -> Result<()> {
+        let names = dict.map(|d| d.tokens_by_id());
+        let terms: Vec<_> = weights
+            .iter()
+            .enumerate()
+            .filter(|(_, w)| **w != 0.0)
+            .map(|(i, w)| {
+                let token = names.as_ref().and_then(|n| n.get(&(i as u32)));
+                json!({ "id": i, "token": token, "weight": w })
+            })
+            .collect();
+        let model = json!({
+            "model": "linear",
+            "dimensionality": weights.len(),
+            "bias": self.bias,
+            "calibration": self.calibration,
+            "weights": terms,
+        });
+        serde_json::to_writer_pretty(&mut *out, &model)?;
+        writeln!(out)
+    }
+
+    /// A graph computing `score = x W + bias` and `probability =
+    /// sigmoid(a' score + b')`, where the logistic is the model's Platt
+    /// calibration if it has one.
+    fn export_onnx(&self, weights: &[f32], out: &mut impl Write) -> Result<()> {
+        let dims = weights.len() as i64;
+        // Platt's P = 1 / (1 + exp(a s + b)) is sigmoid(-a s - b)
+        let (a, b) = self.calibration.map_or((1.0, 0.0), |p| (-p.a, -p.b));
+
+        let mut graph = Vec::new();
+        for (op, inputs, output) in [
+            ("MatMul", ["x", "weights"], "product"),
+            ("Add", ["product", "bias"], "score"),
+            ("Mul", ["score", "platt_a"], "scaled"),
+            ("Add", ["scaled", "platt_b"], "logit"),
+        ] {
+            let mut node = Vec::new();
+            for input in inputs {
+                proto::string(&mut node, 1, input);
+            }
+            proto::string(&mut node, 2, output);
+            proto::string(&mut node, 4, op);
+            proto::message(&mut graph, 1, &node);
+        }
+        let mut sigmoid = Vec::new();
+        proto::string(&mut sigmoid, 1, "logit");
+        proto::string(&mut sigmoid, 2, "probability");
+        proto::string(&mut sigmoid, 4, "Sigmoid");
+        proto::message(&mut graph, 1, &sigmoid);
+
+        proto::string(&mut graph, 2, "mycal");
+        for (name, shape, values) in [
+            ("weights", vec![dims, 1], weights),
+            ("bias", vec![1], &[self.bias][..]),
+            ("platt_a", vec![1], &[a][..]),
+            ("platt_b", vec![1], &[b][..]),
+        ] {
+            proto::message(&mut graph, 5, &proto::tensor(name, &shape, values));
+        }
+        proto::message(&mut graph, 11, &proto::value_info("x", dims));
+        proto::message(&mut graph, 12, &proto::value_info("score", 1));
+        proto::message(&mut graph, 12, &proto::value_info("probability", 1));
+
+        let mut model = Vec::new();
+        proto::varint_field(&mut model, 1, 8); // IR version
+        proto::string(&mut model, 2, "mycal");
+        proto::string(&mut model, 3, env!("CARGO_PKG_VERSION"));
+        proto::message(&mut model, 7, &graph);
+        let mut opset = Vec::new();
+        proto::varint_field(&mut opset, 2, 13);
+        proto::message(&mut model, 8, &opset);
+        out.write_all(&model)
+    }
+}
+
+/// Just enough protobuf encoding to write an ONNX model. Field numbers are
+/// from onnx.proto.
+mod proto {
+    fn varint(out: &mut Vec<u8>, mut n: u64) {
+        while n >= 0x80 {
+            out.push((n as u8 & 0x7f) | 0x80);
+            n >>= 7;
+        }
+        out.push(n as u8);
+    }
+
+    fn key(out: &mut Vec<u8>, field: u32, wire_type: u8) {
+        varint(out, ((field as u64) << 3) | wire_type as u64);
+    }
+
+    pub fn varint_field(out: &mut Vec<u8>, field: u32, n: u64) {
+        key(out, field, 0);
+        varint(out, n);
+    }
+
+    pub fn bytes(out: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+        key(out, field, 2);
+        varint(out, bytes.len() as u64);
+        out.extend_from_slice(bytes);
+    }
+
+    pub fn string(out: &mut Vec<u8>, field: u32, s: &str) {
+        bytes(out, field, s.as_bytes());
+    }
+
+    pub fn message(out: &mut Vec<u8>, field: u32, msg: &[u8]) {
+        bytes(out, field, msg);
+    }
+
+    /// A float TensorProto, stored as little-endian raw data.
+    pub fn tensor(name: &str, shape: &[i64], values: &[f32]) -> Vec<u8> {
+        let mut t = Vec::new();
+        for dim in shape {
+            varint_field(&mut t, 1, *dim as u64);
+        }
+        varint_field(&mut t, 2, 1); // FLOAT
+        string(&mut t, 8, name);
+        let raw: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        bytes(&mut t, 9, &raw);
+        t
+    }
+
+    /// A float ValueInfoProto of shape `[N, columns]`, N being the batch.
+    pub fn value_info(name: &str, columns: i64) -> Vec<u8> {
+        let mut batch = Vec::new();
+        string(&mut batch, 2, "N");
+        let mut dim = Vec::new();
+        varint_field(&mut dim, 1, columns as u64);
+        let mut shape = Vec::new();
+        message(&mut shape, 1, &batch);
+        message(&mut shape, 1, &dim);
+        let mut tensor = Vec::new();
+        varint_field(&mut tensor, 1, 1); // FLOAT
+        message(&mut tensor, 2, &shape);
+        let mut type_proto = Vec::new();
+        message(&mut type_proto, 1, &tensor);
+        let mut info = Vec::new();
+        string(&mut info, 1, name);
+        message(&mut info, 2, &type_proto);
+        info
+    }
+}
@@ -0,0 +1,92 @@
+//! Terms a model must not learn from, such as privileged names or
+//! identifiers that look like personal data. Their weights are held at zero
+//! while training, a model that has weight on one is refused when it is
+//! saved, and they are left out of batch summaries.
+//!
+//! A guardrail file has one entry per line. A line `/REGEX/` bans every
+//! dictionary token the regex matches; any other line is tokenized like
+//! the collection and bans each of its tokens. Blank lines and lines
+//! starting with `#` are ignored. Tokens in a hashed long tail ban their
+//! whole bucket, which errs on the side of learning less.
+
+use crate::{tokens, Dict};
+use regex::Regex;
+use roaring::RoaringBitmap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Result};
+use std::path::Path;
+
+#[derive(Debug, Clone, Default)]
+pub struct Guardrails {
+    /// Banned token ids
+    pub banned: RoaringBitmap,
+}
+
+impl Guardrails {
+    pub fn load(filename: impl AsRef<Path>, dict: &Dict) -> Result<Guardrails> {
+        let fp = BufReader::new(File::open(filename)?);
+        let mut banned = RoaringBitmap::new();
+        for line in fp.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+            match line
+                .strip_prefix('/')
+                .and_then(|l| l.strip_suffix('/'))
+                .filter(|p| !p.is_empty())
+            {
+                Some(pattern) => {
+                    let re = Regex::new(pattern)
+                        .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+                    for (tok, id) in dict.m.iter() {
+                        if re.is_match(tok) {
+                            banned.insert(*id);
+                        }
+                    }
+                }
+                None => {
+                    for tok in tokens(line) {
+                        if let Some(id) = dict.lookup(&tok) {
+                            banned.insert(id);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(Guardrails { banned })
+    }
+
+    pub fn is_banned(&self, tokid: u32) -> bool {
+        self.banned.contains(tokid)
+    }
+
+    /// Zero the banned weights, returning how many were nonzero.
+    pub fn zero(&self, w: &mut [f32]) -> usize {
+        let mut zeroed = 0;
+        for id in self.banned.iter() {
+            if let Some(wt) = w.get_mut(id as usize) {
+                zeroed += usize::from(*wt != 0.0);
+                *wt = 0.0;
+            }
+        }
+        zeroed
+    }
+
+    /// Fail if any banned weight is nonzero.
+    pub fn check(&self, w: &[f32]) -> Result<()> {
+        let learned = self
+            .banned
+            .iter()
+            .filter(|id| w.get(*id as usize).is_some_and(|wt| *wt != 0.0))
+            .count();
+        if learned > 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Model has weight on {} banned terms", learned),
+            ));
+        }
+        Ok(())
+    }
+}
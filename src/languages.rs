@@ -0,0 +1,151 @@
+//! Per-language model blocks for multilingual collections. Hashed n-grams
+//! from several languages in one model dilute each other's signal, so a
+//! topic can train a block per language alongside its shared model, each
+//! on the judged documents of that language, and score every document
+//! with its own language's block. Documents with no language, or in a
+//! language without a block, are scored by the shared model.
+//!
+//! Languages are assigned at build time from a field of each document's
+//! JSON record (see `build_corpus --lang-field`), as set by whatever
+//! language identifier prepared the collection.
+
+use crate::modelset::Examples;
+use crate::search::{search_many, Hit, SearchOptions};
+use crate::{DocsDb, FeatureVec, ScoringModel};
+use roaring::RoaringBitmap;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+
+/// The intids of each language's documents.
+#[derive(Debug, Clone, Default)]
+pub struct Languages {
+    /// Documents in the collection, labeled or not
+    pub num_docs: u32,
+    pub intids: BTreeMap<String, RoaringBitmap>,
+}
+
+impl Languages {
+    /// Read a `.lng` file: the bincode of the document count and a list of
+    /// (language, portable roaring bitmap bytes) pairs.
+    pub fn load(filename: impl AsRef<Path>) -> Result<Languages> {
+        let fp = BufReader::new(File::open(filename)?);
+        let (num_docs, langs): (u32, Vec<(String, Vec<u8>)>) =
+            bincode::deserialize_from(fp).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        let mut intids = BTreeMap::new();
+        for (lang, bytes) in langs {
+            intids.insert(lang, RoaringBitmap::deserialize_from(&bytes[..])?);
+        }
+        Ok(Languages { num_docs, intids })
+    }
+
+    pub fn save(&self, filename: impl AsRef<Path>) -> Result<()> {
+        let mut langs = Vec::with_capacity(self.intids.len());
+        for (lang, intids) in self.intids.iter() {
+            let mut bytes = Vec::with_capacity(intids.serialized_size());
+            intids.serialize_into(&mut bytes)?;
+            langs.push((lang.clone(), bytes));
+        }
+        let mut fp = BufWriter::new(File::create(filename)?);
+        bincode::serialize_into(&mut fp, &(self.num_docs, langs))
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        fp.flush()
+    }
+
+    pub fn insert(&mut self, lang: &str, intid: u32) {
+        self.intids
+            .entry(lang.to_string())
+            .or_default()
+            .insert(intid);
+    }
+
+    pub fn language_of(&self, intid: u32) -> Option<&str> {
+        self.intids
+            .iter()
+            .find(|(_, ids)| ids.contains(intid))
+            .map(|(lang, _)| lang.as_str())
+    }
+
+    /// Every intid not in `keep`.
+    fn all_but(&self, keep: &RoaringBitmap) -> RoaringBitmap {
+        let mut others = RoaringBitmap::new();
+        others.insert_range(0..self.num_docs);
+        others -= keep;
+        others
+    }
+}
+
+/// The file holding language `lang`'s block of the model in `model_file`.
+pub fn block_file(model_file: &Path, lang: &str) -> PathBuf {
+    let mut name = model_file.as_os_str().to_os_string();
+    name.push(format!(".{}", lang));
+    PathBuf::from(name)
+}
+
+/// Split examples by the language of their documents. Documents without a
+/// language are left out.
+pub fn examples_by_language(
+    langs: &Languages,
+    docs: &DocsDb,
+    positives: &[FeatureVec],
+    negatives: &[FeatureVec],
+) -> BTreeMap<String, Examples> {
+    let mut examples: BTreeMap<String, Examples> = BTreeMap::new();
+    let labeled = positives
+        .iter()
+        .map(|fv| (true, fv))
+        .chain(negatives.iter().map(|fv| (false, fv)));
+    for (relevant, fv) in labeled {
+        let Some(lang) = docs
+            .get_intid(&fv.docid)
+            .and_then(|intid| langs.language_of(intid as u32))
+        else {
+            continue;
+        };
+        let (pos, neg) = examples.entry(lang.to_string()).or_default();
+        if relevant {
+            pos.push(fv.clone());
+        } else {
+            neg.push(fv.clone());
+        }
+    }
+    examples
+}
+
+/// Score the collection in one pass, each language's documents with its
+/// block from `blocks` and the rest with `shared`, and return the best
+/// `opts.num_results` overall.
+pub fn search_by_language(
+    shared: &ScoringModel,
+    blocks: &BTreeMap<String, ScoringModel>,
+    langs: &Languages,
+    feats: &mut BufReader<File>,
+    opts: &SearchOptions,
+    progress: impl FnMut(usize),
+) -> Result<Vec<Hit>> {
+    let mut shared_opts = opts.clone();
+    let mut block_opts = Vec::with_capacity(blocks.len());
+    for (lang, model) in blocks.iter() {
+        let Some(intids) = langs.intids.get(lang) else {
+            continue;
+        };
+        let mut o = opts.clone();
+        o.exclude_intids(&langs.all_but(intids));
+        shared_opts.exclude_intids(intids);
+        block_opts.push((model, o));
+    }
+    let mut searches: Vec<(&ScoringModel, &SearchOptions)> =
+        block_opts.iter().map(|(m, o)| (*m, o)).collect();
+    searches.push((shared, &shared_opts));
+
+    // Each document is scored by exactly one search, so the merged hits
+    // are distinct
+    let mut hits: Vec<Hit> = search_many(&searches, feats, progress)?
+        .into_iter()
+        .flatten()
+        .collect();
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+    hits.truncate(opts.num_results);
+    Ok(hits)
+}
@@ -1,39 +1,373 @@
-use bincode::Result;
-use porter_stemmer::stem;
-use rand::seq::SliceRandom;
-use rand::thread_rng;
+//! On-disk formats
+//!
+//! Every serialized structure is written with bincode 1.x default settings:
+//! little-endian, fixed-width integers, and strings/sequences prefixed with a
+//! u64 length. Serialized fields use explicit `u64`/`u32`/`f32` types rather
+//! than `usize`, so files are identical between 32- and 64-bit builds.
+//!
+//! * `<prefix>.ftr`: concatenated [`FeatureVec`] records: docid, a u64
+//!   feature count, then `(id: u32, value: f32)` pairs, then the norm as f32.
+//!   A record with optional [`Section`]s is instead the u64
+//!   [`FEATURE_VEC_MAGIC`], a u16 version, the record as above, and each
+//!   section as a u16 kind, a u32 length and its bytes, ending with kind 0.
+//!   Records are written in intid order, so the nth record is intid n.
+//! * `<prefix>.fch`: optionally, the same vectors in independently
+//!   decodable chunks with compressed features; see [`chunks`].
+//! * `<prefix>.arw`: optionally, the same vectors as Arrow IPC record
+//!   batches, with the `arrow` feature; see `columnar`.
+//! * `<prefix>.lib`: sled database mapping docid to [`DocInfo`]
+//!   (`intid: u64`, `docid`, `offset: u64` into the feature file).
+//! * `<prefix>.dvc`: a `Vec<DocInfo>` in database order.
+//! * `<prefix>.dct`: the [`Dict`], token to u32 id plus per-token idf,
+//!   optionally the [`HashedTail`] that long-tail tokens are hashed into,
+//!   and the tokens dropped as too common.
+//! * model files: a serialized [`Classifier`]. Fields have been appended
+//!   over time, and files written by earlier versions are still read. A [`NaiveBayes`] model
+//!   file is the bytes `MYNB` followed by the serialized model, and a
+//!   [`Rocchio`] model file the same after `MYRO`. A classifier with
+//!   [`quantize`]d weights starts with `MYQW` and the quantized weights,
+//!   followed by the classifier with no weights. Any of these may
+//!   be preceded by a [`ModelHeader`]: the bytes `MYMH`, a u32 format
+//!   version, and the serialized header.
+//! * intid files: the bytes `MYID`, the u32 generation of the numbering
+//!   the intids are in, and a roaring bitmap in the portable roaring
+//!   serialization, as written by [`write_intids`]. Files written before
+//!   generations existed are only the bitmap, and read as generation 0.
+//! * `<prefix>.fpr`: the collection's [`fingerprint`] as hex text, written
+//!   by `build_corpus`.
+//! * `<prefix>.exc`: an intid file of documents routed out of review at
+//!   build time (see [`routing`]); absent if no rules were given.
+//! * `<prefix>.lng`: each document language's intids, for per-language
+//!   model blocks (see [`languages`]); absent unless built with a
+//!   language field.
+//! * `<prefix>.dts`: the bincode of each document's date in days since
+//!   the epoch, by intid, for a [`recency`] prior; absent unless built
+//!   with a date field.
+//! * `<prefix>.del`: an intid file of deleted documents, until the
+//!   collection is compacted (see [`tombstones`]); absent if none are.
+//! * `<prefix>.gen`: the collection's [`read_generation`] as decimal
+//!   text; absent while it is 0.
+//! * `<prefix>.cmp`: present only while a compaction swaps its rewritten
+//!   files into place; see [`tombstones`].
+//! * topic directories: `topic.json` (a JSON [`topic::TopicConfig`]), the
+//!   judgment log `judgments.qrels`, the topic's `model`, and optionally
+//!   its [`guardrails`] file `guardrails.txt`.
+//!
+//! Collections built before token ids became u32 store them as u64 in the
+//! feature and dictionary files; `upgrade-collection` rewrites them in place,
+//! writing the journal `<prefix>.upg` while it swaps the new files in.
+
+pub mod analytics;
+pub mod calibration;
+pub mod cancel;
+pub mod chunks;
+pub mod ciff;
+pub mod classifier;
+#[cfg(feature = "arrow")]
+pub mod columnar;
+#[cfg(feature = "test-support")]
+pub mod conformance;
+pub mod drift;
+pub mod estimate;
+pub mod explain;
+pub mod export;
+pub mod guardrails;
+pub mod languages;
+pub mod merge;
+pub mod modelset;
+pub mod negatives;
+pub mod plan;
+pub mod policy;
+pub mod qrels;
+pub mod quantize;
+pub mod recency;
+pub mod routing;
+pub mod runs;
+pub mod search;
+pub mod selection;
+pub mod stopping;
+pub mod termstats;
+pub mod testdata;
+pub mod tombstones;
+pub mod topic;
+
+pub use classifier::{
+    load_model, Checkpoint, Checkpoints, ClassWeights, Classifier, EtaSchedule, ExampleWeights,
+    GradeWeights, Model, ModelHeader, NaiveBayes, Prune, Rocchio, ScoringModel, TrainConfig,
+    TrainReport, Validation,
+};
+
+use bincode::{Options, Result};
+use porter_stemmer::stem_tokenized;
+use roaring::RoaringBitmap;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Write};
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use unicode_segmentation::UnicodeSegmentation;
 
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
 pub struct DocInfo {
-    pub intid: usize,
+    pub intid: u64,
     pub docid: String,
     pub offset: u64,
 }
 
+impl DocInfo {
+    pub fn decode(bytes: &[u8]) -> Result<DocInfo> {
+        decode_bounded(bytes)
+    }
+}
+
+/// Deserialize a record from an untrusted byte slice without panicking.
+/// Uses the same layout as `bincode::serialize`, but caps allocations at
+/// the size of the input so a corrupt length prefix yields an error
+/// instead of an out-of-memory abort.
+pub fn decode_bounded<'a, T: Deserialize<'a>>(bytes: &'a [u8]) -> Result<T> {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(bytes.len() as u64)
+        .deserialize(bytes)
+}
+
+/// Read `len` bytes, without trusting `len` enough to allocate it up front.
+fn read_bytes(r: &mut impl Read, len: u64) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    r.take(len).read_to_end(&mut bytes)?;
+    if (bytes.len() as u64) < len {
+        return Err(Box::new(bincode::ErrorKind::Io(std::io::Error::from(
+            std::io::ErrorKind::UnexpectedEof,
+        ))));
+    }
+    Ok(bytes)
+}
+
+/// Owns the naming of every file that makes up a collection.
+///
+/// A collection is named by a prefix path such as `data/msmarco`, and each
+/// structure sits next to it with its own extension (`data/msmarco.ftr`,
+/// `data/msmarco.lib`, ...). If the prefix names an existing directory, the
+/// files are placed inside it under the name `collection` instead.
+/// Temporary files go next to the collection too, unless a temporary
+/// directory is given.
+#[derive(Debug, Clone)]
+pub struct CollectionLayout {
+    prefix: PathBuf,
+    temp_dir: Option<PathBuf>,
+}
+
+impl CollectionLayout {
+    pub fn new(prefix: impl AsRef<Path>) -> CollectionLayout {
+        let prefix = prefix.as_ref();
+        let prefix = if prefix.is_dir() {
+            prefix.join("collection")
+        } else {
+            prefix.to_path_buf()
+        };
+        CollectionLayout {
+            prefix,
+            temp_dir: None,
+        }
+    }
+    /// Put temporary files in `dir`, such as a scratch disk with more room.
+    pub fn with_temp_dir(mut self, dir: impl AsRef<Path>) -> CollectionLayout {
+        self.temp_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+    pub fn prefix(&self) -> &Path {
+        &self.prefix
+    }
+    /// The directory holding the collection's files.
+    pub fn dir(&self) -> PathBuf {
+        match self.prefix.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+            _ => PathBuf::from("."),
+        }
+    }
+    /// Where temporary files go.
+    pub fn temp_dir(&self) -> PathBuf {
+        self.temp_dir.clone().unwrap_or_else(|| self.dir())
+    }
+    fn with_extension(&self, ext: &str) -> PathBuf {
+        // Path::with_extension would clobber a dot already in the prefix
+        let mut name = self.prefix.clone().into_os_string();
+        name.push(".");
+        name.push(ext);
+        PathBuf::from(name)
+    }
+    pub fn docsdb(&self) -> PathBuf {
+        self.with_extension("lib")
+    }
+    pub fn kv_docsdb(&self) -> PathBuf {
+        self.with_extension("lb2")
+    }
+    pub fn dict(&self) -> PathBuf {
+        self.with_extension("dct")
+    }
+    pub fn features(&self) -> PathBuf {
+        self.with_extension("ftr")
+    }
+    pub fn chunked_features(&self) -> PathBuf {
+        self.with_extension("fch")
+    }
+    pub fn arrow_features(&self) -> PathBuf {
+        self.with_extension("arw")
+    }
+    /// In a shared temporary directory the name carries a hash of the
+    /// prefix, so that collections with the same name don't collide.
+    pub fn temp_features(&self) -> PathBuf {
+        match &self.temp_dir {
+            Some(dir) => {
+                let name = self.prefix.file_name().unwrap_or_default();
+                let hash =
+                    runs::fnv1a(runs::FNV_OFFSET, self.prefix.as_os_str().as_encoded_bytes());
+                dir.join(format!(
+                    "{}-{:08x}.tmp",
+                    name.to_string_lossy(),
+                    hash as u32
+                ))
+            }
+            None => self.with_extension("tmp"),
+        }
+    }
+    pub fn docvec(&self) -> PathBuf {
+        self.with_extension("dvc")
+    }
+    pub fn splits(&self) -> PathBuf {
+        self.with_extension("cut")
+    }
+    pub fn excluded(&self) -> PathBuf {
+        self.with_extension("exc")
+    }
+    pub fn fingerprint(&self) -> PathBuf {
+        self.with_extension("fpr")
+    }
+    pub fn languages(&self) -> PathBuf {
+        self.with_extension("lng")
+    }
+    pub fn dates(&self) -> PathBuf {
+        self.with_extension("dts")
+    }
+    pub fn deleted(&self) -> PathBuf {
+        self.with_extension("del")
+    }
+    pub fn compacting(&self) -> PathBuf {
+        self.with_extension("cmp")
+    }
+    pub fn generation(&self) -> PathBuf {
+        self.with_extension("gen")
+    }
+    pub fn upgrading(&self) -> PathBuf {
+        self.with_extension("upg")
+    }
+}
+
+/// Fail unless the file system holding `dir` has `needed` bytes free.
+/// `what` says what the space is for, in the error.
+pub fn ensure_space(dir: &Path, needed: u64, what: &str) -> std::io::Result<()> {
+    let available = fs2::available_space(dir)?;
+    if available < needed {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::StorageFull,
+            format!(
+                "{} needs about {} MB in {} but only {} MB are free",
+                what,
+                needed >> 20,
+                dir.display(),
+                available >> 20
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Identifies a collection's contents: a 64-bit FNV-1a hash, in hex, of
+/// the settings it was built with, its tokens in id order, and its docids
+/// in intid order. Models and runs record it, so that a model isn't
+/// silently applied to a collection it wasn't trained on.
+pub fn fingerprint<'a>(
+    settings: &str,
+    dict: &Dict,
+    docids: impl Iterator<Item = &'a str>,
+) -> String {
+    let mut hash = runs::fnv1a(runs::FNV_OFFSET, settings.as_bytes());
+    let mut tokens: Vec<(u32, &str)> = dict.tokens_by_id().into_iter().collect();
+    tokens.sort_unstable();
+    if let Some(tail) = &dict.hashed {
+        hash = runs::fnv1a(
+            hash,
+            format!("\0{}:{}", tail.first_id, tail.buckets).as_bytes(),
+        );
+    }
+    // A separator byte keeps ["ab", "c"] and ["a", "bc"] apart
+    for (_, tok) in tokens {
+        hash = runs::fnv1a(hash, &[0]);
+        hash = runs::fnv1a(hash, tok.as_bytes());
+    }
+    for docid in docids {
+        hash = runs::fnv1a(hash, &[0]);
+        hash = runs::fnv1a(hash, docid.as_bytes());
+    }
+    format!("{:016x}", hash)
+}
+
+/// The fingerprint stored with a collection, if it has one. Collections
+/// built before fingerprints existed don't, until `mycal fingerprint`
+/// writes one.
+pub fn read_fingerprint(coll: &CollectionLayout) -> Option<String> {
+    std::fs::read_to_string(coll.fingerprint())
+        .ok()
+        .map(|s| s.trim().to_string())
+}
+
+/// Which numbering of the collection's documents its intids are in.
+/// Intids are only stable within a generation. A built collection starts
+/// at generation 0, [`tombstones::compact`] moves it to the next, and a
+/// collection [`merge`]d from shards starts past all of theirs, so an
+/// intid file written against another numbering is refused rather than
+/// read as other documents. Docids never change, and runs and judgment
+/// logs keep them, so an intid file can always be written again from its
+/// qrels.
+pub fn read_generation(coll: &CollectionLayout) -> std::io::Result<u32> {
+    match std::fs::read_to_string(coll.generation()) {
+        Ok(s) => s.trim().parse().map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} is not a generation", coll.generation().display()),
+            )
+        }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn write_generation(generation: u32, filename: impl AsRef<Path>) -> std::io::Result<()> {
+    std::fs::write(filename, format!("{}\n", generation))
+}
+
 pub struct DocsDb {
-    pub filename: String,
+    pub filename: PathBuf,
     pub db: sled::Db,
-    pub next_intid: usize,
+    pub next_intid: u64,
 
     batch: sled::Batch,
     batch_len: usize,
 }
 
 impl DocsDb {
-    pub fn open(filename: &str) -> DocsDb {
+    pub fn open(filename: impl AsRef<Path>) -> DocsDb {
         let conf = sled::Config::default()
-            .path(filename.to_owned())
+            .path(filename.as_ref())
             .cache_capacity(10_000_000)
             .use_compression(false)
             .mode(sled::Mode::LowSpace);
         let db = conf.open().unwrap();
 
         DocsDb {
-            filename: filename.to_string(),
+            filename: filename.as_ref().to_path_buf(),
             db,
             next_intid: 0,
             batch: sled::Batch::default(),
@@ -41,16 +375,16 @@ impl DocsDb {
         }
     }
 
-    pub fn create(filename: &str) -> DocsDb {
+    pub fn create(filename: impl AsRef<Path>) -> DocsDb {
         let conf = sled::Config::default()
-            .path(filename.to_owned())
+            .path(filename.as_ref())
             .cache_capacity(10_000_000)
             .use_compression(false)
             .mode(sled::Mode::HighThroughput);
         let db = conf.open().unwrap();
 
         DocsDb {
-            filename: filename.to_string(),
+            filename: filename.as_ref().to_path_buf(),
             db,
             next_intid: 0,
             batch: sled::Batch::default(),
@@ -98,16 +432,16 @@ impl DocsDb {
     pub fn insert_iter(
         &mut self,
         library: &Docs,
-        stuff: impl Iterator<Item = (String, usize)>,
+        stuff: impl Iterator<Item = (String, u64)>,
     ) -> Result<()> {
         stuff.for_each(|(docid, intid)| {
-            let di = library.docs.get(intid).unwrap();
+            let di = library.docs.get(intid as usize).unwrap();
             self.insert_batch(&docid, &di, 100_000);
         });
         Ok(())
     }
 
-    pub fn get_intid(&self, docid: &str) -> Option<usize> {
+    pub fn get_intid(&self, docid: &str) -> Option<u64> {
         let tmp_docid = docid.to_string();
         let docinfo = self.db.get(tmp_docid).unwrap();
         match docinfo {
@@ -119,7 +453,28 @@ impl DocsDb {
         }
     }
 
-    pub fn add_doc(&mut self, docid: &str) -> Option<usize> {
+    /// Resolve docids to a bitmap of intids, skipping any not in the collection.
+    pub fn intids_for<'a>(&self, docids: impl Iterator<Item = &'a str>) -> RoaringBitmap {
+        docids
+            .filter_map(|docid| self.get_intid(docid))
+            .map(|intid| intid as u32)
+            .collect()
+    }
+
+    /// Up to `limit` docids starting with `prefix`, in byte order. sled keeps
+    /// keys sorted, so this walks only the matching range and is cheap
+    /// enough for autocomplete on large collections.
+    pub fn list_docids(&self, prefix: &str, limit: usize) -> Vec<String> {
+        self.db
+            .scan_prefix(prefix)
+            .keys()
+            .take(limit)
+            .filter_map(|key| key.ok())
+            .map(|key| String::from_utf8_lossy(&key).into_owned())
+            .collect()
+    }
+
+    pub fn add_doc(&mut self, docid: &str) -> Option<u64> {
         let tmp_docid = docid.to_string();
         match self.db.get(&tmp_docid) {
             Ok(di) => match di {
@@ -147,9 +502,54 @@ impl DocsDb {
     }
 }
 
+/// Starts an intid file, ahead of the generation its intids are in. A
+/// portable roaring bitmap starts with a different cookie, so older
+/// files without one are told apart.
+const INTIDS_MAGIC: &[u8; 4] = b"MYID";
+
+/// Read an intid file, failing unless its intids are in the numbering of
+/// the given [`read_generation`].
+pub fn read_intids(filename: impl AsRef<Path>, generation: u32) -> std::io::Result<RoaringBitmap> {
+    let filename = filename.as_ref();
+    let mut infp = BufReader::new(File::open(filename)?);
+    let written = if infp.fill_buf()?.starts_with(INTIDS_MAGIC) {
+        infp.consume(INTIDS_MAGIC.len());
+        let mut bytes = [0; 4];
+        infp.read_exact(&mut bytes)?;
+        u32::from_le_bytes(bytes)
+    } else {
+        0
+    };
+    if written != generation {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "{} lists intids from generation {} of the collection, which has since \
+                 renumbered its documents and is at generation {}; write it again",
+                filename.display(),
+                written,
+                generation
+            ),
+        ));
+    }
+    RoaringBitmap::deserialize_from(infp)
+}
+
+pub fn write_intids(
+    intids: &RoaringBitmap,
+    generation: u32,
+    filename: impl AsRef<Path>,
+) -> std::io::Result<()> {
+    let mut outfp = BufWriter::new(File::create(filename)?);
+    outfp.write_all(INTIDS_MAGIC)?;
+    outfp.write_all(&generation.to_le_bytes())?;
+    intids.serialize_into(&mut outfp)?;
+    outfp.flush()
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Docs {
-    pub m: HashMap<String, usize>,
+    pub m: HashMap<String, u64>,
     pub docs: Vec<DocInfo>,
 }
 
@@ -160,18 +560,18 @@ impl Docs {
             docs: Vec::new(),
         }
     }
-    pub fn load(filename: &str) -> Result<Docs> {
+    pub fn load(filename: impl AsRef<Path>) -> Result<Docs> {
         let mut infp = BufReader::new(File::open(filename)?);
         bincode::deserialize_from::<&mut BufReader<File>, Docs>(&mut infp)
     }
-    pub fn get_intid(&self, docid: &str) -> Option<&usize> {
+    pub fn get_intid(&self, docid: &str) -> Option<&u64> {
         self.m.get(docid)
     }
-    pub fn add_doc(&mut self, docid: &str) -> usize {
+    pub fn add_doc(&mut self, docid: &str) -> u64 {
         if self.m.contains_key(docid) {
             self.m.get(docid).unwrap().to_owned()
         } else {
-            let intid = self.docs.len();
+            let intid = self.docs.len() as u64;
             self.m.insert(docid.to_string(), intid);
             self.docs.push(DocInfo {
                 docid: docid.to_string(),
@@ -181,7 +581,7 @@ impl Docs {
             intid
         }
     }
-    pub fn save(&self, filename: &str) -> std::io::Result<()> {
+    pub fn save(&self, filename: impl AsRef<Path>) -> std::io::Result<()> {
         let mut outfp = BufWriter::new(File::create(filename)?);
         bincode::serialize_into(&mut outfp, self).expect("Error writing dictionary");
         outfp.flush()?;
@@ -189,11 +589,39 @@ impl Docs {
     }
 }
 
+/// The hashed region of a two-level vocabulary. Tokens without an exact
+/// id share `buckets` ids starting at `first_id`, picked by a 32-bit FNV-1a
+/// hash of the token, which is stable across builds and platforms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HashedTail {
+    pub first_id: u32,
+    pub buckets: u32,
+}
+
+impl HashedTail {
+    pub fn tokid(&self, tok: &str) -> u32 {
+        let mut hash: u32 = 0x811c9dc5;
+        for b in tok.bytes() {
+            hash ^= b as u32;
+            hash = hash.wrapping_mul(0x01000193);
+        }
+        self.first_id + hash % self.buckets
+    }
+    pub fn contains(&self, tokid: u32) -> bool {
+        tokid >= self.first_id && tokid - self.first_id < self.buckets
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Dict {
-    pub m: HashMap<String, usize>,
-    pub df: HashMap<usize, f32>,
-    pub last_tokid: usize,
+    pub m: HashMap<String, u32>,
+    pub df: HashMap<u32, f32>,
+    pub last_tokid: u32,
+    /// Where tokens without an exact id in `m` are hashed, if anywhere
+    pub hashed: Option<HashedTail>,
+    /// Tokens dropped for being in too many documents (`--max-df`), which
+    /// have no id, exact or hashed
+    pub stopped: HashSet<String>,
 }
 
 impl Dict {
@@ -202,31 +630,70 @@ impl Dict {
             m: HashMap::new(),
             df: HashMap::new(),
             last_tokid: 0,
+            hashed: None,
+            stopped: HashSet::new(),
         }
     }
-    pub fn load(filename: &str) -> Result<Dict> {
-        let mut infp = BufReader::new(File::open(filename)?);
-        bincode::deserialize_from::<&mut BufReader<File>, Dict>(&mut infp)
+    pub fn load(filename: impl AsRef<Path>) -> Result<Dict> {
+        // Dictionaries written before the hashed tail or the stopped
+        // tokens existed end early
+        let bytes = std::fs::read(filename)?;
+        let mut rest: &[u8] = &bytes;
+        let mut dict = Dict::new();
+        dict.m = bincode::deserialize_from(&mut rest)?;
+        dict.df = bincode::deserialize_from(&mut rest)?;
+        dict.last_tokid = bincode::deserialize_from(&mut rest)?;
+        if !rest.is_empty() {
+            dict.hashed = bincode::deserialize_from(&mut rest)?;
+        }
+        if !rest.is_empty() {
+            dict.stopped = bincode::deserialize_from(&mut rest)?;
+        }
+        Ok(dict)
+    }
+    /// The id a token is indexed under: its exact id, or else its bucket in
+    /// the hashed tail. A stopped token has neither.
+    pub fn lookup(&self, tok: &str) -> Option<u32> {
+        if let Some(tokid) = self.m.get(tok) {
+            return Some(*tokid);
+        }
+        if self.stopped.contains(tok) {
+            return None;
+        }
+        self.hashed.map(|h| h.tokid(tok))
     }
     pub fn has_tok(&self, tok: String) -> bool {
         self.m.contains_key(&tok)
     }
-    pub fn get_tokid(&self, tok: String) -> Option<&usize> {
+    pub fn get_tokid(&self, tok: String) -> Option<&u32> {
         self.m.get(&tok)
     }
-    pub fn add_tok(&mut self, tok: String) -> usize {
-        if self.m.contains_key(&tok) {
-            self.m.get(&tok).unwrap().to_owned()
+    /// Borrowed tokens are only copied when they are new to the dictionary.
+    pub fn add_tok(&mut self, tok: impl AsRef<str> + Into<String>) -> u32 {
+        if let Some(tokid) = self.m.get(tok.as_ref()) {
+            *tokid
         } else {
             self.last_tokid += 1;
-            self.m.insert(tok, self.last_tokid);
+            self.m.insert(tok.into(), self.last_tokid);
             self.last_tokid
         }
     }
-    pub fn incr_df(&mut self, tokid: usize) {
+    /// Map token ids back to token strings.
+    pub fn tokens_by_id(&self) -> HashMap<u32, &str> {
+        self.m.iter().map(|(tok, id)| (*id, tok.as_str())).collect()
+    }
+    /// The term frequency behind a stored `(1 + log tf) * idf` weight. A
+    /// term with zero idf, found in every document, counts once.
+    pub fn term_freq(&self, tokid: u32, value: f32) -> f32 {
+        match self.df.get(&tokid) {
+            Some(&idf) if idf > 0.0 => 10f32.powf(value / idf - 1.0).round().max(1.0),
+            _ => 1.0,
+        }
+    }
+    pub fn incr_df(&mut self, tokid: u32) {
         *self.df.entry(tokid).or_insert(0.0) += 1.0;
     }
-    pub fn save(&self, filename: &str) -> std::io::Result<()> {
+    pub fn save(&self, filename: impl AsRef<Path>) -> std::io::Result<()> {
         let mut outfp = BufWriter::new(File::create(filename)?);
         bincode::serialize_into(&mut outfp, self).expect("Error writing dictionary");
         outfp.flush()?;
@@ -234,17 +701,54 @@ impl Dict {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FeaturePair {
-    pub id: usize,
+    pub id: u32,
     pub value: f32,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Starts a feature vector record with sections. A record without starts
+/// with the length of its docid, which is never this large.
+pub const FEATURE_VEC_MAGIC: u64 = 0xffff_ffff_4d59_4656;
+/// The version written after [`FEATURE_VEC_MAGIC`]. Readers read any
+/// version, skipping the sections they don't know.
+pub const FEATURE_VEC_VERSION: u16 = 2;
+
+/// An optional part of a feature vector record, beyond what every reader
+/// needs. Sections of kinds a reader doesn't know are kept as they are, so
+/// that adding one doesn't mean rebuilding every collection. Copies of a
+/// vector made through serde, such as chunked features, don't carry them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub kind: u16,
+    pub bytes: Vec<u8>,
+}
+
+impl Section {
+    /// Term positions
+    pub const POSITIONS: u16 = 1;
+    /// The field each feature came from
+    pub const FIELDS: u16 = 2;
+    /// Quantized feature values
+    pub const QUANTIZED: u16 = 3;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FeatureVec {
     pub docid: String,
     pub features: Vec<FeaturePair>,
     pub squared_norm: f32,
+    /// Written and read by [`FeatureVec::write_into`] and
+    /// [`FeatureVec::read_from`] only
+    #[serde(skip)]
+    pub sections: Vec<Section>,
+}
+
+/// The options bincode's free functions use
+fn bincode_options() -> impl Options + Copy {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
 }
 
 impl FeatureVec {
@@ -253,25 +757,88 @@ impl FeatureVec {
             docid,
             features: Vec::new(),
             squared_norm: 0.0,
+            sections: Vec::new(),
         }
     }
+    /// Panic-free decode of a single serialized FeatureVec, suitable as a
+    /// fuzz target entry point.
+    pub fn decode(mut bytes: &[u8]) -> Result<FeatureVec> {
+        let limit = bytes.len() as u64;
+        Self::read_record(&mut bytes, bincode_options().with_limit(limit))
+    }
     pub fn read_from(fp: &mut BufReader<File>) -> Result<FeatureVec> {
-        bincode::deserialize_from::<&mut BufReader<File>, FeatureVec>(fp)
+        Self::read_record(fp, bincode_options())
     }
-    pub fn write_to(&self, fp: BufWriter<File>) -> Result<()> {
-        bincode::serialize_into(fp, self).expect("Error writing FeatureVec");
+    /// The next record, or `None` at the end of the file. A record cut
+    /// short or corrupt is an error, not the end.
+    pub fn read_next(fp: &mut BufReader<File>) -> Result<Option<FeatureVec>> {
+        if fp.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        Self::read_from(fp).map(Some)
+    }
+    pub fn read_at(fp: &mut BufReader<File>, offset: u64) -> Result<FeatureVec> {
+        fp.seek(SeekFrom::Start(offset))?;
+        Self::read_from(fp)
+    }
+    fn read_record(r: &mut impl Read, opts: impl Options + Copy) -> Result<FeatureVec> {
+        let mut word = [0u8; 8];
+        r.read_exact(&mut word)?;
+        let len = u64::from_le_bytes(word);
+        if len != FEATURE_VEC_MAGIC {
+            // The first layout: `len` was the docid's length
+            let docid = String::from_utf8(read_bytes(r, len)?)
+                .map_err(|e| bincode::ErrorKind::InvalidUtf8Encoding(e.utf8_error()))?;
+            let (features, squared_norm) = opts.deserialize_from(&mut *r)?;
+            return Ok(FeatureVec {
+                docid,
+                features,
+                squared_norm,
+                sections: Vec::new(),
+            });
+        }
+        let _version: u16 = opts.deserialize_from(&mut *r)?;
+        let mut fv: FeatureVec = opts.deserialize_from(&mut *r)?;
+        loop {
+            let kind: u16 = opts.deserialize_from(&mut *r)?;
+            if kind == 0 {
+                return Ok(fv);
+            }
+            let len: u32 = opts.deserialize_from(&mut *r)?;
+            let bytes = read_bytes(r, len as u64)?;
+            fv.sections.push(Section { kind, bytes });
+        }
+    }
+    pub fn write_to(&self, mut fp: BufWriter<File>) -> Result<()> {
+        self.write_into(&mut fp)
+    }
+    /// Write the vector as a feature file record, in the first layout
+    /// unless it has sections.
+    pub fn write_into(&self, out: &mut impl Write) -> Result<()> {
+        if self.sections.is_empty() {
+            return bincode::serialize_into(out, self);
+        }
+        out.write_all(&FEATURE_VEC_MAGIC.to_le_bytes())?;
+        out.write_all(&FEATURE_VEC_VERSION.to_le_bytes())?;
+        bincode::serialize_into(&mut *out, self)?;
+        for section in &self.sections {
+            out.write_all(&section.kind.to_le_bytes())?;
+            out.write_all(&(section.bytes.len() as u32).to_le_bytes())?;
+            out.write_all(&section.bytes)?;
+        }
+        out.write_all(&0u16.to_le_bytes())?;
         Ok(())
     }
     pub fn num_features(&self) -> usize {
         self.features.len()
     }
-    pub fn feature_at(&self, i: usize) -> usize {
+    pub fn feature_at(&self, i: usize) -> u32 {
         self.features[i].id
     }
     pub fn value_at(&self, i: usize) -> f32 {
         self.features[i].value
     }
-    pub fn push(&mut self, id: usize, val: f32) {
+    pub fn push(&mut self, id: u32, val: f32) {
         self.features.push(FeaturePair { id, value: val });
     }
     pub fn compute_norm(&mut self) {
@@ -291,163 +858,89 @@ impl FeatureVec {
     // }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Classifier {
-    pub lambda: f32,
-    pub num_iters: u32,
-
-    pub w: Vec<f32>,
-    pub scale: f32,
-    pub squared_norm: f32,
-}
-
-impl Classifier {
-    pub fn new(dimensionality: usize, num_iters: u32) -> Classifier {
-        Classifier {
-            w: vec![0.0; dimensionality + 1],
-            lambda: 0.0001,
-            num_iters,
-            scale: 1.0,
-            squared_norm: 0.0,
-        }
-    }
-
-    pub fn load(filename: &str) -> Result<Classifier> {
-        let mut infp = BufReader::new(File::open(filename)?);
-        bincode::deserialize_from::<&mut BufReader<File>, Classifier>(&mut infp)
-    }
-
-    pub fn save(&self, filename: &str) -> std::io::Result<()> {
-        let mut outfp = BufWriter::new(File::create(filename)?);
-        bincode::serialize_into(&mut outfp, self).expect("Error writing model");
-        outfp.flush()?;
-        Ok(())
-    }
-
-    const MIN_SCALE: f32 = 0.00000000001;
-
-    pub fn train(&mut self, positives: &Vec<FeatureVec>, negatives: &Vec<FeatureVec>) {
-        assert!(!positives.is_empty(), "No positive examples");
-        assert!(!negatives.is_empty(), "No negative examples");
-        let mut rng = thread_rng();
-
-        for i in 0..self.num_iters {
-            let eta = 1.0 / (self.lambda * (i + 1) as f32);
-            let a = positives.choose(&mut rng).unwrap();
-            let b = negatives.choose(&mut rng).unwrap();
-
-            // let mut loss = self.inner_product_on_difference(a, b);
-            // loss *= y;
-            // loss = loss.exp();
-            // loss = y / (1.0 + loss);
-            let y = 1.0;
-            let ip = self.inner_product_on_difference(a, b);
-            let loss = y / (1.0 + f32::exp(y * ip));
-            // println!("ip {:.5} loss {:.5}", ip, loss);
-
-            // Regularize
-            let scaling_factor = 1.0 - (eta * self.lambda);
-            if scaling_factor > Self::MIN_SCALE {
-                self.scale_by(scaling_factor);
-            } else {
-                self.scale_by(Self::MIN_SCALE);
-            }
-
-            if loss != 0.0 {
-                self.add_vector(a, eta * loss);
-                self.add_vector(b, -1.0 * eta * loss);
-            }
-
-            // Pegasos projection
-            let projection_val = 1.0 / (self.lambda * self.squared_norm).sqrt();
-            if projection_val < 1.0 {
-                self.scale_by(projection_val);
-            }
-        }
-
-        self.scale_to_one();
-
-        let (mut tpos, mut fpos, mut tneg, mut fneg) = (0, 0, 0, 0);
-        for pos in positives.iter() {
-            let p = self.inner_product(pos);
-            if p > 0.0 {
-                tpos += 1
-            } else if p <= 0.0 {
-                fneg += 1
-            }
-        }
-        for neg in negatives.iter() {
-            let p = self.inner_product(neg);
-            if p >= 0.0 {
-                fpos += 1
-            } else if p < 0.0 {
-                tneg += 1
-            }
-        }
-        println!(
-            "training precision {:.5}, recall {:.5}",
-            tpos as f32 / (tpos + fpos) as f32,
-            tpos as f32 / (tpos + fneg) as f32
-        );
+fn is_alpha(s: &str) -> bool {
+    if s.is_ascii() {
+        s.bytes().all(|b| b.is_ascii_alphabetic())
+    } else {
+        s.chars().all(|c| c.is_alphabetic())
     }
+}
 
-    pub fn inner_product(&self, x: &FeatureVec) -> f32 {
-        let mut prod = 0.0;
-        for feat in x.features.iter() {
-            prod += self.w[feat.id] * feat.value;
+// Case folding uses the Unicode default mapping, never the process locale,
+// and only allocates when folding changes some character.
+fn fold_case(s: &str) -> Cow<'_, str> {
+    if s.is_ascii() {
+        if s.bytes().any(|b| b.is_ascii_uppercase()) {
+            Cow::Owned(s.to_ascii_lowercase())
+        } else {
+            Cow::Borrowed(s)
         }
-        prod * self.scale
-    }
-
-    pub fn inner_product_on_difference(&self, a: &FeatureVec, b: &FeatureVec) -> f32 {
-        let mut prod = 0.0;
-        prod += self.inner_product(a);
-        prod += self.inner_product(b) * -1.0;
-        prod
+    } else if s.chars().all(|c| c.to_lowercase().eq([c])) {
+        Cow::Borrowed(s)
+    } else {
+        Cow::Owned(s.to_lowercase())
     }
+}
 
-    fn scale_to_one(&mut self) {
-        for wt in self.w.iter_mut() {
-            *wt *= self.scale;
+// The length of the prefix of `word` that the stemmer's output spells, if
+// it is one. Stemming mostly only cuts off a suffix.
+fn stem_prefix(word: &str, stem: &[&str]) -> Option<usize> {
+    let mut len = 0;
+    for piece in stem {
+        if !word[len..].starts_with(piece) {
+            return None;
         }
-        self.scale = 1.0;
+        len += piece.len();
     }
+    Some(len)
+}
 
-    fn scale_by(&mut self, scaling_factor: f32) {
-        if self.scale < Self::MIN_SCALE {
-            self.scale_to_one();
-        }
-        self.squared_norm *= scaling_factor * scaling_factor;
-
-        if scaling_factor > 0.0 {
-            self.scale *= scaling_factor;
+// Porter-stem a folded token, as `porter_stemmer::stem` does. `letters`
+// is scratch space for a token's graphemes, reused from token to token, so
+// a token that needed no folding is stemmed without allocating unless its
+// stem isn't a prefix of it.
+fn stem_token<'a>(word: Cow<'a, str>, letters: &mut Vec<&'a str>) -> Cow<'a, str> {
+    match word {
+        Cow::Borrowed(word) => {
+            letters.clear();
+            letters.extend(word.graphemes(true));
+            let stem = stem_tokenized(std::mem::take(letters));
+            let stemmed = match stem_prefix(word, &stem) {
+                Some(len) => Cow::Borrowed(&word[..len]),
+                None => Cow::Owned(stem.concat()),
+            };
+            *letters = stem;
+            stemmed
         }
-    }
-
-    fn add_vector(&mut self, x: &FeatureVec, x_scale: f32) {
-        let mut inner_product = 0.0;
-
-        for feat in x.features.iter() {
-            let this_x_value = feat.value * x_scale;
-            let this_x_feature = feat.id;
-            inner_product += self.w[this_x_feature] * this_x_value;
-            self.w[this_x_feature] += this_x_value / self.scale;
+        Cow::Owned(mut word) => {
+            let stem = stem_tokenized(word.graphemes(true).collect());
+            match stem_prefix(&word, &stem) {
+                Some(len) => {
+                    drop(stem);
+                    word.truncate(len);
+                    Cow::Owned(word)
+                }
+                None => Cow::Owned(stem.concat()),
+            }
         }
-
-        self.squared_norm +=
-            x.squared_norm * x_scale * x_scale + (2.0 * self.scale * inner_product);
     }
 }
 
-fn is_alpha(s: &str) -> bool {
-    s.chars().all(|c| c.is_alphabetic())
-}
-
 // Tokens are stemmed, lowercased sequences of alphanumeric characters
-pub fn tokenize(text: &str) -> Vec<String> {
+pub fn tokens(text: &str) -> impl Iterator<Item = Cow<'_, str>> {
+    let mut letters = Vec::new();
     text.split(|c: char| !c.is_alphanumeric())
         .filter(|s| s.len() >= 2)
-        .map(|s| s.to_lowercase())
-        .map(|s| if is_alpha(&s) { stem(&s) } else { s })
-        .collect()
+        .map(fold_case)
+        .map(move |s| {
+            if is_alpha(&s) {
+                stem_token(s, &mut letters)
+            } else {
+                s
+            }
+        })
+}
+
+pub fn tokenize(text: &str) -> Vec<String> {
+    tokens(text).map(Cow::into_owned).collect()
 }
@@ -1,34 +1,326 @@
-use clap::{Arg, ArgMatches, Command};
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use kdam::{tqdm, BarExt};
-use min_max_heap::MinMaxHeap;
-use mycal::{Classifier, Dict, DocInfo, DocsDb, FeatureVec};
-use ordered_float::OrderedFloat;
-use rand::distributions::Uniform;
-use rand::Rng;
-use std::cmp::Ordering;
-use std::collections::HashSet;
+use mycal::analytics::round_stats;
+use mycal::cancel::ctrl_c;
+use mycal::chunks::{write_chunked, ChunkedFeatures};
+use mycal::ciff;
+use mycal::estimate::estimate_scores;
+use mycal::explain::{summarize_batch, TERMS_PER_DOC};
+use mycal::export::{ExportFormat, Privacy};
+use mycal::guardrails::Guardrails;
+use mycal::languages::{block_file, examples_by_language, search_by_language, Languages};
+use mycal::merge;
+use mycal::modelset::{examples_by_topic, Examples, ModelSet};
+use mycal::negatives::{NegativeSampler, NegativeStrategy};
+use mycal::plan::{plan, BatchSchedule, Budget, GainCurve};
+use mycal::policy::{Action, RoundPolicy};
+use mycal::qrels::{read_judgment_csv, read_qrels, Judgment};
+use mycal::quantize::{Quantization, QuantizedWeights};
+use mycal::recency::{parse_date, today, Dates, Recency};
+use mycal::runs::{diff_runs, evaluate, hash_file, hash_intids, read_run, RankChange, RunMetadata};
+use mycal::search::{bm25_search, rerank, search, search_chunked, suggest, Hit, SearchOptions};
+use mycal::selection::{cooccurring, score_terms};
+use mycal::stopping::{self, estimate_recall, KneeRule};
+use mycal::termstats::{self, TermStats};
+use mycal::testdata::TestData;
+use mycal::tombstones;
+use mycal::topic::{Batch, Strategy, Topic, TopicConfig};
+use mycal::{
+    decode_bounded, fingerprint, load_model, read_fingerprint, read_generation, read_intids,
+    tokens, write_intids, Checkpoint, Checkpoints, ClassWeights, Classifier, CollectionLayout,
+    Dict, DocInfo, DocsDb, EtaSchedule, ExampleWeights, FeatureVec, GradeWeights, Model,
+    ModelHeader, NaiveBayes, Prune, Rocchio, ScoringModel, TrainConfig, TrainReport, Validation,
+};
+use rand::rngs::StdRng;
+use rand::seq::{index, SliceRandom};
+use rand::{Rng, SeedableRng};
+use roaring::RoaringBitmap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::error::Error;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Seek, SeekFrom};
-use std::path::Path;
-use std::str::FromStr;
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::vec::Vec;
 
 fn cli() -> Command {
-    Command::new("mycal")
+    let cli = Command::new("mycal")
         .about("A continuous active learning tool")
         .subcommand_required(true)
         .arg_required_else_help(true)
+        .after_help(
+            "Exit status: 0 on success, 2 for bad arguments, 3 for a missing collection, \
+             4 for a corrupt collection or model, 5 for no training data, 130 if \
+             cancelled with Ctrl-C, 1 otherwise.",
+        )
+        .arg(Arg::new("coll").help("The collection prefix"))
+        .arg(Arg::new("model").help("The model file"))
+        .arg(
+            Arg::new("topic")
+                .short('t')
+                .long("topic")
+                .help("Topic directory; supplies the collection, model and judgments"),
+        )
         .arg(
-            Arg::new("coll")
-                .help("The collection prefix")
-                .required(true),
+            Arg::new("json")
+                .long("json")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Report errors on standard error as a JSON object"),
+        )
+        .subcommand(
+            Command::new("init-topic")
+                .about("Create a topic directory for the collection")
+                .arg(Arg::new("dir").help("The topic directory").required(true))
+                .arg(
+                    Arg::new("strategy")
+                        .short('s')
+                        .long("strategy")
+                        .value_parser(["relevance", "uncertainty"])
+                        .default_value("relevance")
+                        .help("How documents are chosen for review"),
+                )
+                .arg(
+                    Arg::new("batch_size")
+                        .short('b')
+                        .long("batch-size")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("100")
+                        .help("Documents per review batch"),
+                )
+                .arg(
+                    Arg::new("negatives")
+                        .short('n')
+                        .long("negatives")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("0")
+                        .help("Randomly-sampled nonrelevant documents added when training"),
+                )
+                .arg(
+                    Arg::new("level")
+                        .short('l')
+                        .long("level")
+                        .value_parser(clap::value_parser!(i32))
+                        .default_value("1")
+                        .help("Minimum relevance level in the judgments to count as relevant."),
+                )
+                .arg(
+                    Arg::new("prune_min")
+                        .long("prune-min")
+                        .value_parser(clap::value_parser!(f32))
+                        .help("Zero weights smaller in magnitude than this when training"),
+                )
+                .arg(
+                    Arg::new("l1_ratio")
+                        .long("l1-ratio")
+                        .value_parser(parse_l1_ratio)
+                        .default_value("0")
+                        .help("Share of regularization spent on L1, for sparser models"),
+                )
+                .arg(
+                    Arg::new("retrain_every")
+                        .long("retrain-every")
+                        .value_parser(clap::value_parser!(usize))
+                        .help("Have review retrain after this many judgments, even mid-batch"),
+                )
+                .arg(
+                    Arg::new("min_precision")
+                        .long("min-precision")
+                        .value_parser(clap::value_parser!(f64))
+                        .help("Have review retrain mid-batch when the batch is less relevant than this"),
+                )
+                .arg(
+                    Arg::new("uncertainty_ratio")
+                        .long("uncertainty-ratio")
+                        .value_parser(clap::value_parser!(f64))
+                        .help("Have review switch to uncertainty sampling at this knee slope ratio"),
+                )
+                .arg(
+                    Arg::new("no_stop")
+                        .long("no-stop")
+                        .action(ArgAction::SetTrue)
+                        .help("Have review go on after the knee rule fires"),
+                ),
+        )
+        .subcommand(
+            Command::new("review")
+                .about("Take the topic's next review step: retrain, switch strategy, serve a batch, or stop")
+                .arg(
+                    Arg::new("dry_run")
+                        .long("dry-run")
+                        .action(ArgAction::SetTrue)
+                        .help("Only print the step the topic's policy calls for"),
+                ),
+        )
+        .subcommand(
+            Command::new("round")
+                .about("Log a batch's judgments, retrain, and print the next batch in one step")
+                .arg(Arg::new("judgments").help("Qrels or CSV judgments of the last batch"))
+                .arg(
+                    Arg::new("csv")
+                        .long("csv")
+                        .action(ArgAction::SetTrue)
+                        .help("Read the judgments as CSV (the default for names ending in .csv)"),
+                )
+                .arg(
+                    Arg::new("update")
+                        .long("update")
+                        .value_parser(clap::value_parser!(u32))
+                        .default_value("50000")
+                        .help("Training steps to continue the model for"),
+                )
+                .arg(
+                    Arg::new("fresh")
+                        .long("fresh")
+                        .action(ArgAction::SetTrue)
+                        .help("Train a new model instead of continuing the last one"),
+                )
+                .arg(
+                    Arg::new("exclude")
+                        .short('e')
+                        .long("exclude")
+                        .action(ArgAction::Append)
+                        .help("Qrels file of further documents to exclude (may be repeated)"),
+                ),
+        )
+        .subcommand(
+            Command::new("import-judgments")
+                .about("Append judgments from a qrels file or CSV review export to the topic's log")
+                .arg(
+                    Arg::new("file")
+                        .help("The qrels or CSV file")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("csv")
+                        .long("csv")
+                        .action(ArgAction::SetTrue)
+                        .help("Read the file as CSV (the default for names ending in .csv)"),
+                )
+                .arg(
+                    Arg::new("round")
+                        .short('r')
+                        .long("round")
+                        .value_parser(clap::value_parser!(u32))
+                        .help("Round to attribute the judgments to (default: the next round)"),
+                )
+                .arg(
+                    Arg::new("keep_rounds")
+                        .long("keep-rounds")
+                        .action(ArgAction::SetTrue)
+                        .help("Keep the rounds recorded in the file where present"),
+                )
+                .arg(
+                    Arg::new("source_topic")
+                        .short('s')
+                        .long("source-topic")
+                        .help("Only import judgments for this topic id"),
+                ),
+        )
+        .subcommand(
+            Command::new("judge")
+                .about("Log one judgment in the topic's session as it is made")
+                .arg(Arg::new("docid").help("The judged document").required(true))
+                .arg(
+                    Arg::new("rel")
+                        .help("Relevance grade")
+                        .value_parser(clap::value_parser!(i32))
+                        .required(true)
+                        .allow_negative_numbers(true),
+                ),
+        )
+        .subcommand(
+            Command::new("plan")
+                .about("Project the rest of the topic's review under a time budget")
+                .arg(
+                    Arg::new("docs_per_hour")
+                        .long("docs-per-hour")
+                        .value_parser(clap::value_parser!(f64))
+                        .required(true)
+                        .help("Documents a reviewer judges per hour"),
+                )
+                .arg(
+                    Arg::new("hours")
+                        .long("hours")
+                        .value_parser(clap::value_parser!(f64))
+                        .required(true)
+                        .help("Review hours left in the budget"),
+                )
+                .arg(
+                    Arg::new("hours_per_day")
+                        .long("hours-per-day")
+                        .value_parser(clap::value_parser!(f64))
+                        .default_value("8")
+                        .help("Review hours per day, for completion dates"),
+                )
+                .arg(
+                    Arg::new("round_minutes")
+                        .long("round-minutes")
+                        .value_parser(clap::value_parser!(f64))
+                        .default_value("10")
+                        .help("Minutes each round spends retraining and scoring"),
+                )
+                .arg(
+                    Arg::new("stop_precision")
+                        .long("stop-precision")
+                        .value_parser(clap::value_parser!(f64))
+                        .default_value("0.05")
+                        .help("Stop once a batch is expected to be less relevant than this"),
+                )
+                .arg(
+                    Arg::new("batch_sizes")
+                        .short('b')
+                        .long("batch-sizes")
+                        .value_parser(clap::value_parser!(u64))
+                        .value_delimiter(',')
+                        .help("Batch sizes to compare (default: half, one, and two times the topic's)"),
+                ),
+        )
+        .subcommand(
+            Command::new("session")
+                .about("Manage the topic's review session")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("new").about("Start a session for a topic that predates them"),
+                )
+                .subcommand(
+                    Command::new("status")
+                        .about("Show the batches served, judged and snapshotted in each round"),
+                )
+                .subcommand(
+                    Command::new("export")
+                        .about("Write the session's judged documents, for exclusion or scoring")
+                        .arg(Arg::new("out_file").help("Output file").required(true))
+                        .arg(
+                            Arg::new("format")
+                                .long("format")
+                                .value_parser(["qrels", "intids"])
+                                .default_value("qrels")
+                                .help("A qrels file for --exclude, or an intid bitmap for --exclude-ids"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("analytics")
+                .about("Report each round's scoring time, judging latency and throughput")
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_parser(["csv", "json"])
+                        .default_value("csv")
+                        .help("Output format"),
+                ),
         )
-        .arg(Arg::new("model").help("The model file").required(true))
         .subcommand(
             Command::new("train")
                 .about("Apply the given qrels file as training examples")
-                .arg(Arg::new("qrels_file").help("The qrels file"))
+                .arg(Arg::new("qrels_file").help("The qrels file (default: topic judgments)"))
                 .arg(
                     Arg::new("negatives")
                         .short('n')
@@ -37,6 +329,17 @@ fn cli() -> Command {
                         .default_value("0")
                         .help("Add n randomly-sampled documents as nonrelevant."),
                 )
+                .arg(
+                    Arg::new("negative_strategy")
+                        .long("negative-strategy")
+                        .visible_alias("neg-strategy")
+                        .value_parser(clap::value_parser!(NegativeStrategy))
+                        .default_value("uniform")
+                        .help(
+                            "How to sample negatives: uniform, low-score, tail, deciles, length, \
+                             or strata:PREFIX,...",
+                        ),
+                )
                 .arg(
                     Arg::new("level")
                         .short('l')
@@ -44,6 +347,156 @@ fn cli() -> Command {
                         .value_parser(clap::value_parser!(i32))
                         .default_value("1")
                         .help("Minimum relevance level in the qrels to count as relevant."),
+                )
+                .arg(
+                    Arg::new("prune_min")
+                        .long("prune-min")
+                        .value_parser(clap::value_parser!(f32))
+                        .conflicts_with("prune_top")
+                        .help("Zero weights smaller in magnitude than this before saving"),
+                )
+                .arg(
+                    Arg::new("prune_top")
+                        .long("prune-top")
+                        .value_parser(clap::value_parser!(usize))
+                        .help("Keep only the k largest-magnitude weights before saving"),
+                )
+                .arg(
+                    Arg::new("learner")
+                        .long("learner")
+                        .value_parser(["pegasos", "nb", "rocchio"])
+                        .default_value("pegasos")
+                        .help(
+                            "Learner for a new model: pairwise logistic SGD, naive Bayes, \
+                             or a Rocchio centroid",
+                        ),
+                )
+                .arg(
+                    Arg::new("batch_size")
+                        .short('b')
+                        .long("batch-size")
+                        .value_parser(clap::value_parser!(u32).range(1..))
+                        .default_value("1")
+                        .help("Pairs averaged into each step of a new model's training"),
+                )
+                .arg(
+                    Arg::new("iterations")
+                        .short('i')
+                        .long("iterations")
+                        .value_parser(clap::value_parser!(u32))
+                        .help("Training steps for a new model (default: 200000 / batch size)"),
+                )
+                .arg(
+                    Arg::new("per_language")
+                        .long("per-language")
+                        .action(ArgAction::SetTrue)
+                        .help("Also train a block of the model for each language in the collection"),
+                )
+                .arg(
+                    Arg::new("checkpoint_every")
+                        .long("checkpoint-every")
+                        .value_parser(clap::value_parser!(u32).range(1..))
+                        .help("Save the training state to MODEL.ckpt every n iterations"),
+                )
+                .arg(
+                    Arg::new("resume")
+                        .long("resume")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["qrels_file", "holdout", "update", "per_language"])
+                        .help("Finish an interrupted training run from MODEL.ckpt, on its examples"),
+                )
+                .arg(guardrails_arg().help(
+                    "Hold the weights of the terms in this file at zero \
+                     (default: the topic's guardrails.txt)",
+                ))
+                .arg(
+                    Arg::new("no_intercept")
+                        .long("no-intercept")
+                        .action(ArgAction::SetTrue)
+                        .help("Keep a new model's decision boundary through the origin"),
+                )
+                .arg(
+                    Arg::new("l1_ratio")
+                        .long("l1-ratio")
+                        .value_parser(parse_l1_ratio)
+                        .default_value("0")
+                        .help("Share of a new model's regularization spent on L1, for sparser models"),
+                )
+                .arg(
+                    Arg::new("eta_schedule")
+                        .long("eta-schedule")
+                        .value_parser(clap::value_parser!(EtaSchedule))
+                        .default_value("pegasos")
+                        .help("A new model's step size schedule: pegasos, invscaling, constant, or cosine"),
+                )
+                .arg(
+                    Arg::new("eta0")
+                        .long("eta0")
+                        .value_parser(clap::value_parser!(f32))
+                        .default_value("0.1")
+                        .help("Initial step size of the invscaling, constant and cosine schedules"),
+                )
+                .arg(
+                    Arg::new("power_t")
+                        .long("power-t")
+                        .value_parser(clap::value_parser!(f32))
+                        .default_value("0.5")
+                        .help("Exponent of the invscaling schedule"),
+                )
+                .arg(
+                    Arg::new("class_weights")
+                        .long("class-weights")
+                        .value_parser(clap::value_parser!(ClassWeights))
+                        .default_value("uniform")
+                        .help("Class weights in a new model's intercept fit or naive Bayes prior, not its SGD: uniform, balanced, or POS:NEG"),
+                )
+                .arg(
+                    Arg::new("grade_weights")
+                        .long("grade-weights")
+                        .value_parser(clap::value_parser!(GradeWeights))
+                        .help(
+                            "Weight judged examples by relevance grade: linear (grade 2 counts \
+                             double at level 1), or GRADE:WEIGHT,...",
+                        ),
+                )
+                .arg(
+                    Arg::new("tune")
+                        .long("tune")
+                        .action(ArgAction::SetTrue)
+                        .help("Pick a new model's lambda by cross-validation"),
+                )
+                .arg(
+                    Arg::new("folds")
+                        .long("folds")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("5")
+                        .help("Cross-validation folds for --tune"),
+                )
+                .arg(
+                    Arg::new("update")
+                        .long("update")
+                        .value_parser(clap::value_parser!(u32))
+                        .conflicts_with("holdout")
+                        .help("Continue training an existing model for this many iterations"),
+                )
+                .arg(
+                    Arg::new("holdout")
+                        .long("holdout")
+                        .value_parser(clap::value_parser!(f32))
+                        .help("Hold out this fraction of examples and stop when their loss stops improving"),
+                )
+                .arg(
+                    Arg::new("patience")
+                        .long("patience")
+                        .value_parser(clap::value_parser!(u32))
+                        .default_value("5")
+                        .help("Validation checks (every 1000 iterations) without improvement before stopping"),
+                )
+                .arg(
+                    Arg::new("calibrate")
+                        .long("calibrate")
+                        .action(ArgAction::SetTrue)
+                        .help("Fit a probability calibration on the training examples"),
                 ),
         )
         .subcommand(
@@ -61,9 +514,181 @@ fn cli() -> Command {
                     Arg::new("exclude")
                         .short('e')
                         .long("exclude")
-                        .help("Qrels file of documents to exclude"),
+                        .action(ArgAction::Append)
+                        .help("Qrels file of documents to exclude (may be repeated)"),
+                )
+                .arg(
+                    Arg::new("exclude_ids")
+                        .short('x')
+                        .long("exclude-ids")
+                        .action(ArgAction::Append)
+                        .help("Binary intid file of documents to exclude (may be repeated)"),
+                )
+                .arg(
+                    Arg::new("min_score")
+                        .long("min-score")
+                        .value_parser(clap::value_parser!(f32))
+                        .help("Only return documents scoring at least this"),
+                )
+                .arg(
+                    Arg::new("include_routed")
+                        .long("include-routed")
+                        .action(ArgAction::SetTrue)
+                        .help("Also score documents routed out of review at build time"),
+                )
+                .arg(
+                    Arg::new("proba")
+                        .short('p')
+                        .long("proba")
+                        .action(ArgAction::SetTrue)
+                        .help("Print P(relevant) instead of the raw score"),
+                )
+                .arg(
+                    Arg::new("sample")
+                        .long("sample")
+                        .value_parser(clap::value_parser!(usize))
+                        .help(
+                            "Score only a random sample of this many documents and print \
+                             estimates of the score distribution and the top n",
+                        ),
+                )
+                .arg(
+                    Arg::new("strata")
+                        .long("strata")
+                        .value_delimiter(',')
+                        .requires("sample")
+                        .help("Sample equally from each of these comma-separated docid prefixes"),
+                )
+                .arg(
+                    Arg::new("metadata")
+                        .long("metadata")
+                        .action(ArgAction::SetTrue)
+                        .help("Start the output with a comment describing how it was produced"),
+                )
+                .arg(
+                    Arg::new("summary")
+                        .long("summary")
+                        .help("Write the terms contributing most to the returned batch to this file"),
+                )
+                .arg(
+                    Arg::new("summary_terms")
+                        .long("summary-terms")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("20")
+                        .requires("summary")
+                        .help("Number of terms in the batch summary"),
+                )
+                .arg(guardrails_arg().requires("summary").help(
+                    "Leave the terms in this file out of the batch summary \
+                     (default: the topic's guardrails.txt)",
+                ))
+                .args(recency_args()),
+        )
+        .subcommand(
+            Command::new("suggest")
+                .about("Suggest the unjudged documents the model is least sure of")
+                .arg(
+                    Arg::new("num_scores")
+                        .short('n')
+                        .long("num_scores")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("10")
+                        .help("Number of documents to suggest"),
+                )
+                .arg(
+                    Arg::new("exclude")
+                        .short('e')
+                        .long("exclude")
+                        .action(ArgAction::Append)
+                        .help("Qrels file of judged documents to leave out (may be repeated)"),
+                )
+                .arg(
+                    Arg::new("exclude_ids")
+                        .short('x')
+                        .long("exclude-ids")
+                        .action(ArgAction::Append)
+                        .help("Binary intid file of documents to exclude (may be repeated)"),
+                )
+                .arg(
+                    Arg::new("min_score")
+                        .long("min-score")
+                        .value_parser(clap::value_parser!(f32))
+                        .help("Only suggest documents scoring at least this"),
+                )
+                .arg(
+                    Arg::new("include_routed")
+                        .long("include-routed")
+                        .action(ArgAction::SetTrue)
+                        .help("Also suggest documents routed out of review at build time"),
+                )
+                .arg(
+                    Arg::new("proba")
+                        .short('p')
+                        .long("proba")
+                        .action(ArgAction::SetTrue)
+                        .help("Print P(relevant) instead of the raw score"),
+                ),
+        )
+        .subcommand(
+            Command::new("search")
+                .about("Rank the collection by BM25 against keywords, to find seed documents")
+                .arg(
+                    Arg::new("query")
+                        .required(true)
+                        .action(ArgAction::Append)
+                        .help("Query text, tokenized like the collection"),
+                )
+                .arg(
+                    Arg::new("num_scores")
+                        .short('n')
+                        .long("num_scores")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("100")
+                        .help("Number of top-scoring documents to retrieve"),
+                )
+                .arg(
+                    Arg::new("exclude")
+                        .short('e')
+                        .long("exclude")
+                        .action(ArgAction::Append)
+                        .help("Qrels file of documents to exclude (may be repeated)"),
+                )
+                .arg(
+                    Arg::new("exclude_ids")
+                        .short('x')
+                        .long("exclude-ids")
+                        .action(ArgAction::Append)
+                        .help("Binary intid file of documents to exclude (may be repeated)"),
+                )
+                .arg(
+                    Arg::new("min_score")
+                        .long("min-score")
+                        .value_parser(clap::value_parser!(f32))
+                        .help("Only return documents scoring at least this"),
+                )
+                .arg(
+                    Arg::new("include_routed")
+                        .long("include-routed")
+                        .action(ArgAction::SetTrue)
+                        .help("Also search documents routed out of review at build time"),
                 ),
         )
+        .subcommand(
+            Command::new("rerank")
+                .about("Reorder a list of candidate docids by score")
+                .arg(
+                    Arg::new("candidates")
+                        .help("File of docids, one per line (default: standard input)"),
+                )
+                .arg(
+                    Arg::new("proba")
+                        .short('p')
+                        .long("proba")
+                        .action(ArgAction::SetTrue)
+                        .help("Print P(relevant) instead of the raw score"),
+                )
+                .args(recency_args()),
+        )
         .subcommand(
             Command::new("score_one")
                 .about("Score one document, by docid")
@@ -73,221 +698,3356 @@ fn cli() -> Command {
                         .required(true),
                 ),
         )
-}
+        .subcommand(
+            Command::new("list-docids")
+                .about("List docids starting with a prefix")
+                .arg(
+                    Arg::new("prefix")
+                        .help("The docid prefix")
+                        .default_value(""),
+                )
+                .arg(
+                    Arg::new("limit")
+                        .short('n')
+                        .long("limit")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("20")
+                        .help("Maximum number of docids to list"),
+                ),
+        )
+        .subcommand(
+            Command::new("exclude-from-qrels")
+                .about("Write the judged documents in a qrels file as a binary intid file")
+                .arg(Arg::new("qrels_file").help("The qrels file").required(true))
+                .arg(
+                    Arg::new("out_file")
+                        .help("The intid file to write")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("delete-docs")
+                .about("Mark documents deleted, so they are no longer scored or sampled")
+                .arg(
+                    Arg::new("docids")
+                        .help("Docids to delete")
+                        .num_args(1..)
+                        .required_unless_present("file"),
+                )
+                .arg(
+                    Arg::new("file")
+                        .short('f')
+                        .long("file")
+                        .help("A file of docids to delete, one per line"),
+                ),
+        )
+        .subcommand(
+            Command::new("merge-index")
+                .about("Merge separately built collections into a new one")
+                .arg(
+                    Arg::new("out_prefix")
+                        .help("The prefix for the merged collection")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("shards")
+                        .help("The collections to merge, in order")
+                        .num_args(1..)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("export-ciff")
+                .about("Write the collection's postings as a CIFF file, for other IR systems")
+                .arg(
+                    Arg::new("ciff_file")
+                        .help("The CIFF file to write, compressed if it ends in .gz")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("description")
+                        .long("description")
+                        .default_value("")
+                        .help("Description to put in the file's header"),
+                ),
+        )
+        .subcommand(
+            Command::new("import-ciff")
+                .about("Build a new collection from a CIFF file")
+                .arg(
+                    Arg::new("ciff_file")
+                        .help("The CIFF file to read, compressed if it ends in .gz")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("out_prefix")
+                        .help("The prefix for the new collection")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("compact")
+                .about("Rewrite the collection without its deleted documents, renumbering the rest"),
+        )
+        .subcommand(
+            Command::new("diff-models")
+                .about("Report the largest weight changes between two models")
+                .arg(Arg::new("model_a").help("The first model").required(true))
+                .arg(Arg::new("model_b").help("The second model").required(true))
+                .arg(
+                    Arg::new("other_coll")
+                        .short('o')
+                        .long("other-coll")
+                        .help("Collection of the second model; aligns weights by token string"),
+                )
+                .arg(
+                    Arg::new("num_changes")
+                        .short('n')
+                        .long("num_changes")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("25")
+                        .help("Number of largest weight changes to list"),
+                ),
+        )
+        .subcommand(
+            Command::new("chunk-features")
+                .about("Write the chunked copy of the feature file that score scans")
+                .arg(
+                    Arg::new("chunk_size")
+                        .long("chunk-size")
+                        .value_parser(clap::value_parser!(u32).range(1..))
+                        .default_value("4096")
+                        .help("Documents per chunk"),
+                ),
+        )
+        .subcommand(
+            Command::new("export-model")
+                .about("Write the model's weights for use outside mycal")
+                .arg(
+                    Arg::new("out_file")
+                        .help("The file to write")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("format")
+                        .short('f')
+                        .long("format")
+                        .value_parser(clap::value_parser!(ExportFormat))
+                        .default_value("json")
+                        .help("json (with tokens, given a collection) or onnx"),
+                )
+                .arg(
+                    Arg::new("epsilon")
+                        .long("epsilon")
+                        .value_parser(clap::value_parser!(f32))
+                        .help(
+                            "Privatize the model first: clip the weights and add Laplace noise of \
+                             scale 2*clip/epsilon to each (smaller is noisier). The noise is per \
+                             weight, with no formal differential privacy guarantee",
+                        ),
+                )
+                .arg(
+                    Arg::new("clip")
+                        .long("clip")
+                        .value_parser(clap::value_parser!(f32))
+                        .default_value("1.0")
+                        .requires("epsilon")
+                        .help("Bound on the magnitude of a privatized weight"),
+                )
+                .arg(
+                    Arg::new("min_df")
+                        .long("min-df")
+                        .value_parser(clap::value_parser!(f32))
+                        .default_value("5")
+                        .requires("epsilon")
+                        .help("Drop terms in fewer documents than this (needs a collection)"),
+                )
+                .arg(
+                    Arg::new("qrels_file")
+                        .long("qrels")
+                        .requires("epsilon")
+                        .help("Report the privatized model's AUC on these judgments (needs a collection)"),
+                ),
+        )
+        .subcommand(
+            Command::new("check-model")
+                .about("Check that the model can score this collection, such as one sharing its hashed feature space"),
+        )
+        .subcommand(
+            Command::new("fingerprint")
+                .about("Print the collection's fingerprint, computing and saving it if it has none"),
+        )
+        .subcommand(
+            Command::new("remap-model")
+                .about("Move a model's weights to this collection's token ids, matching tokens")
+                .arg(
+                    Arg::new("old_coll")
+                        .help("The collection the model was trained on")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("out_file")
+                        .short('o')
+                        .long("out")
+                        .help("Write the remapped model here instead of over the original"),
+                ),
+        )
+        .subcommand(
+            Command::new("prune-model")
+                .about("Zero a model's negligible weights, to bound the cost of scoring")
+                .arg(
+                    Arg::new("top")
+                        .short('k')
+                        .long("top")
+                        .value_parser(clap::value_parser!(usize))
+                        .required_unless_present("min")
+                        .conflicts_with("min")
+                        .help("Keep only the k largest-magnitude weights"),
+                )
+                .arg(
+                    Arg::new("min")
+                        .long("min")
+                        .value_parser(clap::value_parser!(f32))
+                        .help("Zero weights smaller in magnitude than this"),
+                )
+                .arg(
+                    Arg::new("out_file")
+                        .short('o')
+                        .long("out")
+                        .help("Write the pruned model here instead of over the original"),
+                ),
+        )
+        .subcommand(
+            Command::new("quantize-model")
+                .about("Store a classifier's weights in less precision, to shrink the file")
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .value_parser(clap::value_parser!(Quantization))
+                        .default_value("f16")
+                        .help("f16 (half the size), int8 (a quarter), or f32 to undo"),
+                )
+                .arg(
+                    Arg::new("out_file")
+                        .short('o')
+                        .long("out")
+                        .help("Write the quantized model here instead of over the original"),
+                ),
+        )
+        .subcommand(
+            Command::new("term-report")
+                .about("List the terms that best separate relevant from nonrelevant judgments")
+                .arg(Arg::new("qrels_file").help("The qrels file (default: topic judgments)"))
+                .arg(
+                    Arg::new("level")
+                        .short('l')
+                        .long("level")
+                        .value_parser(clap::value_parser!(i32))
+                        .default_value("1")
+                        .help("Minimum relevance level in the qrels to count as relevant."),
+                )
+                .arg(
+                    Arg::new("metric")
+                        .short('m')
+                        .long("metric")
+                        .value_parser(["chi2", "ig"])
+                        .default_value("chi2")
+                        .help("Rank terms by chi-square or information gain"),
+                )
+                .arg(
+                    Arg::new("num_terms")
+                        .short('n')
+                        .long("num_terms")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("50")
+                        .help("Number of terms to list"),
+                ),
+        )
+        .subcommand(
+            Command::new("term-stats")
+                .about("Report document and collection frequencies of the collection's terms")
+                .arg(
+                    Arg::new("terms")
+                        .help("Terms to report (default: the most frequent)")
+                        .num_args(0..),
+                )
+                .arg(
+                    Arg::new("num_terms")
+                        .short('n')
+                        .long("num-terms")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("20")
+                        .help("How many of the most frequent terms to list"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_parser(["text", "json"])
+                        .default_value("text")
+                        .help("Output format"),
+                ),
+        )
+        .subcommand(
+            Command::new("related-terms")
+                .about("List the terms found with a term in relevant judged documents, for seed searches")
+                .arg(Arg::new("term").help("The term").required(true))
+                .arg(Arg::new("qrels_file").help("The qrels file (default: topic judgments)"))
+                .arg(
+                    Arg::new("level")
+                        .short('l')
+                        .long("level")
+                        .value_parser(clap::value_parser!(i32))
+                        .default_value("1")
+                        .help("Minimum relevance level in the qrels to count as relevant."),
+                )
+                .arg(
+                    Arg::new("num_terms")
+                        .short('n')
+                        .long("num_terms")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("20")
+                        .help("Number of terms to list"),
+                ),
+        )
+        .subcommand(
+            Command::new("stop-check")
+                .about("Check whether the review can stop, by the knee method")
+                .arg(
+                    Arg::new("qrels_file")
+                        .help("Judgments in the order they were made (default: topic judgments)"),
+                )
+                .arg(
+                    Arg::new("level")
+                        .short('l')
+                        .long("level")
+                        .value_parser(clap::value_parser!(i32))
+                        .default_value("1")
+                        .help("Minimum relevance level in the qrels to count as relevant."),
+                )
+                .arg(
+                    Arg::new("min_reviewed")
+                        .long("min-reviewed")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("150")
+                        .help("Never stop before this many documents are reviewed"),
+                ),
+        )
+        .subcommand(
+            Command::new("draw-sample")
+                .about("Draw a random sample of the unreviewed documents, to judge or for recall-estimate")
+                .arg(
+                    Arg::new("qrels_file")
+                        .help("Judgments made so far (default: topic judgments, or none)"),
+                )
+                .arg(
+                    Arg::new("num_docs")
+                        .short('n')
+                        .long("num-docs")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("500")
+                        .help("Sample size"),
+                )
+                .arg(
+                    Arg::new("exclude")
+                        .short('e')
+                        .long("exclude")
+                        .action(ArgAction::Append)
+                        .help("Another qrels file of documents to leave out (may be repeated)"),
+                )
+                .arg(
+                    Arg::new("strata")
+                        .long("by-length")
+                        .value_parser(clap::value_parser!(usize))
+                        .help("Draw equally from this many groups of documents by length"),
+                )
+                .arg(
+                    Arg::new("qrels")
+                        .long("qrels")
+                        .action(ArgAction::SetTrue)
+                        .help("Print qrels lines with ? for the judgment, to fill in"),
+                )
+                .arg(
+                    Arg::new("qid")
+                        .long("qid")
+                        .requires("qrels")
+                        .help("Topic id for the qrels lines (default: the topic's name, or 0)"),
+                ),
+        )
+        .subcommand(
+            Command::new("recall-estimate")
+                .about("Estimate the recall reached from a judged sample of the unreviewed documents")
+                .arg(
+                    Arg::new("sample")
+                        .help("Qrels for the documents drawn by draw-sample")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("qrels_file")
+                        .long("qrels")
+                        .help("Judgments made by the review (default: topic judgments)"),
+                )
+                .arg(
+                    Arg::new("level")
+                        .short('l')
+                        .long("level")
+                        .value_parser(clap::value_parser!(i32))
+                        .default_value("1")
+                        .help("Minimum relevance level in the qrels to count as relevant."),
+                )
+                .arg(
+                    Arg::new("target")
+                        .long("target")
+                        .value_parser(clap::value_parser!(f64))
+                        .default_value("0.8")
+                        .help("Recall the review is meant to reach"),
+                )
+                .arg(
+                    Arg::new("confidence")
+                        .long("confidence")
+                        .value_parser(clap::value_parser!(f64))
+                        .default_value("0.95")
+                        .help("Confidence level of the recall bound"),
+                ),
+        )
+        .subcommand(
+            Command::new("run-topics")
+                .about("Train a model per topic in a qrels file and score them all in one pass")
+                .arg(Arg::new("qrels_file").help("The qrels file").required(true))
+                .arg(
+                    Arg::new("model_dir")
+                        .help("Directory of per-topic models, named by topic id")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("num_scores")
+                        .short('n')
+                        .long("num_scores")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("1000")
+                        .help("Number of documents to retrieve per topic"),
+                )
+                .arg(
+                    Arg::new("level")
+                        .short('l')
+                        .long("level")
+                        .value_parser(clap::value_parser!(i32))
+                        .default_value("1")
+                        .help("Minimum relevance level in the qrels to count as relevant."),
+                )
+                .arg(
+                    Arg::new("tag")
+                        .long("tag")
+                        .default_value("mycal")
+                        .help("Run tag for the last column"),
+                )
+                .arg(
+                    Arg::new("metadata")
+                        .long("metadata")
+                        .action(ArgAction::SetTrue)
+                        .help("Start each topic's ranking with a comment describing how it was produced"),
+                ),
+        )
+        .subcommand(
+            Command::new("gen-testdata")
+                .about("Write a synthetic corpus and qrels with a planted relevant set")
+                .arg(
+                    Arg::new("out_prefix")
+                        .help("Writes <prefix>.jsonl and <prefix>.qrels")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("docs")
+                        .long("docs")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("10000")
+                        .help("Number of documents"),
+                )
+                .arg(
+                    Arg::new("vocab")
+                        .long("vocab")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("5000")
+                        .help("Number of distinct terms (at least 20)"),
+                )
+                .arg(
+                    Arg::new("relevant")
+                        .long("relevant")
+                        .value_parser(clap::value_parser!(f32))
+                        .default_value("0.02")
+                        .help("Fraction of documents planted as relevant"),
+                )
+                .arg(
+                    Arg::new("seed")
+                        .long("seed")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("1")
+                        .help("Random seed; the same seed gives the same files"),
+                ),
+        )
+        .subcommand(
+            Command::new("diff-runs")
+                .about("Report rank changes between two scoring rounds")
+                .arg(Arg::new("old_run").help("The earlier run").required(true))
+                .arg(Arg::new("new_run").help("The later run").required(true))
+                .arg(
+                    Arg::new("depth")
+                        .short('k')
+                        .long("depth")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("100")
+                        .help("Depth of the top-k used for entrants and overlap"),
+                )
+                .arg(
+                    Arg::new("movers")
+                        .short('m')
+                        .long("movers")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("20")
+                        .help("Number of biggest rank movers to list"),
+                ),
+        )
+        .subcommand(
+            Command::new("eval")
+                .about("Score a run against judgments: precision and recall at cutoffs, AP, gain curve")
+                .arg(Arg::new("run").help("The run").required(true))
+                .arg(Arg::new("qrels_file").help("The qrels file").required(true))
+                .arg(
+                    Arg::new("level")
+                        .short('l')
+                        .long("level")
+                        .value_parser(clap::value_parser!(i32))
+                        .default_value("1")
+                        .help("Minimum relevance level in the qrels to count as relevant."),
+                )
+                .arg(
+                    Arg::new("qid")
+                        .long("qid")
+                        .help("Evaluate only this topic's run lines and qrels (default: the run's only topic)"),
+                )
+                .arg(
+                    Arg::new("cutoffs")
+                        .short('k')
+                        .long("cutoffs")
+                        .value_parser(clap::value_parser!(usize))
+                        .value_delimiter(',')
+                        .default_value("5,10,20,50,100,1000")
+                        .help("Ranks to report precision and recall at"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_parser(["text", "csv", "json"])
+                        .default_value("text")
+                        .help("A summary, the gain curve as CSV, or everything as JSON"),
+                ),
+        );
+    #[cfg(feature = "arrow")]
+    let cli = cli.subcommand(
+        Command::new("arrow-features")
+            .about("Write an Arrow IPC copy of the feature file that score scans")
+            .arg(
+                Arg::new("batch_size")
+                    .long("batch-size")
+                    .value_parser(clap::value_parser!(usize))
+                    .default_value("4096")
+                    .help("Documents per record batch"),
+            ),
+    );
+    cli
+}
+
+/// What went wrong, for the exit status and the JSON error report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Failure {
+    BadArgs,
+    MissingCollection,
+    CorruptIndex,
+    NoTrainingData,
+    Cancelled,
+    Other,
+}
+
+impl Failure {
+    fn code(self) -> u8 {
+        match self {
+            Failure::Other => 1,
+            Failure::BadArgs => 2,
+            Failure::MissingCollection => 3,
+            Failure::CorruptIndex => 4,
+            Failure::NoTrainingData => 5,
+            Failure::Cancelled => 130,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Failure::Other => "error",
+            Failure::BadArgs => "bad_args",
+            Failure::MissingCollection => "missing_collection",
+            Failure::CorruptIndex => "corrupt_index",
+            Failure::NoTrainingData => "no_training_data",
+            Failure::Cancelled => "cancelled",
+        }
+    }
+
+    /// Classify an error, looking inside I/O errors for a [`Failed`].
+    fn of(e: &(dyn Error + 'static)) -> Failure {
+        if let Some(failed) = e.downcast_ref::<Failed>() {
+            return failed.failure;
+        }
+        if let Some(e) = e.downcast_ref::<std::io::Error>() {
+            if let Some(failed) = e.get_ref().and_then(|e| e.downcast_ref::<Failed>()) {
+                return failed.failure;
+            }
+            return match e.kind() {
+                ErrorKind::InvalidData | ErrorKind::UnexpectedEof => Failure::CorruptIndex,
+                ErrorKind::InvalidInput => Failure::BadArgs,
+                ErrorKind::Interrupted => Failure::Cancelled,
+                _ => Failure::Other,
+            };
+        }
+        if e.downcast_ref::<bincode::Error>().is_some() {
+            return Failure::CorruptIndex;
+        }
+        Failure::Other
+    }
+}
+
+#[derive(Debug)]
+struct Failed {
+    failure: Failure,
+    message: String,
+}
+
+impl std::fmt::Display for Failed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for Failed {}
+
+/// An I/O error carrying a [`Failure`], for functions that return those.
+fn failed(failure: Failure, message: impl Into<String>) -> std::io::Error {
+    std::io::Error::other(Failed {
+        failure,
+        message: message.into(),
+    })
+}
+
+/// Write an error for a person, or as `{"error": {...}}` for a script.
+fn report_error(failure: Failure, message: &str, json: bool) {
+    if json {
+        let envelope = serde_json::json!({
+            "error": {
+                "kind": failure.name(),
+                "code": failure.code(),
+                "message": message,
+            }
+        });
+        eprintln!("{}", envelope);
+    } else {
+        eprintln!("Error: {}", message);
+    }
+}
+
+fn main() -> ExitCode {
+    // Arguments are checked before --json can be read from them
+    let json = std::env::args().any(|a| a == "--json");
+    let args = match cli().try_get_matches() {
+        Ok(args) => args,
+        Err(e) if !json || !e.use_stderr() => e.exit(),
+        Err(e) => {
+            let rendered = e.to_string();
+            let first = rendered.lines().next().unwrap_or_default();
+            report_error(Failure::BadArgs, first.trim_start_matches("error: "), true);
+            return ExitCode::from(Failure::BadArgs.code());
+        }
+    };
+    if json {
+        std::panic::set_hook(Box::new(|info| {
+            let message = info
+                .payload()
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| info.payload().downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "panic".to_string());
+            let envelope = serde_json::json!({
+                "error": { "kind": "internal", "code": 101, "message": message }
+            });
+            eprintln!("{}", envelope);
+        }));
+    }
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            let failure = Failure::of(e.as_ref());
+            report_error(failure, &e.to_string(), json);
+            ExitCode::from(failure.code())
+        }
+    }
+}
+
+fn run(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let topic = args
+        .get_one::<String>("topic")
+        .map(Topic::open)
+        .transpose()?;
+    let coll = args
+        .get_one::<String>("coll")
+        .map(CollectionLayout::new)
+        .or_else(|| topic.as_ref().map(Topic::collection));
+    if let Some(c) = coll.as_ref() {
+        // Before anything reads a collection whose files disagree
+        tombstones::recover(c)?;
+    }
+    let model_file = args
+        .get_one::<String>("model")
+        .map(PathBuf::from)
+        .or_else(|| topic.as_ref().map(Topic::model_file));
+    let need_coll = || match coll.as_ref() {
+        Some(c) if c.dict().exists() => Ok(c),
+        Some(c) => Err(Failed {
+            failure: Failure::MissingCollection,
+            message: format!("No collection at {}", c.prefix().display()),
+        }),
+        None => Err(Failed {
+            failure: Failure::BadArgs,
+            message: "This subcommand needs a collection prefix or --topic".to_string(),
+        }),
+    };
+    let need_model = || {
+        model_file.as_deref().ok_or(Failed {
+            failure: Failure::BadArgs,
+            message: "This subcommand needs a model file or --topic".to_string(),
+        })
+    };
+
+    match args.subcommand() {
+        Some(("init-topic", init_args)) => {
+            init_topic(need_coll()?, init_args)?;
+        }
+        Some(("review", review_args)) => {
+            let topic = topic.as_ref().ok_or("review needs --topic")?;
+            review(topic, review_args)?;
+        }
+        Some(("round", round_args)) => {
+            let topic = topic.as_ref().ok_or("round needs --topic")?;
+            review_round(topic, round_args)?;
+        }
+        Some(("import-judgments", import_args)) => {
+            let topic = topic.as_ref().ok_or("import-judgments needs --topic")?;
+            import_judgments(topic, import_args)?;
+        }
+        Some(("judge", judge_args)) => {
+            let topic = topic.as_ref().ok_or("judge needs --topic")?;
+            judge(topic, judge_args)?;
+        }
+        Some(("plan", plan_args)) => {
+            let topic = topic.as_ref().ok_or("plan needs --topic")?;
+            plan_review(topic, plan_args)?;
+        }
+        Some(("session", session_args)) => {
+            let topic = topic.as_ref().ok_or("session needs --topic")?;
+            match session_args.subcommand() {
+                Some(("new", _)) => {
+                    topic.start_session()?;
+                }
+                Some(("status", _)) => session_status(topic)?,
+                Some(("export", export_args)) => export_session(topic, export_args)?,
+                _ => unreachable!(),
+            }
+        }
+        Some(("analytics", analytics_args)) => {
+            let topic = topic.as_ref().ok_or("analytics needs --topic")?;
+            review_analytics(topic, analytics_args)?;
+        }
+        Some(("train", qrels_args)) => {
+            let (_, report) = train_qrels(need_coll()?, need_model()?, qrels_args, topic.as_ref())?;
+            println!("{}", report);
+        }
+        Some(("score", score_args)) => {
+            score_collection(need_coll()?, need_model()?, score_args, topic.as_ref())?;
+        }
+        Some(("suggest", suggest_args)) => {
+            suggest_documents(need_coll()?, need_model()?, suggest_args, topic.as_ref())?;
+        }
+        Some(("search", search_args)) => {
+            keyword_search(need_coll()?, search_args, topic.as_ref())?;
+        }
+        Some(("rerank", rerank_args)) => {
+            rerank_candidates(need_coll()?, need_model()?, rerank_args)?;
+        }
+        Some(("score_one", score_one_args)) => {
+            score_one_doc(need_coll()?, need_model()?, score_one_args)?;
+        }
+        Some(("list-docids", list_args)) => {
+            let docs = DocsDb::open(need_coll()?.docsdb());
+            let prefix = list_args.get_one::<String>("prefix").unwrap();
+            let limit = *list_args.get_one::<usize>("limit").unwrap();
+            docs.list_docids(prefix, limit)
+                .iter()
+                .for_each(|docid| println!("{}", docid));
+        }
+        Some(("exclude-from-qrels", excl_args)) => {
+            exclude_from_qrels(need_coll()?, excl_args)?;
+        }
+        Some(("delete-docs", delete_args)) => {
+            delete_docs(need_coll()?, delete_args)?;
+        }
+        Some(("merge-index", merge_args)) => {
+            let out = CollectionLayout::new(merge_args.get_one::<String>("out_prefix").unwrap());
+            let shards: Vec<CollectionLayout> = merge_args
+                .get_many::<String>("shards")
+                .unwrap()
+                .map(CollectionLayout::new)
+                .collect();
+            let mut progress = tqdm!();
+            let report = merge::merge(&out, &shards, |n| {
+                progress.update(n);
+            })?;
+            eprintln!();
+            println!(
+                "merged {} documents and {} terms; left out {} duplicate and {} deleted documents",
+                report.num_docs, report.num_terms, report.duplicates, report.deleted
+            );
+        }
+        Some(("export-ciff", ciff_args)) => {
+            let coll = need_coll()?;
+            let path = Path::new(ciff_args.get_one::<String>("ciff_file").unwrap());
+            let description = ciff_args.get_one::<String>("description").unwrap();
+            let mut file = BufWriter::new(File::create(path)?);
+            let mut progress = tqdm!();
+            let mut tick = |n| {
+                progress.update(n);
+            };
+            let report = if path.extension().is_some_and(|e| e == "gz") {
+                let mut gz = GzEncoder::new(file, Compression::default());
+                let report = ciff::export(coll, &mut gz, description, &mut tick)?;
+                gz.finish()?.flush()?;
+                report
+            } else {
+                ciff::export(coll, &mut file, description, &mut tick)?
+            };
+            eprintln!();
+            println!(
+                "exported {} documents and {} terms with {} postings; left out {} deleted documents and {} hashed buckets",
+                report.num_docs, report.num_terms, report.num_postings, report.deleted, report.buckets
+            );
+        }
+        Some(("import-ciff", ciff_args)) => {
+            let path = Path::new(ciff_args.get_one::<String>("ciff_file").unwrap());
+            let file = File::open(path)?;
+            let mut input: Box<dyn BufRead> = if path.extension().is_some_and(|e| e == "gz") {
+                Box::new(BufReader::new(GzDecoder::new(file)))
+            } else {
+                Box::new(BufReader::new(file))
+            };
+            let out = CollectionLayout::new(ciff_args.get_one::<String>("out_prefix").unwrap());
+            let mut progress = tqdm!();
+            let report = ciff::import(&mut input, &out, |n| {
+                progress.update(n);
+            })?;
+            eprintln!();
+            println!(
+                "imported {} documents and {} terms with {} postings",
+                report.num_docs, report.num_terms, report.num_postings
+            );
+        }
+        Some(("compact", _)) => {
+            let mut progress = tqdm!();
+            let done = tombstones::compact(need_coll()?, |n| {
+                progress.update(n);
+            })?;
+            eprintln!();
+            if done.removed == 0 {
+                println!("no deleted documents to remove");
+            } else {
+                println!("removed {} documents, {} left", done.removed, done.kept);
+            }
+        }
+        Some(("diff-models", diff_args)) => {
+            diff_model_files(coll.as_ref(), diff_args)?;
+        }
+        Some(("chunk-features", chunk_args)) => {
+            let coll = need_coll()?;
+            let chunk_size = *chunk_args.get_one::<u32>("chunk_size").unwrap();
+            let mut progress = tqdm!();
+            let num_docs =
+                write_chunked(coll.features(), coll.chunked_features(), chunk_size, |n| {
+                    progress.update(n);
+                })?;
+            eprintln!();
+            println!("wrote {} documents", num_docs);
+        }
+        #[cfg(feature = "arrow")]
+        Some(("arrow-features", arrow_args)) => {
+            let coll = need_coll()?;
+            let batch_size = *arrow_args.get_one::<usize>("batch_size").unwrap();
+            let mut progress = tqdm!();
+            let num_docs = mycal::columnar::write_arrow(
+                coll.features(),
+                coll.arrow_features(),
+                batch_size,
+                |n| {
+                    progress.update(n);
+                },
+            )?;
+            eprintln!();
+            println!("wrote {} documents", num_docs);
+        }
+        Some(("export-model", export_args)) => {
+            export_model_file(coll.as_ref(), need_model()?, export_args)?;
+        }
+        Some(("check-model", _)) => {
+            check_model_file(need_coll()?, need_model()?, topic.as_ref())?;
+        }
+        Some(("fingerprint", _)) => {
+            println!("{}", collection_fingerprint(need_coll()?)?);
+        }
+        Some(("remap-model", remap_args)) => {
+            remap_model_file(need_coll()?, need_model()?, remap_args)?;
+        }
+        Some(("prune-model", prune_args)) => {
+            prune_model_file(need_model()?, prune_args)?;
+        }
+        Some(("quantize-model", quantize_args)) => {
+            quantize_model_file(need_model()?, quantize_args)?;
+        }
+        Some(("term-report", report_args)) => {
+            term_report(need_coll()?, report_args, topic.as_ref())?;
+        }
+        Some(("term-stats", stats_args)) => {
+            term_stats(need_coll()?, stats_args)?;
+        }
+        Some(("related-terms", related_args)) => {
+            related_terms(need_coll()?, related_args, topic.as_ref())?;
+        }
+        Some(("stop-check", stop_args)) => {
+            stop_check(coll.as_ref(), stop_args, topic.as_ref())?;
+        }
+        Some(("draw-sample", draw_args)) => {
+            draw_sample(need_coll()?, draw_args, topic.as_ref())?;
+        }
+        Some(("recall-estimate", recall_args)) => {
+            recall_estimate(need_coll()?, recall_args, topic.as_ref())?;
+        }
+        Some(("run-topics", run_args)) => {
+            run_topics(need_coll()?, run_args)?;
+        }
+        Some(("gen-testdata", gen_args)) => {
+            gen_testdata(gen_args)?;
+        }
+        Some(("diff-runs", diff_args)) => {
+            diff_run_files(diff_args)?;
+        }
+        Some(("eval", eval_args)) => {
+            eval_run(eval_args)?;
+        }
+        Some((&_, _)) => panic!("No subcommand specified"),
+        None => panic!("No subcommand specified"),
+    }
+    Ok(())
+}
+
+/// Apply the knee stopping rule to a judgment log. Given a collection,
+/// also estimate how many relevant documents are left.
+fn stop_check(
+    coll: Option<&CollectionLayout>,
+    stop_args: &ArgMatches,
+    topic: Option<&Topic>,
+) -> Result<(), Box<dyn Error>> {
+    let qrels_file = qrels_or_topic(stop_args, topic)?;
+    let min = arg_or_topic(stop_args, "level", topic.map(|t| t.config.relevance_level));
+    let rule = KneeRule {
+        min_reviewed: *stop_args.get_one::<usize>("min_reviewed").unwrap(),
+        ..KneeRule::default()
+    };
+    let curve = stopping::gain_curve(&read_qrels(qrels_file)?, min);
+    let unreviewed = coll.map(|c| {
+        DocsDb::open(c.docsdb())
+            .db
+            .len()
+            .saturating_sub(curve.len())
+    });
+    let result = stopping::check(&curve, &rule, unreviewed);
+
+    println!("{} reviewed, {} relevant", result.reviewed, result.relevant);
+    match result.knee {
+        Some((k, found)) => println!(
+            "knee at {} reviewed ({} relevant), slope ratio {:.1}, {:.0} needed",
+            k, found, result.slope_ratio, result.threshold
+        ),
+        None => println!("no knee yet"),
+    }
+    if let Some(left) = result.remaining {
+        println!("about {:.0} relevant left to find", left);
+    }
+    if result.stop {
+        println!("stop: the review can stop");
+    } else {
+        println!("continue: the review should go on");
+    }
+    Ok(())
+}
+
+/// Documents that can still be reviewed: not judged in `judged`, not
+/// routed out of review, and not deleted.
+fn unreviewed(
+    coll: &CollectionLayout,
+    docs: &DocsDb,
+    judged: &[Judgment],
+) -> Result<RoaringBitmap, std::io::Error> {
+    let mut left = RoaringBitmap::new();
+    left.insert_range(0..docs.db.len() as u32);
+    left -= docs.intids_for(judged.iter().map(|j| j.docid.as_str()));
+    if coll.excluded().exists() {
+        left -= read_intids(coll.excluded(), read_generation(coll)?)?;
+    }
+    left -= tombstones::deleted(coll)?;
+    Ok(left)
+}
+
+/// A sample of up to `n` of the `(intid, length)` pairs: they are split
+/// into `strata` groups of equal size by length, and an equal share drawn
+/// from each, the first groups taking one more where `n` doesn't divide
+/// evenly. The sample is in order of stratum, shortest first.
+fn sample_by_length(
+    docs: &mut [(u32, usize)],
+    n: usize,
+    strata: usize,
+    rng: &mut impl Rng,
+) -> Vec<u32> {
+    let strata = strata.clamp(1, docs.len().max(1));
+    let n = n.min(docs.len());
+    docs.sort_by_key(|(intid, len)| (*len, *intid));
+    let mut sample = Vec::with_capacity(n);
+    for s in 0..strata {
+        let group = &docs[s * docs.len() / strata..(s + 1) * docs.len() / strata];
+        let want = (n / strata + usize::from(s < n % strata)).min(group.len());
+        sample.extend(
+            index::sample(rng, group.len(), want)
+                .iter()
+                .map(|i| group[i].0),
+        );
+    }
+    sample
+}
+
+/// Print a simple random sample of the unreviewed documents, as docids or
+/// as qrels lines with `?` in place of the judgment. With `--by-length`,
+/// short and long documents alike are drawn, where a uniform sample of a
+/// collection dominated by short messages might not include a single long
+/// report.
+fn draw_sample(
+    coll: &CollectionLayout,
+    draw_args: &ArgMatches,
+    topic: Option<&Topic>,
+) -> Result<(), Box<dyn Error>> {
+    // Before any review there are no judgments to leave out
+    let mut judged = if draw_args.contains_id("qrels_file") || topic.is_some() {
+        read_qrels(qrels_or_topic(draw_args, topic)?)?
+    } else {
+        Vec::new()
+    };
+    for file in draw_args
+        .get_many::<String>("exclude")
+        .into_iter()
+        .flatten()
+    {
+        judged.extend(read_qrels(file)?);
+    }
+    let n = *draw_args.get_one::<usize>("num_docs").unwrap();
+    let docvec_fp = BufReader::new(File::open(coll.docvec())?);
+    let mut docvec: Vec<DocInfo> = bincode::deserialize_from(docvec_fp)?;
+    // The docid vector is in docid order
+    docvec.sort_by_key(|di| di.intid);
+    let docs = DocsDb::open(coll.docsdb());
+    let left = unreviewed(coll, &docs, &judged)?;
+    let mut rng = rng_for(topic)?;
+
+    let sample = match draw_args.get_one::<usize>("strata") {
+        Some(strata) => {
+            let mut feats = BufReader::new(File::open(coll.features())?);
+            let mut lengths = Vec::with_capacity(left.len() as usize);
+            for intid in left.iter() {
+                let fv = FeatureVec::read_at(&mut feats, docvec[intid as usize].offset)?;
+                lengths.push((intid, fv.num_features()));
+            }
+            sample_by_length(&mut lengths, n, *strata, &mut rng)
+        }
+        None => {
+            let left: Vec<u32> = left.into_iter().collect();
+            left.choose_multiple(&mut rng, n.min(left.len()))
+                .copied()
+                .collect()
+        }
+    };
+    let qid = match draw_args.get_one::<String>("qid") {
+        Some(qid) => qid.clone(),
+        None => topic.map_or("0".to_string(), Topic::name),
+    };
+    for intid in &sample {
+        let docid = &docvec[*intid as usize].docid;
+        if draw_args.get_flag("qrels") {
+            println!("{} 0 {} ?", qid, docid);
+        } else {
+            println!("{}", docid);
+        }
+    }
+    if sample.len() < n {
+        eprintln!("warning: only {} documents are unreviewed", sample.len());
+    }
+    Ok(())
+}
+
+/// Estimate recall from the review's judgments and a judged sample drawn
+/// by [`draw_sample`]. Sampled documents judged in the review log too are
+/// counted as part of the sample only.
+fn recall_estimate(
+    coll: &CollectionLayout,
+    recall_args: &ArgMatches,
+    topic: Option<&Topic>,
+) -> Result<(), Box<dyn Error>> {
+    let sample = read_qrels(recall_args.get_one::<String>("sample").unwrap())?;
+    let min = arg_or_topic(
+        recall_args,
+        "level",
+        topic.map(|t| t.config.relevance_level),
+    );
+    let target = *recall_args.get_one::<f64>("target").unwrap();
+    let confidence = *recall_args.get_one::<f64>("confidence").unwrap();
+    if !(0.0..1.0).contains(&confidence) {
+        return Err(failed(Failure::BadArgs, "Confidence must be in [0, 1)").into());
+    }
+    let sampled: HashSet<&str> = sample.iter().map(|j| j.docid.as_str()).collect();
+    let judged: Vec<Judgment> = read_qrels(qrels_or_topic(recall_args, topic)?)?
+        .into_iter()
+        .filter(|j| !sampled.contains(j.docid.as_str()))
+        .collect();
+    let docs = DocsDb::open(coll.docsdb());
+    let population = unreviewed(coll, &docs, &judged)?.len() as usize;
+    let found = judged.iter().filter(|j| j.rel >= min).count();
+    let sample_relevant = sample.iter().filter(|j| j.rel >= min).count();
+    let est = estimate_recall(found, population, sample.len(), sample_relevant, confidence);
+
+    println!(
+        "{} relevant found; {} of {} sampled from {} unreviewed are relevant",
+        est.found, est.sample_relevant, est.sample_size, est.population
+    );
+    println!(
+        "about {:.0} relevant left (at most {:.0}), recall {:.3} (at least {:.3})",
+        est.remaining, est.remaining_upper, est.recall, est.recall_lower
+    );
+    if est.reached(target) {
+        println!(
+            "you have likely reached {:.0}% recall ({:.0}% confidence)",
+            target * 100.0,
+            confidence * 100.0
+        );
+    } else {
+        println!(
+            "you cannot yet say you have reached {:.0}% recall ({:.0}% confidence)",
+            target * 100.0,
+            confidence * 100.0
+        );
+    }
+    Ok(())
+}
+
+/// Train every topic in a qrels file and print a TREC run. Each topic's
+/// judged documents are left out of its ranking.
+fn run_topics(coll: &CollectionLayout, run_args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let qrels = read_qrels(run_args.get_one::<String>("qrels_file").unwrap())?;
+    let model_dir = PathBuf::from(run_args.get_one::<String>("model_dir").unwrap());
+    let num_scores = *run_args.get_one::<usize>("num_scores").unwrap();
+    let min = *run_args.get_one::<i32>("level").unwrap();
+    let tag = run_args.get_one::<String>("tag").unwrap();
+    std::fs::create_dir_all(&model_dir)?;
+
+    let dict = load_dict(coll)?;
+    let docs = DocsDb::open(coll.docsdb());
+    let mut feats = BufReader::new(File::open(coll.features())?);
+    let examples = examples_by_topic(&qrels, &docs, &mut feats, min)?;
+
+    let mut models = ModelSet::new();
+    for topic in examples.keys() {
+        let path = model_dir.join(topic);
+        if path.exists() {
+            models.insert(topic, open_model(&path)?);
+        }
+    }
+    let header = collection_header(coll, &dict, None);
+    for model in models.models.values() {
+        check_header(model.as_ref(), &header)?;
+    }
+    let reports = models.train_all(&examples, || {
+        let mut model = Classifier::new(dict.last_tokid as usize, 200000);
+        model.header = Some(header.clone());
+        Box::new(model)
+    });
+    for (topic, report) in reports.iter() {
+        eprintln!("{}: {}", topic, report);
+        models.models[topic].save(&model_dir.join(topic))?;
+    }
+    for topic in examples.keys().filter(|t| !reports.contains_key(*t)) {
+        eprintln!(
+            "warning: topic {} needs relevant and nonrelevant judgments, skipped",
+            topic
+        );
+    }
+
+    let mut judged: HashMap<&str, Vec<&str>> = HashMap::new();
+    for j in qrels.iter() {
+        judged.entry(&j.topic).or_default().push(&j.docid);
+    }
+    feats.rewind()?;
+    let started = SystemTime::now();
+    let options = |topic: &str| {
+        let mut opts = SearchOptions::new(num_scores);
+        opts.exclude_docids(&docs, judged[topic].iter().copied());
+        opts
+    };
+    let mut progress = tqdm!();
+    let runs = models.score_all(&mut feats, options, |n| {
+        progress.update(n);
+    })?;
+    for (topic, hits) in runs.iter() {
+        if run_args.get_flag("metadata") {
+            let model_file = model_dir.join(topic);
+            let mut meta = run_metadata(coll, &model_file, &options(topic), None, started)?;
+            meta.topic = Some(topic.clone());
+            println!("{}", meta.to_comment());
+        }
+        for (rank, hit) in hits.iter().enumerate() {
+            println!(
+                "{} Q0 {} {} {} {}",
+                topic,
+                hit.docid,
+                rank + 1,
+                hit.score,
+                tag
+            );
+        }
+    }
+    Ok(())
+}
+
+fn gen_testdata(gen_args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let prefix = gen_args.get_one::<String>("out_prefix").unwrap();
+    let mut data = TestData::new(
+        *gen_args.get_one::<usize>("docs").unwrap(),
+        *gen_args.get_one::<usize>("vocab").unwrap(),
+    );
+    data.relevant = *gen_args.get_one::<f32>("relevant").unwrap();
+    data.seed = *gen_args.get_one::<u64>("seed").unwrap();
+    if data.docs == 0 || data.vocab < 2 * TestData::TOPIC_TERMS {
+        return Err(format!(
+            "Need at least one document and {} terms",
+            2 * TestData::TOPIC_TERMS
+        )
+        .into());
+    }
+
+    let relevant = data.write(format!("{}.jsonl", prefix), format!("{}.qrels", prefix))?;
+    println!(
+        "wrote {} documents, {} relevant, to {}.jsonl and {}.qrels",
+        data.docs,
+        relevant.len(),
+        prefix,
+        prefix
+    );
+    Ok(())
+}
+
+fn init_topic(coll: &CollectionLayout, init_args: &ArgMatches) -> Result<Topic, std::io::Error> {
+    let dir = init_args.get_one::<String>("dir").unwrap();
+    let dict = load_dict(coll)?;
+
+    // Store the collection as an absolute path so the topic works from anywhere
+    let mut config = TopicConfig::new(std::env::current_dir()?.join(coll.prefix()));
+    config.strategy = init_args.get_one::<String>("strategy").unwrap().parse()?;
+    config.batch_size = *init_args.get_one::<usize>("batch_size").unwrap();
+    config.negatives = *init_args.get_one::<usize>("negatives").unwrap();
+    config.relevance_level = *init_args.get_one::<i32>("level").unwrap();
+    config.prune_min = init_args.get_one::<f32>("prune_min").copied();
+    config.l1_ratio = *init_args.get_one::<f32>("l1_ratio").unwrap();
+    config.policy = RoundPolicy {
+        retrain_every: init_args.get_one::<usize>("retrain_every").copied(),
+        min_precision: init_args.get_one::<f64>("min_precision").copied(),
+        uncertainty_ratio: init_args.get_one::<f64>("uncertainty_ratio").copied(),
+        stop: !init_args.get_flag("no_stop"),
+    };
+
+    let header = collection_header(coll, &dict, Some(&config.tokenizer));
+    let topic = Topic::create(dir, config)?;
+    // An untrained model, so the topic scores and trains like any other
+    let mut model = Classifier::new(dict.last_tokid as usize, 200000);
+    model.header = Some(header);
+    model.save(topic.model_file())?;
+    println!("created topic {} in {}", topic.name(), topic.dir.display());
+    Ok(topic)
+}
+
+/// Take the steps the topic's round policy calls for, up to serving the
+/// next batch, waiting on judgments, or stopping. Retraining and serving
+/// run `train` and `score` (or `suggest`) with the topic's settings.
+fn review(topic: &Topic, review_args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let coll = topic.collection();
+    let model_file = topic.model_file();
+    let mut topic = topic.clone();
+    let defaults = |name: &str| subcommand_args(name, &[]);
+    loop {
+        let session = topic.session()?.unwrap_or_default();
+        let judgments = topic.judgments()?;
+        let decision = topic.config.policy.decide(
+            topic.config.strategy,
+            &session,
+            &judgments,
+            topic.config.relevance_level,
+        );
+        eprintln!("{}", decision);
+        if review_args.get_flag("dry_run") {
+            return Ok(());
+        }
+        match decision.action {
+            Action::Switch(strategy) => {
+                topic.config.strategy = strategy;
+                topic.save()?;
+            }
+            Action::Retrain => {
+                let (_, report) =
+                    train_qrels(&coll, &model_file, &defaults("train")?, Some(&topic))?;
+                eprintln!("{}", report);
+            }
+            Action::Serve => {
+                match topic.config.strategy {
+                    Strategy::Relevance => {
+                        score_collection(&coll, &model_file, &defaults("score")?, Some(&topic))?
+                    }
+                    Strategy::Uncertainty => {
+                        suggest_documents(&coll, &model_file, &defaults("suggest")?, Some(&topic))?
+                    }
+                };
+                return Ok(());
+            }
+            Action::Stop | Action::Wait { .. } => return Ok(()),
+        }
+    }
+}
+
+/// Run one round of review in one go: log the judgments of the last batch,
+/// train on them from where the model left off, and print the next batch,
+/// chosen by the topic's strategy with judged and routed documents left
+/// out.
+fn review_round(topic: &Topic, round_args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let coll = topic.collection();
+    let model_file = topic.model_file();
+    if let Some(file) = round_args.get_one::<String>("judgments") {
+        let mut import = vec![file.as_str()];
+        if round_args.get_flag("csv") {
+            import.push("--csv");
+        }
+        import_judgments(topic, &subcommand_args("import-judgments", &import)?)?;
+    }
+
+    let trained_on = topic.session()?.map_or(0, |s| s.judgments);
+    if topic.judgments()?.len() > trained_on {
+        let update = round_args.get_one::<u32>("update").unwrap().to_string();
+        let train: Vec<&str> = if round_args.get_flag("fresh") {
+            vec![]
+        } else {
+            vec!["--update", &update]
+        };
+        let (_, report) = train_qrels(
+            &coll,
+            &model_file,
+            &subcommand_args("train", &train)?,
+            Some(topic),
+        )?;
+        eprintln!("{}", report);
+    }
+
+    let mut serve = Vec::new();
+    for efn in round_args
+        .get_many::<String>("exclude")
+        .into_iter()
+        .flatten()
+    {
+        serve.extend(["--exclude", efn.as_str()]);
+    }
+    match topic.config.strategy {
+        Strategy::Relevance => score_collection(
+            &coll,
+            &model_file,
+            &subcommand_args("score", &serve)?,
+            Some(topic),
+        )?,
+        Strategy::Uncertainty => suggest_documents(
+            &coll,
+            &model_file,
+            &subcommand_args("suggest", &serve)?,
+            Some(topic),
+        )?,
+    };
+    Ok(())
+}
+
+/// A subcommand's arguments as parsed from `args`, so that one command can
+/// run another as its command line would.
+fn subcommand_args(name: &str, args: &[&str]) -> Result<ArgMatches, clap::Error> {
+    let matches = cli().try_get_matches_from(["mycal", name].iter().chain(args))?;
+    Ok(matches.subcommand_matches(name).cloned().unwrap())
+}
+
+/// Fit the topic's gain curve and print a projection for each batch
+/// schedule, marking the recommended one.
+fn plan_review(topic: &Topic, plan_args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let curve = GainCurve::from_judgments(&topic.judgments()?, topic.config.relevance_level);
+    let fit = curve
+        .fit()
+        .ok_or("Planning needs judgments from at least two rounds")?;
+    let budget = Budget {
+        docs_per_hour: *plan_args.get_one::<f64>("docs_per_hour").unwrap(),
+        hours: *plan_args.get_one::<f64>("hours").unwrap(),
+        round_overhead: *plan_args.get_one::<f64>("round_minutes").unwrap() / 60.0,
+    };
+    let hours_per_day = *plan_args.get_one::<f64>("hours_per_day").unwrap();
+    let stop = *plan_args.get_one::<f64>("stop_precision").unwrap();
+    let sizes: Vec<u64> = match plan_args.get_many::<u64>("batch_sizes") {
+        Some(sizes) => sizes.copied().collect(),
+        None => {
+            let b = topic.config.batch_size as u64;
+            vec![(b / 2).max(1), b, b * 2]
+        }
+    };
+    let schedules: Vec<BatchSchedule> = sizes
+        .iter()
+        .flat_map(|n| [BatchSchedule::Fixed(*n), BatchSchedule::Growing(*n)])
+        .collect();
+
+    println!(
+        "{} reviewed, {} relevant; batch precision now {:.3}",
+        curve.reviewed(),
+        curve.relevant(),
+        fit.precision_at(curve.reviewed() as f64)
+    );
+    if let Some(left) = fit.remaining(curve.reviewed() as f64) {
+        println!("about {:.0} relevant left to find", left);
+    }
+    let (projections, best) = plan(&curve, &fit, &schedules, &budget, stop);
+    println!("schedule\trounds\treviewed\trelevant\trecall\thours\tdone");
+    for (i, p) in projections.iter().enumerate() {
+        println!(
+            "{}{}\t{}\t{}\t{:.0}\t{}\t{:.1}\t{}",
+            if Some(i) == best { "* " } else { "" },
+            p.schedule,
+            p.rounds,
+            p.reviewed,
+            p.relevant,
+            p.recall.map_or("-".to_string(), |r| format!("{:.3}", r)),
+            p.hours,
+            if p.finished {
+                date_after_days(p.hours / hours_per_day)
+            } else {
+                "over budget".to_string()
+            }
+        );
+    }
+    Ok(())
+}
+
+/// The calendar date `days` from now, as YYYY-MM-DD (UTC).
+fn date_after_days(days: f64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    civil_date(now + (days.ceil() * 86400.0) as u64)
+}
+
+/// Seconds since the epoch as a UTC date.
+fn civil_date(secs: u64) -> String {
+    // Days since the epoch to a civil date, after Howard Hinnant
+    let z = (secs / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Print what each of the session's batches cost: the time spent scoring
+/// for it, and how long its judgments took to come back.
+fn review_analytics(topic: &Topic, analytics_args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let session = topic
+        .session()?
+        .ok_or("Topic has no session; start one with session new")?;
+    let stats = round_stats(&session, &topic.judgments()?, topic.config.relevance_level);
+    if analytics_args.get_one::<String>("format").unwrap() == "json" {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+    let opt = |v: Option<String>| v.unwrap_or_default();
+    println!("round,served_at,served,judged,relevant,scoring_seconds,first_judged_at,last_judged_at,first_latency,latency,throughput");
+    for s in &stats {
+        println!(
+            "{},{},{},{},{},{},{},{},{},{},{}",
+            s.round,
+            s.served_at,
+            s.served,
+            s.judged,
+            s.relevant,
+            opt(s.scoring_seconds.map(|t| format!("{:.3}", t))),
+            opt(s.first_judged_at.map(|t| t.to_string())),
+            opt(s.last_judged_at.map(|t| t.to_string())),
+            opt(s.first_latency.map(|t| t.to_string())),
+            opt(s.latency.map(|t| t.to_string())),
+            opt(s.throughput.map(|t| format!("{:.1}", t))),
+        );
+    }
+    Ok(())
+}
+
+/// Print the session's rounds: documents served, judged and found
+/// relevant, the model snapshot committed after each, and drift alarms.
+fn session_status(topic: &Topic) -> Result<(), Box<dyn Error>> {
+    let session = topic
+        .session()?
+        .ok_or("Topic has no session; start one with session new")?;
+    let judgments = topic.judgments()?;
+    let min = topic.config.relevance_level;
+    // Sessions from before start times were kept have none
+    let started = match session.created {
+        0 => String::new(),
+        t => format!("started {}, ", civil_date(t)),
+    };
+    println!(
+        "session {}{} judged, {} relevant",
+        started,
+        judgments.len(),
+        judgments.iter().filter(|j| j.rel >= min).count()
+    );
+    let last = session
+        .batches
+        .iter()
+        .map(|b| b.round)
+        .chain(judgments.iter().filter_map(|j| j.round))
+        .max()
+        .unwrap_or(0);
+    println!("round\tserved\tjudged\trelevant\tsnapshot\talarms");
+    for round in 1..=last {
+        let served: usize = session
+            .batches
+            .iter()
+            .filter(|b| b.round == round)
+            .map(|b| b.docids.len())
+            .sum();
+        let judged: Vec<&Judgment> = judgments
+            .iter()
+            .filter(|j| j.round == Some(round))
+            .collect();
+        let snapshot = session.snapshots.iter().find(|s| s.round == round);
+        let alarms: Vec<&str> = session
+            .alarms
+            .iter()
+            .filter(|a| a.round == round)
+            .map(|a| a.kind.as_str())
+            .collect();
+        println!(
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            round,
+            served,
+            judged.len(),
+            judged.iter().filter(|j| j.rel >= min).count(),
+            snapshot.map_or("-", |s| s.file.as_str()),
+            if alarms.is_empty() {
+                "-".to_string()
+            } else {
+                alarms.join(",")
+            }
+        );
+    }
+    Ok(())
+}
+
+/// Write the documents judged so far as qrels, or as an intid bitmap that
+/// `--exclude` takes.
+fn export_session(topic: &Topic, export_args: &ArgMatches) -> Result<(), std::io::Error> {
+    let out_file = export_args.get_one::<String>("out_file").unwrap();
+    let judgments = topic.judgments()?;
+    if export_args.get_one::<String>("format").unwrap() == "intids" {
+        let coll = topic.collection();
+        let docs = DocsDb::open(coll.docsdb());
+        let intids = docs.intids_for(judgments.iter().map(|j| j.docid.as_str()));
+        println!("{} of {} judged docs found", intids.len(), judgments.len());
+        return write_intids(&intids, read_generation(&coll)?, out_file);
+    }
+    let mut out = BufWriter::new(File::create(out_file)?);
+    for j in &judgments {
+        writeln!(
+            out,
+            "{} {} {} {}",
+            j.topic,
+            j.round.unwrap_or(0),
+            j.docid,
+            j.rel
+        )?;
+    }
+    out.flush()
+}
+
+fn import_judgments(topic: &Topic, import_args: &ArgMatches) -> Result<(), std::io::Error> {
+    let file = import_args.get_one::<String>("file").unwrap();
+    let mut incoming = if import_args.get_flag("csv") || file.ends_with(".csv") {
+        read_judgment_csv(file)?
+    } else {
+        read_qrels(file)?
+    };
+    if let Some(source) = import_args.get_one::<String>("source_topic") {
+        incoming.retain(|j| j.topic == *source);
+    }
+    if !import_args.get_flag("keep_rounds") {
+        incoming.iter_mut().for_each(|j| j.round = None);
+    }
+    let round = match import_args.get_one::<u32>("round") {
+        Some(r) => *r,
+        None => topic.last_round()? + 1,
+    };
+
+    // The last judgment of a document in the file wins, and documents
+    // already in the log keep their existing judgment
+    let total = incoming.len();
+    let mut seen: HashSet<String> = topic.judgments()?.into_iter().map(|j| j.docid).collect();
+    let mut fresh: Vec<Judgment> = incoming
+        .into_iter()
+        .rev()
+        .filter(|j| seen.insert(j.docid.clone()))
+        .collect();
+    fresh.reverse();
+
+    topic.append_judgments(round, &fresh)?;
+    println!(
+        "imported {} judgments into {} ({} duplicate or already judged)",
+        fresh.len(),
+        topic.name(),
+        total - fresh.len()
+    );
+    Ok(())
+}
+
+/// Log a judgment of a document in the topic's collection. The next
+/// `train` learns from it along with the rest of the log.
+fn judge(topic: &Topic, judge_args: &ArgMatches) -> Result<(), std::io::Error> {
+    let docid = judge_args.get_one::<String>("docid").unwrap();
+    let rel = *judge_args.get_one::<i32>("rel").unwrap();
+    if DocsDb::open(topic.collection().docsdb())
+        .get(docid)
+        .is_none()
+    {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} is not in the collection", docid),
+        ));
+    }
+    let judgment = topic.add_judgment(docid, rel)?;
+    println!(
+        "judged {} {} in round {}",
+        judgment.docid,
+        judgment.rel,
+        judgment.round.unwrap_or(0)
+    );
+    Ok(())
+}
+
+/// The guardrails file option. Each command says in its help what the
+/// terms are kept out of.
+fn guardrails_arg() -> Arg {
+    Arg::new("guardrails").long("guardrails")
+}
+
+/// The guardrails named by the arguments or kept with the topic, if any.
+fn load_guardrails(
+    args: &ArgMatches,
+    topic: Option<&Topic>,
+    dict: &Dict,
+) -> Result<Option<Arc<Guardrails>>, std::io::Error> {
+    let file = match args.get_one::<String>("guardrails") {
+        Some(f) => PathBuf::from(f),
+        None => match topic.map(|t| t.guardrails_file()) {
+            Some(f) if f.exists() => f,
+            _ => return Ok(None),
+        },
+    };
+    let guardrails = Guardrails::load(file, dict)?;
+    eprintln!("{} banned terms", guardrails.banned.len());
+    Ok(Some(Arc::new(guardrails)))
+}
+
+/// Options for a recency prior, shared by the scorers.
+fn recency_args() -> [Arg; 3] {
+    [
+        Arg::new("recency_half_life")
+            .long("recency-half-life")
+            .value_parser(clap::value_parser!(f32))
+            .help(
+                "Add a recency prior to each score that halves every this many days of \
+                 document age (needs a collection built with --date-field)",
+            ),
+        Arg::new("recency_weight")
+            .long("recency-weight")
+            .value_parser(clap::value_parser!(f32))
+            .default_value("1.0")
+            .requires("recency_half_life")
+            .help("The prior given a document dated today"),
+        Arg::new("as_of")
+            .long("as-of")
+            .value_parser(parse_as_of)
+            .requires("recency_half_life")
+            .help("Measure document ages from this date, YYYY-MM-DD (default today)"),
+    ]
+}
+
+fn parse_as_of(s: &str) -> Result<i32, String> {
+    parse_date(s).ok_or_else(|| format!("{} is not a date", s))
+}
+
+/// The recency prior the arguments ask for, if any.
+fn recency_prior(
+    coll: &CollectionLayout,
+    args: &ArgMatches,
+) -> Result<Option<Recency>, std::io::Error> {
+    let Some(half_life) = args.get_one::<f32>("recency_half_life") else {
+        return Ok(None);
+    };
+    if !coll.dates().exists() {
+        return Err(std::io::Error::new(
+            ErrorKind::NotFound,
+            "the collection has no document dates; rebuild it with --date-field",
+        ));
+    }
+    Ok(Some(Recency {
+        dates: Dates::load(coll.dates())?,
+        as_of: args.get_one::<i32>("as_of").copied().unwrap_or_else(today),
+        half_life: *half_life,
+        weight: *args.get_one::<f32>("recency_weight").unwrap(),
+    }))
+}
+
+/// The value of an option, unless it was left at its default and the topic
+/// configures it.
+fn arg_or_topic<T: Clone + Send + Sync + 'static>(
+    args: &ArgMatches,
+    id: &str,
+    from_topic: Option<T>,
+) -> T {
+    match (args.value_source(id), from_topic) {
+        (Some(ValueSource::DefaultValue), Some(v)) => v,
+        _ => args.get_one::<T>(id).unwrap().clone(),
+    }
+}
+
+fn parse_l1_ratio(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(r) if (0.0..=1.0).contains(&r) => Ok(r),
+        _ => Err(format!("{} is not between 0 and 1", s)),
+    }
+}
+
+/// The topic session's next random number generator, so the review's
+/// draws can be repeated, or else one seeded from the OS.
+fn rng_for(topic: Option<&Topic>) -> Result<StdRng, std::io::Error> {
+    match topic {
+        Some(topic) => topic.rng(),
+        None => Ok(StdRng::from_entropy()),
+    }
+}
+
+/// The qrels file named on the command line, or else the topic's judgments.
+fn qrels_or_topic(args: &ArgMatches, topic: Option<&Topic>) -> Result<PathBuf, std::io::Error> {
+    args.get_one::<String>("qrels_file")
+        .map(PathBuf::from)
+        .or_else(|| topic.map(Topic::judgments_file))
+        .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidInput, "No qrels file or --topic"))
+}
+
+fn train_qrels(
+    coll: &CollectionLayout,
+    model_file: &Path,
+    qrels_args: &ArgMatches,
+    topic: Option<&Topic>,
+) -> Result<(Box<dyn Model>, TrainReport), std::io::Error> {
+    if qrels_args.get_flag("resume") {
+        return resume_training(coll, model_file, qrels_args, topic);
+    }
+    let dict = load_dict(coll)?;
+
+    let docs = DocsDb::open(coll.docsdb());
+    let mut feats = BufReader::new(File::open(coll.features())?);
+
+    let config = topic.map(|t| &t.config);
+    let qrels_file = qrels_or_topic(qrels_args, topic)?;
+    let min = &arg_or_topic(qrels_args, "level", config.map(|c| c.relevance_level));
+    let mut rng = rng_for(topic)?;
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let args = cli().get_matches();
-    let coll_prefix = args.get_one::<String>("coll").unwrap();
-    let model_file = args.get_one::<String>("model").unwrap();
+    let mut pos = Vec::new();
+    let mut neg = Vec::new();
+    let mut using = HashSet::new();
+    let mut grades = HashMap::new();
 
-    match args.subcommand() {
-        Some(("train", qrels_args)) => {
-            train_qrels(coll_prefix, model_file, qrels_args)?;
+    for (j, fv) in judged_fvs(&docs, &mut feats, qrels_file)? {
+        using.insert(j.docid.clone());
+        grades.insert(j.docid.clone(), j.rel);
+        if j.rel < *min {
+            neg.push(fv);
+            println!("qrels-neg {} {}", j.docid, j.rel);
+        } else {
+            pos.push(fv);
+            println!("qrels-pos {} {}", j.docid, j.rel);
+        };
+    }
+
+    let num_neg = &arg_or_topic(qrels_args, "negatives", config.map(|c| c.negatives));
+    if *num_neg > 0 {
+        let mut docvec = load_docvec(coll)?;
+        let deleted = tombstones::deleted(coll)?;
+        docvec.retain(|di| !deleted.contains(di.intid as u32));
+        let strategy = qrels_args
+            .get_one::<NegativeStrategy>("negative_strategy")
+            .unwrap();
+        // Score-based sampling asks the model being updated, if there is one
+        let by_score = matches!(
+            strategy,
+            NegativeStrategy::LowScore | NegativeStrategy::Tail | NegativeStrategy::Deciles
+        );
+        let current = if by_score && model_file.exists() {
+            Some(open_model(model_file)?.scoring_model())
+        } else {
+            if by_score {
+                eprintln!("warning: no model to score negatives with, sampling uniformly");
+            }
+            None
+        };
+        let mut sampler = NegativeSampler {
+            docvec: &docvec,
+            feats: &mut feats,
+            exclude: &mut using,
+            rng: &mut rng,
+        };
+        let sampled = sampler.sample(*num_neg, strategy, &pos, current.as_ref())?;
+        if sampled.len() < *num_neg {
+            eprintln!(
+                "warning: only {} of {} negatives could be sampled",
+                sampled.len(),
+                num_neg
+            );
         }
-        Some(("score", score_args)) => {
-            score_collection(coll_prefix, model_file, score_args)?;
+        for fv in sampled {
+            println!("samp-neg {} {}", fv.docid, 0);
+            neg.push(fv);
         }
-        Some(("score_one", score_one_args)) => {
-            score_one_doc(coll_prefix, model_file, score_one_args)?;
+    }
+
+    if pos.is_empty() || neg.is_empty() {
+        return Err(failed(
+            Failure::NoTrainingData,
+            format!(
+                "Training needs relevant and nonrelevant examples, and has {} and {}",
+                pos.len(),
+                neg.len()
+            ),
+        ));
+    }
+
+    // A new model is made once the examples are in hand, since tuning
+    // needs them
+    let model_path = model_file;
+    let header = collection_header(coll, &dict, config.map(|c| c.tokenizer.as_str()));
+    let tune = qrels_args.get_flag("tune");
+    let seed = rng.gen();
+    let mut model: Box<dyn Model>;
+    let existing = model_path.exists();
+    if existing {
+        if tune {
+            eprintln!("warning: --tune only applies to new models");
         }
-        Some((&_, _)) => panic!("No subcommand specified"),
-        None => panic!("No subcommand specified"),
+        model = open_model(model_file)?;
+        check_header(model.as_ref(), &header)?;
+    } else {
+        let class_weights = *qrels_args.get_one::<ClassWeights>("class_weights").unwrap();
+        model = match qrels_args.get_one::<String>("learner").unwrap().as_str() {
+            "nb" => {
+                let mut nb = NaiveBayes::new(dict.last_tokid as usize);
+                nb.class_weights = class_weights;
+                Box::new(nb)
+            }
+            "rocchio" => Box::new(Rocchio::new(dict.last_tokid as usize)),
+            _ => {
+                let batch_size = *qrels_args.get_one::<u32>("batch_size").unwrap();
+                let num_iters = match qrels_args.get_one::<u32>("iterations") {
+                    Some(n) => *n,
+                    None => (200000 / batch_size).max(1),
+                };
+                let mut c = Classifier::new(dict.last_tokid as usize, num_iters);
+                c.seed = Some(seed);
+                c.fit_intercept = !qrels_args.get_flag("no_intercept");
+                c.batch_size = batch_size;
+                c.class_weights = class_weights;
+                c.l1_ratio = arg_or_topic(qrels_args, "l1_ratio", config.map(|c| c.l1_ratio));
+                c.train_config = TrainConfig {
+                    schedule: *qrels_args.get_one::<EtaSchedule>("eta_schedule").unwrap(),
+                    eta0: *qrels_args.get_one::<f32>("eta0").unwrap(),
+                    power_t: *qrels_args.get_one::<f32>("power_t").unwrap(),
+                };
+                if tune && pos.len() > 1 && neg.len() > 1 {
+                    let folds = *qrels_args.get_one::<usize>("folds").unwrap();
+                    let (lambda, auc) =
+                        c.tune_lambda(&mut pos, &mut neg, &Classifier::LAMBDA_GRID, folds);
+                    println!("chose lambda {} (AUC {:.5})", lambda, auc);
+                } else if tune {
+                    eprintln!(
+                        "warning: too few examples to cross-validate, keeping default lambda"
+                    );
+                }
+                Box::new(c)
+            }
+        };
+    }
+
+    let grade_weights = qrels_args.get_one::<GradeWeights>("grade_weights");
+    let weigh = |model: &mut Box<dyn Model>, pos: &[FeatureVec], neg: &[FeatureVec]| {
+        if let Some(scheme) = grade_weights {
+            model.set_example_weights(grade_example_weights(scheme, &grades, *min, pos, neg));
+        }
+    };
+    weigh(&mut model, &pos, &neg);
+    model.set_seed(seed);
+    let guardrails = load_guardrails(qrels_args, topic, &dict)?;
+    if let Some(guardrails) = &guardrails {
+        model.set_guardrails(guardrails.clone());
+    }
+    let checkpoints = checkpoints_arg(model_file, qrels_args);
+    if let Some(checkpoints) = checkpoints.clone() {
+        if qrels_args.contains_id("holdout") {
+            eprintln!("warning: no checkpoints are written while holding out examples");
+        }
+        model.set_checkpoints(checkpoints);
+    }
+
+    let report = match qrels_args.get_one::<f32>("holdout") {
+        Some(frac) if pos.len() > 1 && neg.len() > 1 => {
+            let mut validation = Validation {
+                positives: hold_out(&mut pos, *frac, &mut rng),
+                negatives: hold_out(&mut neg, *frac, &mut rng),
+                check_every: 1000,
+                patience: *qrels_args.get_one::<u32>("patience").unwrap(),
+            };
+            weigh(&mut model, &pos, &neg);
+            let report = model.train_early_stopping(&pos, &neg, &validation);
+            pos.append(&mut validation.positives);
+            neg.append(&mut validation.negatives);
+            report
+        }
+        Some(_) => {
+            eprintln!("warning: too few examples to hold out, training on all of them");
+            model.train(&pos, &neg)
+        }
+        None => match qrels_args.get_one::<u32>("update") {
+            Some(n) if existing => model.update(&pos, &neg, *n),
+            Some(_) => {
+                eprintln!("warning: no model to update, training a new one");
+                model.train(&pos, &neg)
+            }
+            None => model.train(&pos, &neg),
+        },
+    };
+    if qrels_args.get_flag("per_language") {
+        let langs = Languages::load(coll.languages())?;
+        let examples = examples_by_language(&langs, &docs, &pos, &neg);
+        train_language_blocks(model_file, &examples, &dict, &header, guardrails.as_ref())?;
+    }
+    finish_model(&mut model, pos, neg, model_file, header, qrels_args, topic)?;
+    if checkpoints.is_some() {
+        remove_checkpoint(model_file)?;
+    }
+    Ok((model, report))
+}
+
+/// Prune, calibrate and save a trained model as the train arguments say.
+/// A topic's own model is committed along with its session record.
+fn finish_model(
+    model: &mut Box<dyn Model>,
+    mut pos: Vec<FeatureVec>,
+    mut neg: Vec<FeatureVec>,
+    model_file: &Path,
+    header: ModelHeader,
+    qrels_args: &ArgMatches,
+    topic: Option<&Topic>,
+) -> Result<(), std::io::Error> {
+    let config = topic.map(|t| &t.config);
+    let prune = match (
+        qrels_args.get_one::<f32>("prune_min"),
+        qrels_args.get_one::<usize>("prune_top"),
+    ) {
+        (Some(t), _) => Some(Prune::MinWeight(*t)),
+        (_, Some(k)) => Some(Prune::TopK(*k)),
+        _ => config.and_then(|c| c.prune_min).map(Prune::MinWeight),
+    };
+    if let Some(how) = prune {
+        let zeroed = model.prune(how);
+        println!("pruned {} weights", zeroed);
+    }
+    if qrels_args.get_flag("calibrate") {
+        let labels: Vec<i8> = pos
+            .iter()
+            .map(|_| 1)
+            .chain(neg.iter().map(|_| -1))
+            .collect();
+        pos.append(&mut neg);
+        model.calibrate(&pos, &labels);
+        let platt = model.calibration().unwrap();
+        println!("calibration a {:.5} b {:.5}", platt.a, platt.b);
+    }
+    if model.header().is_none() {
+        model.set_header(header);
+    }
+    match topic.filter(|t| t.model_file() == model_file) {
+        Some(topic) => {
+            let session = topic.commit(|path| model.save(path))?;
+            println!(
+                "committed model for round {} ({} judgments)",
+                session.round, session.judgments
+            );
+            Ok(())
+        }
+        None => model.save(model_file),
+    }
+}
+
+fn checkpoints_arg(model_file: &Path, qrels_args: &ArgMatches) -> Option<Checkpoints> {
+    qrels_args
+        .get_one::<u32>("checkpoint_every")
+        .map(|every| Checkpoints {
+            every: *every,
+            file: Checkpoint::file_for(model_file),
+        })
+}
+
+/// A finished run's checkpoint is of no more use.
+fn remove_checkpoint(model_file: &Path) -> Result<(), std::io::Error> {
+    let file = Checkpoint::file_for(model_file);
+    if file.exists() {
+        std::fs::remove_file(file)?;
     }
     Ok(())
 }
 
-fn train_qrels(
-    coll_prefix: &str,
-    model_file: &str,
+/// Carry on the training run checkpointed next to `model_file`, reading
+/// its examples back from the collection, and finish the model as
+/// [`train_qrels`] would.
+fn resume_training(
+    coll: &CollectionLayout,
+    model_file: &Path,
     qrels_args: &ArgMatches,
-) -> Result<Classifier, std::io::Error> {
-    let docsdb_file = coll_prefix.to_string() + ".lib";
-    let dict_file = coll_prefix.to_string() + ".dct";
-    let feat_file = coll_prefix.to_string() + ".ftr";
+    topic: Option<&Topic>,
+) -> Result<(Box<dyn Model>, TrainReport), std::io::Error> {
+    let file = Checkpoint::file_for(model_file);
+    let checkpoint = Checkpoint::load(&file).map_err(|e| {
+        std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Could not read checkpoint {}: {}", file.display(), e),
+        )
+    })?;
+    let dict = load_dict(coll)?;
+    let docs = DocsDb::open(coll.docsdb());
+    let mut feats = BufReader::new(File::open(coll.features())?);
+    let mut read = |docids: &[String]| -> Result<Vec<FeatureVec>, std::io::Error> {
+        let mut fvs = Vec::with_capacity(docids.len());
+        for docid in docids {
+            let di = docs.get(docid).ok_or_else(|| {
+                std::io::Error::new(
+                    ErrorKind::NotFound,
+                    format!("{} is not in the collection", docid),
+                )
+            })?;
+            let mut fv = read_fv(&mut feats, &di)?;
+            if fv.squared_norm == 0.0 {
+                fv.compute_norm();
+            }
+            fvs.push(fv);
+        }
+        Ok(fvs)
+    };
+    let pos = read(&checkpoint.positives)?;
+    let neg = read(&checkpoint.negatives)?;
+    println!(
+        "resuming at iteration {} of {}",
+        checkpoint.model.steps, checkpoint.end
+    );
 
-    let dict = Dict::load(&dict_file).unwrap();
+    let checkpoints = checkpoints_arg(model_file, qrels_args);
+    let guardrails = load_guardrails(qrels_args, topic, &dict)?;
+    let (model, report) = Classifier::resume(checkpoint, checkpoints, guardrails, &pos, &neg);
+    let mut model: Box<dyn Model> = Box::new(model);
+    let tokenizer = topic.map(|t| t.config.tokenizer.as_str());
+    let header = collection_header(coll, &dict, tokenizer);
+    finish_model(&mut model, pos, neg, model_file, header, qrels_args, topic)?;
+    remove_checkpoint(model_file)?;
+    Ok((model, report))
+}
 
-    let model_path = Path::new(model_file);
-    let mut model: Classifier;
-    if model_path.exists() {
-        model = Classifier::load(model_file).unwrap();
-    } else {
-        model = Classifier::new(dict.m.len(), 200000);
+/// Train and save a block of the model for each language with both
+/// relevant and nonrelevant examples, next to the model file. Existing
+/// blocks go on training.
+fn train_language_blocks(
+    model_file: &Path,
+    examples: &BTreeMap<String, Examples>,
+    dict: &Dict,
+    header: &ModelHeader,
+    guardrails: Option<&Arc<Guardrails>>,
+) -> Result<(), std::io::Error> {
+    let mut blocks = ModelSet::new();
+    for lang in examples.keys() {
+        let path = block_file(model_file, lang);
+        if path.exists() {
+            let mut block = open_model(&path)?;
+            check_header(block.as_ref(), header)?;
+            if let Some(guardrails) = guardrails {
+                block.set_guardrails(guardrails.clone());
+            }
+            blocks.insert(lang, block);
+        }
+    }
+    let reports = blocks.train_all(examples, || {
+        let mut block = Classifier::new(dict.last_tokid as usize, 200000);
+        block.guardrails = guardrails.cloned();
+        Box::new(block)
+    });
+    for lang in examples.keys().filter(|l| !reports.contains_key(*l)) {
+        eprintln!(
+            "warning: too few {} examples for a block, using the shared model",
+            lang
+        );
+    }
+    for (lang, report) in reports {
+        println!("{} block: {}", lang, report);
+        let block = blocks.models.get_mut(&lang).unwrap();
+        if block.header().is_none() {
+            block.set_header(header.clone());
+        }
+        block.save(&block_file(model_file, &lang))?;
     }
+    Ok(())
+}
 
-    let docs = DocsDb::open(&docsdb_file);
-    let mut feats = BufReader::new(File::open(feat_file).expect("Could not open feature file"));
+/// A collection's languages, and a model's blocks for some of them
+type LanguageBlocks = (Languages, BTreeMap<String, ScoringModel>);
+
+/// The collection's languages and the blocks `model_file` has for them,
+/// or `None` if the collection has no languages or the model no blocks.
+fn language_blocks(
+    coll: &CollectionLayout,
+    model_file: &Path,
+    tokenizer: Option<&str>,
+) -> Result<Option<LanguageBlocks>, std::io::Error> {
+    if !coll.languages().exists() {
+        return Ok(None);
+    }
+    let langs = Languages::load(coll.languages())?;
+    let mut blocks = BTreeMap::new();
+    for lang in langs.intids.keys() {
+        let path = block_file(model_file, lang);
+        if path.exists() {
+            let block = open_model(&path)?;
+            check_collection(block.as_ref(), coll, tokenizer)?;
+            blocks.insert(lang.clone(), block.scoring_model());
+        }
+    }
+    Ok((!blocks.is_empty()).then_some((langs, blocks)))
+}
 
-    let qrels_file = qrels_args.get_one::<String>("qrels_file").unwrap();
+/// Weights for training examples from the grades of their judgments, by
+/// docid. Sampled negatives have no grade and count once.
+fn grade_example_weights(
+    scheme: &GradeWeights,
+    grades: &HashMap<String, i32>,
+    level: i32,
+    pos: &[FeatureVec],
+    neg: &[FeatureVec],
+) -> ExampleWeights {
+    let weigh = |xs: &[FeatureVec]| {
+        xs.iter()
+            .map(|fv| {
+                grades
+                    .get(&fv.docid)
+                    .map_or(1.0, |rel| scheme.weight(*rel, level))
+            })
+            .collect()
+    };
+    ExampleWeights {
+        positives: weigh(pos),
+        negatives: weigh(neg),
+    }
+}
 
-    let qrels = BufReader::new(File::open(qrels_file).expect("Could not open qrels file"));
-    let mut pos = Vec::new();
-    let mut neg = Vec::new();
-    let mut using = HashSet::new();
+/// Move a random `frac` of `examples` (at least one, and never all) into
+/// the returned held-out set.
+fn hold_out(examples: &mut Vec<FeatureVec>, frac: f32, rng: &mut impl Rng) -> Vec<FeatureVec> {
+    examples.shuffle(rng);
+    let n = ((examples.len() as f32 * frac).ceil() as usize).clamp(1, examples.len() - 1);
+    examples.split_off(examples.len() - n)
+}
 
-    qrels
-        .lines()
-        .filter(|result| !result.as_ref().unwrap().starts_with('#'))
-        .map(|line| {
-            let line = line.unwrap();
-            line.split_whitespace().map(|x| x.to_string()).collect()
-        })
-        .for_each(|fields: Vec<String>| {
-            if let Some(dib) = docs.db.get(&fields[2]).unwrap() {
-                using.insert(fields[2].clone());
-                let di: DocInfo = bincode::deserialize(&dib).unwrap();
-                feats
-                    .seek(SeekFrom::Start(di.offset))
-                    .expect("Seek error in feats");
-                let mut fv =
-                    FeatureVec::read_from(&mut feats).expect("Error reading feature vector");
-                if fv.squared_norm == 0.0 {
-                    fv.compute_norm();
-                }
-                let rel = i32::from_str(&fields[3]).unwrap();
-                let min = qrels_args.get_one::<i32>("level").unwrap();
-
-                if rel < *min {
-                    neg.push(fv);
-                    println!("qrels-neg {} {}", fields[2], fields[3]);
-                } else {
-                    pos.push(fv);
-                    println!("qrels-pos {} {}", fields[2], fields[3]);
-                };
+/// Fetch the feature vectors of the documents judged in a qrels file,
+/// skipping docids that are not in the collection.
+fn judged_fvs(
+    docs: &DocsDb,
+    feats: &mut BufReader<File>,
+    qrels_file: impl AsRef<Path>,
+) -> Result<Vec<(Judgment, FeatureVec)>, std::io::Error> {
+    let mut judged = Vec::new();
+    for j in read_qrels(qrels_file)? {
+        if let Some(di) = docs.get(&j.docid) {
+            let mut fv = read_fv(feats, &di)?;
+            if fv.squared_norm == 0.0 {
+                fv.compute_norm();
             }
-        });
+            judged.push((j, fv));
+        }
+    }
+    Ok(judged)
+}
 
-    let num_neg = qrels_args.get_one::<usize>("negatives").unwrap();
-    if *num_neg > 0 {
-        let docvec_file = coll_prefix.to_string() + ".dvc";
-        let docvec_fp = BufReader::new(File::open(docvec_file)?);
-        let docvec: Vec<DocInfo> = bincode::deserialize_from(docvec_fp).unwrap();
-        let numdocs = docvec.len();
-        let mut rng = rand::thread_rng();
-        let uniform = Uniform::new(0, numdocs as usize);
-
-        (&mut rng)
-            .sample_iter(uniform)
-            .take(*num_neg)
-            .map(|mut i| {
-                let mut my_mut_rng = rand::thread_rng();
-                while using.contains(&docvec[i].docid) {
-                    i = (&mut my_mut_rng).sample(uniform);
-                }
-                using.insert(docvec[i].docid.clone());
-                println!("samp-neg {} {}", docvec[i].docid, 0);
-                docvec[i].offset
-            })
-            .for_each(|offset| {
-                feats
-                    .seek(SeekFrom::Start(offset))
-                    .expect("Seek error in feats");
-                let fv = FeatureVec::read_from(&mut feats).expect("Error reading feature vector");
-                neg.push(fv);
-            });
+fn score_collection(
+    coll: &CollectionLayout,
+    model_file: &Path,
+    score_args: &ArgMatches,
+    topic: Option<&Topic>,
+) -> Result<Vec<Hit>, std::io::Error> {
+    let started = SystemTime::now();
+    let model = open_model(model_file)?;
+    let tokenizer = topic.map(|t| t.config.tokenizer.as_str());
+    check_collection(model.as_ref(), coll, tokenizer)?;
+    let model = model.scoring_model();
+    let mut opts = search_options(coll, score_args, topic)?;
+    opts.recency = recency_prior(coll, score_args)?.map(Arc::new);
+    if let Some(n) = score_args.get_one::<usize>("sample") {
+        estimate_collection(coll, &model, &opts, *n, score_args, topic)?;
+        return Ok(Vec::new());
+    }
+
+    // Per-language blocks take precedence, then the chunked or Arrow copy,
+    // which are faster to scan
+    let mut progress = tqdm!();
+    let routing = language_blocks(coll, model_file, tokenizer)?;
+    let top = if let Some((langs, blocks)) = &routing {
+        let mut feats = BufReader::new(File::open(coll.features())?);
+        search_by_language(&model, blocks, langs, &mut feats, &opts, |n| {
+            progress.update(n);
+        })?
+    } else if coll.chunked_features().exists() {
+        let mut chunks = ChunkedFeatures::open(coll.chunked_features())?;
+        search_chunked(&[(&model, &opts)], &mut chunks, |n| {
+            progress.update(n);
+        })?
+        .pop()
+        .unwrap_or_default()
+    } else if let Some(top) = search_arrow_copy(coll, &model, &opts, |n| {
+        progress.update(n);
+    })? {
+        top
+    } else {
+        let mut feats = BufReader::new(File::open(coll.features())?);
+        search(&model, &mut feats, &opts, |n| {
+            progress.update(n);
+        })?
+    };
+    opts.cancel.check()?;
+    if score_args.get_flag("metadata") {
+        let meta = run_metadata(coll, model_file, &opts, tokenizer, started)?;
+        println!("{}", meta.to_comment());
+    }
+    if score_args.contains_id("summary") {
+        write_batch_summary(coll, &model, routing.as_ref(), &top, score_args, topic)?;
+    }
+
+    let proba = score_args.get_flag("proba");
+    if proba && !model.is_calibrated() {
+        eprintln!("warning: model is not calibrated, printing the logistic of the score");
+    }
+    top.iter().for_each(|hit| {
+        if proba {
+            println!("{} {}", hit.docid, model.probability(hit.score))
+        } else {
+            println!("{} {}", hit.docid, hit.score)
+        }
+    });
+    record_batch(coll, topic, model_file, &top, Some(&model), started)?;
+
+    Ok(top)
+}
+
+/// Score the collection's Arrow copy, if it has one.
+#[cfg(feature = "arrow")]
+fn search_arrow_copy(
+    coll: &CollectionLayout,
+    model: &ScoringModel,
+    opts: &SearchOptions,
+    progress: impl FnMut(usize),
+) -> Result<Option<Vec<Hit>>, std::io::Error> {
+    if !coll.arrow_features().exists() {
+        return Ok(None);
     }
+    let mut feats = mycal::columnar::ArrowFeatures::open(coll.arrow_features())?;
+    Ok(mycal::search::search_arrow(&[(model, opts)], &mut feats, progress)?.pop())
+}
 
-    model.train(&pos, &neg);
-    model.save(model_file)?;
-    Ok(model)
+#[cfg(not(feature = "arrow"))]
+fn search_arrow_copy(
+    _coll: &CollectionLayout,
+    _model: &ScoringModel,
+    _opts: &SearchOptions,
+    _progress: impl FnMut(usize),
+) -> Result<Option<Vec<Hit>>, std::io::Error> {
+    Ok(None)
 }
 
-#[derive(Eq, Debug, Clone)]
-struct DocScore {
-    docid: String,
-    score: OrderedFloat<f32>,
+/// Log hits served by a topic's own model as the next round's batch, and
+/// warn of drift from the last one. Hits ranked by `model`'s score keep
+/// their scores and leading terms for the comparison. `started` is when
+/// scoring began, for the batch's cost.
+fn record_batch(
+    coll: &CollectionLayout,
+    topic: Option<&Topic>,
+    model_file: &Path,
+    hits: &[Hit],
+    ranked_by: Option<&ScoringModel>,
+    started: SystemTime,
+) -> Result<(), std::io::Error> {
+    let Some(topic) = topic.filter(|t| t.model_file() == model_file) else {
+        return Ok(());
+    };
+    let mut batch = Batch {
+        docids: hits.iter().map(|h| h.docid.clone()).collect(),
+        seconds: started.elapsed().ok().map(|d| d.as_secs_f64()),
+        ..Batch::default()
+    };
+    if let Some(model) = ranked_by {
+        let docs = DocsDb::open(coll.docsdb());
+        let mut feats = BufReader::new(File::open(coll.features())?);
+        let mut fvs = Vec::with_capacity(hits.len());
+        for di in hits.iter().filter_map(|h| docs.get(&h.docid)) {
+            fvs.push(read_fv(&mut feats, &di)?);
+        }
+        let explained: Vec<(&ScoringModel, &FeatureVec)> =
+            fvs.iter().map(|fv| (model, fv)).collect();
+        let summary = summarize_batch(&explained, TERMS_PER_DOC, None);
+        batch.scores = hits.iter().map(|h| h.score).collect();
+        batch.terms = summary
+            .terms
+            .iter()
+            .take(TERMS_PER_DOC)
+            .map(|t| t.tokid)
+            .collect();
+    }
+    for alarm in topic.record_batch(batch)? {
+        eprintln!("warning: drift: {}", alarm);
+    }
+    Ok(())
 }
 
-impl Ord for DocScore {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.score.cmp(&other.score).reverse()
+/// Summarize the terms behind a batch of hits into the summary file, each
+/// hit explained by the language block that scored it, if any.
+fn write_batch_summary(
+    coll: &CollectionLayout,
+    model: &ScoringModel,
+    routing: Option<&LanguageBlocks>,
+    hits: &[Hit],
+    score_args: &ArgMatches,
+    topic: Option<&Topic>,
+) -> Result<(), std::io::Error> {
+    let summary_file = score_args.get_one::<String>("summary").unwrap();
+    let num_terms = *score_args.get_one::<usize>("summary_terms").unwrap();
+    let dict = load_dict(coll)?;
+    let guardrails = load_guardrails(score_args, topic, &dict)?;
+    let docs = DocsDb::open(coll.docsdb());
+    let mut feats = BufReader::new(File::open(coll.features())?);
+    let mut fvs = Vec::with_capacity(hits.len());
+    for hit in hits {
+        let di = docs.get(&hit.docid).ok_or_else(|| {
+            failed(
+                Failure::CorruptIndex,
+                format!("{} is missing from the docs db", hit.docid),
+            )
+        })?;
+        fvs.push(read_fv(&mut feats, &di)?);
     }
+    let scored_by = |hit: &Hit| {
+        routing
+            .and_then(|(langs, blocks)| blocks.get(langs.language_of(hit.intid)?))
+            .unwrap_or(model)
+    };
+    let batch: Vec<(&ScoringModel, &FeatureVec)> =
+        hits.iter().map(scored_by).zip(fvs.iter()).collect();
+    let summary = summarize_batch(&batch, TERMS_PER_DOC, guardrails.as_deref());
+    let mut out = BufWriter::new(File::create(summary_file)?);
+    summary.write(&dict, num_terms, &mut out)?;
+    out.flush()
+}
+
+/// Print the unjudged documents nearest the decision boundary, the ones
+/// whose judgments would teach the model the most. A topic's judgments are
+/// left out; otherwise pass them with `--exclude`.
+fn suggest_documents(
+    coll: &CollectionLayout,
+    model_file: &Path,
+    suggest_args: &ArgMatches,
+    topic: Option<&Topic>,
+) -> Result<Vec<Hit>, std::io::Error> {
+    let started = SystemTime::now();
+    let model = open_model(model_file)?;
+    let tokenizer = topic.map(|t| t.config.tokenizer.as_str());
+    check_collection(model.as_ref(), coll, tokenizer)?;
+    let model = model.scoring_model();
+    let opts = search_options(coll, suggest_args, topic)?;
+
+    let mut feats = BufReader::new(File::open(coll.features())?);
+    let mut progress = tqdm!();
+    let hits = suggest(&model, &mut feats, &opts, |n| {
+        progress.update(n);
+    })?;
+    eprintln!();
+    opts.cancel.check()?;
+    let proba = suggest_args.get_flag("proba");
+    hits.iter().for_each(|hit| {
+        if proba {
+            println!("{} {}", hit.docid, model.probability(hit.score))
+        } else {
+            println!("{} {}", hit.docid, hit.score)
+        }
+    });
+    record_batch(coll, topic, model_file, &hits, None, started)?;
+    Ok(hits)
 }
 
-impl PartialOrd for DocScore {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other).reverse())
+/// Print the collection's best BM25 matches for a keyword query. Query
+/// terms not in the dictionary (and not hashed) are reported and ignored.
+fn keyword_search(
+    coll: &CollectionLayout,
+    search_args: &ArgMatches,
+    topic: Option<&Topic>,
+) -> Result<Vec<Hit>, std::io::Error> {
+    let dict = load_dict(coll)?;
+    let mut query = Vec::new();
+    for text in search_args.get_many::<String>("query").unwrap() {
+        for tok in tokens(text) {
+            match dict.lookup(&tok) {
+                Some(id) => query.push(id),
+                None => eprintln!("warning: {} is not in the collection", tok),
+            }
+        }
     }
+    let opts = search_options(coll, search_args, topic)?;
+
+    let mut feats = BufReader::new(File::open(coll.features())?);
+    let mut progress = tqdm!();
+    let top = bm25_search(&query, &dict, &mut feats, &opts, |n| {
+        progress.update(n);
+    })?;
+    eprintln!();
+    opts.cancel.check()?;
+    top.iter()
+        .for_each(|hit| println!("{} {}", hit.docid, hit.score));
+    Ok(top)
 }
 
-impl PartialEq for DocScore {
-    fn eq(&self, other: &Self) -> bool {
-        self.score == other.score
+/// Score a sample of the collection and print the estimated score
+/// quantiles, the estimated score of the nth best document, and with
+/// strata, how many of the top n each stratum is expected to supply.
+fn estimate_collection(
+    coll: &CollectionLayout,
+    model: &ScoringModel,
+    opts: &SearchOptions,
+    n: usize,
+    score_args: &ArgMatches,
+    topic: Option<&Topic>,
+) -> Result<(), std::io::Error> {
+    let docvec = load_docvec(coll)?;
+    let mut feats = BufReader::new(File::open(coll.features())?);
+    let prefixes: Vec<String> = score_args
+        .get_many::<String>("strata")
+        .map(|p| p.cloned().collect())
+        .unwrap_or_default();
+    let est = estimate_scores(
+        model,
+        &docvec,
+        &mut feats,
+        opts,
+        n,
+        &prefixes,
+        &mut rng_for(topic)?,
+    )?;
+
+    println!(
+        "sampled {} of {} documents",
+        est.samples.len(),
+        est.population
+    );
+    for q in [0.5, 0.9, 0.99, 0.999] {
+        if let Some(score) = est.quantile(q) {
+            println!("quantile {} score {}", q, score);
+        }
     }
+    let Some(cutoff) = est.cutoff(opts.num_results) else {
+        return Ok(());
+    };
+    let cutoff = opts.min_score.map_or(cutoff, |min| cutoff.max(min));
+    println!(
+        "top {} cutoff {} (about {:.0} documents at or above)",
+        opts.num_results,
+        cutoff,
+        est.count_above(cutoff)
+    );
+    if !prefixes.is_empty() {
+        for (prefix, count) in est.composition(cutoff) {
+            println!("{}\t{:.0}", prefix, count);
+        }
+    }
+    Ok(())
 }
 
-fn score_collection(
-    coll_prefix: &str,
-    model_file: &str,
+/// Score candidates from another retrieval system and print them best
+/// first. Only the first field of each line is read, so a list of
+/// `docid score` lines works too.
+fn rerank_candidates(
+    coll: &CollectionLayout,
+    model_file: &Path,
+    rerank_args: &ArgMatches,
+) -> Result<Vec<Hit>, std::io::Error> {
+    let model = open_model(model_file)?;
+    check_collection(model.as_ref(), coll, None)?;
+    let model = model.scoring_model();
+    let docs = DocsDb::open(coll.docsdb());
+    let mut feats = BufReader::new(File::open(coll.features())?);
+
+    let input: Box<dyn BufRead> = match rerank_args.get_one::<String>("candidates") {
+        Some(f) => Box::new(BufReader::new(File::open(f)?)),
+        None => Box::new(std::io::stdin().lock()),
+    };
+    let mut candidates = Vec::new();
+    for line in input.lines() {
+        if let Some(docid) = line?.split_whitespace().next() {
+            candidates.push(docid.to_string());
+        }
+    }
+
+    let (hits, missing) = rerank(
+        &model,
+        &docs,
+        &mut feats,
+        candidates.iter().map(|d| d.as_str()),
+        recency_prior(coll, rerank_args)?.as_ref(),
+    )?;
+    for docid in missing {
+        eprintln!("warning: {} is not in the collection", docid);
+    }
+    let proba = rerank_args.get_flag("proba");
+    hits.iter().for_each(|hit| {
+        if proba {
+            println!("{} {}", hit.docid, model.probability(hit.score))
+        } else {
+            println!("{} {}", hit.docid, hit.score)
+        }
+    });
+    Ok(hits)
+}
+
+/// Describe a scoring pass that began at `started` and has just finished.
+fn run_metadata(
+    coll: &CollectionLayout,
+    model_file: &Path,
+    opts: &SearchOptions,
+    tokenizer: Option<&str>,
+    started: SystemTime,
+) -> Result<RunMetadata, std::io::Error> {
+    Ok(RunMetadata {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        topic: None,
+        model: model_file.display().to_string(),
+        model_hash: hash_file(model_file)?,
+        collection: coll.prefix().display().to_string(),
+        collection_hash: hash_file(coll.dict())?,
+        fingerprint: read_fingerprint(coll),
+        tokenizer: tokenizer.unwrap_or("porter").to_string(),
+        strategy: opts.strategy,
+        num_results: opts.num_results,
+        num_excluded: opts.exclude.len(),
+        exclude_hash: hash_intids(&opts.exclude),
+        started: started
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+        seconds: started.elapsed().map_or(0.0, |d| d.as_secs_f64()),
+    })
+}
+
+/// Resolve the score options, including every source of excluded
+/// documents, into intids.
+fn search_options(
+    coll: &CollectionLayout,
     score_args: &ArgMatches,
-) -> Result<Vec<DocScore>, std::io::Error> {
-    let model = Classifier::load(model_file).unwrap();
-    let n = score_args.get_one::<usize>("num_scores").unwrap();
-    let exclude_fn = score_args.get_one::<String>("exclude");
+    topic: Option<&Topic>,
+) -> Result<SearchOptions, std::io::Error> {
+    let n = arg_or_topic(score_args, "num_scores", topic.map(|t| t.config.batch_size));
+    let mut opts = SearchOptions::new(n);
+    opts.min_score = score_args.get_one::<f32>("min_score").copied();
+    opts.cancel = ctrl_c();
+    if let Some(topic) = topic {
+        opts.strategy = topic.config.strategy;
+    }
+
+    if let Some(exclude_fns) = score_args.get_many::<String>("exclude") {
+        let docs = DocsDb::open(coll.docsdb());
+        for efn in exclude_fns {
+            let judged = read_qrels(efn)?;
+            opts.exclude_docids(&docs, judged.iter().map(|j| j.docid.as_str()));
+        }
+    }
+    let generation = read_generation(coll)?;
+    if let Some(exclude_fns) = score_args.get_many::<String>("exclude_ids") {
+        for efn in exclude_fns {
+            opts.exclude_intids(&read_intids(efn, generation)?);
+        }
+    }
+    if coll.excluded().exists() && !score_args.get_flag("include_routed") {
+        opts.exclude_intids(&read_intids(coll.excluded(), generation)?);
+    }
+    opts.exclude_intids(&tombstones::deleted(coll)?);
+    // Documents already judged for the topic are never worth reviewing again
+    if let Some(topic) = topic {
+        let docs = DocsDb::open(coll.docsdb());
+        let judged = read_qrels(topic.judgments_file())?;
+        opts.exclude_docids(&docs, judged.iter().map(|j| j.docid.as_str()));
+    }
+    Ok(opts)
+}
+
+fn exclude_from_qrels(
+    coll: &CollectionLayout,
+    excl_args: &ArgMatches,
+) -> Result<(), std::io::Error> {
+    let qrels_file = excl_args.get_one::<String>("qrels_file").unwrap();
+    let out_file = excl_args.get_one::<String>("out_file").unwrap();
 
-    let mut exclude = HashSet::new();
-    match exclude_fn {
-        Some(efn) => {
-            let exclude_fp = BufReader::new(File::open(efn)?);
-            exclude_fp
-                .lines()
-                .map(|line| line.unwrap().split_whitespace().nth(1).unwrap().to_string())
-                .for_each(|d| {
-                    exclude.insert(d);
-                });
+    let docs = DocsDb::open(coll.docsdb());
+    let judged = read_qrels(qrels_file)?;
+    let intids = docs.intids_for(judged.iter().map(|j| j.docid.as_str()));
+    println!("{} of {} judged docs found", intids.len(), judged.len());
+    write_intids(&intids, read_generation(coll)?, out_file)
+}
+
+/// Add the given docids to the collection's tombstones. Docids not in the
+/// collection are reported and skipped.
+fn delete_docs(coll: &CollectionLayout, delete_args: &ArgMatches) -> Result<(), std::io::Error> {
+    let mut docids: Vec<String> = delete_args
+        .get_many::<String>("docids")
+        .map(|d| d.cloned().collect())
+        .unwrap_or_default();
+    if let Some(file) = delete_args.get_one::<String>("file") {
+        for line in BufReader::new(File::open(file)?).lines() {
+            docids.extend(line?.split_whitespace().next().map(|d| d.to_string()));
         }
-        _ => (),
     }
+    let docs = DocsDb::open(coll.docsdb());
+    for docid in docids.iter().filter(|d| docs.get_intid(d).is_none()) {
+        eprintln!("warning: {} is not in the collection", docid);
+    }
+    let intids = docs.intids_for(docids.iter().map(|d| d.as_str()));
+    let added = tombstones::delete(coll, &intids)?;
+    println!(
+        "deleted {} documents, {} awaiting compaction",
+        added,
+        tombstones::deleted(coll)?.len()
+    );
+    Ok(())
+}
+
+fn term_report(
+    coll: &CollectionLayout,
+    report_args: &ArgMatches,
+    topic: Option<&Topic>,
+) -> Result<(), std::io::Error> {
+    let qrels_file = qrels_or_topic(report_args, topic)?;
+    let min = arg_or_topic(
+        report_args,
+        "level",
+        topic.map(|t| t.config.relevance_level),
+    );
+    let metric = report_args.get_one::<String>("metric").unwrap();
+    let num_terms = *report_args.get_one::<usize>("num_terms").unwrap();
+
+    let dict = load_dict(coll)?;
+    let docs = DocsDb::open(coll.docsdb());
+    let mut feats = BufReader::new(File::open(coll.features())?);
+
+    let (pos, neg): (Vec<_>, Vec<_>) = judged_fvs(&docs, &mut feats, qrels_file)?
+        .into_iter()
+        .partition(|(j, _)| j.rel >= min);
+    let pos: Vec<FeatureVec> = pos.into_iter().map(|(_, fv)| fv).collect();
+    let neg: Vec<FeatureVec> = neg.into_iter().map(|(_, fv)| fv).collect();
 
-    let feat_file = coll_prefix.to_string() + ".ftr";
+    let mut scores = score_terms(&pos, &neg);
+    match metric.as_str() {
+        "ig" => scores.sort_by(|a, b| b.info_gain.total_cmp(&a.info_gain)),
+        _ => scores.sort_by(|a, b| b.chi_square.total_cmp(&a.chi_square)),
+    }
 
-    let mut top_scores: MinMaxHeap<DocScore> = MinMaxHeap::new();
+    // The dictionary holds idf = log10(N/df), so invert it to recover df
+    let num_docs = docs.db.len() as f32;
+    let names = dict.tokens_by_id();
+    println!("{} relevant, {} nonrelevant judged", pos.len(), neg.len());
+    println!("term\tdir\tpos_df\tneg_df\tcoll_df\tchi2\tig");
+    for ts in scores.iter().take(num_terms) {
+        let coll_df = dict
+            .df
+            .get(&ts.tokid)
+            .map_or(0.0, |idf| num_docs / 10f32.powf(*idf));
+        let dir = if ts.favors_relevant(pos.len(), neg.len()) {
+            "+"
+        } else {
+            "-"
+        };
+        println!(
+            "{}\t{}\t{}\t{}\t{:.0}\t{:.4}\t{:.4}",
+            names.get(&ts.tokid).unwrap_or(&"?"),
+            dir,
+            ts.pos_df,
+            ts.neg_df,
+            coll_df,
+            ts.chi_square,
+            ts.info_gain
+        );
+    }
+    Ok(())
+}
 
-    let mut feats = BufReader::new(File::open(feat_file)?);
+/// Print the collection's overall statistics and those of the given
+/// terms, or of its most frequent ones.
+fn term_stats(coll: &CollectionLayout, stats_args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let dict = load_dict(coll)?;
+    let mut feats = BufReader::new(File::open(coll.features())?);
     let mut progress = tqdm!();
+    let stats = termstats::collect(&dict, &mut feats, |n| {
+        progress.update(n);
+    })?;
+    eprintln!();
 
-    while let Ok(fv) = FeatureVec::read_from(&mut feats) {
-        if exclude.contains(&fv.docid) {
-            continue;
+    let names = dict.tokens_by_id();
+    let terms: Vec<(String, TermStats)> = match stats_args.get_many::<String>("terms") {
+        Some(terms) => {
+            let mut found = Vec::new();
+            for tok in terms.filter_map(|t| tokens(t).next()) {
+                match dict.lookup(&tok) {
+                    Some(tokid) => found.push((tok.into_owned(), stats.term(tokid, &dict))),
+                    None => eprintln!("warning: {} is not in the collection", tok),
+                }
+            }
+            found
         }
-        let score = model.inner_product(&fv);
-        top_scores.push(DocScore {
-            docid: fv.docid,
-            score: OrderedFloat(score),
-        });
+        None => stats
+            .by_df()
+            .into_iter()
+            .take(*stats_args.get_one::<usize>("num_terms").unwrap())
+            .map(|t| (names.get(&t.tokid).unwrap_or(&"?").to_string(), t.clone()))
+            .collect(),
+    };
+
+    let c = &stats.collection;
+    if stats_args.get_one::<String>("format").unwrap() == "json" {
+        let terms: Vec<serde_json::Value> = terms
+            .iter()
+            .map(|(term, t)| serde_json::json!({ "term": term, "stats": t }))
+            .collect();
+        let report = serde_json::json!({ "collection": c, "terms": terms });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    println!(
+        "{} documents, {} without features",
+        c.num_docs, c.empty_docs
+    );
+    match c.hashed_buckets {
+        Some(buckets) => println!("{} exact terms, {} hashed buckets", c.exact_terms, buckets),
+        None => println!("{} exact terms", c.exact_terms),
+    }
+    println!(
+        "{} term ids used, {} in only one document",
+        c.terms_used, c.singletons
+    );
+    println!(
+        "{} features standing for {:.0} tokens, {} bytes",
+        c.num_features, c.num_tokens, c.feature_bytes
+    );
+    println!("term\ttokid\tdf\tcf\tidf\tbytes");
+    for (term, t) in &terms {
+        println!(
+            "{}\t{}\t{}\t{:.0}\t{:.4}\t{}",
+            term, t.tokid, t.df, t.cf, t.idf, t.bytes
+        );
+    }
+    Ok(())
+}
+
+/// Print the terms that co-occur most strongly with a term in the relevant
+/// judged documents, weighted by collection idf.
+fn related_terms(
+    coll: &CollectionLayout,
+    related_args: &ArgMatches,
+    topic: Option<&Topic>,
+) -> Result<(), Box<dyn Error>> {
+    let term = related_args.get_one::<String>("term").unwrap();
+    let qrels_file = qrels_or_topic(related_args, topic)?;
+    let min = arg_or_topic(
+        related_args,
+        "level",
+        topic.map(|t| t.config.relevance_level),
+    );
+    let num_terms = *related_args.get_one::<usize>("num_terms").unwrap();
+
+    let dict = load_dict(coll)?;
+    let tok = tokens(term).next().ok_or("No term to look up")?;
+    let tokid = dict
+        .lookup(&tok)
+        .ok_or_else(|| format!("{} is not in the collection", tok))?;
+    let docs = DocsDb::open(coll.docsdb());
+    let mut feats = BufReader::new(File::open(coll.features())?);
+    let pos: Vec<FeatureVec> = judged_fvs(&docs, &mut feats, qrels_file)?
+        .into_iter()
+        .filter(|(j, _)| j.rel >= min)
+        .map(|(_, fv)| fv)
+        .collect();
+
+    let related = cooccurring(tokid, &pos, |t| {
+        f64::from(dict.df.get(&t).copied().unwrap_or(0.0))
+    });
+    let names = dict.tokens_by_id();
+    let with = pos
+        .iter()
+        .filter(|fv| fv.features.iter().any(|f| f.id == tokid))
+        .count();
+    println!("{} of {} relevant documents have {}", with, pos.len(), tok);
+    println!("term\tboth\tweight");
+    for c in related.iter().take(num_terms) {
+        println!(
+            "{}\t{}\t{:.4}",
+            names.get(&c.tokid).unwrap_or(&"?"),
+            c.both,
+            c.weight
+        );
+    }
+    Ok(())
+}
+
+/// The header of a model trained on `coll`. Without a topic the tokenizer
+/// is the default one.
+fn collection_header(coll: &CollectionLayout, dict: &Dict, tokenizer: Option<&str>) -> ModelHeader {
+    let mut header = ModelHeader::new(
+        coll.prefix().display().to_string(),
+        tokenizer.unwrap_or("porter"),
+        dict.last_tokid,
+    );
+    header.fingerprint = read_fingerprint(coll);
+    header.hashed = dict.hashed;
+    header
+}
+
+/// Refuse a model built for another collection. Models saved before
+/// headers existed can't be checked, and are used as they are.
+fn check_header(model: &dyn Model, header: &ModelHeader) -> Result<(), std::io::Error> {
+    match model.header() {
+        Some(h) => h.check(header),
+        None => Ok(()),
+    }
+}
+
+/// The collection's dictionary. One that is there but can't be read means
+/// the collection is corrupt.
+fn load_dict(coll: &CollectionLayout) -> Result<Dict, std::io::Error> {
+    Dict::load(coll.dict()).map_err(|e| {
+        let failure = match e.as_ref() {
+            bincode::ErrorKind::Io(io) if io.kind() == ErrorKind::NotFound => {
+                Failure::MissingCollection
+            }
+            _ => Failure::CorruptIndex,
+        };
+        failed(
+            failure,
+            format!("Could not read {}: {}", coll.dict().display(), e),
+        )
+    })
+}
+
+/// The collection's docid vector. One that can't be read is corrupt.
+fn load_docvec(coll: &CollectionLayout) -> Result<Vec<DocInfo>, std::io::Error> {
+    let bytes = std::fs::read(coll.docvec())?;
+    decode_bounded(&bytes).map_err(|e| {
+        failed(
+            Failure::CorruptIndex,
+            format!("Could not read {}: {}", coll.docvec().display(), e),
+        )
+    })
+}
+
+/// The feature vector of `di`. One that can't be read means the feature
+/// file is corrupt.
+fn read_fv(feats: &mut BufReader<File>, di: &DocInfo) -> Result<FeatureVec, std::io::Error> {
+    FeatureVec::read_at(feats, di.offset).map_err(|e| {
+        failed(
+            Failure::CorruptIndex,
+            format!("Could not read the features of {}: {}", di.docid, e),
+        )
+    })
+}
+
+/// Load a model of any learner type. One that is there but can't be read
+/// is corrupt.
+fn open_model(model_file: &Path) -> Result<Box<dyn Model>, std::io::Error> {
+    load_model(model_file).map_err(|e| {
+        let failure = match e.as_ref() {
+            bincode::ErrorKind::Io(io) if io.kind() == ErrorKind::NotFound => Failure::Other,
+            _ => Failure::CorruptIndex,
+        };
+        failed(
+            failure,
+            format!("Could not read model {}: {}", model_file.display(), e),
+        )
+    })
+}
+
+/// Read a model that has to be a logistic regression [`Classifier`], for
+/// the commands that work on its weights and scale directly.
+fn open_classifier(model_file: &Path) -> Result<Classifier, std::io::Error> {
+    Classifier::load(model_file).map_err(|e| {
+        let (failure, reason) = match e.as_ref() {
+            bincode::ErrorKind::Io(io) if io.kind() == ErrorKind::NotFound => {
+                (Failure::Other, e.to_string())
+            }
+            // Another learner's model
+            bincode::ErrorKind::Io(io) if io.kind() == ErrorKind::InvalidInput => {
+                (Failure::BadArgs, io.to_string())
+            }
+            _ => (Failure::CorruptIndex, e.to_string()),
+        };
+        failed(
+            failure,
+            format!("Could not read model {}: {}", model_file.display(), reason),
+        )
+    })
+}
+
+fn check_collection(
+    model: &dyn Model,
+    coll: &CollectionLayout,
+    tokenizer: Option<&str>,
+) -> Result<(), std::io::Error> {
+    let dict = load_dict(coll)?;
+    check_header(model, &collection_header(coll, &dict, tokenizer))
+}
+
+/// Say whether a model can score a collection and why, or fail saying why
+/// not. A model trained on a collection hashed into a fixed feature space
+/// can score any other built with the same tokenizer and buckets.
+fn check_model_file(
+    coll: &CollectionLayout,
+    model_file: &Path,
+    topic: Option<&Topic>,
+) -> Result<(), Box<dyn Error>> {
+    let model = open_model(model_file)?;
+    let dict = load_dict(coll)?;
+    let tokenizer = topic.map(|t| t.config.tokenizer.as_str());
+    let ours = collection_header(coll, &dict, tokenizer);
+    let Some(header) = model.header() else {
+        println!("model has no header, so it can't be checked");
+        return Ok(());
+    };
+    header.check(&ours)?;
+    match (&header.fingerprint, &ours.fingerprint, header.hashed) {
+        (Some(a), Some(b), Some(tail)) if a != b => println!(
+            "compatible: shares the hashed feature space ({} buckets) of {}",
+            tail.buckets, header.collection
+        ),
+        _ => println!("compatible: built for this collection's vocabulary"),
+    }
+    Ok(())
+}
 
-        while top_scores.len() > *n {
-            top_scores.pop_min();
+fn export_model_file(
+    coll: Option<&CollectionLayout>,
+    model_file: &Path,
+    export_args: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    let mut model = open_classifier(model_file)?;
+    let dict = coll.map(load_dict).transpose()?;
+    if let Some(epsilon) = export_args.get_one::<f32>("epsilon") {
+        let privacy = Privacy {
+            clip: *export_args.get_one::<f32>("clip").unwrap(),
+            epsilon: *epsilon,
+            min_df: *export_args.get_one::<f32>("min_df").unwrap(),
+        };
+        model = privatize_model(&model, &privacy, coll, dict.as_ref(), export_args)?;
+    }
+    let format = *export_args.get_one::<ExportFormat>("format").unwrap();
+    let mut out = BufWriter::new(File::create(
+        export_args.get_one::<String>("out_file").unwrap(),
+    )?);
+    model.export(format, dict.as_ref(), &mut out)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Privatize a model for export and report what it cost.
+fn privatize_model(
+    model: &Classifier,
+    privacy: &Privacy,
+    coll: Option<&CollectionLayout>,
+    dict: Option<&Dict>,
+    export_args: &ArgMatches,
+) -> Result<Classifier, Box<dyn Error>> {
+    let docs = coll.map(|c| DocsDb::open(c.docsdb()));
+    let df_source = dict.zip(docs.as_ref().map(|d| d.db.len()));
+    if df_source.is_none() {
+        eprintln!("warning: no collection, so no rare terms are dropped");
+    }
+    let mut judged = None;
+    if let Some(qrels_file) = export_args.get_one::<String>("qrels_file") {
+        match (coll, &docs) {
+            (Some(coll), Some(docs)) => {
+                let mut feats = BufReader::new(File::open(coll.features())?);
+                let (pos, neg): (Vec<_>, Vec<_>) = judged_fvs(docs, &mut feats, qrels_file)?
+                    .into_iter()
+                    .partition(|(j, _)| j.rel > 0);
+                let pos: Vec<FeatureVec> = pos.into_iter().map(|(_, fv)| fv).collect();
+                let neg: Vec<FeatureVec> = neg.into_iter().map(|(_, fv)| fv).collect();
+                judged = Some((pos, neg));
+            }
+            _ => eprintln!("warning: no collection to read the judged documents from"),
         }
-        progress.update(1);
     }
 
-    let top = top_scores.into_vec_desc();
-    top.iter()
-        .for_each(|ds| println!("{} {}", ds.docid, ds.score));
+    let (private, report) = model.privatize(
+        privacy,
+        df_source,
+        judged.as_ref().map(|(p, n)| (&p[..], &n[..])),
+        &mut rand::thread_rng(),
+    );
+    println!(
+        "clipped {} weights, dropped {} rare terms, cosine similarity {:.4}",
+        report.clipped, report.dropped, report.cosine
+    );
+    if let Some((before, after)) = report.auc {
+        println!("AUC on judgments {:.4} before, {:.4} after", before, after);
+    }
+    Ok(private)
+}
 
-    Ok(top)
+/// The stored fingerprint, or else one computed from the dictionary and
+/// docs db and saved. The build settings of an older collection aren't
+/// known, so they don't enter into it.
+fn collection_fingerprint(coll: &CollectionLayout) -> Result<String, Box<dyn Error>> {
+    if let Some(fp) = read_fingerprint(coll) {
+        return Ok(fp);
+    }
+    let dict = load_dict(coll)?;
+    let docs = DocsDb::open(coll.docsdb());
+    let mut docids: Vec<(u64, String)> = docs
+        .db
+        .iter()
+        .map(|res| bincode::deserialize::<DocInfo>(&res.unwrap().1).unwrap())
+        .map(|di| (di.intid, di.docid))
+        .collect();
+    docids.sort_unstable();
+    let fp = fingerprint("", &dict, docids.iter().map(|(_, d)| d.as_str()));
+    std::fs::write(coll.fingerprint(), format!("{}\n", fp))?;
+    Ok(fp)
+}
+
+/// Remap a model after its collection's vocabulary was rebuilt. The
+/// model's header then describes the new collection.
+fn remap_model_file(
+    coll: &CollectionLayout,
+    model_file: &Path,
+    remap_args: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    let mut model = open_classifier(model_file)?;
+    let old_coll = CollectionLayout::new(remap_args.get_one::<String>("old_coll").unwrap());
+    let from = load_dict(&old_coll)?;
+    let to = load_dict(coll)?;
+    if let Some(header) = &model.header {
+        header.check(&collection_header(
+            &old_coll,
+            &from,
+            Some(&header.tokenizer),
+        ))?;
+    }
+
+    let dropped = model.remap(&from, &to);
+    let kept = model.w.iter().filter(|w| **w != 0.0).count();
+    println!("remapped {} weights, dropped {}", kept, dropped);
+    let tokenizer = model.header.as_ref().map(|h| h.tokenizer.clone());
+    model.header = Some(collection_header(coll, &to, tokenizer.as_deref()));
+    let out_file = remap_args
+        .get_one::<String>("out_file")
+        .map_or(model_file, Path::new);
+    model.save(out_file)?;
+    Ok(())
+}
+
+/// Prune a saved model of any learner type.
+fn prune_model_file(model_file: &Path, prune_args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let mut model = open_model(model_file)?;
+    let how = match prune_args.get_one::<usize>("top") {
+        Some(k) => Prune::TopK(*k),
+        None => Prune::MinWeight(*prune_args.get_one::<f32>("min").unwrap()),
+    };
+    let zeroed = model.prune(how);
+    println!(
+        "pruned {} weights; the rest, the bias and the calibration are unchanged",
+        zeroed
+    );
+    let out_file = prune_args
+        .get_one::<String>("out_file")
+        .map_or(model_file, Path::new);
+    model.save(out_file)?;
+    Ok(())
+}
+
+/// Rewrite a classifier with its weights quantized, reporting the largest
+/// change to any weight.
+fn quantize_model_file(
+    model_file: &Path,
+    quantize_args: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    let mut model = open_classifier(model_file)?;
+    model.quantization = *quantize_args.get_one::<Quantization>("to").unwrap();
+    let out_file = quantize_args
+        .get_one::<String>("out_file")
+        .map_or(model_file, Path::new);
+    let before = std::mem::take(&mut model.w);
+    model.w = QuantizedWeights::new(&before, model.quantization)
+        .map_or_else(|| before.clone(), |q| q.dequantize());
+    let error = before
+        .iter()
+        .zip(model.w.iter())
+        .map(|(a, b)| (a - b).abs() * model.scale)
+        .fold(0.0f32, f32::max);
+    model.save(out_file)?;
+    println!(
+        "wrote {} bytes, largest weight change {}",
+        std::fs::metadata(out_file)?.len(),
+        error
+    );
+    Ok(())
+}
+
+fn diff_model_files(
+    coll: Option<&CollectionLayout>,
+    diff_args: &ArgMatches,
+) -> Result<(), std::io::Error> {
+    let a = open_classifier(Path::new(diff_args.get_one::<String>("model_a").unwrap()))?;
+    let b = open_classifier(Path::new(diff_args.get_one::<String>("model_b").unwrap()))?;
+    let num_changes = *diff_args.get_one::<usize>("num_changes").unwrap();
+
+    let dict_a = coll.map(load_dict).transpose()?;
+    let dict_b = diff_args
+        .get_one::<String>("other_coll")
+        .map(|p| load_dict(&CollectionLayout::new(p)))
+        .transpose()?;
+
+    let weight = |m: &Classifier, i: u32| m.w.get(i as usize).map_or(0.0, |w| w * m.scale);
+
+    // (label, weight in a, weight in b)
+    let mut aligned: Vec<(String, f32, f32)> = match (&dict_a, &dict_b) {
+        (Some(da), Some(db)) => {
+            let mut v: Vec<(String, f32, f32)> =
+                da.m.iter()
+                    .map(|(tok, &ia)| {
+                        let wb = db.m.get(tok).map_or(0.0, |&ib| weight(&b, ib));
+                        (tok.clone(), weight(&a, ia), wb)
+                    })
+                    .collect();
+            v.extend(
+                db.m.iter()
+                    .filter(|(tok, _)| !da.m.contains_key(*tok))
+                    .map(|(tok, &ib)| (tok.clone(), 0.0, weight(&b, ib))),
+            );
+            v
+        }
+        _ => {
+            let names = dict_a.as_ref().map(|d| d.tokens_by_id());
+            (0..a.w.len().max(b.w.len()) as u32)
+                .map(|i| {
+                    let label = names
+                        .as_ref()
+                        .and_then(|n| n.get(&i))
+                        .map_or(i.to_string(), |tok| format!("{} ({})", tok, i));
+                    (label, weight(&a, i), weight(&b, i))
+                })
+                .collect()
+        }
+    };
+    aligned.retain(|(_, wa, wb)| *wa != 0.0 || *wb != 0.0);
+    aligned.sort_by(|x, y| (y.2 - y.1).abs().total_cmp(&(x.2 - x.1).abs()));
+
+    let dot: f32 = aligned.iter().map(|(_, wa, wb)| wa * wb).sum();
+    let norm_a = aligned.iter().map(|(_, wa, _)| wa * wa).sum::<f32>().sqrt();
+    let norm_b = aligned.iter().map(|(_, _, wb)| wb * wb).sum::<f32>().sqrt();
+    let nonzero = |m: &Classifier| m.w.iter().filter(|w| **w != 0.0).count();
+
+    println!("{:>12} {:>12} {:>12}", "", "a", "b");
+    println!("{:>12} {:>12} {:>12}", "lambda", a.lambda, b.lambda);
+    println!(
+        "{:>12} {:>12} {:>12}",
+        "num_iters", a.num_iters, b.num_iters
+    );
+    println!("{:>12} {:>12} {:>12}", "scale", a.scale, b.scale);
+    println!("{:>12} {:>12} {:>12}", "bias", a.bias, b.bias);
+    println!("{:>12} {:>12.5} {:>12.5}", "norm", norm_a, norm_b);
+    println!("{:>12} {:>12} {:>12}", "nonzero", nonzero(&a), nonzero(&b));
+    println!("cosine similarity: {:.5}", dot / (norm_a * norm_b));
+    println!("largest weight changes:");
+    for (label, wa, wb) in aligned.iter().take(num_changes) {
+        println!("  {} {:.5} -> {:.5} ({:+.5})", label, wa, wb, wb - wa);
+    }
+    Ok(())
+}
+
+fn eval_run(eval_args: &ArgMatches) -> Result<(), std::io::Error> {
+    let mut run = read_run(eval_args.get_one::<String>("run").unwrap())?;
+    let mut qrels = read_qrels(eval_args.get_one::<String>("qrels_file").unwrap())?;
+    let topics: BTreeSet<&str> = run.iter().filter_map(|e| e.topic.as_deref()).collect();
+    let qid = match eval_args.get_one::<String>("qid") {
+        Some(qid) => Some(qid.clone()),
+        None if topics.len() > 1 => {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "The run ranks {} topics; choose one with --qid",
+                    topics.len()
+                ),
+            ))
+        }
+        None => topics.first().map(|t| t.to_string()),
+    };
+    if let Some(qid) = qid {
+        run.retain(|e| e.topic.as_ref().is_none_or(|t| *t == qid));
+        qrels.retain(|j| j.topic == qid);
+    }
+    let min = *eval_args.get_one::<i32>("level").unwrap();
+    let cutoffs: Vec<usize> = eval_args
+        .get_many::<usize>("cutoffs")
+        .unwrap()
+        .copied()
+        .collect();
+    let eval = evaluate(&run, &qrels, min, &cutoffs);
+
+    match eval_args.get_one::<String>("format").unwrap().as_str() {
+        "json" => println!("{}", serde_json::to_string_pretty(&eval)?),
+        "csv" => {
+            println!("rank,relevant,precision,recall");
+            for (i, found) in eval.gain.iter().enumerate() {
+                println!(
+                    "{},{},{:.4},{:.4}",
+                    i + 1,
+                    found,
+                    *found as f64 / (i + 1) as f64,
+                    *found as f64 / eval.relevant.max(1) as f64
+                );
+            }
+        }
+        _ => {
+            println!(
+                "{} retrieved, {} of {} relevant",
+                eval.retrieved, eval.relevant_retrieved, eval.relevant
+            );
+            println!("average precision: {:.4}", eval.average_precision);
+            println!("k\tprecision\trecall");
+            for c in &eval.cutoffs {
+                println!("{}\t{:.4}\t{:.4}", c.k, c.precision, c.recall);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn diff_run_files(diff_args: &ArgMatches) -> Result<(), std::io::Error> {
+    let old = read_run(diff_args.get_one::<String>("old_run").unwrap())?;
+    let new = read_run(diff_args.get_one::<String>("new_run").unwrap())?;
+    let depth = *diff_args.get_one::<usize>("depth").unwrap();
+    let num_movers = *diff_args.get_one::<usize>("movers").unwrap();
+
+    // Runs from different collections can share docids, so say so
+    let fingerprint = |arg| {
+        RunMetadata::read(diff_args.get_one::<String>(arg).unwrap())
+            .ok()
+            .and_then(|blocks| blocks.into_iter().next())
+            .and_then(|meta| meta.fingerprint)
+    };
+    if let (Some(a), Some(b)) = (fingerprint("old_run"), fingerprint("new_run")) {
+        if a != b {
+            eprintln!(
+                "warning: the runs are from different collections ({} and {})",
+                a, b
+            );
+        }
+    }
+
+    let diff = diff_runs(&old, &new, depth);
+    let rank = |r: Option<usize>| r.map_or("-".to_string(), |r| r.to_string());
+    let print_change =
+        |c: &RankChange| println!("  {} {} -> {}", c.docid, rank(c.old_rank), rank(c.new_rank));
+
+    println!("docs in both runs: {}", diff.common);
+    println!("top-{} overlap: {:.4}", diff.depth, diff.overlap);
+    println!("kendall tau: {:.4}", diff.kendall_tau);
+    println!("spearman rho: {:.4}", diff.spearman_rho);
+    println!("biggest movers:");
+    diff.movers.iter().take(num_movers).for_each(print_change);
+    println!("new in top-{}: {}", diff.depth, diff.entered.len());
+    diff.entered.iter().for_each(print_change);
+    println!("dropped from top-{}: {}", diff.depth, diff.dropped.len());
+    diff.dropped.iter().for_each(print_change);
+    Ok(())
 }
 
 fn score_one_doc(
-    coll_prefix: &str,
-    model_file: &str,
+    coll: &CollectionLayout,
+    model_file: &Path,
     score_one_args: &ArgMatches,
 ) -> Result<f32, std::io::Error> {
     let docid = score_one_args.get_one::<String>("docid").unwrap();
 
-    let docsdb_file = coll_prefix.to_string() + ".lib";
-    let feat_file = coll_prefix.to_string() + ".ftr";
-
-    let model = Classifier::load(model_file).unwrap();
+    let model = open_model(model_file)?;
+    check_collection(model.as_ref(), coll, None)?;
+    let model = model.scoring_model();
 
-    let docs = DocsDb::open(&docsdb_file);
-    let mut feats = BufReader::new(File::open(feat_file).expect("Could not open feature file"));
+    let docs = DocsDb::open(coll.docsdb());
+    let mut feats = BufReader::new(File::open(coll.features())?);
 
-    let dib = docs.db.get(docid).unwrap().unwrap();
-    let di: DocInfo = bincode::deserialize(&dib).unwrap();
-    feats.seek(SeekFrom::Start(di.offset))?;
-    let fv = FeatureVec::read_from(&mut feats).expect("Error deserializing feature vec");
+    let dib = docs.db.get(docid)?.ok_or_else(|| {
+        std::io::Error::new(
+            ErrorKind::NotFound,
+            format!("{} is not in the collection", docid),
+        )
+    })?;
+    let di = DocInfo::decode(&dib).map_err(|e| {
+        failed(
+            Failure::CorruptIndex,
+            format!("Could not read the entry for {}: {}", docid, e),
+        )
+    })?;
+    let fv = read_fv(&mut feats, &di)?;
 
     let score = model.inner_product(&fv);
     println!("{:?}", score);
@@ -0,0 +1,325 @@
+//! Merging collections built separately, such as shards of one document
+//! set built on different machines, into one. The shards' vocabularies are
+//! combined into a new dictionary, ids most frequent first as build_corpus
+//! assigns them, and each term's idf is recomputed from the documents it
+//! occurs in across all the shards. Every feature value is a term's
+//! `(1 + log tf) * idf`, so it is rescaled from the shard's idf to the
+//! merged one. Documents keep the shards' order and are renumbered from
+//! zero, and the routing, language and date files follow them. The merged
+//! collection's generation is past every shard's, so no shard's intid
+//! files are read against it.
+//!
+//! Shards with hashed vocabularies must hash into the same number of
+//! buckets. A term hashed in one shard but given an exact id in another
+//! stays in its bucket in the documents of the first. A docid already
+//! taken by an earlier shard, and a document a shard has deleted (see
+//! [`crate::tombstones`]), is left out. Optional record sections are
+//! dropped, since what they hold is in the shard's term ids.
+
+use crate::languages::Languages;
+use crate::recency::Dates;
+use crate::{
+    fingerprint, read_generation, read_intids, tombstones, write_generation, write_intids,
+    CollectionLayout, Dict, DocInfo, DocsDb, FeatureVec, HashedTail,
+};
+use roaring::RoaringBitmap;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Result, Seek, Write};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MergeReport {
+    pub num_docs: u64,
+    pub num_terms: usize,
+    /// Documents left out because an earlier shard had the docid
+    pub duplicates: u64,
+    /// Documents left out because their shard had deleted them
+    pub deleted: u64,
+}
+
+fn corrupt(e: bincode::Error) -> Error {
+    Error::new(ErrorKind::InvalidData, e)
+}
+
+fn load_dict(coll: &CollectionLayout) -> Result<Dict> {
+    Dict::load(coll.dict()).map_err(corrupt)
+}
+
+#[derive(Default)]
+struct DocFreqs<'a> {
+    tokens: HashMap<&'a str, u64>,
+    buckets: HashMap<u32, u64>,
+}
+
+/// Which of a shard's documents are kept: not deleted, and with a docid
+/// no earlier shard had. Each kept document's terms are counted toward
+/// their document frequencies, exact terms by token and hashed ones by
+/// bucket.
+fn scan_shard<'a>(
+    shard: &CollectionLayout,
+    dict: &'a Dict,
+    seen: &mut HashSet<String>,
+    counts: &mut DocFreqs<'a>,
+    report: &mut MergeReport,
+    progress: &mut impl FnMut(usize),
+) -> Result<RoaringBitmap> {
+    let deleted = tombstones::deleted(shard)?;
+    let names = dict.tokens_by_id();
+    let mut keep = RoaringBitmap::new();
+    let mut feats = BufReader::new(File::open(shard.features())?);
+    let mut intid = 0;
+    while let Some(fv) = FeatureVec::read_next(&mut feats).map_err(corrupt)? {
+        progress(1);
+        if deleted.contains(intid) {
+            report.deleted += 1;
+        } else if !seen.insert(fv.docid) {
+            report.duplicates += 1;
+        } else {
+            keep.insert(intid);
+            for f in &fv.features {
+                match dict.hashed {
+                    Some(tail) if tail.contains(f.id) => {
+                        *counts.buckets.entry(f.id - tail.first_id).or_insert(0) += 1;
+                    }
+                    _ => {
+                        if let Some(tok) = names.get(&f.id) {
+                            *counts.tokens.entry(tok).or_insert(0) += 1;
+                        }
+                    }
+                }
+            }
+        }
+        intid += 1;
+    }
+    Ok(keep)
+}
+
+/// How one shard's term ids become the merged collection's.
+struct Remap {
+    exact: HashMap<u32, u32>,
+    hashed: Option<(HashedTail, u32)>,
+    /// The term's idf in the shard, for rescaling
+    idf: HashMap<u32, f32>,
+}
+
+impl Remap {
+    fn tokid(&self, id: u32) -> Option<u32> {
+        match self.hashed {
+            Some((tail, first_id)) if tail.contains(id) => Some(first_id + id - tail.first_id),
+            _ => self.exact.get(&id).copied(),
+        }
+    }
+}
+
+/// Build the merged dictionary for `num_docs` documents, and each shard's
+/// mapping into it. Terms no kept document has are dropped.
+fn merge_dicts(dicts: &[Dict], counts: DocFreqs, num_docs: u64) -> (Dict, Vec<Remap>) {
+    let idf_of = |df: u64| (num_docs as f32 / df.max(1) as f32).log10();
+    let mut merged = Dict::new();
+    let mut terms: Vec<(&str, u64)> = counts.tokens.into_iter().collect();
+    terms.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+    for (tok, df) in terms {
+        let tokid = merged.add_tok(tok);
+        merged.df.insert(tokid, idf_of(df));
+    }
+    if let Some(buckets) = dicts[0].hashed.map(|h| h.buckets) {
+        let tail = HashedTail {
+            first_id: merged.last_tokid + 1,
+            buckets,
+        };
+        for (bucket, df) in counts.buckets {
+            merged.df.insert(tail.first_id + bucket, idf_of(df));
+        }
+        merged.last_tokid = tail.first_id + buckets - 1;
+        merged.hashed = Some(tail);
+    }
+    // A token too common in one shard may have an id from another
+    for dict in dicts {
+        merged.stopped.extend(
+            dict.stopped
+                .iter()
+                .filter(|tok| !merged.m.contains_key(*tok))
+                .cloned(),
+        );
+    }
+
+    let remaps = dicts
+        .iter()
+        .map(|dict| Remap {
+            exact: dict
+                .m
+                .iter()
+                .filter_map(|(tok, id)| Some((*id, *merged.m.get(tok)?)))
+                .collect(),
+            hashed: dict
+                .hashed
+                .zip(merged.hashed)
+                .map(|(tail, ours)| (tail, ours.first_id)),
+            idf: dict.df.clone(),
+        })
+        .collect();
+    (merged, remaps)
+}
+
+/// Rewrite a shard's vector in the merged vocabulary.
+fn remap_vector(fv: FeatureVec, remap: &Remap, merged: &Dict) -> FeatureVec {
+    let mut out = FeatureVec::new(fv.docid);
+    for f in &fv.features {
+        let Some(tokid) = remap.tokid(f.id) else {
+            continue;
+        };
+        let old_idf = remap.idf.get(&f.id).copied().unwrap_or(0.0);
+        let new_idf = merged.df.get(&tokid).copied().unwrap_or(0.0);
+        // A term in every document of its shard has lost its tf
+        let value = if old_idf > 0.0 {
+            f.value / old_idf * new_idf
+        } else {
+            new_idf
+        };
+        out.push(tokid, value);
+    }
+    out.compute_norm();
+    out
+}
+
+/// Merge `shards`, in order, into a new collection at `out`. Each shard is
+/// read twice, and `progress` called with each document read.
+pub fn merge(
+    out: &CollectionLayout,
+    shards: &[CollectionLayout],
+    mut progress: impl FnMut(usize),
+) -> Result<MergeReport> {
+    if shards.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "No collections to merge",
+        ));
+    }
+    if out.dict().exists() || out.features().exists() {
+        return Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!("A collection exists at {}", out.prefix().display()),
+        ));
+    }
+    let mut dicts = Vec::with_capacity(shards.len());
+    let mut generation = 0;
+    for shard in shards {
+        dicts.push(load_dict(shard)?);
+        generation = generation.max(read_generation(shard)? + 1);
+    }
+    let buckets = dicts[0].hashed.map(|h| h.buckets);
+    if dicts.iter().any(|d| d.hashed.map(|h| h.buckets) != buckets) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Shards must all hash into the same number of buckets, or none",
+        ));
+    }
+
+    // The first pass decides which documents are kept and counts their
+    // terms, the second writes them
+    let mut report = MergeReport::default();
+    let mut seen = HashSet::new();
+    let mut counts = DocFreqs::default();
+    let mut keep = Vec::with_capacity(shards.len());
+    for (shard, dict) in shards.iter().zip(&dicts) {
+        keep.push(scan_shard(
+            shard,
+            dict,
+            &mut seen,
+            &mut counts,
+            &mut report,
+            &mut progress,
+        )?);
+    }
+    drop(seen);
+    report.num_docs = keep.iter().map(|k| k.len()).sum();
+    let (dict, remaps) = merge_dicts(&dicts, counts, report.num_docs);
+    report.num_terms = dict.m.len();
+
+    let mut binout = BufWriter::new(File::create(out.features())?);
+    let mut docs = DocsDb::create(out.docsdb());
+    let mut divec: Vec<DocInfo> = Vec::new();
+    let mut routed = RoaringBitmap::new();
+    let mut langs = Languages::default();
+    let mut dates = Dates::default();
+    let (mut any_routed, mut any_langs, mut any_dates) = (false, false, false);
+
+    for ((shard, remap), keep) in shards.iter().zip(&remaps).zip(&keep) {
+        // The shard's intid to the merged one
+        let mut renumbered: Vec<Option<u32>> = Vec::new();
+        let mut feats = BufReader::new(File::open(shard.features())?);
+        while let Some(fv) = FeatureVec::read_next(&mut feats).map_err(corrupt)? {
+            progress(1);
+            if !keep.contains(renumbered.len() as u32) {
+                renumbered.push(None);
+                continue;
+            }
+            let di = DocInfo {
+                intid: divec.len() as u64,
+                docid: fv.docid.clone(),
+                offset: binout.stream_position()?,
+            };
+            renumbered.push(Some(di.intid as u32));
+            remap_vector(fv, remap, &dict)
+                .write_into(&mut binout)
+                .map_err(Error::other)?;
+            docs.insert_batch(&di.docid, &di, 100_000);
+            divec.push(di);
+        }
+        let new_id = |i: u32| renumbered.get(i as usize).copied().flatten();
+
+        if shard.excluded().exists() {
+            any_routed = true;
+            let shard_routed = read_intids(shard.excluded(), read_generation(shard)?)?;
+            routed.extend(shard_routed.iter().filter_map(new_id));
+        }
+        if shard.languages().exists() {
+            any_langs = true;
+            for (lang, intids) in Languages::load(shard.languages())?.intids {
+                for intid in intids.iter().filter_map(new_id) {
+                    langs.insert(&lang, intid);
+                }
+            }
+        }
+        if shard.dates().exists() {
+            any_dates = true;
+            let shard_dates = Dates::load(shard.dates())?;
+            for i in 0..renumbered.len() as u32 {
+                if let (Some(intid), Some(day)) = (new_id(i), shard_dates.get(i)) {
+                    dates.insert(intid, day);
+                }
+            }
+        }
+    }
+    binout.flush()?;
+    docs.process_remaining();
+    docs.db.flush()?;
+
+    if any_routed {
+        write_intids(&routed, generation, out.excluded())?;
+    }
+    if any_langs {
+        langs.num_docs = divec.len() as u32;
+        langs.save(out.languages())?;
+    }
+    if any_dates {
+        dates.days.resize(divec.len(), None);
+        dates.save(out.dates())?;
+    }
+    dict.save(out.dict())?;
+    let settings = format!(
+        "merged={} hash_buckets={:?}",
+        shards.len(),
+        dict.hashed.map(|h| h.buckets)
+    );
+    let fp = fingerprint(&settings, &dict, divec.iter().map(|di| di.docid.as_str()));
+    std::fs::write(out.fingerprint(), format!("{}\n", fp))?;
+    write_generation(generation, out.generation())?;
+
+    // The docid vector is in database order, which is docid order
+    divec.sort_by(|a, b| a.docid.cmp(&b.docid));
+    let mut vecfile = BufWriter::new(File::create(out.docvec())?);
+    bincode::serialize_into(&mut vecfile, &divec).map_err(Error::other)?;
+    vecfile.flush()?;
+    Ok(report)
+}
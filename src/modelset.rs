@@ -0,0 +1,95 @@
+use crate::qrels::Judgment;
+use crate::search::{search_many, Hit, SearchOptions};
+use crate::{DocsDb, FeatureVec, Model, ScoringModel, TrainReport};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Result, Seek, SeekFrom};
+
+/// Judged examples for one topic: (relevant, nonrelevant).
+pub type Examples = (Vec<FeatureVec>, Vec<FeatureVec>);
+
+/// Models for many topics over one collection, keyed by topic id. A
+/// TREC-style run has dozens of topics, and [`ModelSet::score_all`] scores
+/// all of them in a single pass over the feature file instead of one pass
+/// per topic.
+#[derive(Default)]
+pub struct ModelSet {
+    pub models: BTreeMap<String, Box<dyn Model>>,
+}
+
+impl ModelSet {
+    pub fn new() -> ModelSet {
+        ModelSet::default()
+    }
+
+    pub fn insert(&mut self, topic: impl Into<String>, model: Box<dyn Model>) {
+        self.models.insert(topic.into(), model);
+    }
+
+    /// Train each topic's model on its examples. Topics without a model
+    /// get one from `new_model`; topics without both relevant and
+    /// nonrelevant examples are skipped.
+    pub fn train_all(
+        &mut self,
+        examples: &BTreeMap<String, Examples>,
+        new_model: impl Fn() -> Box<dyn Model>,
+    ) -> BTreeMap<String, TrainReport> {
+        let mut reports = BTreeMap::new();
+        for (topic, (pos, neg)) in examples {
+            if pos.is_empty() || neg.is_empty() {
+                continue;
+            }
+            let model = self.models.entry(topic.clone()).or_insert_with(&new_model);
+            reports.insert(topic.clone(), model.train(pos, neg));
+        }
+        reports
+    }
+
+    /// Score the collection once for every topic, returning each topic's
+    /// hits under the options `opts` gives for it.
+    pub fn score_all(
+        &self,
+        feats: &mut BufReader<File>,
+        opts: impl Fn(&str) -> SearchOptions,
+        progress: impl FnMut(usize),
+    ) -> Result<BTreeMap<String, Vec<Hit>>> {
+        let scoring: Vec<(&String, ScoringModel, SearchOptions)> = self
+            .models
+            .iter()
+            .map(|(topic, model)| (topic, model.scoring_model(), opts(topic)))
+            .collect();
+        let searches: Vec<(&ScoringModel, &SearchOptions)> =
+            scoring.iter().map(|(_, m, o)| (m, o)).collect();
+        let hits = search_many(&searches, feats, progress)?;
+        Ok(scoring
+            .iter()
+            .map(|(topic, _, _)| topic.to_string())
+            .zip(hits)
+            .collect())
+    }
+}
+
+/// Fetch the judged documents of every topic in `judgments`, split at
+/// relevance level `min_rel`. Docids not in the collection are skipped.
+pub fn examples_by_topic(
+    judgments: &[Judgment],
+    docs: &DocsDb,
+    feats: &mut BufReader<File>,
+    min_rel: i32,
+) -> Result<BTreeMap<String, Examples>> {
+    let mut examples: BTreeMap<String, Examples> = BTreeMap::new();
+    for j in judgments {
+        let Some(di) = docs.get(&j.docid) else {
+            continue;
+        };
+        feats.seek(SeekFrom::Start(di.offset))?;
+        let fv = FeatureVec::read_from(feats).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        let (pos, neg) = examples.entry(j.topic.clone()).or_default();
+        if j.rel >= min_rel {
+            pos.push(fv);
+        } else {
+            neg.push(fv);
+        }
+    }
+    Ok(examples)
+}
@@ -0,0 +1,251 @@
+use crate::{DocInfo, FeatureVec, ScoringModel};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Result, Seek, SeekFrom};
+use std::str::FromStr;
+
+/// How unjudged documents are sampled as presumed-nonrelevant training
+/// examples. Uniform sampling over-represents the short junk documents
+/// that fill most collections, so the others aim for negatives that look
+/// more like what the model has to tell apart.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NegativeStrategy {
+    /// Every unjudged document is equally likely
+    Uniform,
+    /// Documents the current model scores low are more likely, in
+    /// proportion to their probability of being nonrelevant
+    LowScore,
+    /// The documents the current model scores lowest among a random pool
+    Tail,
+    /// An equal share from each tenth of a random pool ranked by the
+    /// current model, so negatives span the whole score range
+    Deciles,
+    /// Documents about as long as a randomly chosen relevant example
+    Length,
+    /// An equal share from each docid prefix, such as a source or
+    /// custodian prefix
+    Strata(Vec<String>),
+}
+
+impl FromStr for NegativeStrategy {
+    type Err = Error;
+
+    /// `uniform`, `low-score`, `tail`, `deciles`, `length`, or
+    /// `strata:PREFIX,PREFIX,...`
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "uniform" => Ok(NegativeStrategy::Uniform),
+            "low-score" => Ok(NegativeStrategy::LowScore),
+            "tail" => Ok(NegativeStrategy::Tail),
+            "deciles" => Ok(NegativeStrategy::Deciles),
+            "length" => Ok(NegativeStrategy::Length),
+            _ => match s.strip_prefix("strata:") {
+                Some(prefixes) if !prefixes.is_empty() => Ok(NegativeStrategy::Strata(
+                    prefixes.split(',').map(str::to_string).collect(),
+                )),
+                _ => Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Unknown negative strategy {}", s),
+                )),
+            },
+        }
+    }
+}
+
+/// Candidates scored per negative wanted by the strategies that use the
+/// model
+const POOL_FACTOR: usize = 10;
+/// Candidates tried per negative under [`NegativeStrategy::Length`]
+const LENGTH_TRIES: usize = 50;
+
+/// Draws negatives from a collection's docvec, never returning a docid in
+/// `exclude` and adding each one it returns.
+pub struct NegativeSampler<'a, R: Rng> {
+    pub docvec: &'a [DocInfo],
+    pub feats: &'a mut BufReader<File>,
+    pub exclude: &'a mut HashSet<String>,
+    pub rng: &'a mut R,
+}
+
+impl<R: Rng> NegativeSampler<'_, R> {
+    /// Sample up to `n` negatives. Fewer come back if the collection (or a
+    /// stratum) runs out of unjudged documents. `LowScore`, `Tail` and
+    /// `Deciles` need `model`, and `Length` needs `positives`; without them
+    /// sampling is uniform.
+    pub fn sample(
+        &mut self,
+        n: usize,
+        strategy: &NegativeStrategy,
+        positives: &[FeatureVec],
+        model: Option<&ScoringModel>,
+    ) -> Result<Vec<FeatureVec>> {
+        match (strategy, model) {
+            (NegativeStrategy::LowScore, Some(model)) => self.low_score(n, model),
+            (NegativeStrategy::Tail, Some(model)) => self.tail(n, model),
+            (NegativeStrategy::Deciles, Some(model)) => self.deciles(n, model),
+            (NegativeStrategy::Length, _) if !positives.is_empty() => self.length(n, positives),
+            (NegativeStrategy::Strata(prefixes), _) => self.strata(n, prefixes),
+            _ => self.uniform(n, 0..self.docvec.len()),
+        }
+    }
+
+    /// Pick an unexcluded index in `range` uniformly, giving up after a
+    /// bounded number of tries so a nearly exhausted range can't spin.
+    fn pick(&mut self, range: std::ops::Range<usize>) -> Option<usize> {
+        if range.is_empty() {
+            return None;
+        }
+        for _ in 0..(range.len() * 4).clamp(100, 100_000) {
+            let i = self.rng.gen_range(range.clone());
+            if !self.exclude.contains(&self.docvec[i].docid) {
+                return Some(i);
+            }
+        }
+        None
+    }
+
+    fn read(&mut self, i: usize) -> Result<FeatureVec> {
+        self.feats.seek(SeekFrom::Start(self.docvec[i].offset))?;
+        FeatureVec::read_from(self.feats).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    fn take(&mut self, i: usize) -> Result<FeatureVec> {
+        self.exclude.insert(self.docvec[i].docid.clone());
+        self.read(i)
+    }
+
+    fn uniform(&mut self, n: usize, range: std::ops::Range<usize>) -> Result<Vec<FeatureVec>> {
+        let mut negs = Vec::with_capacity(n);
+        while negs.len() < n {
+            let Some(i) = self.pick(range.clone()) else {
+                break;
+            };
+            negs.push(self.take(i)?);
+        }
+        Ok(negs)
+    }
+
+    /// Up to `n * POOL_FACTOR` distinct unexcluded documents, read and
+    /// scored by `model`. Picks of documents already in the pool are
+    /// bounded like [`Self::pick`]'s tries, so a collection with fewer
+    /// unexcluded documents than that yields a smaller pool.
+    fn scored_pool(
+        &mut self,
+        n: usize,
+        model: &ScoringModel,
+    ) -> Result<(Vec<usize>, Vec<FeatureVec>, Vec<f32>)> {
+        let mut pool = Vec::new();
+        let mut seen = HashSet::new();
+        let max_misses = (self.docvec.len() * 4).clamp(100, 100_000);
+        let mut misses = 0;
+        while pool.len() < n * POOL_FACTOR && misses < max_misses {
+            match self.pick(0..self.docvec.len()) {
+                Some(i) if seen.insert(i) => {
+                    pool.push(i);
+                    misses = 0;
+                }
+                Some(_) => misses += 1,
+                None => break,
+            }
+        }
+        let mut fvs = Vec::with_capacity(pool.len());
+        for i in pool.iter() {
+            fvs.push(self.read(*i)?);
+        }
+        let scores = model.score_batch(&fvs);
+        Ok((pool, fvs, scores))
+    }
+
+    /// Take the chosen members of a scored pool.
+    fn take_from_pool(
+        &mut self,
+        pool: &[usize],
+        fvs: Vec<FeatureVec>,
+        chosen: &[usize],
+    ) -> Vec<FeatureVec> {
+        let mut fvs: Vec<Option<FeatureVec>> = fvs.into_iter().map(Some).collect();
+        let mut negs = Vec::with_capacity(chosen.len());
+        for k in chosen {
+            self.exclude.insert(self.docvec[pool[*k]].docid.clone());
+            negs.push(fvs[*k].take().unwrap());
+        }
+        negs
+    }
+
+    fn low_score(&mut self, n: usize, model: &ScoringModel) -> Result<Vec<FeatureVec>> {
+        let (pool, fvs, scores) = self.scored_pool(n, model)?;
+        let weights: Vec<f32> = scores.iter().map(|s| 1.0 - model.probability(*s)).collect();
+        let chosen: Vec<usize> = (0..pool.len())
+            .collect::<Vec<_>>()
+            .choose_multiple_weighted(self.rng, n.min(pool.len()), |&k| weights[k].max(1e-6))
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?
+            .copied()
+            .collect();
+        Ok(self.take_from_pool(&pool, fvs, &chosen))
+    }
+
+    fn tail(&mut self, n: usize, model: &ScoringModel) -> Result<Vec<FeatureVec>> {
+        let (pool, fvs, scores) = self.scored_pool(n, model)?;
+        let mut ranked: Vec<usize> = (0..pool.len()).collect();
+        ranked.sort_by(|a, b| scores[*a].total_cmp(&scores[*b]));
+        ranked.truncate(n);
+        Ok(self.take_from_pool(&pool, fvs, &ranked))
+    }
+
+    fn deciles(&mut self, n: usize, model: &ScoringModel) -> Result<Vec<FeatureVec>> {
+        let (pool, fvs, scores) = self.scored_pool(n, model)?;
+        let mut ranked: Vec<usize> = (0..pool.len()).collect();
+        ranked.sort_by(|a, b| scores[*a].total_cmp(&scores[*b]));
+        let mut chosen = Vec::with_capacity(n);
+        for d in 0..10 {
+            let decile = &ranked[d * ranked.len() / 10..(d + 1) * ranked.len() / 10];
+            let share = n / 10 + usize::from(d < n % 10);
+            chosen.extend(decile.choose_multiple(self.rng, share.min(decile.len())));
+        }
+        Ok(self.take_from_pool(&pool, fvs, &chosen))
+    }
+
+    fn length(&mut self, n: usize, positives: &[FeatureVec]) -> Result<Vec<FeatureVec>> {
+        let mut negs = Vec::with_capacity(n);
+        while negs.len() < n {
+            let want = positives.choose(self.rng).unwrap().features.len();
+            let slack = (want / 4).max(2);
+            let mut best: Option<(usize, usize)> = None;
+            for _ in 0..LENGTH_TRIES {
+                let Some(i) = self.pick(0..self.docvec.len()) else {
+                    break;
+                };
+                let off = self.read(i)?.features.len().abs_diff(want);
+                if best.is_none_or(|(_, b)| off < b) {
+                    best = Some((i, off));
+                }
+                if off <= slack {
+                    break;
+                }
+            }
+            let Some((i, _)) = best else {
+                break;
+            };
+            negs.push(self.take(i)?);
+        }
+        Ok(negs)
+    }
+
+    /// The docvec is in docid order, so each prefix's documents are
+    /// contiguous.
+    fn strata(&mut self, n: usize, prefixes: &[String]) -> Result<Vec<FeatureVec>> {
+        let mut negs = Vec::with_capacity(n);
+        for (k, prefix) in prefixes.iter().enumerate() {
+            let share = n / prefixes.len() + usize::from(k < n % prefixes.len());
+            let lo = self
+                .docvec
+                .partition_point(|d| d.docid.as_str() < prefix.as_str());
+            let hi =
+                lo + self.docvec[lo..].partition_point(|d| d.docid.starts_with(prefix.as_str()));
+            negs.extend(self.uniform(share, lo..hi)?);
+        }
+        Ok(negs)
+    }
+}
@@ -0,0 +1,238 @@
+//! Planning the rest of a review under a budget. The gain curve of a topic's
+//! judgment log — relevant documents found against documents reviewed,
+//! round by round — is fit with a batch precision that decays
+//! exponentially as review goes on, and each batch schedule is simulated
+//! against that fit until the batch precision falls below a stopping
+//! threshold or the budget runs out.
+
+use crate::qrels::Judgment;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Documents reviewed and found relevant in each round, in round order.
+/// Judgments without a round count as round 0.
+#[derive(Debug, Clone, Default)]
+pub struct GainCurve {
+    pub rounds: Vec<(u64, u64)>,
+}
+
+impl GainCurve {
+    pub fn from_judgments(judgments: &[Judgment], min_rel: i32) -> GainCurve {
+        let mut rounds: BTreeMap<u32, (u64, u64)> = BTreeMap::new();
+        for j in judgments {
+            let (reviewed, relevant) = rounds.entry(j.round.unwrap_or(0)).or_default();
+            *reviewed += 1;
+            *relevant += u64::from(j.rel >= min_rel);
+        }
+        GainCurve {
+            rounds: rounds.into_values().collect(),
+        }
+    }
+
+    pub fn reviewed(&self) -> u64 {
+        self.rounds.iter().map(|r| r.0).sum()
+    }
+
+    pub fn relevant(&self) -> u64 {
+        self.rounds.iter().map(|r| r.1).sum()
+    }
+
+    /// Fit `log precision = log p0 - decay * reviewed` to the rounds by
+    /// least squares, each round weighted by its size and placed at the
+    /// midpoint of the documents it reviewed. Needs at least two rounds.
+    pub fn fit(&self) -> Option<GainFit> {
+        if self.rounds.len() < 2 {
+            return None;
+        }
+        let mut points = Vec::with_capacity(self.rounds.len());
+        let mut before = 0.0;
+        for (reviewed, relevant) in self.rounds.iter() {
+            let n = *reviewed as f64;
+            // Smoothed, so a round with nothing relevant still has a log
+            let precision = (*relevant as f64 + 0.5) / (n + 1.0);
+            points.push((before + n / 2.0, precision.ln(), n));
+            before += n;
+        }
+        let total: f64 = points.iter().map(|p| p.2).sum();
+        let mean_x = points.iter().map(|p| p.0 * p.2).sum::<f64>() / total;
+        let mean_y = points.iter().map(|p| p.1 * p.2).sum::<f64>() / total;
+        let sxx: f64 = points.iter().map(|p| p.2 * (p.0 - mean_x).powi(2)).sum();
+        let sxy: f64 = points
+            .iter()
+            .map(|p| p.2 * (p.0 - mean_x) * (p.1 - mean_y))
+            .sum();
+        if sxx == 0.0 {
+            return None;
+        }
+        let slope = sxy / sxx;
+        Some(GainFit {
+            p0: (mean_y - slope * mean_x).exp(),
+            decay: -slope,
+        })
+    }
+}
+
+/// Batch precision `p0 * exp(-decay * x)` after `x` documents reviewed.
+#[derive(Debug, Clone, Copy)]
+pub struct GainFit {
+    pub p0: f64,
+    pub decay: f64,
+}
+
+impl GainFit {
+    pub fn precision_at(&self, reviewed: f64) -> f64 {
+        (self.p0 * (-self.decay * reviewed).exp()).min(1.0)
+    }
+
+    /// Expected relevant documents among those reviewed from `from` to
+    /// `to`.
+    pub fn expected_relevant(&self, from: f64, to: f64) -> f64 {
+        if self.decay.abs() < 1e-12 {
+            return self.precision_at(from) * (to - from);
+        }
+        let found = self.p0 / self.decay * ((-self.decay * from).exp() - (-self.decay * to).exp());
+        found.min(to - from)
+    }
+
+    /// Expected relevant documents left after `from` have been reviewed;
+    /// unbounded unless precision is falling.
+    pub fn remaining(&self, from: f64) -> Option<f64> {
+        (self.decay > 0.0).then(|| self.p0 / self.decay * (-self.decay * from).exp())
+    }
+}
+
+/// How many documents each future round reviews.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BatchSchedule {
+    /// The same number every round
+    Fixed(u64),
+    /// Starting at this many and growing by a tenth each round, as in
+    /// AutoTAR
+    Growing(u64),
+}
+
+impl BatchSchedule {
+    fn size(&self, round: usize) -> u64 {
+        match self {
+            BatchSchedule::Fixed(n) => *n,
+            BatchSchedule::Growing(n) => {
+                let mut size = *n;
+                for _ in 0..round {
+                    size += size.div_ceil(10);
+                }
+                size
+            }
+        }
+    }
+}
+
+impl fmt::Display for BatchSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BatchSchedule::Fixed(n) => write!(f, "fixed {}", n),
+            BatchSchedule::Growing(n) => write!(f, "growing from {}", n),
+        }
+    }
+}
+
+/// The reviewers' time.
+#[derive(Debug, Clone, Copy)]
+pub struct Budget {
+    pub docs_per_hour: f64,
+    /// Review hours left
+    pub hours: f64,
+    /// Hours each round costs besides reading, such as retraining and
+    /// scoring
+    pub round_overhead: f64,
+}
+
+/// What one schedule is expected to do with the budget.
+#[derive(Debug, Clone)]
+pub struct Projection {
+    pub schedule: BatchSchedule,
+    pub rounds: usize,
+    pub reviewed: u64,
+    /// Expected relevant documents found, counting those already found
+    pub relevant: f64,
+    pub hours: f64,
+    /// Whether batch precision fell below the stopping threshold before
+    /// the budget ran out
+    pub finished: bool,
+    /// Expected share of all relevant documents found, if the fit says
+    /// how many there are
+    pub recall: Option<f64>,
+}
+
+/// Rounds simulated before giving up on a schedule that never stops
+const MAX_ROUNDS: usize = 100_000;
+
+/// Simulate `schedule` from where `curve` leaves off. Review stops after
+/// the first batch whose expected precision is below `stop_precision`,
+/// or before a round that would overrun the budget.
+pub fn project(
+    curve: &GainCurve,
+    fit: &GainFit,
+    schedule: BatchSchedule,
+    budget: &Budget,
+    stop_precision: f64,
+) -> Projection {
+    let start = curve.reviewed() as f64;
+    let mut p = Projection {
+        schedule,
+        rounds: 0,
+        reviewed: 0,
+        relevant: curve.relevant() as f64,
+        hours: 0.0,
+        finished: false,
+        recall: None,
+    };
+    while p.rounds < MAX_ROUNDS {
+        let size = schedule.size(p.rounds);
+        let hours = size as f64 / budget.docs_per_hour + budget.round_overhead;
+        if size == 0 || p.hours + hours > budget.hours {
+            break;
+        }
+        let from = start + p.reviewed as f64;
+        let found = fit.expected_relevant(from, from + size as f64);
+        p.rounds += 1;
+        p.reviewed += size;
+        p.relevant += found;
+        p.hours += hours;
+        if found / (size as f64) < stop_precision {
+            p.finished = true;
+            break;
+        }
+    }
+    p.recall = fit
+        .remaining(start + p.reviewed as f64)
+        .map(|left| p.relevant / (p.relevant + left));
+    p
+}
+
+/// Project every schedule and pick one: the quickest of those that finish
+/// within the budget, or failing that the one expected to find the most.
+pub fn plan(
+    curve: &GainCurve,
+    fit: &GainFit,
+    schedules: &[BatchSchedule],
+    budget: &Budget,
+    stop_precision: f64,
+) -> (Vec<Projection>, Option<usize>) {
+    let projections: Vec<Projection> = schedules
+        .iter()
+        .map(|s| project(curve, fit, *s, budget, stop_precision))
+        .collect();
+    let best = projections
+        .iter()
+        .enumerate()
+        .filter(|(_, p)| p.finished)
+        .min_by(|a, b| a.1.hours.total_cmp(&b.1.hours))
+        .or_else(|| {
+            projections
+                .iter()
+                .enumerate()
+                .max_by(|a, b| a.1.relevant.total_cmp(&b.1.relevant))
+        })
+        .map(|(i, _)| i);
+    (projections, best)
+}
@@ -0,0 +1,171 @@
+//! When a topic's review moves on to its next step. A review alternates
+//! between judging a served batch and retraining on the judgments, and the
+//! round policy decides which is due: retrain once the batch is judged, or
+//! sooner after a set number of judgments or when the batch turns out
+//! poor; switch from relevance to uncertainty sampling once the gain curve
+//! flattens; and stop when the knee rule of [`crate::stopping`] fires.
+
+use crate::qrels::Judgment;
+use crate::stopping::{self, KneeRule};
+use crate::topic::{Session, Strategy};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+
+/// Share of a batch that must be judged before its precision is trusted
+pub const MIN_JUDGED_SHARE: f64 = 0.25;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoundPolicy {
+    /// Retrain after this many judgments, even mid-batch
+    pub retrain_every: Option<usize>,
+    /// Retrain mid-batch when the judged part of the batch is less
+    /// relevant than this
+    pub min_precision: Option<f64>,
+    /// Switch to uncertainty sampling once the gain curve's slope ratio
+    /// (see [`stopping::check`]) reaches this
+    pub uncertainty_ratio: Option<f64>,
+    /// Stop when the knee rule fires
+    pub stop: bool,
+}
+
+impl Default for RoundPolicy {
+    fn default() -> Self {
+        RoundPolicy {
+            retrain_every: None,
+            min_precision: None,
+            uncertainty_ratio: None,
+            stop: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    /// The knee rule fired
+    Stop,
+    /// Change the topic's strategy
+    Switch(Strategy),
+    /// Train on the judgments logged since the last commit
+    Retrain,
+    /// Score a new batch with the committed model
+    Serve,
+    /// Documents of the last batch are still to be judged
+    Wait { unjudged: usize },
+}
+
+#[derive(Debug, Clone)]
+pub struct Decision {
+    pub action: Action,
+    /// Why, for the reviewer
+    pub reason: String,
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match &self.action {
+            Action::Stop => "stop".to_string(),
+            Action::Switch(Strategy::Relevance) => "switch to relevance".to_string(),
+            Action::Switch(Strategy::Uncertainty) => "switch to uncertainty".to_string(),
+            Action::Retrain => "retrain".to_string(),
+            Action::Serve => "serve".to_string(),
+            Action::Wait { unjudged } => format!("wait for {} judgments", unjudged),
+        };
+        write!(f, "{}: {}", action, self.reason)
+    }
+}
+
+impl RoundPolicy {
+    /// The next step for a topic reviewed with `strategy`, given its
+    /// session and judgment log.
+    pub fn decide(
+        &self,
+        strategy: Strategy,
+        session: &Session,
+        judgments: &[Judgment],
+        min_rel: i32,
+    ) -> Decision {
+        let decision = |action, reason: String| Decision { action, reason };
+        let curve = stopping::gain_curve(judgments, min_rel);
+        let knee = stopping::check(&curve, &KneeRule::default(), None);
+        if self.stop && knee.stop {
+            return decision(
+                Action::Stop,
+                format!(
+                    "slope ratio {:.1} after {} reviewed",
+                    knee.slope_ratio, knee.reviewed
+                ),
+            );
+        }
+        if let Some(ratio) = self.uncertainty_ratio {
+            if strategy == Strategy::Relevance && knee.knee.is_some() && knee.slope_ratio >= ratio {
+                return decision(
+                    Action::Switch(Strategy::Uncertainty),
+                    format!("gain has flattened, slope ratio {:.1}", knee.slope_ratio),
+                );
+            }
+        }
+
+        let fresh = judgments.len().saturating_sub(session.judgments);
+        let Some(batch) = session.batches.last() else {
+            return match fresh {
+                0 => decision(Action::Serve, "no batch served yet".to_string()),
+                n => decision(
+                    Action::Retrain,
+                    format!("{} judgments, no batch served yet", n),
+                ),
+            };
+        };
+        let judged: HashSet<&str> = judgments.iter().map(|j| j.docid.as_str()).collect();
+        let in_batch: Vec<&Judgment> = {
+            let served: HashSet<&str> = batch.docids.iter().map(|d| d.as_str()).collect();
+            judgments
+                .iter()
+                .filter(|j| served.contains(j.docid.as_str()))
+                .collect()
+        };
+        let unjudged = batch
+            .docids
+            .iter()
+            .filter(|d| !judged.contains(d.as_str()))
+            .count();
+
+        if fresh > 0 {
+            if unjudged == 0 {
+                return decision(Action::Retrain, format!("round {} is judged", batch.round));
+            }
+            if self.retrain_every.is_some_and(|n| fresh >= n) {
+                return decision(
+                    Action::Retrain,
+                    format!("{} judgments since the last commit", fresh),
+                );
+            }
+            let share = in_batch.len() as f64 / batch.docids.len().max(1) as f64;
+            let precision = in_batch.iter().filter(|j| j.rel >= min_rel).count() as f64
+                / in_batch.len().max(1) as f64;
+            if share >= MIN_JUDGED_SHARE && self.min_precision.is_some_and(|p| precision < p) {
+                return decision(
+                    Action::Retrain,
+                    format!("batch precision {:.3} so far", precision),
+                );
+            }
+        }
+        if unjudged == 0 {
+            return decision(
+                Action::Serve,
+                format!("round {} is judged and trained on", batch.round),
+            );
+        }
+        // A model committed since the batch was served should serve the rest
+        if session.committed > batch.served {
+            return decision(
+                Action::Serve,
+                format!("the model is newer than round {}", batch.round),
+            );
+        }
+        decision(
+            Action::Wait { unjudged },
+            format!("round {} is being judged", batch.round),
+        )
+    }
+}
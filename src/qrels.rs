@@ -0,0 +1,129 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind};
+use std::path::Path;
+
+#[derive(Debug, Clone)]
+pub struct Judgment {
+    pub topic: String,
+    pub docid: String,
+    pub rel: i32,
+    /// The iteration field, when it is a number. Topic judgment logs use
+    /// it for the review round in which the judgment was made.
+    pub round: Option<u32>,
+    /// When the judgment was logged, in seconds since the Unix epoch, from
+    /// the fifth field of a topic judgment log
+    pub logged: Option<u64>,
+}
+
+/// Read a TREC qrels file (`topic iteration docid rel`). Lines starting with
+/// `#` are skipped. A fifth field, if a number, is the time the judgment was
+/// logged, and any others are ignored.
+pub fn read_qrels(filename: impl AsRef<Path>) -> std::io::Result<Vec<Judgment>> {
+    let fp = BufReader::new(File::open(filename)?);
+    let mut qrels = Vec::new();
+    for line in fp.lines() {
+        let line = line?;
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 4 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Short qrels line: {}", line),
+            ));
+        }
+        let rel = fields[3]
+            .parse::<i32>()
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        qrels.push(Judgment {
+            topic: fields[0].to_string(),
+            docid: fields[2].to_string(),
+            rel,
+            round: fields[1].parse().ok(),
+            logged: fields.get(4).and_then(|t| t.parse().ok()),
+        });
+    }
+    Ok(qrels)
+}
+
+/// Read judgments from a CSV review export. The first row names the
+/// columns: `docid` and one of `rel`, `relevance`, `label` or `judgment`
+/// are required, `topic` and `round` are optional, and others are ignored.
+/// Labels may be integer grades or yes/no style words.
+pub fn read_judgment_csv(filename: impl AsRef<Path>) -> std::io::Result<Vec<Judgment>> {
+    let fp = BufReader::new(File::open(filename)?);
+    let mut lines = fp.lines();
+    let header: Vec<String> = match lines.next() {
+        Some(line) => split_csv_line(&line?)
+            .iter()
+            .map(|h| h.trim().to_lowercase())
+            .collect(),
+        None => return Ok(Vec::new()),
+    };
+    let column = |names: &[&str]| header.iter().position(|h| names.contains(&h.as_str()));
+    let missing = |what: &str| Error::new(ErrorKind::InvalidData, format!("No {} column", what));
+    let docid_col = column(&["docid", "doc_id", "document"]).ok_or_else(|| missing("docid"))?;
+    let rel_col =
+        column(&["rel", "relevance", "label", "judgment"]).ok_or_else(|| missing("label"))?;
+    let topic_col = column(&["topic"]);
+    let round_col = column(&["round"]);
+
+    let mut judgments = Vec::new();
+    for line in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields = split_csv_line(&line);
+        let field = |i: usize| fields.get(i).map_or("", |f| f.trim());
+        let rel = parse_label(field(rel_col)).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Unrecognized label in: {}", line),
+            )
+        })?;
+        judgments.push(Judgment {
+            topic: topic_col.map_or(String::new(), |i| field(i).to_string()),
+            docid: field(docid_col).to_string(),
+            rel,
+            round: round_col.and_then(|i| field(i).parse().ok()),
+            logged: None,
+        });
+    }
+    Ok(judgments)
+}
+
+fn parse_label(label: &str) -> Option<i32> {
+    if let Ok(rel) = label.parse::<i32>() {
+        return Some(rel);
+    }
+    match label.to_lowercase().as_str() {
+        "yes" | "y" | "true" | "relevant" | "responsive" => Some(1),
+        "no" | "n" | "false" | "nonrelevant" | "not relevant" | "non-responsive"
+        | "nonresponsive" => Some(0),
+        _ => None,
+    }
+}
+
+/// Split one CSV line, honoring double-quoted fields and `""` escapes.
+/// Quoted fields may not span lines.
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
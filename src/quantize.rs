@@ -0,0 +1,135 @@
+//! Compact storage for [`crate::Classifier`] weights. A quantized model
+//! file holds its weights as IEEE half floats, or as bytes with one shared
+//! scale factor, and is dequantized to `f32` when it is loaded, so it
+//! scores exactly as the rounded weights say. Half precision keeps about
+//! three significant digits; int8 keeps weights to within half a step of
+//! the largest weight / 127.
+
+use serde::{Deserialize, Serialize};
+use std::io::{Error, ErrorKind};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Quantization {
+    /// Full precision, as models have always been stored
+    #[default]
+    F32,
+    /// Half the size
+    F16,
+    /// A quarter the size
+    Int8,
+}
+
+impl FromStr for Quantization {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "f32" => Ok(Quantization::F32),
+            "f16" => Ok(Quantization::F16),
+            "int8" => Ok(Quantization::Int8),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown quantization {}", s),
+            )),
+        }
+    }
+}
+
+/// Quantized weights as written to a model file.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum QuantizedWeights {
+    F16(Vec<u16>),
+    /// Each weight is its byte times the scale
+    Int8 {
+        scale: f32,
+        values: Vec<i8>,
+    },
+}
+
+impl QuantizedWeights {
+    /// Quantize `w`, or `None` for [`Quantization::F32`].
+    pub fn new(w: &[f32], how: Quantization) -> Option<QuantizedWeights> {
+        match how {
+            Quantization::F32 => None,
+            Quantization::F16 => Some(QuantizedWeights::F16(
+                w.iter().map(|x| f32_to_f16(*x)).collect(),
+            )),
+            Quantization::Int8 => {
+                let max = w.iter().fold(0.0f32, |m, x| m.max(x.abs()));
+                let scale = if max > 0.0 { max / 127.0 } else { 1.0 };
+                let values = w
+                    .iter()
+                    .map(|x| (x / scale).round().clamp(-127.0, 127.0) as i8)
+                    .collect();
+                Some(QuantizedWeights::Int8 { scale, values })
+            }
+        }
+    }
+
+    pub fn quantization(&self) -> Quantization {
+        match self {
+            QuantizedWeights::F16(_) => Quantization::F16,
+            QuantizedWeights::Int8 { .. } => Quantization::Int8,
+        }
+    }
+
+    pub fn dequantize(&self) -> Vec<f32> {
+        match self {
+            QuantizedWeights::F16(values) => values.iter().map(|h| f16_to_f32(*h)).collect(),
+            QuantizedWeights::Int8 { scale, values } => {
+                values.iter().map(|q| *q as f32 * scale).collect()
+            }
+        }
+    }
+}
+
+/// Round to the nearest half float, ties to even. Values too large for a
+/// half become infinite, and those too small become zero.
+fn f32_to_f16(x: f32) -> u16 {
+    let bits = x.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exp = ((bits >> 23) & 0xff) as i32;
+    let mant = bits & 0x7f_ffff;
+    if exp == 0xff {
+        return sign | 0x7c00 | if mant != 0 { 0x200 } else { 0 };
+    }
+    let e = exp - 127 + 15;
+    if e >= 0x1f {
+        return sign | 0x7c00;
+    }
+    // The mantissa bits below the half's, and where they round up
+    let (half, rem, shift) = if e <= 0 {
+        if e < -10 {
+            return sign;
+        }
+        let shift = (14 - e) as u32;
+        let m = mant | 0x80_0000;
+        (m >> shift, m & ((1 << shift) - 1), shift)
+    } else {
+        (((e as u32) << 10) | (mant >> 13), mant & 0x1fff, 13)
+    };
+    let halfway = 1 << (shift - 1);
+    let round_up = rem > halfway || (rem == halfway && half & 1 == 1);
+    // A carry out of the mantissa correctly bumps the exponent
+    sign | (half + u32::from(round_up)) as u16
+}
+
+fn f16_to_f32(h: u16) -> f32 {
+    let sign = ((h & 0x8000) as u32) << 16;
+    let exp = ((h >> 10) & 0x1f) as u32;
+    let mant = (h & 0x3ff) as u32;
+    match exp {
+        0 => {
+            // Zero or subnormal: mant * 2^-24
+            let x = mant as f32 / (1 << 24) as f32;
+            if sign != 0 {
+                -x
+            } else {
+                x
+            }
+        }
+        0x1f => f32::from_bits(sign | 0x7f80_0000 | (mant << 13)),
+        _ => f32::from_bits(sign | ((exp + 127 - 15) << 23) | (mant << 13)),
+    }
+}
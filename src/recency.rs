@@ -0,0 +1,107 @@
+//! A recency prior for triage-style reviews, where newer documents must
+//! surface sooner. Each document's date is taken at build time from a field
+//! of its JSON record (see `build_corpus --date-field`), and at score time
+//! a document `age` days old gets `weight * 0.5^(age / half_life)` added to
+//! its model score. Documents without a date, or dated after the reference
+//! day, get no boost and the full boost respectively.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Result, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Each document's date as days since 1970-01-01, indexed by intid.
+#[derive(Debug, Clone, Default)]
+pub struct Dates {
+    pub days: Vec<Option<i32>>,
+}
+
+impl Dates {
+    /// Read a `.dts` file: the bincode of the day list.
+    pub fn load(filename: impl AsRef<Path>) -> Result<Dates> {
+        let fp = BufReader::new(File::open(filename)?);
+        let days =
+            bincode::deserialize_from(fp).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        Ok(Dates { days })
+    }
+
+    pub fn save(&self, filename: impl AsRef<Path>) -> Result<()> {
+        let mut fp = BufWriter::new(File::create(filename)?);
+        bincode::serialize_into(&mut fp, &self.days)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        fp.flush()
+    }
+
+    pub fn insert(&mut self, intid: u32, day: i32) {
+        let i = intid as usize;
+        if self.days.len() <= i {
+            self.days.resize(i + 1, None);
+        }
+        self.days[i] = Some(day);
+    }
+
+    pub fn get(&self, intid: u32) -> Option<i32> {
+        self.days.get(intid as usize).copied().flatten()
+    }
+}
+
+/// Parse a date as days since 1970-01-01: an ISO date, possibly followed
+/// by a time (`2023-04-01`, `2023-04-01T12:00:00Z`), or a number of
+/// seconds since the epoch.
+pub fn parse_date(s: &str) -> Option<i32> {
+    let s = s.trim();
+    if let Ok(secs) = s.parse::<i64>() {
+        return Some(secs.div_euclid(86400) as i32);
+    }
+    let date = s.get(..10)?;
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    Some(days_from_civil(year, month, day) as i32)
+}
+
+/// Days since the epoch of a proleptic Gregorian date, after Howard Hinnant
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Today, in days since the epoch (UTC).
+pub fn today() -> i32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| (d.as_secs() / 86400) as i32)
+}
+
+/// The prior added to each document's score.
+#[derive(Debug, Clone)]
+pub struct Recency {
+    pub dates: Dates,
+    /// The day ages are measured from
+    pub as_of: i32,
+    /// Days for the boost to fall by half
+    pub half_life: f32,
+    /// The boost given a document dated on or after `as_of`
+    pub weight: f32,
+}
+
+impl Recency {
+    pub fn prior(&self, intid: u32) -> f32 {
+        match self.dates.get(intid) {
+            Some(day) => {
+                let age = (self.as_of - day).max(0) as f32;
+                self.weight * 0.5f32.powf(age / self.half_life)
+            }
+            None => 0.0,
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Route {
+    Include,
+    Exclude,
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub route: Route,
+    /// `docid`, or the name of a field in the document's JSON record
+    pub field: String,
+    pub pattern: Regex,
+}
+
+/// Build-time rules that keep documents out of review without keeping
+/// them out of the collection. Routed documents are still indexed, but
+/// their intids are written to the collection's excluded file, which the
+/// scorers skip.
+///
+/// A rules file has one rule per line, `include|exclude FIELD REGEX`, where
+/// the regex is the rest of the line. Blank lines and lines starting with
+/// `#` are ignored. The first rule that matches a document decides its
+/// route; documents no rule matches are included.
+#[derive(Debug, Clone, Default)]
+pub struct RoutingRules {
+    pub rules: Vec<Rule>,
+}
+
+impl RoutingRules {
+    pub fn load(filename: impl AsRef<Path>) -> std::io::Result<RoutingRules> {
+        let fp = BufReader::new(File::open(filename)?);
+        let mut rules = Vec::new();
+        for line in fp.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+            let bad = |why: String| Error::new(ErrorKind::InvalidData, why);
+            let mut parts = line.splitn(3, char::is_whitespace);
+            let route = match parts.next() {
+                Some("include") => Route::Include,
+                Some("exclude") => Route::Exclude,
+                _ => {
+                    return Err(bad(format!(
+                        "Rule must start with include or exclude: {}",
+                        line
+                    )))
+                }
+            };
+            let (field, pattern) = match (parts.next(), parts.next()) {
+                (Some(f), Some(p)) => (f.to_string(), p.trim()),
+                _ => return Err(bad(format!("Rule needs a field and a pattern: {}", line))),
+            };
+            let pattern = Regex::new(pattern).map_err(|e| bad(e.to_string()))?;
+            rules.push(Rule {
+                route,
+                field,
+                pattern,
+            });
+        }
+        Ok(RoutingRules { rules })
+    }
+
+    pub fn route(&self, docid: &str, doc: &Map<String, Value>) -> Route {
+        for rule in &self.rules {
+            let matched = if rule.field == "docid" {
+                rule.pattern.is_match(docid)
+            } else {
+                match doc.get(&rule.field) {
+                    Some(Value::String(s)) => rule.pattern.is_match(s),
+                    Some(Value::Null) | None => false,
+                    Some(v) => rule.pattern.is_match(&v.to_string()),
+                }
+            };
+            if matched {
+                return rule.route;
+            }
+        }
+        Route::Include
+    }
+}
@@ -0,0 +1,371 @@
+use crate::qrels::Judgment;
+use crate::topic::Strategy;
+use roaring::RoaringBitmap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read};
+use std::path::Path;
+
+#[derive(Debug, Clone)]
+pub struct RunEntry {
+    /// The topic column of a TREC run line; `mycal score` lines have none
+    pub topic: Option<String>,
+    pub docid: String,
+    pub score: f32,
+}
+
+/// Read a ranking, best document first. Accepts both the `docid score`
+/// lines printed by `mycal score` and six-column TREC run lines
+/// (`topic Q0 docid rank score tag`), keeping the topic of the latter.
+/// Lines starting with `#` are skipped.
+pub fn read_run(filename: impl AsRef<Path>) -> std::io::Result<Vec<RunEntry>> {
+    let fp = BufReader::new(File::open(filename)?);
+    let mut run = Vec::new();
+    for line in fp.lines() {
+        let line = line?;
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (topic, docid, score) = match fields.len() {
+            2 => (None, fields[0], fields[1]),
+            6 => (Some(fields[0]), fields[2], fields[4]),
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Unrecognized run line: {}", line),
+                ))
+            }
+        };
+        let score = score
+            .parse::<f32>()
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        run.push(RunEntry {
+            topic: topic.map(str::to_string),
+            docid: docid.to_string(),
+            score,
+        });
+    }
+    Ok(run)
+}
+
+/// How a ranked list was produced, so an audit can reconstruct it. It is
+/// written as a comment line at the head of a run, `# run` followed by the
+/// JSON, which [`read_run`] skips.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunMetadata {
+    pub version: String,
+    pub topic: Option<String>,
+    pub model: String,
+    pub model_hash: String,
+    pub collection: String,
+    /// Hash of the dictionary, which changes whenever the collection is
+    /// rebuilt
+    pub collection_hash: String,
+    /// The collection's [`crate::fingerprint`], if it has one
+    #[serde(default)]
+    pub fingerprint: Option<String>,
+    pub tokenizer: String,
+    pub strategy: Strategy,
+    pub num_results: usize,
+    pub num_excluded: u64,
+    /// Hash of the excluded intids, in their portable serialization
+    pub exclude_hash: String,
+    /// When scoring started, in seconds since the Unix epoch
+    pub started: u64,
+    pub seconds: f64,
+}
+
+impl RunMetadata {
+    pub const PREFIX: &'static str = "# run ";
+
+    pub fn to_comment(&self) -> String {
+        format!(
+            "{}{}",
+            Self::PREFIX,
+            serde_json::to_string(self).expect("Error writing run metadata")
+        )
+    }
+
+    /// Every metadata block in a run file, in order.
+    pub fn read(filename: impl AsRef<Path>) -> std::io::Result<Vec<RunMetadata>> {
+        let fp = BufReader::new(File::open(filename)?);
+        let mut blocks = Vec::new();
+        for line in fp.lines() {
+            let line = line?;
+            if let Some(json) = line.strip_prefix(Self::PREFIX) {
+                blocks.push(serde_json::from_str(json)?);
+            }
+        }
+        Ok(blocks)
+    }
+}
+
+/// A 64-bit FNV-1a hash of a file's contents, in hex.
+pub fn hash_file(filename: impl AsRef<Path>) -> std::io::Result<String> {
+    let mut fp = BufReader::new(File::open(filename)?);
+    let mut buf = [0u8; 64 * 1024];
+    let mut hash = FNV_OFFSET;
+    loop {
+        let n = fp.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hash = fnv1a(hash, &buf[..n]);
+    }
+    Ok(format!("{:016x}", hash))
+}
+
+/// A 64-bit FNV-1a hash of a set of intids, in hex.
+pub fn hash_intids(intids: &RoaringBitmap) -> String {
+    let mut bytes = Vec::with_capacity(intids.serialized_size());
+    intids
+        .serialize_into(&mut bytes)
+        .expect("Error serializing intids");
+    format!("{:016x}", fnv1a(FNV_OFFSET, &bytes))
+}
+
+pub(crate) const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+pub(crate) fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// A document's position in two rankings. Ranks are 1-based; `None` means
+/// the document is absent from that run.
+#[derive(Debug, Clone)]
+pub struct RankChange {
+    pub docid: String,
+    pub old_rank: Option<usize>,
+    pub new_rank: Option<usize>,
+}
+
+impl RankChange {
+    pub fn shift(&self) -> i64 {
+        match (self.old_rank, self.new_rank) {
+            (Some(o), Some(n)) => o as i64 - n as i64,
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RunDiff {
+    pub depth: usize,
+    /// Number of documents ranked in both runs
+    pub common: usize,
+    /// Fraction of the top `depth` shared by both runs
+    pub overlap: f64,
+    pub kendall_tau: f64,
+    pub spearman_rho: f64,
+    /// Documents in both runs, largest absolute rank change first
+    pub movers: Vec<RankChange>,
+    /// Documents in the new top `depth` that were not in the old one
+    pub entered: Vec<RankChange>,
+    /// Documents in the old top `depth` that fell out of the new one
+    pub dropped: Vec<RankChange>,
+}
+
+pub fn diff_runs(old: &[RunEntry], new: &[RunEntry], depth: usize) -> RunDiff {
+    let old_ranks: HashMap<&str, usize> = old
+        .iter()
+        .enumerate()
+        .map(|(i, e)| (e.docid.as_str(), i + 1))
+        .collect();
+    let new_ranks: HashMap<&str, usize> = new
+        .iter()
+        .enumerate()
+        .map(|(i, e)| (e.docid.as_str(), i + 1))
+        .collect();
+
+    let change = |docid: &str| RankChange {
+        docid: docid.to_string(),
+        old_rank: old_ranks.get(docid).copied(),
+        new_rank: new_ranks.get(docid).copied(),
+    };
+
+    let mut movers: Vec<RankChange> = old
+        .iter()
+        .filter(|e| new_ranks.contains_key(e.docid.as_str()))
+        .map(|e| change(&e.docid))
+        .collect();
+
+    // New ranks of the common documents, listed in old-rank order
+    let new_order: Vec<usize> = movers.iter().map(|c| c.new_rank.unwrap()).collect();
+    let kendall_tau = kendall_tau(&new_order);
+    let spearman_rho = spearman_rho(&new_order);
+
+    movers.sort_by_key(|c| std::cmp::Reverse(c.shift().abs()));
+    movers.retain(|c| c.shift() != 0);
+
+    let in_top = |rank: Option<usize>| rank.is_some_and(|r| r <= depth);
+    let entered: Vec<RankChange> = new
+        .iter()
+        .take(depth)
+        .map(|e| change(&e.docid))
+        .filter(|c| !in_top(c.old_rank))
+        .collect();
+    let dropped: Vec<RankChange> = old
+        .iter()
+        .take(depth)
+        .map(|e| change(&e.docid))
+        .filter(|c| !in_top(c.new_rank))
+        .collect();
+
+    let top_len = depth.min(old.len()).min(new.len()).max(1);
+    let shared = new.len().min(depth) - entered.len();
+
+    RunDiff {
+        depth,
+        common: new_order.len(),
+        overlap: shared as f64 / top_len as f64,
+        kendall_tau,
+        spearman_rho,
+        movers,
+        entered,
+        dropped,
+    }
+}
+
+/// Kendall's tau between the identity ranking and `ranks`, computed by
+/// counting inversions with a merge sort so long runs stay O(n log n).
+pub fn kendall_tau(ranks: &[usize]) -> f64 {
+    let n = ranks.len();
+    if n < 2 {
+        return 1.0;
+    }
+    let mut v = ranks.to_vec();
+    let mut buf = vec![0; n];
+    let inversions = count_inversions(&mut v, &mut buf);
+    let pairs = (n * (n - 1) / 2) as f64;
+    1.0 - 2.0 * inversions as f64 / pairs
+}
+
+fn count_inversions(v: &mut [usize], buf: &mut [usize]) -> u64 {
+    let n = v.len();
+    if n < 2 {
+        return 0;
+    }
+    let mid = n / 2;
+    let mut count = {
+        let (left, right) = v.split_at_mut(mid);
+        count_inversions(left, &mut buf[..mid]) + count_inversions(right, &mut buf[mid..])
+    };
+    let (mut i, mut j, mut k) = (0, mid, 0);
+    while i < mid && j < n {
+        if v[i] <= v[j] {
+            buf[k] = v[i];
+            i += 1;
+        } else {
+            buf[k] = v[j];
+            count += (mid - i) as u64;
+            j += 1;
+        }
+        k += 1;
+    }
+    buf[k..k + mid - i].copy_from_slice(&v[i..mid]);
+    k += mid - i;
+    buf[k..k + n - j].copy_from_slice(&v[j..n]);
+    v.copy_from_slice(&buf[..n]);
+    count
+}
+
+/// Spearman's rho between the identity ranking and `ranks`, after
+/// re-ranking `ranks` densely so gaps from non-shared documents don't count.
+pub fn spearman_rho(ranks: &[usize]) -> f64 {
+    let n = ranks.len();
+    if n < 2 {
+        return 1.0;
+    }
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by_key(|&i| ranks[i]);
+    let mut dense = vec![0; n];
+    for (r, &i) in order.iter().enumerate() {
+        dense[i] = r;
+    }
+    let d2: f64 = dense
+        .iter()
+        .enumerate()
+        .map(|(i, &r)| {
+            let d = i as f64 - r as f64;
+            d * d
+        })
+        .sum();
+    let n = n as f64;
+    1.0 - 6.0 * d2 / (n * (n * n - 1.0))
+}
+
+/// Precision and recall at a rank cutoff.
+#[derive(Debug, Clone, Serialize)]
+pub struct AtCutoff {
+    pub k: usize,
+    pub precision: f64,
+    pub recall: f64,
+}
+
+/// A run scored against judgments. Unjudged documents count as
+/// nonrelevant, and a document ranked twice counts at its first rank.
+#[derive(Debug, Clone, Serialize)]
+pub struct Evaluation {
+    /// Relevant documents in the judgments
+    pub relevant: usize,
+    pub retrieved: usize,
+    pub relevant_retrieved: usize,
+    pub average_precision: f64,
+    pub cutoffs: Vec<AtCutoff>,
+    /// Relevant documents found by each rank
+    pub gain: Vec<usize>,
+}
+
+pub fn evaluate(
+    run: &[RunEntry],
+    qrels: &[Judgment],
+    min_rel: i32,
+    cutoffs: &[usize],
+) -> Evaluation {
+    let relevant: HashSet<&str> = qrels
+        .iter()
+        .filter(|j| j.rel >= min_rel)
+        .map(|j| j.docid.as_str())
+        .collect();
+    let mut seen = HashSet::new();
+    let mut gain = Vec::with_capacity(run.len());
+    let mut found = 0;
+    let mut precision_sum = 0.0;
+    for entry in run.iter().filter(|e| seen.insert(e.docid.as_str())) {
+        if relevant.contains(entry.docid.as_str()) {
+            found += 1;
+            precision_sum += found as f64 / (gain.len() + 1) as f64;
+        }
+        gain.push(found);
+    }
+    let num_rel = relevant.len().max(1) as f64;
+    let cutoffs = cutoffs
+        .iter()
+        .map(|&k| {
+            // A run shorter than k is padded with nonrelevant documents
+            let found = match k.min(gain.len()) {
+                0 => 0,
+                n => gain[n - 1],
+            };
+            AtCutoff {
+                k,
+                precision: found as f64 / k.max(1) as f64,
+                recall: found as f64 / num_rel,
+            }
+        })
+        .collect();
+    Evaluation {
+        relevant: relevant.len(),
+        retrieved: gain.len(),
+        relevant_retrieved: found,
+        average_precision: precision_sum / num_rel,
+        cutoffs,
+        gain,
+    }
+}
@@ -0,0 +1,455 @@
+use crate::cancel::CancellationToken;
+use crate::chunks::{ChunkedFeatures, RawChunk};
+use crate::recency::Recency;
+use crate::topic::Strategy;
+use crate::{Dict, DocsDb, FeatureVec, ScoringModel};
+use min_max_heap::MinMaxHeap;
+use ordered_float::OrderedFloat;
+use roaring::RoaringBitmap;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Result, Seek, SeekFrom};
+use std::sync::Arc;
+
+fn corrupt(e: bincode::Error) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("Corrupt feature file: {}", e),
+    )
+}
+
+/// Everything a scoring pass needs besides the model, resolved once so
+/// repeated passes over a collection (one per review round, say) don't
+/// re-read exclude files or look docids up again.
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    /// Intids never returned: excluded, judged, and routed documents
+    pub exclude: RoaringBitmap,
+    pub strategy: Strategy,
+    /// Number of top-ranked documents to return
+    pub num_results: usize,
+    /// Documents scoring below this are never returned
+    pub min_score: Option<f32>,
+    /// Documents read from the feature file and scored together
+    pub batch_size: usize,
+    /// A prior added to every document's model score
+    pub recency: Option<Arc<Recency>>,
+    /// Stops the pass early, returning what was scored so far
+    pub cancel: CancellationToken,
+}
+
+impl SearchOptions {
+    pub fn new(num_results: usize) -> SearchOptions {
+        SearchOptions {
+            exclude: RoaringBitmap::new(),
+            strategy: Strategy::Relevance,
+            num_results,
+            min_score: None,
+            batch_size: 1024,
+            recency: None,
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    pub fn exclude_intids(&mut self, intids: &RoaringBitmap) {
+        self.exclude |= intids;
+    }
+
+    pub fn exclude_docids<'a>(&mut self, docs: &DocsDb, docids: impl Iterator<Item = &'a str>) {
+        self.exclude |= docs.intids_for(docids);
+    }
+
+    /// The model score combined with any prior.
+    pub fn adjusted(&self, intid: u32, score: f32) -> f32 {
+        match &self.recency {
+            Some(recency) => score + recency.prior(intid),
+            None => score,
+        }
+    }
+
+    /// The key documents are ranked by under the strategy; larger is better.
+    fn rank_key(&self, score: f32) -> f32 {
+        match self.strategy {
+            Strategy::Relevance => score,
+            Strategy::Uncertainty => -score.abs(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Hit {
+    pub intid: u32,
+    pub docid: String,
+    pub score: f32,
+}
+
+struct Ranked {
+    key: OrderedFloat<f32>,
+    hit: Hit,
+}
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key.cmp(&other.key)
+    }
+}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl Eq for Ranked {}
+
+/// Score every document in a feature file and return the best
+/// `opts.num_results`, best first. `progress` is called with the number of
+/// documents read after each batch. A record that can't be read fails the
+/// search with `InvalidData`, rather than ending it early.
+pub fn search(
+    model: &ScoringModel,
+    feats: &mut BufReader<File>,
+    opts: &SearchOptions,
+    progress: impl FnMut(usize),
+) -> Result<Vec<Hit>> {
+    Ok(search_many(&[(model, opts)], feats, progress)?
+        .pop()
+        .unwrap_or_default())
+}
+
+/// The `opts.num_results` documents nearest the model's decision boundary
+/// (smallest absolute score), nearest first, whatever `opts.strategy` says.
+/// Judged documents should be in `opts.exclude`.
+pub fn suggest(
+    model: &ScoringModel,
+    feats: &mut BufReader<File>,
+    opts: &SearchOptions,
+    progress: impl FnMut(usize),
+) -> Result<Vec<Hit>> {
+    let opts = SearchOptions {
+        strategy: Strategy::Uncertainty,
+        ..opts.clone()
+    };
+    search(model, feats, &opts, progress)
+}
+
+/// Run several searches in one pass over a feature file, returning each
+/// search's hits in the order given. Documents are read in batches of the
+/// largest batch size among the options.
+pub fn search_many(
+    searches: &[(&ScoringModel, &SearchOptions)],
+    feats: &mut BufReader<File>,
+    mut progress: impl FnMut(usize),
+) -> Result<Vec<Vec<Hit>>> {
+    let batch_size = searches
+        .iter()
+        .map(|(_, opts)| opts.batch_size)
+        .max()
+        .unwrap_or(1)
+        .max(1);
+    let mut tops: Vec<MinMaxHeap<Ranked>> = searches.iter().map(|_| MinMaxHeap::new()).collect();
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut first_intid: u32 = 0;
+    let mut done = false;
+
+    let cancelled = || searches.iter().any(|(_, opts)| opts.cancel.is_cancelled());
+    while !done && !cancelled() {
+        batch.clear();
+        while batch.len() < batch_size {
+            let Some(fv) = FeatureVec::read_next(feats).map_err(corrupt)? else {
+                done = true;
+                break;
+            };
+            batch.push(fv);
+        }
+
+        rank_batch(searches, &mut tops, &batch, first_intid);
+        first_intid += batch.len() as u32;
+        progress(batch.len());
+    }
+
+    Ok(into_hits(tops))
+}
+
+/// [`search_many`] over a chunked feature file. Chunks whose documents
+/// every search excludes are never read, and with the `parallel` feature
+/// chunks are decoded several at a time.
+pub fn search_chunked(
+    searches: &[(&ScoringModel, &SearchOptions)],
+    chunks: &mut ChunkedFeatures,
+    mut progress: impl FnMut(usize),
+) -> Result<Vec<Vec<Hit>>> {
+    let mut tops: Vec<MinMaxHeap<Ranked>> = searches.iter().map(|_| MinMaxHeap::new()).collect();
+    let group = decode_group_size();
+    let mut raw = Vec::with_capacity(group);
+    let mut i = 0;
+
+    let cancelled = || searches.iter().any(|(_, opts)| opts.cancel.is_cancelled());
+    while i < chunks.num_chunks() && !cancelled() {
+        raw.clear();
+        while raw.len() < group && i < chunks.num_chunks() {
+            let intids = chunks.intids(i);
+            let skip = searches
+                .iter()
+                .all(|(_, opts)| opts.exclude.contains_range(intids.clone()));
+            if skip {
+                progress(intids.len());
+            } else {
+                raw.push(chunks.read_chunk(i)?);
+            }
+            i += 1;
+        }
+
+        for (chunk, batch) in raw.iter().zip(decode_chunks(&raw)?) {
+            rank_batch(searches, &mut tops, &batch, chunk.first_intid);
+            progress(batch.len());
+        }
+    }
+
+    Ok(into_hits(tops))
+}
+
+/// [`search_many`] over an Arrow copy of the feature file, a record batch
+/// at a time.
+#[cfg(feature = "arrow")]
+pub fn search_arrow(
+    searches: &[(&ScoringModel, &SearchOptions)],
+    feats: &mut crate::columnar::ArrowFeatures,
+    mut progress: impl FnMut(usize),
+) -> Result<Vec<Vec<Hit>>> {
+    let mut tops: Vec<MinMaxHeap<Ranked>> = searches.iter().map(|_| MinMaxHeap::new()).collect();
+    let mut first_intid: u32 = 0;
+
+    let cancelled = || searches.iter().any(|(_, opts)| opts.cancel.is_cancelled());
+    while !cancelled() {
+        let Some(batch) = feats.next() else {
+            break;
+        };
+        let batch = batch?;
+        for ((model, opts), top) in searches.iter().zip(tops.iter_mut()) {
+            for i in 0..batch.len() {
+                let (ids, values) = batch.features(i);
+                let score = model.score_slices(ids, values);
+                offer(top, opts, first_intid + i as u32, score, || {
+                    batch.docid(i).to_string()
+                });
+            }
+        }
+        first_intid += batch.len() as u32;
+        progress(batch.len());
+    }
+
+    Ok(into_hits(tops))
+}
+
+#[cfg(feature = "parallel")]
+fn decode_group_size() -> usize {
+    rayon::current_num_threads()
+}
+
+#[cfg(not(feature = "parallel"))]
+fn decode_group_size() -> usize {
+    1
+}
+
+#[cfg(feature = "parallel")]
+fn decode_chunks(raw: &[RawChunk]) -> Result<Vec<Vec<FeatureVec>>> {
+    use rayon::prelude::*;
+    raw.par_iter().map(RawChunk::decode).collect()
+}
+
+#[cfg(not(feature = "parallel"))]
+fn decode_chunks(raw: &[RawChunk]) -> Result<Vec<Vec<FeatureVec>>> {
+    raw.iter().map(RawChunk::decode).collect()
+}
+
+/// Score a batch of consecutive documents starting at `first_intid` for
+/// every search, keeping each search's best in its heap.
+fn rank_batch(
+    searches: &[(&ScoringModel, &SearchOptions)],
+    tops: &mut [MinMaxHeap<Ranked>],
+    batch: &[FeatureVec],
+    first_intid: u32,
+) {
+    for ((model, opts), top) in searches.iter().zip(tops.iter_mut()) {
+        let scores = model.score_batch(batch);
+        for (offset, (fv, score)) in batch.iter().zip(scores).enumerate() {
+            offer(top, opts, first_intid + offset as u32, score, || {
+                fv.docid.clone()
+            });
+        }
+    }
+}
+
+/// Keep document `intid` in a search's heap if it scores among the best.
+/// Its docid is only fetched if it does.
+fn offer(
+    top: &mut MinMaxHeap<Ranked>,
+    opts: &SearchOptions,
+    intid: u32,
+    score: f32,
+    docid: impl FnOnce() -> String,
+) {
+    let score = opts.adjusted(intid, score);
+    if opts.exclude.contains(intid) || opts.min_score.is_some_and(|min| score < min) {
+        return;
+    }
+    let key = OrderedFloat(opts.rank_key(score));
+    if top.len() >= opts.num_results && top.peek_min().is_some_and(|m| key <= m.key) {
+        return;
+    }
+    top.push(Ranked {
+        key,
+        hit: Hit {
+            intid,
+            docid: docid(),
+            score,
+        },
+    });
+    while top.len() > opts.num_results {
+        top.pop_min();
+    }
+}
+
+fn into_hits(tops: Vec<MinMaxHeap<Ranked>>) -> Vec<Vec<Hit>> {
+    tops.into_iter()
+        .map(|top| top.into_vec_desc().into_iter().map(|r| r.hit).collect())
+        .collect()
+}
+
+/// BM25 term frequency saturation
+const BM25_K1: f32 = 1.2;
+/// BM25 document length normalization
+const BM25_B: f32 = 0.75;
+
+/// A document matching a BM25 query, not yet scored.
+struct Bm25Match {
+    hit: Hit,
+    len: f32,
+    /// (token id, term frequency) for each query term in the document
+    terms: Vec<(u32, f32)>,
+}
+
+/// Rank documents by BM25 against a keyword query of token ids, for
+/// finding seed documents before any model exists. A repeated query term
+/// counts once per repetition. There is no inverted file, so this is one
+/// pass over the feature file like [`search`]: term frequencies and
+/// document lengths are recovered from the stored `(1 + log tf) * idf`
+/// weights, and the dictionary's idf stands in for BM25's. Terms in every
+/// document have zero idf and count as occurring once.
+pub fn bm25_search(
+    query: &[u32],
+    dict: &Dict,
+    feats: &mut BufReader<File>,
+    opts: &SearchOptions,
+    mut progress: impl FnMut(usize),
+) -> Result<Vec<Hit>> {
+    let mut query_tf: HashMap<u32, f32> = HashMap::new();
+    for id in query {
+        *query_tf.entry(*id).or_insert(0.0) += 1.0;
+    }
+    let idf = |id: u32| dict.df.get(&id).copied().unwrap_or(0.0);
+
+    // Only documents matching a query term are kept until the average
+    // length is known
+    let mut matches: Vec<Bm25Match> = Vec::new();
+    let mut total_len = 0.0f64;
+    let mut intid: u32 = 0;
+    while let Some(fv) = FeatureVec::read_next(feats).map_err(corrupt)? {
+        if opts.cancel.is_cancelled() {
+            break;
+        }
+        let mut len = 0.0;
+        let mut terms = Vec::new();
+        for f in fv.features.iter() {
+            let tf = dict.term_freq(f.id, f.value);
+            len += tf;
+            if query_tf.contains_key(&f.id) {
+                terms.push((f.id, tf));
+            }
+        }
+        total_len += len as f64;
+        if !terms.is_empty() && !opts.exclude.contains(intid) {
+            let hit = Hit {
+                intid,
+                docid: fv.docid,
+                score: 0.0,
+            };
+            matches.push(Bm25Match { hit, len, terms });
+        }
+        intid += 1;
+        progress(1);
+    }
+    let avg_len = (total_len / intid.max(1) as f64) as f32;
+
+    let mut top = MinMaxHeap::new();
+    for Bm25Match {
+        mut hit,
+        len,
+        terms,
+    } in matches
+    {
+        let norm = BM25_K1 * (1.0 - BM25_B + BM25_B * len / avg_len);
+        hit.score = terms
+            .iter()
+            .map(|(id, tf)| query_tf[id] * idf(*id) * tf * (BM25_K1 + 1.0) / (tf + norm))
+            .sum();
+        if opts.min_score.is_some_and(|min| hit.score < min) {
+            continue;
+        }
+        top.push(Ranked {
+            key: OrderedFloat(hit.score),
+            hit,
+        });
+        while top.len() > opts.num_results {
+            top.pop_min();
+        }
+    }
+    Ok(into_hits(vec![top]).pop().unwrap_or_default())
+}
+
+/// Score an externally supplied candidate list, such as the output of a
+/// keyword search, and return the candidates best first. Candidates with
+/// equal scores keep their input order. Docids not in the collection are
+/// returned separately. A recency prior is added as [`search`] adds it.
+pub fn rerank<'a>(
+    model: &ScoringModel,
+    docs: &DocsDb,
+    feats: &mut BufReader<File>,
+    docids: impl Iterator<Item = &'a str>,
+    recency: Option<&Recency>,
+) -> Result<(Vec<Hit>, Vec<String>)> {
+    let mut batch = Vec::new();
+    let mut intids = Vec::new();
+    let mut missing = Vec::new();
+    for docid in docids {
+        let Some(di) = docs.get(docid) else {
+            missing.push(docid.to_string());
+            continue;
+        };
+        feats.seek(SeekFrom::Start(di.offset))?;
+        batch.push(FeatureVec::read_from(feats).map_err(corrupt)?);
+        intids.push(di.intid as u32);
+    }
+
+    let scores = model.score_batch(&batch);
+    let mut hits: Vec<Hit> = batch
+        .into_iter()
+        .zip(scores)
+        .zip(intids)
+        .map(|((fv, score), intid)| Hit {
+            intid,
+            docid: fv.docid,
+            score: score + recency.map_or(0.0, |r| r.prior(intid)),
+        })
+        .collect();
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+    Ok((hits, missing))
+}
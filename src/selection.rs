@@ -0,0 +1,112 @@
+use crate::FeatureVec;
+use std::collections::{HashMap, HashSet};
+
+/// How strongly one term separates the relevant from the nonrelevant
+/// judged documents, treating each term as present or absent.
+#[derive(Debug, Clone)]
+pub struct TermScore {
+    pub tokid: u32,
+    /// Judged relevant documents containing the term
+    pub pos_df: usize,
+    /// Judged nonrelevant documents containing the term
+    pub neg_df: usize,
+    pub chi_square: f64,
+    pub info_gain: f64,
+}
+
+impl TermScore {
+    /// True if the term is more common among relevant documents.
+    pub fn favors_relevant(&self, num_pos: usize, num_neg: usize) -> bool {
+        self.pos_df as f64 / num_pos.max(1) as f64 > self.neg_df as f64 / num_neg.max(1) as f64
+    }
+}
+
+fn entropy(a: f64, b: f64) -> f64 {
+    let n = a + b;
+    [a, b]
+        .iter()
+        .filter(|x| **x > 0.0)
+        .map(|x| -(x / n) * (x / n).log2())
+        .sum()
+}
+
+fn doc_freqs<'a>(fvs: impl IntoIterator<Item = &'a FeatureVec>) -> HashMap<u32, usize> {
+    let mut df = HashMap::new();
+    for fv in fvs {
+        let terms: HashSet<u32> = fv.features.iter().map(|f| f.id).collect();
+        for t in terms {
+            *df.entry(t).or_insert(0) += 1;
+        }
+    }
+    df
+}
+
+/// Score every term occurring in the judged documents by chi-square and
+/// information gain with respect to the relevant/nonrelevant split.
+pub fn score_terms(pos: &[FeatureVec], neg: &[FeatureVec]) -> Vec<TermScore> {
+    let pos_df = doc_freqs(pos);
+    let neg_df = doc_freqs(neg);
+    let (np, nn) = (pos.len() as f64, neg.len() as f64);
+    let n = np + nn;
+    let class_entropy = entropy(np, nn);
+
+    let terms: HashSet<u32> = pos_df.keys().chain(neg_df.keys()).copied().collect();
+    terms
+        .into_iter()
+        .map(|tokid| {
+            let a = *pos_df.get(&tokid).unwrap_or(&0);
+            let b = *neg_df.get(&tokid).unwrap_or(&0);
+            let (af, bf) = (a as f64, b as f64);
+            let (cf, df) = (np - af, nn - bf);
+
+            let denom = (af + cf) * (bf + df) * (af + bf) * (cf + df);
+            let chi_square = if denom > 0.0 {
+                n * (af * df - bf * cf).powi(2) / denom
+            } else {
+                0.0
+            };
+            let p_t = (af + bf) / n;
+            let info_gain = class_entropy - p_t * entropy(af, bf) - (1.0 - p_t) * entropy(cf, df);
+
+            TermScore {
+                tokid,
+                pos_df: a,
+                neg_df: b,
+                chi_square,
+                info_gain,
+            }
+        })
+        .collect()
+}
+
+/// A term found alongside a query term in relevant documents.
+#[derive(Debug, Clone)]
+pub struct Cooccurrence {
+    pub tokid: u32,
+    /// Relevant documents containing both terms
+    pub both: usize,
+    /// Share of the relevant documents with the query term that also
+    /// have this one, times its collection idf, so terms common
+    /// everywhere rank below those particular to the topic
+    pub weight: f64,
+}
+
+/// The terms co-occurring with `tokid` in the relevant documents `pos`,
+/// highest weight first, weighted by the collection `idf`.
+pub fn cooccurring(tokid: u32, pos: &[FeatureVec], idf: impl Fn(u32) -> f64) -> Vec<Cooccurrence> {
+    let with: Vec<&FeatureVec> = pos
+        .iter()
+        .filter(|fv| fv.features.iter().any(|f| f.id == tokid))
+        .collect();
+    let mut terms: Vec<Cooccurrence> = doc_freqs(with.iter().copied())
+        .into_iter()
+        .filter(|(t, _)| *t != tokid)
+        .map(|(t, both)| Cooccurrence {
+            tokid: t,
+            both,
+            weight: both as f64 / with.len() as f64 * idf(t),
+        })
+        .collect();
+    terms.sort_by(|a, b| b.weight.total_cmp(&a.weight).then(a.tokid.cmp(&b.tokid)));
+    terms
+}
@@ -0,0 +1,215 @@
+//! Deciding when a review can stop, by the knee method of Cormack and
+//! Grossman ("Engineering Quality and Reliability in Technology-Assisted
+//! Review", SIGIR 2016). The gain curve of the judgment log — relevant
+//! documents found against documents reviewed, one step per judgment — rises
+//! steeply while the model is still finding relevant documents and flattens
+//! once they run out. The knee is the point of the curve farthest above the
+//! line from the origin to its end, and the review can stop when the slope
+//! before the knee is enough times the slope after it.
+//!
+//! The knee rule says nothing about recall. For a defensible stopping
+//! decision, a simple random sample of the unreviewed documents is judged,
+//! and [`estimate_recall`] bounds the relevant documents left behind.
+
+use crate::qrels::Judgment;
+
+/// The knee method's thresholds. The defaults are the paper's.
+#[derive(Debug, Clone, Copy)]
+pub struct KneeRule {
+    /// Never stop before this many documents are reviewed
+    pub min_reviewed: usize,
+    /// The slope ratio needed to stop is this less the number of relevant
+    /// documents found, capped at `relevant_cap`
+    pub ratio: f64,
+    pub relevant_cap: usize,
+}
+
+impl Default for KneeRule {
+    fn default() -> Self {
+        KneeRule {
+            min_reviewed: 150,
+            ratio: 156.0,
+            relevant_cap: 150,
+        }
+    }
+}
+
+impl KneeRule {
+    /// The slope ratio needed to stop having found `relevant` documents.
+    /// Finding more relevant documents makes the rule less demanding.
+    pub fn threshold(&self, relevant: usize) -> f64 {
+        self.ratio - relevant.min(self.relevant_cap) as f64
+    }
+}
+
+/// Relevant documents found after each judgment, in the order given.
+pub fn gain_curve(judgments: &[Judgment], min_rel: i32) -> Vec<usize> {
+    judgments
+        .iter()
+        .scan(0, |found, j| {
+            *found += usize::from(j.rel >= min_rel);
+            Some(*found)
+        })
+        .collect()
+}
+
+/// The number of documents reviewed at the knee of a gain curve: where
+/// the curve is farthest above the line from the origin to its end. None
+/// if the curve is empty or found nothing.
+pub fn find_knee(curve: &[usize]) -> Option<usize> {
+    let (&last, n) = (curve.last()?, curve.len());
+    if last == 0 {
+        return None;
+    }
+    // Distance above the line, up to a constant factor
+    let above = |i: usize| curve[i] as f64 * n as f64 - (i + 1) as f64 * last as f64;
+    (0..n)
+        .max_by(|a, b| above(*a).total_cmp(&above(*b)))
+        .map(|i| i + 1)
+}
+
+#[derive(Debug, Clone)]
+pub struct StopCheck {
+    pub reviewed: usize,
+    pub relevant: usize,
+    /// Documents reviewed at the knee, and relevant found by then
+    pub knee: Option<(usize, usize)>,
+    /// Slope before the knee over slope after it
+    pub slope_ratio: f64,
+    pub threshold: f64,
+    pub stop: bool,
+    /// Relevant documents expected among the unreviewed ones, at the
+    /// rate since the knee, if the collection size is known
+    pub remaining: Option<f64>,
+}
+
+/// Apply the knee rule to a gain curve. `unreviewed` is the number of
+/// documents not yet reviewed, for the estimate of what is left.
+pub fn check(curve: &[usize], rule: &KneeRule, unreviewed: Option<usize>) -> StopCheck {
+    let reviewed = curve.len();
+    let relevant = curve.last().copied().unwrap_or(0);
+    let threshold = rule.threshold(relevant);
+    let knee = find_knee(curve).map(|k| (k, curve[k - 1]));
+    let mut stop_check = StopCheck {
+        reviewed,
+        relevant,
+        knee,
+        slope_ratio: 0.0,
+        threshold,
+        stop: false,
+        remaining: None,
+    };
+    let Some((k, found)) = knee else {
+        return stop_check;
+    };
+    // One relevant document is added after the knee, so that a flat tail
+    // gives a large but finite ratio
+    let before = found as f64 / k as f64;
+    let after = (relevant - found + 1) as f64 / (reviewed - k).max(1) as f64;
+    stop_check.slope_ratio = before / after;
+    stop_check.stop = reviewed >= rule.min_reviewed && stop_check.slope_ratio >= threshold;
+    stop_check.remaining = unreviewed.map(|u| after * u as f64);
+    stop_check
+}
+
+/// Recall achieved so far, estimated from a judged random sample of the
+/// documents not reviewed.
+#[derive(Debug, Clone)]
+pub struct RecallEstimate {
+    /// Relevant documents found by the review
+    pub found: usize,
+    /// Documents not reviewed, that the sample was drawn from
+    pub population: usize,
+    pub sample_size: usize,
+    pub sample_relevant: usize,
+    /// Relevant documents estimated to be left, and the upper bound
+    pub remaining: f64,
+    pub remaining_upper: f64,
+    /// Estimated recall, and the lower bound at the confidence level
+    pub recall: f64,
+    pub recall_lower: f64,
+    pub confidence: f64,
+}
+
+impl RecallEstimate {
+    /// Whether recall has reached `target` at the estimate's confidence.
+    pub fn reached(&self, target: f64) -> bool {
+        self.recall_lower >= target
+    }
+}
+
+/// Estimate recall from `found` relevant documents, with `sample_relevant`
+/// of `sample_size` documents sampled from the `population` not reviewed
+/// judged relevant. The bound on what is left uses the one-sided exact
+/// (Clopper-Pearson) upper limit on the sample's proportion relevant.
+pub fn estimate_recall(
+    found: usize,
+    population: usize,
+    sample_size: usize,
+    sample_relevant: usize,
+    confidence: f64,
+) -> RecallEstimate {
+    let proportion = sample_relevant as f64 / sample_size.max(1) as f64;
+    let upper = binomial_upper(sample_relevant, sample_size, confidence);
+    let remaining = proportion * population as f64;
+    let remaining_upper = upper * population as f64;
+    let recall_of = |left: f64| {
+        if found == 0 {
+            0.0
+        } else {
+            found as f64 / (found as f64 + left)
+        }
+    };
+    RecallEstimate {
+        found,
+        population,
+        sample_size,
+        sample_relevant,
+        remaining,
+        remaining_upper,
+        recall: recall_of(remaining),
+        recall_lower: recall_of(remaining_upper),
+        confidence,
+    }
+}
+
+/// The proportion p for which `k` or fewer successes in `n` trials has
+/// probability `1 - confidence`, found by bisection.
+fn binomial_upper(k: usize, n: usize, confidence: f64) -> f64 {
+    if k >= n {
+        return 1.0;
+    }
+    let alpha = 1.0 - confidence;
+    let (mut lo, mut hi) = (k as f64 / n as f64, 1.0);
+    for _ in 0..60 {
+        let mid = (lo + hi) / 2.0;
+        if binomial_cdf(k, n, mid) > alpha {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    hi
+}
+
+/// P(X <= k) for X ~ Binomial(n, p), summed in log space so that large
+/// samples don't underflow.
+fn binomial_cdf(k: usize, n: usize, p: f64) -> f64 {
+    if p <= 0.0 {
+        return 1.0;
+    }
+    if p >= 1.0 {
+        return if k >= n { 1.0 } else { 0.0 };
+    }
+    let (lp, lq) = (p.ln(), (1.0 - p).ln());
+    // ln C(n, i), built up from ln C(n, 0) = 0
+    let mut ln_choose = 0.0;
+    let mut total = 0.0;
+    for i in 0..=k.min(n) {
+        if i > 0 {
+            ln_choose += ((n - i + 1) as f64).ln() - (i as f64).ln();
+        }
+        total += (ln_choose + i as f64 * lp + (n - i) as f64 * lq).exp();
+    }
+    total.min(1.0)
+}
@@ -0,0 +1,112 @@
+//! Statistics of a collection's terms, for checking tokenization choices
+//! (a tokenizer that splits too eagerly shows up as a vocabulary of terms
+//! found in one document) and for deciding idf weighting and pruning, such
+//! as build_corpus's `--max-df` and `--exact-terms`. There is no inverted
+//! file, so they come from one pass over the feature file. Term
+//! frequencies are recovered from the stored weights as in
+//! [`crate::search::bm25_search`], and a term's bytes are what its
+//! `(id, value)` pairs take up in the feature file, the forward file's
+//! counterpart of a posting list's size.
+
+use crate::{Dict, FeatureVec};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Result};
+
+/// Bytes of one `(id: u32, value: f32)` pair in a feature record
+pub const PAIR_BYTES: u64 = 8;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TermStats {
+    pub tokid: u32,
+    /// Documents containing the term
+    pub df: u64,
+    /// Occurrences of the term in the collection
+    pub cf: f64,
+    pub idf: f32,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CollectionStats {
+    pub num_docs: u64,
+    /// Documents with no features at all
+    pub empty_docs: u64,
+    /// Terms with their own id
+    pub exact_terms: usize,
+    /// Shared ids that other terms are hashed into, if any
+    pub hashed_buckets: Option<u32>,
+    /// Term ids occurring in some document, and in only one
+    pub terms_used: usize,
+    pub singletons: usize,
+    /// `(id, value)` pairs in the feature file, and tokens they stand for
+    pub num_features: u64,
+    pub num_tokens: f64,
+    pub feature_bytes: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    pub collection: CollectionStats,
+    terms: HashMap<u32, TermStats>,
+}
+
+impl Stats {
+    /// A term's statistics. A term id no document uses has a df of zero.
+    pub fn term(&self, tokid: u32, dict: &Dict) -> TermStats {
+        self.terms.get(&tokid).cloned().unwrap_or(TermStats {
+            tokid,
+            idf: dict.df.get(&tokid).copied().unwrap_or(0.0),
+            ..TermStats::default()
+        })
+    }
+
+    /// Every term used, most documents first.
+    pub fn by_df(&self) -> Vec<&TermStats> {
+        let mut terms: Vec<&TermStats> = self.terms.values().collect();
+        terms.sort_by(|a, b| b.df.cmp(&a.df).then(a.tokid.cmp(&b.tokid)));
+        terms
+    }
+}
+
+/// Gather the statistics of every term in a feature file, calling
+/// `progress` with each document read.
+pub fn collect(
+    dict: &Dict,
+    feats: &mut BufReader<File>,
+    mut progress: impl FnMut(usize),
+) -> Result<Stats> {
+    let mut stats = Stats::default();
+    let coll = &mut stats.collection;
+    coll.exact_terms = dict.m.len();
+    coll.hashed_buckets = dict.hashed.map(|h| h.buckets);
+    coll.feature_bytes = feats.get_ref().metadata()?.len();
+
+    while let Some(fv) = FeatureVec::read_next(feats).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("Corrupt feature file: {}", e),
+        )
+    })? {
+        coll.num_docs += 1;
+        coll.empty_docs += u64::from(fv.features.is_empty());
+        coll.num_features += fv.features.len() as u64;
+        for f in &fv.features {
+            let tf = dict.term_freq(f.id, f.value);
+            coll.num_tokens += f64::from(tf);
+            let term = stats.terms.entry(f.id).or_insert_with(|| TermStats {
+                tokid: f.id,
+                idf: dict.df.get(&f.id).copied().unwrap_or(0.0),
+                ..TermStats::default()
+            });
+            term.df += 1;
+            term.cf += f64::from(tf);
+            term.bytes += PAIR_BYTES;
+        }
+        progress(1);
+    }
+    coll.terms_used = stats.terms.len();
+    coll.singletons = stats.terms.values().filter(|t| t.df == 1).count();
+    Ok(stats)
+}
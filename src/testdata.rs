@@ -0,0 +1,105 @@
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::seq::index::sample;
+use rand::{Rng, SeedableRng};
+use roaring::RoaringBitmap;
+use serde_json::json;
+use std::fs::File;
+use std::io::{BufWriter, Result, Write};
+use std::path::Path;
+
+/// A synthetic corpus with a planted relevant set, for checking a build
+/// and a training run end to end. The same settings always produce the
+/// same files.
+///
+/// Background words are drawn from a Zipf-like distribution over `vocab`
+/// terms. Relevant documents also draw a share of their words from a small
+/// set of topic terms, so a working classifier should rank them first.
+/// Terms are `t0`, `t1` and so on, which the tokenizer leaves unstemmed.
+#[derive(Debug, Clone)]
+pub struct TestData {
+    pub docs: usize,
+    pub vocab: usize,
+    /// Fraction of documents planted as relevant; at least one always is
+    pub relevant: f32,
+    pub seed: u64,
+}
+
+impl TestData {
+    /// Number of topic terms
+    pub const TOPIC_TERMS: usize = 10;
+    /// Share of a relevant document's words that are topic terms
+    pub const TOPIC_SHARE: f64 = 0.3;
+    /// The topic id in the written qrels
+    pub const TOPIC: &'static str = "synthetic";
+
+    pub fn new(docs: usize, vocab: usize) -> TestData {
+        TestData {
+            docs,
+            vocab,
+            relevant: 0.02,
+            seed: 1,
+        }
+    }
+
+    pub fn docid(i: usize) -> String {
+        format!("doc{:07}", i)
+    }
+
+    /// Write the corpus as JSON lines that `build_corpus` reads, and a
+    /// qrels file judging every planted relevant document relevant.
+    /// Returns the indexes of the relevant documents.
+    pub fn write(
+        &self,
+        docs_file: impl AsRef<Path>,
+        qrels_file: impl AsRef<Path>,
+    ) -> Result<RoaringBitmap> {
+        assert!(
+            self.vocab >= 2 * Self::TOPIC_TERMS,
+            "Vocabulary too small for the topic terms"
+        );
+        assert!(self.docs > 0, "No documents to write");
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let background = WeightedIndex::new((1..=self.vocab).map(|r| 1.0 / r as f64))
+            .expect("Vocabulary must not be empty");
+        // Topic terms come from the middle of the distribution, common
+        // enough to survive pruning but not so common they are everywhere
+        let topic_terms: Vec<usize> = sample(&mut rng, self.vocab / 2, Self::TOPIC_TERMS)
+            .into_iter()
+            .map(|t| t + self.vocab / 4)
+            .collect();
+
+        let num_relevant = ((self.docs as f32 * self.relevant).ceil() as usize).clamp(1, self.docs);
+        let relevant: RoaringBitmap = sample(&mut rng, self.docs, num_relevant)
+            .into_iter()
+            .map(|i| i as u32)
+            .collect();
+
+        let mut docs_out = BufWriter::new(File::create(docs_file)?);
+        for i in 0..self.docs {
+            let is_relevant = relevant.contains(i as u32);
+            let len = rng.gen_range(20..80);
+            let words: Vec<String> = (0..len)
+                .map(|_| {
+                    let term = if is_relevant && rng.gen_bool(Self::TOPIC_SHARE) {
+                        topic_terms[rng.gen_range(0..topic_terms.len())]
+                    } else {
+                        background.sample(&mut rng)
+                    };
+                    format!("t{}", term)
+                })
+                .collect();
+            let doc = json!({ "pid": Self::docid(i), "passage": words.join(" ") });
+            writeln!(docs_out, "{}", doc)?;
+        }
+        docs_out.flush()?;
+
+        let mut qrels_out = BufWriter::new(File::create(qrels_file)?);
+        for i in relevant.iter() {
+            writeln!(qrels_out, "{} 0 {} 1", Self::TOPIC, Self::docid(i as usize))?;
+        }
+        qrels_out.flush()?;
+
+        Ok(relevant)
+    }
+}
@@ -0,0 +1,249 @@
+//! Deleting documents from a built collection. Rebuilding a large
+//! collection to drop a few documents, such as ones clawed back as
+//! privileged, takes hours, so a deleted document is first only marked: its
+//! intid goes in the collection's tombstone file `<prefix>.del`, and
+//! scoring, sampling and negative sampling pass over it from then on.
+//! [`compact`] later rewrites the collection's files without the deleted
+//! documents.
+//!
+//! Compacting renumbers the documents that are left, and moves the
+//! collection to a new [`crate::read_generation`]. Intid files written
+//! before it, such as a session exported with `--format intids`, are then
+//! refused, and must be written again. Docids don't change, and neither do
+//! the vocabulary or any document's feature values, so models trained on
+//! the collection still apply to it.
+//!
+//! Each rewritten file is written beside the one it replaces, as
+//! `<file>.new`, and only once all of them are does the journal
+//! `<prefix>.cmp` appear. The new files are then renamed into place, the
+//! tombstones removed, and the journal last. If that is interrupted, the
+//! journal says the swap is to be finished, and [`recover`] finishes it
+//! before the collection is next read, so the old tombstones are never
+//! read against the new numbering.
+
+use crate::chunks::{write_chunked, ChunkedFeatures};
+use crate::languages::Languages;
+use crate::recency::Dates;
+use crate::{
+    read_generation, read_intids, write_generation, write_intids, CollectionLayout, DocInfo,
+    DocsDb, FeatureVec,
+};
+use roaring::RoaringBitmap;
+use std::fs::{remove_dir_all, remove_file, rename, File};
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Result, Seek, Write};
+use std::path::{Path, PathBuf};
+
+/// The collection's deleted intids, empty if nothing has been deleted.
+/// An interrupted compaction is finished first.
+pub fn deleted(coll: &CollectionLayout) -> Result<RoaringBitmap> {
+    recover(coll)?;
+    if coll.deleted().exists() {
+        read_intids(coll.deleted(), read_generation(coll)?)
+    } else {
+        Ok(RoaringBitmap::new())
+    }
+}
+
+/// Add `intids` to the collection's tombstones, returning how many weren't
+/// already deleted.
+pub fn delete(coll: &CollectionLayout, intids: &RoaringBitmap) -> Result<u64> {
+    let mut dead = deleted(coll)?;
+    let before = dead.len();
+    dead |= intids;
+    if dead.len() > before {
+        write_intids(&dead, read_generation(coll)?, coll.deleted())?;
+    }
+    Ok(dead.len() - before)
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Compaction {
+    pub kept: u32,
+    pub removed: u32,
+}
+
+/// The files a compaction rewrites
+fn rewritten(coll: &CollectionLayout) -> [PathBuf; 9] {
+    [
+        coll.generation(),
+        coll.features(),
+        coll.docsdb(),
+        coll.docvec(),
+        coll.excluded(),
+        coll.languages(),
+        coll.dates(),
+        coll.chunked_features(),
+        coll.arrow_features(),
+    ]
+}
+
+/// Where the rewritten copy of `path` is written
+fn new_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".new");
+    PathBuf::from(name)
+}
+
+/// Move the rewritten files into place, then remove the tombstones, the
+/// splits and the journal. Every step can be repeated, so running this
+/// again finishes a swap that was interrupted.
+fn swap_in(coll: &CollectionLayout) -> Result<()> {
+    for path in rewritten(coll) {
+        let new = new_path(&path);
+        if !new.exists() {
+            continue;
+        }
+        if path.is_dir() {
+            remove_dir_all(&path)?;
+        }
+        rename(&new, &path)?;
+    }
+    for path in [coll.splits(), coll.deleted()] {
+        if path.exists() {
+            remove_file(path)?;
+        }
+    }
+    remove_file(coll.compacting())
+}
+
+/// Finish a compaction that was interrupted while swapping its files in.
+/// Until then the collection's files disagree about its numbering.
+pub fn recover(coll: &CollectionLayout) -> Result<()> {
+    if coll.compacting().exists() {
+        swap_in(coll)?;
+    }
+    Ok(())
+}
+
+/// Rewrite the collection without its deleted documents: the feature file,
+/// docs db and docid vector, the routing, language and date files and
+/// the chunked and Arrow copies if it has them, and move it to the next
+/// generation. Feature-file splits from `find-ftr-splits` no longer line
+/// up, and are removed. Call `progress` with each document read.
+pub fn compact(coll: &CollectionLayout, mut progress: impl FnMut(usize)) -> Result<Compaction> {
+    let dead = deleted(coll)?;
+    if dead.is_empty() {
+        return Ok(Compaction::default());
+    }
+    // Left by a compaction that stopped before its journal was written
+    for path in rewritten(coll) {
+        let new = new_path(&path);
+        if new.is_dir() {
+            remove_dir_all(&new)?;
+        } else if new.exists() {
+            remove_file(&new)?;
+        }
+    }
+    let corrupt = |e| Error::new(ErrorKind::InvalidData, e);
+    let generation = read_generation(coll)? + 1;
+
+    // Old intid to new, for the documents that are kept
+    let mut renumbered: Vec<Option<u32>> = Vec::new();
+    let mut kept = Vec::new();
+    {
+        let mut binin = BufReader::new(File::open(coll.features())?);
+        let mut binout = BufWriter::new(File::create(new_path(&coll.features()))?);
+        while let Some(fv) = FeatureVec::read_next(&mut binin).map_err(corrupt)? {
+            progress(1);
+            if dead.contains(renumbered.len() as u32) {
+                renumbered.push(None);
+                continue;
+            }
+            renumbered.push(Some(kept.len() as u32));
+            kept.push(DocInfo {
+                intid: kept.len() as u64,
+                docid: fv.docid.clone(),
+                offset: binout.stream_position()?,
+            });
+            fv.write_into(&mut binout).map_err(Error::other)?;
+        }
+        binout.flush()?;
+    }
+    let remap = |intids: &RoaringBitmap| -> RoaringBitmap {
+        intids
+            .iter()
+            .filter_map(|i| renumbered.get(i as usize).copied().flatten())
+            .collect()
+    };
+
+    {
+        let mut docs = DocsDb::create(new_path(&coll.docsdb()));
+        for di in &kept {
+            docs.insert_batch(&di.docid, di, 100_000);
+        }
+        docs.process_remaining();
+        docs.db.flush()?;
+    }
+    if coll.docvec().exists() {
+        // The docid vector is in database order, which is docid order
+        kept.sort_by(|a, b| a.docid.cmp(&b.docid));
+        let mut vecfile = BufWriter::new(File::create(new_path(&coll.docvec()))?);
+        bincode::serialize_into(&mut vecfile, &kept).map_err(Error::other)?;
+        vecfile.flush()?;
+    }
+    if coll.excluded().exists() {
+        write_intids(
+            &remap(&read_intids(coll.excluded(), generation - 1)?),
+            generation,
+            new_path(&coll.excluded()),
+        )?;
+    }
+    if coll.languages().exists() {
+        let mut langs = Languages::load(coll.languages())?;
+        langs.num_docs = kept.len() as u32;
+        for intids in langs.intids.values_mut() {
+            *intids = remap(intids);
+        }
+        langs.save(new_path(&coll.languages()))?;
+    }
+    if coll.dates().exists() {
+        let dates = Dates::load(coll.dates())?;
+        let days = (0..renumbered.len() as u32)
+            .filter(|i| !dead.contains(*i))
+            .map(|i| dates.get(i))
+            .collect();
+        Dates { days }.save(new_path(&coll.dates()))?;
+    }
+    if coll.chunked_features().exists() {
+        let chunk_size = ChunkedFeatures::open(coll.chunked_features())?.chunk_size;
+        write_chunked(
+            new_path(&coll.features()),
+            new_path(&coll.chunked_features()),
+            chunk_size,
+            |_| {},
+        )?;
+    }
+    if coll.arrow_features().exists() {
+        rewrite_arrow(coll)?;
+    }
+
+    write_generation(generation, new_path(&coll.generation()))?;
+
+    File::create(coll.compacting())?.sync_all()?;
+    swap_in(coll)?;
+
+    Ok(Compaction {
+        kept: kept.len() as u32,
+        removed: (renumbered.len() - kept.len()) as u32,
+    })
+}
+
+#[cfg(feature = "arrow")]
+fn rewrite_arrow(coll: &CollectionLayout) -> Result<()> {
+    use crate::columnar::{write_arrow, DEFAULT_BATCH_SIZE};
+    write_arrow(
+        new_path(&coll.features()),
+        new_path(&coll.arrow_features()),
+        DEFAULT_BATCH_SIZE,
+        |_| {},
+    )?;
+    Ok(())
+}
+
+/// Without the `arrow` feature the copy can't be rewritten, and a stale
+/// one would be scored by a build with it. Removing it early is safe, as
+/// scoring falls back to the feature file.
+#[cfg(not(feature = "arrow"))]
+fn rewrite_arrow(coll: &CollectionLayout) -> Result<()> {
+    remove_file(coll.arrow_features())
+}
@@ -0,0 +1,468 @@
+use crate::drift::{self, DriftAlarm, DriftThresholds};
+use crate::policy::RoundPolicy;
+use crate::qrels::{read_qrels, Judgment};
+use crate::runs::hash_file;
+use crate::CollectionLayout;
+use rand::rngs::StdRng;
+use rand::{thread_rng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::fs::{copy, create_dir_all, remove_file, rename, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Strategy {
+    /// Review the highest-scoring unjudged documents
+    Relevance,
+    /// Review the unjudged documents nearest the decision boundary, which
+    /// teach the model the most
+    Uncertainty,
+}
+
+impl FromStr for Strategy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "relevance" => Ok(Strategy::Relevance),
+            "uncertainty" => Ok(Strategy::Uncertainty),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown strategy {}", s),
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TopicConfig {
+    /// Prefix of the collection this topic reviews
+    pub collection: PathBuf,
+    pub tokenizer: String,
+    pub strategy: Strategy,
+    /// Documents per review batch, used as the default depth for `score`
+    pub batch_size: usize,
+    /// Randomly sampled documents added as nonrelevant when training
+    pub negatives: usize,
+    /// Minimum judgment grade that counts as relevant
+    pub relevance_level: i32,
+    /// Weights below this magnitude are pruned when the model is saved
+    pub prune_min: Option<f32>,
+    /// Share of the regularization spent on an L1 penalty; see
+    /// [`crate::Classifier::l1_ratio`]
+    #[serde(default)]
+    pub l1_ratio: f32,
+    /// When `review` retrains, switches strategy and stops
+    #[serde(default)]
+    pub policy: RoundPolicy,
+    /// When a batch differs enough from the last to raise an alarm
+    #[serde(default)]
+    pub drift: DriftThresholds,
+}
+
+impl TopicConfig {
+    pub fn new(collection: impl AsRef<Path>) -> TopicConfig {
+        TopicConfig {
+            collection: collection.as_ref().to_path_buf(),
+            tokenizer: "porter".to_string(),
+            strategy: Strategy::Relevance,
+            batch_size: 100,
+            negatives: 0,
+            relevance_level: 1,
+            prune_min: None,
+            l1_ratio: 0.0,
+            policy: RoundPolicy::default(),
+            drift: DriftThresholds::default(),
+        }
+    }
+}
+
+/// A topic's review session: the batches served for review, a snapshot of
+/// the model committed after each round, and the last commit's
+/// bookkeeping. What was judged is in the judgment log. Times are seconds
+/// since the Unix epoch.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Session {
+    #[serde(default)]
+    pub created: u64,
+    /// The last round judged when the model was last committed
+    pub round: u32,
+    /// Judgments in the log when the model was last committed
+    pub judgments: usize,
+    /// [`hash_file`] of the committed model, once there is one
+    pub model_hash: Option<String>,
+    pub committed: u64,
+    /// Oldest first
+    #[serde(default)]
+    pub batches: Vec<Batch>,
+    /// The last model committed in each round, oldest first
+    #[serde(default)]
+    pub snapshots: Vec<Snapshot>,
+    /// Every random draw the review makes comes from a generator seeded by
+    /// `seed` and the number of generators handed out before it, so a
+    /// copy of the topic resumed elsewhere draws the same samples
+    #[serde(default)]
+    pub seed: u64,
+    #[serde(default)]
+    pub draws: u64,
+    /// Drift between batches past the topic's thresholds, oldest first
+    #[serde(default)]
+    pub alarms: Vec<DriftAlarm>,
+}
+
+/// Documents served for review in a round.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Batch {
+    pub round: u32,
+    pub served: u64,
+    pub docids: Vec<String>,
+    /// Each document's score, for batches ranked by score
+    #[serde(default)]
+    pub scores: Vec<f32>,
+    /// The terms that most put the batch on top, leading first
+    #[serde(default)]
+    pub terms: Vec<u32>,
+    /// Seconds spent scoring the collection for the batch
+    #[serde(default)]
+    pub seconds: Option<f64>,
+}
+
+/// A committed model, kept after the topic's model moves on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub round: u32,
+    /// Relative to the topic directory
+    pub file: String,
+    pub model_hash: String,
+    pub committed: u64,
+}
+
+impl Session {
+    fn new() -> Session {
+        Session {
+            created: now(),
+            seed: thread_rng().gen(),
+            ..Session::default()
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// A topic directory holds everything one review needs apart from the
+/// collection itself: `topic.json` (the [`TopicConfig`]), the judgment log
+/// `judgments.qrels`, the model file `model`, the [`Session`] record
+/// `session.json` with its model `snapshots/`, and optionally the terms the
+/// model must not learn, `guardrails.txt` (see [`crate::guardrails`]).
+///
+/// The judgment log is a qrels file whose iteration column records the
+/// round in which each judgment was made, so it can be passed anywhere a
+/// qrels file is accepted. A fifth field holds the time the judgment was
+/// logged, in seconds since the Unix epoch.
+///
+/// The model and session record are replaced together by
+/// [`Topic::commit`]: the new model is written to `model.pending` and its
+/// round's snapshot to `snapshots/model.pending`, the record naming them
+/// replaces `session.json` by a rename, and then the snapshot and the model
+/// are renamed into place. Opening the topic finishes a commit that got as
+/// far as the record and discards one that didn't, so a crash leaves
+/// either the old model, snapshots and record or the new ones.
+#[derive(Debug, Clone)]
+pub struct Topic {
+    pub dir: PathBuf,
+    pub config: TopicConfig,
+}
+
+impl Topic {
+    pub fn create(dir: impl AsRef<Path>, config: TopicConfig) -> std::io::Result<Topic> {
+        let topic = Topic {
+            dir: dir.as_ref().to_path_buf(),
+            config,
+        };
+        if topic.config_file().exists() {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("Topic {} already exists", topic.dir.display()),
+            ));
+        }
+        create_dir_all(&topic.dir)?;
+        File::create(topic.judgments_file())?;
+        topic.save()?;
+        topic.start_session()?;
+        Ok(topic)
+    }
+
+    pub fn open(dir: impl AsRef<Path>) -> std::io::Result<Topic> {
+        let dir = dir.as_ref().to_path_buf();
+        let fp = BufReader::new(File::open(dir.join("topic.json"))?);
+        let config = serde_json::from_reader(fp)?;
+        let topic = Topic { dir, config };
+        topic.recover()?;
+        Ok(topic)
+    }
+
+    /// Finish or discard a commit interrupted by a crash.
+    fn recover(&self) -> std::io::Result<()> {
+        let pending = self.pending_model_file();
+        if !pending.exists() {
+            return Ok(());
+        }
+        match self.session()? {
+            Some(session) if Some(hash_file(&pending)?) == session.model_hash => {
+                self.finish_commit(&session)
+            }
+            _ => {
+                let staged = self.pending_snapshot_file();
+                if staged.exists() {
+                    remove_file(staged)?;
+                }
+                remove_file(pending)
+            }
+        }
+    }
+
+    /// Move a committed model and its snapshot into place. Either rename
+    /// may already have been done.
+    fn finish_commit(&self, session: &Session) -> std::io::Result<()> {
+        let staged = self.pending_snapshot_file();
+        if staged.exists() {
+            if let Some(snapshot) = session.snapshots.iter().find(|s| s.round == session.round) {
+                rename(staged, self.dir.join(&snapshot.file))?;
+            }
+        }
+        rename(self.pending_model_file(), self.model_file())
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let mut fp = BufWriter::new(File::create(self.config_file())?);
+        serde_json::to_writer_pretty(&mut fp, &self.config)?;
+        fp.flush()
+    }
+
+    /// The topic id written into judgment logs and runs
+    pub fn name(&self) -> String {
+        self.dir
+            .file_name()
+            .map_or("topic".to_string(), |n| n.to_string_lossy().to_string())
+    }
+
+    pub fn collection(&self) -> CollectionLayout {
+        CollectionLayout::new(&self.config.collection)
+    }
+
+    pub fn config_file(&self) -> PathBuf {
+        self.dir.join("topic.json")
+    }
+
+    pub fn judgments_file(&self) -> PathBuf {
+        self.dir.join("judgments.qrels")
+    }
+
+    pub fn model_file(&self) -> PathBuf {
+        self.dir.join("model")
+    }
+
+    pub fn session_file(&self) -> PathBuf {
+        self.dir.join("session.json")
+    }
+
+    fn pending_model_file(&self) -> PathBuf {
+        self.dir.join("model.pending")
+    }
+
+    fn pending_snapshot_file(&self) -> PathBuf {
+        self.snapshots_dir().join("model.pending")
+    }
+
+    pub fn snapshots_dir(&self) -> PathBuf {
+        self.dir.join("snapshots")
+    }
+
+    /// The session, if one has been started. Topics created before
+    /// sessions existed get one at their first commit or batch.
+    pub fn session(&self) -> std::io::Result<Option<Session>> {
+        if !self.session_file().exists() {
+            return Ok(None);
+        }
+        let fp = BufReader::new(File::open(self.session_file())?);
+        Ok(Some(serde_json::from_reader(fp)?))
+    }
+
+    /// Start a session for a topic without one.
+    pub fn start_session(&self) -> std::io::Result<Session> {
+        if self.session_file().exists() {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("Topic {} already has a session", self.dir.display()),
+            ));
+        }
+        let session = Session::new();
+        self.save_session(&session)?;
+        Ok(session)
+    }
+
+    fn session_or_new(&self) -> std::io::Result<Session> {
+        Ok(self.session()?.unwrap_or_else(Session::new))
+    }
+
+    /// Replace the session record in one rename.
+    fn save_session(&self, session: &Session) -> std::io::Result<()> {
+        let tmp = self.dir.join("session.json.tmp");
+        let mut fp = BufWriter::new(File::create(&tmp)?);
+        serde_json::to_writer_pretty(&mut fp, session)?;
+        fp.into_inner()?.sync_all()?;
+        rename(&tmp, self.session_file())
+    }
+
+    /// The session's next random number generator. Each call hands out a
+    /// new one, and the session records that it did.
+    pub fn rng(&self) -> std::io::Result<StdRng> {
+        let mut session = self.session_or_new()?;
+        let rng = StdRng::seed_from_u64(
+            session
+                .seed
+                .wrapping_add(session.draws.wrapping_mul(0x9e37_79b9_7f4a_7c15)),
+        );
+        session.draws += 1;
+        self.save_session(&session)?;
+        Ok(rng)
+    }
+
+    /// Record a batch served for review in the coming round, returning
+    /// the drift alarms it raised against the batch before it. `round`
+    /// and `served` are filled in.
+    pub fn record_batch(&self, mut batch: Batch) -> std::io::Result<Vec<DriftAlarm>> {
+        let mut session = self.session_or_new()?;
+        batch.round = self.last_round()? + 1;
+        batch.served = now();
+        let alarms = match session.batches.last() {
+            Some(prev) => drift::check(prev, &batch, &self.config.drift),
+            None => Vec::new(),
+        };
+        session.alarms.extend(alarms.iter().cloned());
+        session.batches.push(batch);
+        self.save_session(&session)?;
+        Ok(alarms)
+    }
+
+    /// Replace the topic's model and its session record together. `save`
+    /// writes the model to the path it is given.
+    pub fn commit(
+        &self,
+        save: impl FnOnce(&Path) -> std::io::Result<()>,
+    ) -> std::io::Result<Session> {
+        let pending = self.pending_model_file();
+        save(&pending)?;
+        File::open(&pending)?.sync_all()?;
+        let mut session = self.session_or_new()?;
+        session.round = self.last_round()?;
+        session.judgments = self.judgments()?.len();
+        session.model_hash = Some(hash_file(&pending)?);
+        session.committed = now();
+
+        // A retrained model replaces the round's snapshot, once committed
+        let file = format!("snapshots/model.r{}", session.round);
+        create_dir_all(self.snapshots_dir())?;
+        let staged = self.pending_snapshot_file();
+        copy(&pending, &staged)?;
+        File::open(&staged)?.sync_all()?;
+        session.snapshots.retain(|s| s.round != session.round);
+        session.snapshots.push(Snapshot {
+            round: session.round,
+            file,
+            model_hash: session.model_hash.clone().unwrap_or_default(),
+            committed: session.committed,
+        });
+
+        // The commit point: from here on the pending model is the topic's
+        self.save_session(&session)?;
+        self.finish_commit(&session)?;
+        Ok(session)
+    }
+
+    pub fn guardrails_file(&self) -> PathBuf {
+        self.dir.join("guardrails.txt")
+    }
+
+    pub fn judgments(&self) -> std::io::Result<Vec<Judgment>> {
+        read_qrels(self.judgments_file())
+    }
+
+    /// The highest round in the judgment log, or 0 if nothing is judged yet.
+    pub fn last_round(&self) -> std::io::Result<u32> {
+        Ok(self
+            .judgments()?
+            .iter()
+            .filter_map(|j| j.round)
+            .max()
+            .unwrap_or(0))
+    }
+
+    /// Append judgments to the log. Each keeps its own round if it has one,
+    /// and is otherwise attributed to `round`.
+    pub fn append_judgments(&self, round: u32, judgments: &[Judgment]) -> std::io::Result<()> {
+        let now = now();
+        let name = self.name();
+        let mut fp = BufWriter::new(
+            OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(self.judgments_file())?,
+        );
+        for j in judgments {
+            writeln!(
+                fp,
+                "{} {} {} {} {}",
+                name,
+                j.round.unwrap_or(round),
+                j.docid,
+                j.rel,
+                now
+            )?;
+        }
+        fp.flush()
+    }
+
+    /// Log one judgment as it is made. It belongs to the round of the
+    /// latest batch that served the document, or else the round under
+    /// review. A document already judged keeps its judgment.
+    pub fn add_judgment(&self, docid: &str, rel: i32) -> std::io::Result<Judgment> {
+        let judgments = self.judgments()?;
+        if judgments.iter().any(|j| j.docid == docid) {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{} is already judged", docid),
+            ));
+        }
+        let batches = self.session()?.map(|s| s.batches).unwrap_or_default();
+        let current = batches
+            .last()
+            .map(|b| b.round)
+            .into_iter()
+            .chain(judgments.iter().filter_map(|j| j.round))
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        let round = batches
+            .iter()
+            .rev()
+            .find(|b| b.docids.iter().any(|d| d == docid))
+            .map_or(current, |b| b.round);
+        let judgment = Judgment {
+            topic: self.name(),
+            docid: docid.to_string(),
+            rel,
+            round: Some(round),
+            logged: Some(now()),
+        };
+        self.append_judgments(round, std::slice::from_ref(&judgment))?;
+        Ok(judgment)
+    }
+}
@@ -0,0 +1,31 @@
+//! A collection written as CIFF and read back in holds the same term
+//! counts, so writing the copy out again gives the same file.
+
+use mycal::ciff;
+use mycal::conformance::Fixture;
+use mycal::testdata::TestData;
+use mycal::{CollectionLayout, Dict, DocsDb};
+
+#[test]
+fn ciff_round_trips() {
+    let fixture = Fixture::build(&TestData::new(200, 100)).unwrap();
+    let mut first = Vec::new();
+    let exported = ciff::export(&fixture.coll, &mut first, "fixture", |_| {}).unwrap();
+    assert_eq!(exported.num_docs, 200);
+
+    let copy = CollectionLayout::new(fixture.dir.join("copy"));
+    let imported = ciff::import(&mut first.as_slice(), &copy, |_| {}).unwrap();
+    assert_eq!(imported.num_docs, exported.num_docs);
+    assert_eq!(imported.num_terms, exported.num_terms);
+    assert_eq!(imported.num_postings, exported.num_postings);
+
+    let dict = Dict::load(copy.dict()).unwrap();
+    assert_eq!(dict.m.len(), exported.num_terms);
+    let docs = DocsDb::open(copy.docsdb());
+    assert_eq!(docs.get(&TestData::docid(17)).unwrap().intid, 17);
+    drop(docs);
+
+    let mut second = Vec::new();
+    ciff::export(&copy, &mut second, "fixture", |_| {}).unwrap();
+    assert_eq!(first, second);
+}
@@ -0,0 +1,41 @@
+//! Compacting a collection renumbers its documents, so intid files written
+//! before it are refused instead of excluding other documents.
+
+use mycal::conformance::Fixture;
+use mycal::testdata::TestData;
+use mycal::{read_generation, read_intids, tombstones, write_intids, DocsDb};
+use roaring::RoaringBitmap;
+use std::io::ErrorKind;
+
+#[test]
+fn compacting_refuses_earlier_intid_files() {
+    let fixture = Fixture::build(&TestData::new(200, 100)).unwrap();
+    let coll = &fixture.coll;
+    let before: RoaringBitmap = [17, 111, 126].into_iter().collect();
+    let intids_file = fixture.dir.join("judged.ids");
+    write_intids(&before, read_generation(coll).unwrap(), &intids_file).unwrap();
+    assert_eq!(
+        read_intids(&intids_file, read_generation(coll).unwrap()).unwrap(),
+        before
+    );
+
+    let dead: RoaringBitmap = [3, 40, 90].into_iter().collect();
+    tombstones::delete(coll, &dead).unwrap();
+    let compaction = tombstones::compact(coll, |_| {}).unwrap();
+    assert_eq!(compaction.removed, 3);
+    assert_eq!(read_generation(coll).unwrap(), 1);
+
+    let err = read_intids(&intids_file, read_generation(coll).unwrap()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    // Written again against the new numbering, the same documents read back
+    let docs = DocsDb::open(coll.docsdb());
+    let docids: Vec<String> = before.iter().map(|i| TestData::docid(i as usize)).collect();
+    let after = docs.intids_for(docids.iter().map(|d| d.as_str()));
+    assert_eq!(after, [16, 108, 123].into_iter().collect());
+    write_intids(&after, read_generation(coll).unwrap(), &intids_file).unwrap();
+    assert_eq!(
+        read_intids(&intids_file, read_generation(coll).unwrap()).unwrap(),
+        after
+    );
+}
@@ -0,0 +1,35 @@
+//! Every learner mycal ships passes the conformance suite on a small
+//! synthetic collection.
+
+use mycal::conformance::{check_model, Fixture};
+use mycal::testdata::TestData;
+use mycal::{Classifier, NaiveBayes, Rocchio};
+
+fn fixture() -> Fixture {
+    let data = TestData {
+        relevant: 0.05,
+        ..TestData::new(400, 200)
+    };
+    Fixture::build(&data).unwrap()
+}
+
+#[test]
+fn classifier_conforms() {
+    let fixture = fixture();
+    let mut model = Classifier::new(fixture.dimensionality(), 200_000);
+    check_model(&mut model, &fixture).unwrap();
+}
+
+#[test]
+fn naive_bayes_conforms() {
+    let fixture = fixture();
+    let mut model = NaiveBayes::new(fixture.dimensionality());
+    check_model(&mut model, &fixture).unwrap();
+}
+
+#[test]
+fn rocchio_conforms() {
+    let fixture = fixture();
+    let mut model = Rocchio::new(fixture.dimensionality());
+    check_model(&mut model, &fixture).unwrap();
+}
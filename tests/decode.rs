@@ -0,0 +1,97 @@
+//! Feature file records and docs db entries survive being written and
+//! decoded, and decoding arbitrary bytes fails cleanly instead of
+//! panicking or allocating without bound.
+
+use mycal::{DocInfo, FeaturePair, FeatureVec, Section, FEATURE_VEC_MAGIC};
+use proptest::prelude::*;
+
+fn feature_vec(with_sections: bool) -> impl Strategy<Value = FeatureVec> {
+    let sections = if with_sections {
+        prop::collection::vec(
+            (1..=u16::MAX, prop::collection::vec(any::<u8>(), 0..64))
+                .prop_map(|(kind, bytes)| Section { kind, bytes }),
+            1..4,
+        )
+        .boxed()
+    } else {
+        Just(Vec::new()).boxed()
+    };
+    (
+        ".{0,32}",
+        prop::collection::vec((any::<u32>(), any::<f32>()), 0..64),
+        any::<f32>(),
+        sections,
+    )
+        .prop_map(|(docid, pairs, squared_norm, sections)| FeatureVec {
+            docid,
+            features: pairs
+                .into_iter()
+                .map(|(id, value)| FeaturePair { id, value })
+                .collect(),
+            squared_norm,
+            sections,
+        })
+}
+
+fn assert_same(a: &FeatureVec, b: &FeatureVec) {
+    assert_eq!(a.docid, b.docid);
+    let bits = |fv: &FeatureVec| -> Vec<(u32, u32)> {
+        fv.features
+            .iter()
+            .map(|f| (f.id, f.value.to_bits()))
+            .collect()
+    };
+    assert_eq!(bits(a), bits(b));
+    assert_eq!(a.squared_norm.to_bits(), b.squared_norm.to_bits());
+    assert_eq!(a.sections, b.sections);
+}
+
+fn roundtrip(fv: &FeatureVec) -> FeatureVec {
+    let mut bytes = Vec::new();
+    fv.write_into(&mut bytes).unwrap();
+    FeatureVec::decode(&bytes).unwrap()
+}
+
+proptest! {
+    #[test]
+    fn feature_vec_first_layout_roundtrips(fv in feature_vec(false)) {
+        let mut bytes = Vec::new();
+        fv.write_into(&mut bytes).unwrap();
+        prop_assert_ne!(&bytes[..8], &FEATURE_VEC_MAGIC.to_le_bytes()[..]);
+        assert_same(&fv, &roundtrip(&fv));
+    }
+
+    #[test]
+    fn feature_vec_with_sections_roundtrips(fv in feature_vec(true)) {
+        let mut bytes = Vec::new();
+        fv.write_into(&mut bytes).unwrap();
+        prop_assert_eq!(&bytes[..8], &FEATURE_VEC_MAGIC.to_le_bytes()[..]);
+        assert_same(&fv, &roundtrip(&fv));
+    }
+
+    #[test]
+    fn doc_info_roundtrips(intid in any::<u64>(), docid in ".{0,32}", offset in any::<u64>()) {
+        let di = DocInfo { intid, docid, offset };
+        let bytes = bincode::serialize(&di).unwrap();
+        prop_assert_eq!(DocInfo::decode(&bytes).unwrap(), di);
+    }
+
+    #[test]
+    fn feature_vec_decode_never_panics(bytes in prop::collection::vec(any::<u8>(), 0..512)) {
+        let _ = FeatureVec::decode(&bytes);
+    }
+
+    #[test]
+    fn feature_vec_decode_with_magic_never_panics(
+        bytes in prop::collection::vec(any::<u8>(), 0..512),
+    ) {
+        let mut record = FEATURE_VEC_MAGIC.to_le_bytes().to_vec();
+        record.extend(bytes);
+        let _ = FeatureVec::decode(&record);
+    }
+
+    #[test]
+    fn doc_info_decode_never_panics(bytes in prop::collection::vec(any::<u8>(), 0..512)) {
+        let _ = DocInfo::decode(&bytes);
+    }
+}